@@ -2,6 +2,9 @@
 -- 视频工具数据库完整Schema
 -- ========================================
 
+-- 全文搜索使用 trigram 索引（支持中文子串匹配）
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
 -- ========================================
 -- 1. 视频分析模块
 -- ========================================
@@ -45,10 +48,24 @@ CREATE TABLE IF NOT EXISTS projects (
     id UUID PRIMARY KEY DEFAULT gen_random_uuid(),  -- 项目唯一标识
     title VARCHAR(255) NOT NULL,                    -- 项目标题
     script TEXT,                                     -- 剧本内容 (Markdown 格式)
-    cover_image_url TEXT,                            -- 封面图 URL
+    cover_image_url TEXT,                            -- 手动指定的封面图 URL（为空时使用第一个分镜的首帧图）
     global_image_prompt TEXT,                        -- 生成首帧图的全局提示词
     global_video_prompt TEXT,                        -- 生成视频的全局提示词
     project_type VARCHAR(20) DEFAULT 'video',        -- 项目类型: 'video' 或 'comic'
+    description TEXT,                                -- 项目简介（可选）
+    aspect_ratio VARCHAR(10) DEFAULT '9:16',         -- 输出画面比例，如 '9:16'、'16:9'
+    resolution VARCHAR(10) DEFAULT '1080p',          -- 输出分辨率: '720p'、'1080p' 或 '4k'
+    fps INTEGER DEFAULT 24,                          -- 输出帧率
+    target_runtime DOUBLE PRECISION,                 -- 目标总时长（秒，可选）
+    prompt_variables JSONB NOT NULL DEFAULT '{}',    -- 提示词变量，供 {{var:名称}} 占位符引用
+    prompt_translation VARCHAR(20) NOT NULL DEFAULT 'original', -- 提示词翻译模式: original/translated/both
+    image_provider VARCHAR(20),                      -- 图片生成服务: gemini/openai/sd_webui/comfyui（为空使用服务器默认）
+    image_model VARCHAR(100),                        -- 图片生成模型（为空使用服务默认模型）
+    continuity_mode VARCHAR(20) NOT NULL DEFAULT 'off', -- 首帧图连贯模式: off/sequence/always（参考上一分镜画面）
+    image_postprocess JSONB,                         -- 首帧图后处理配置（为空使用服务器默认）
+    forked_from UUID REFERENCES projects(id) ON DELETE SET NULL, -- 复制来源项目ID（可选）
+    archived_at TIMESTAMPTZ,                         -- 归档时间（为空表示未归档）
+    deleted_at TIMESTAMPTZ,                          -- 移入回收站时间（为空表示未删除）
     created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),  -- 创建时间
     updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()   -- 更新时间
 );
@@ -59,13 +76,15 @@ CREATE TABLE IF NOT EXISTS storyboard_scenes (
     id SERIAL PRIMARY KEY,                          -- 分镜唯一标识
     project_id UUID NOT NULL REFERENCES projects(id) ON DELETE CASCADE, -- 关联的项目ID
     scene_index INTEGER NOT NULL,                   -- 分镜序号 (对应 JSON 中的 id)
-    start_time VARCHAR(20),                         -- 时间轴开始 (可选)
-    end_time VARCHAR(20),                           -- 时间轴结束 (可选)
+    start_time VARCHAR(20),                         -- 时间轴开始 (根据分镜时长自动计算)
+    end_time VARCHAR(20),                           -- 时间轴结束 (根据分镜时长自动计算)
     duration DOUBLE PRECISION,                      -- 分镜时长 (秒，可选)
     first_frame_prompt TEXT,                        -- 首帧图提示词
     video_prompt TEXT,                              -- 视频提示词
     latest_image_url TEXT,                          -- 最新生成的图片 URL
     latest_video_url TEXT,                          -- 最新生成的视频 URL
+    status VARCHAR(20) NOT NULL DEFAULT 'draft',    -- 审核状态: draft/in_review/approved/locked
+    sequence VARCHAR(100),                          -- 所属场次/场景段落（连贯模式按此判断是否延续上一分镜）
     created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),  -- 创建时间
     updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),  -- 更新时间
     UNIQUE(project_id, scene_index)                 -- 确保每个项目的分镜序号唯一
@@ -78,7 +97,22 @@ CREATE TABLE IF NOT EXISTS generation_history (
     scene_id INTEGER NOT NULL REFERENCES storyboard_scenes(id) ON DELETE CASCADE, -- 关联的分镜ID
     generation_type VARCHAR(20) NOT NULL,           -- 生成类型: 'image' 或 'video'
     prompt TEXT NOT NULL,                           -- 使用的提示词
+    translated_prompt TEXT,                         -- 发送给模型的英文翻译（未翻译时为空）
     result_url TEXT NOT NULL,                       -- 生成结果 URL
+    candidate_batch_id UUID,                        -- 候选图批次ID（一次生成多个候选图时共享）
+    source_history_id INTEGER REFERENCES generation_history(id) ON DELETE SET NULL, -- 局部重绘的原图版本
+    mask_url TEXT,                                  -- 局部重绘使用的蒙版 URL
+    original_url TEXT,                              -- 后处理前的原图 URL（未后处理时为空）
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()   -- 创建时间
+);
+
+-- 视频生成任务表
+-- 记录提交给 VEO 的提示词，轮询到完成时据此写入生成历史
+CREATE TABLE IF NOT EXISTS video_tasks (
+    video_id VARCHAR(255) PRIMARY KEY,              -- VEO 返回的视频任务ID
+    scene_id INTEGER NOT NULL REFERENCES storyboard_scenes(id) ON DELETE CASCADE, -- 关联的分镜ID
+    prompt TEXT NOT NULL,                           -- 完整提示词（展开占位符后）
+    translated_prompt TEXT,                         -- 发送给模型的英文翻译（未翻译时为空）
     created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()   -- 创建时间
 );
 
@@ -91,6 +125,77 @@ CREATE TABLE IF NOT EXISTS composite_videos (
     created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()   -- 创建时间
 );
 
+-- 分镜审核备注表
+-- 存储分镜的审核意见和状态变更记录
+CREATE TABLE IF NOT EXISTS scene_review_notes (
+    id SERIAL PRIMARY KEY,                          -- 备注唯一标识
+    scene_id INTEGER NOT NULL REFERENCES storyboard_scenes(id) ON DELETE CASCADE, -- 关联的分镜ID
+    author VARCHAR(100) NOT NULL,                   -- 备注作者
+    note TEXT NOT NULL,                             -- 备注内容
+    status VARCHAR(20),                             -- 随备注变更的状态（可选）
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()   -- 创建时间
+);
+
+-- 分镜草稿表
+-- 存储文本模型根据剧本拆解出的分镜草稿，确认后写入分镜表
+CREATE TABLE IF NOT EXISTS storyboard_drafts (
+    id SERIAL PRIMARY KEY,                          -- 草稿唯一标识
+    project_id UUID NOT NULL REFERENCES projects(id) ON DELETE CASCADE, -- 关联的项目ID
+    scenes JSONB NOT NULL,                          -- 草稿分镜列表（时长、提示词、出场角色）
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()   -- 创建时间
+);
+
+-- 提示词编辑历史表
+-- 记录分镜提示词、全局提示词和剧本的每次修改
+CREATE TABLE IF NOT EXISTS prompt_edits (
+    id SERIAL PRIMARY KEY,                          -- 编辑记录唯一标识
+    project_id UUID NOT NULL REFERENCES projects(id) ON DELETE CASCADE, -- 关联的项目ID
+    scene_id INTEGER REFERENCES storyboard_scenes(id) ON DELETE CASCADE, -- 关联的分镜ID（为空表示项目级字段）
+    field VARCHAR(30) NOT NULL,                     -- 字段: first_frame_prompt/video_prompt/global_image_prompt/global_video_prompt/script
+    old_value TEXT,                                 -- 修改前内容
+    new_value TEXT,                                 -- 修改后内容
+    author VARCHAR(100),                            -- 修改人（可选）
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()   -- 修改时间
+);
+
+-- 批量生成任务表
+-- 记录批量生成首帧图等任务的进度和每个分镜的结果
+CREATE TABLE IF NOT EXISTS generation_batches (
+    id UUID PRIMARY KEY,                            -- 批量任务唯一标识
+    project_id UUID NOT NULL REFERENCES projects(id) ON DELETE CASCADE, -- 关联的项目ID
+    generation_type VARCHAR(20) NOT NULL,           -- 生成类型: 'image'
+    status VARCHAR(20) NOT NULL,                    -- 状态: running/completed/interrupted
+    concurrency INTEGER NOT NULL,                   -- 并发数
+    total INTEGER NOT NULL,                         -- 分镜总数
+    completed INTEGER NOT NULL DEFAULT 0,           -- 已完成数量（含失败和跳过）
+    succeeded INTEGER NOT NULL DEFAULT 0,           -- 成功数量
+    failed INTEGER NOT NULL DEFAULT 0,              -- 失败数量
+    skipped INTEGER NOT NULL DEFAULT 0,             -- 跳过数量（如锁定的分镜）
+    items JSONB NOT NULL,                           -- 每个分镜的状态、结果 URL 和错误信息
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),  -- 创建时间
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()   -- 更新时间
+);
+
+-- 提示词翻译缓存表
+-- 按提示词哈希缓存文本模型的英文翻译，避免重复调用
+CREATE TABLE IF NOT EXISTS prompt_translations (
+    prompt_hash CHAR(64) PRIMARY KEY,               -- 原文的 SHA-256
+    source_text TEXT NOT NULL,                      -- 原文
+    translated_text TEXT NOT NULL,                  -- 英文翻译
+    model VARCHAR(100) NOT NULL,                    -- 翻译使用的模型
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()   -- 创建时间
+);
+
+-- 项目快照表
+-- 存储项目的恢复点：剧本、全局提示词、分镜提示词/时长、当前选用的图片/视频和出场角色
+CREATE TABLE IF NOT EXISTS project_snapshots (
+    id SERIAL PRIMARY KEY,                          -- 快照唯一标识
+    project_id UUID NOT NULL REFERENCES projects(id) ON DELETE CASCADE, -- 关联的项目ID
+    name VARCHAR(255) NOT NULL,                     -- 快照名称
+    data JSONB NOT NULL,                            -- 快照内容
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()   -- 创建时间
+);
+
 -- ========================================
 -- 3. 角色管理模块
 -- ========================================
@@ -125,6 +230,15 @@ CREATE TABLE IF NOT EXISTS project_characters (
     CONSTRAINT project_characters_link_character_id_fkey FOREIGN KEY (character_id) REFERENCES characters(id) ON DELETE CASCADE
 );
 
+-- 分镜出场角色表
+-- 记录每个分镜中出场的角色，生成首帧图时只附带这些角色的参考图
+CREATE TABLE IF NOT EXISTS scene_characters (
+    scene_id INTEGER NOT NULL REFERENCES storyboard_scenes(id) ON DELETE CASCADE, -- 分镜ID
+    character_id UUID NOT NULL REFERENCES characters(id) ON DELETE CASCADE,     -- 角色ID
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),  -- 创建时间
+    PRIMARY KEY (scene_id, character_id)
+);
+
 -- ========================================
 -- 索引优化
 -- ========================================
@@ -140,6 +254,21 @@ CREATE INDEX IF NOT EXISTS idx_storyboard_project_id ON storyboard_scenes(projec
 CREATE INDEX IF NOT EXISTS idx_history_scene_id ON generation_history(scene_id);
 CREATE INDEX IF NOT EXISTS idx_history_scene_type ON generation_history(scene_id, generation_type);
 CREATE INDEX IF NOT EXISTS idx_composite_project_id ON composite_videos(project_id);
+CREATE INDEX IF NOT EXISTS idx_storyboard_drafts_project_id ON storyboard_drafts(project_id);
+CREATE INDEX IF NOT EXISTS idx_scene_review_notes_scene_id ON scene_review_notes(scene_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_generation_batches_project_id ON generation_batches(project_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_prompt_edits_project_id ON prompt_edits(project_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_prompt_edits_scene_id ON prompt_edits(scene_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_project_snapshots_project_id ON project_snapshots(project_id, created_at DESC);
+
+-- 全文搜索索引（trigram，配合 ILIKE 使用）
+CREATE INDEX IF NOT EXISTS idx_projects_title_trgm ON projects USING GIN (title gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS idx_projects_script_trgm ON projects USING GIN (script gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS idx_projects_global_image_prompt_trgm ON projects USING GIN (global_image_prompt gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS idx_projects_global_video_prompt_trgm ON projects USING GIN (global_video_prompt gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS idx_storyboard_first_frame_prompt_trgm ON storyboard_scenes USING GIN (first_frame_prompt gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS idx_storyboard_video_prompt_trgm ON storyboard_scenes USING GIN (video_prompt gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS idx_history_prompt_trgm ON generation_history USING GIN (prompt gin_trgm_ops);
 
 -- 角色管理模块索引
 CREATE INDEX IF NOT EXISTS idx_characters_created_at ON characters(created_at DESC);
@@ -147,6 +276,38 @@ CREATE INDEX IF NOT EXISTS idx_characters_updated_at ON characters(updated_at DE
 CREATE INDEX IF NOT EXISTS idx_characters_name ON characters(name);
 CREATE INDEX IF NOT EXISTS idx_project_characters_project_id ON project_characters(project_id);
 CREATE INDEX IF NOT EXISTS idx_project_characters_order ON project_characters(project_id, display_order);
+CREATE INDEX IF NOT EXISTS idx_scene_characters_character_id ON scene_characters(character_id);
+
+-- ========================================
+-- 已有数据库升级
+-- ========================================
+
+ALTER TABLE projects ADD COLUMN IF NOT EXISTS forked_from UUID REFERENCES projects(id) ON DELETE SET NULL;
+ALTER TABLE projects ADD COLUMN IF NOT EXISTS description TEXT;
+ALTER TABLE projects ADD COLUMN IF NOT EXISTS aspect_ratio VARCHAR(10) DEFAULT '9:16';
+ALTER TABLE projects ADD COLUMN IF NOT EXISTS resolution VARCHAR(10) DEFAULT '1080p';
+ALTER TABLE projects ADD COLUMN IF NOT EXISTS fps INTEGER DEFAULT 24;
+ALTER TABLE projects ADD COLUMN IF NOT EXISTS prompt_variables JSONB NOT NULL DEFAULT '{}';
+ALTER TABLE projects ADD COLUMN IF NOT EXISTS archived_at TIMESTAMPTZ;
+ALTER TABLE projects ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+ALTER TABLE projects ADD COLUMN IF NOT EXISTS target_runtime DOUBLE PRECISION;
+ALTER TABLE projects ADD COLUMN IF NOT EXISTS prompt_translation VARCHAR(20) NOT NULL DEFAULT 'original';
+ALTER TABLE projects ADD COLUMN IF NOT EXISTS image_provider VARCHAR(20);
+ALTER TABLE projects ADD COLUMN IF NOT EXISTS image_model VARCHAR(100);
+ALTER TABLE projects ADD COLUMN IF NOT EXISTS continuity_mode VARCHAR(20) NOT NULL DEFAULT 'off';
+ALTER TABLE projects ADD COLUMN IF NOT EXISTS image_postprocess JSONB;
+ALTER TABLE generation_history ADD COLUMN IF NOT EXISTS translated_prompt TEXT;
+ALTER TABLE generation_history ADD COLUMN IF NOT EXISTS candidate_batch_id UUID;
+ALTER TABLE generation_history ADD COLUMN IF NOT EXISTS source_history_id INTEGER REFERENCES generation_history(id) ON DELETE SET NULL;
+ALTER TABLE generation_history ADD COLUMN IF NOT EXISTS mask_url TEXT;
+ALTER TABLE generation_history ADD COLUMN IF NOT EXISTS original_url TEXT;
+ALTER TABLE storyboard_scenes ADD COLUMN IF NOT EXISTS status VARCHAR(20) NOT NULL DEFAULT 'draft';
+ALTER TABLE storyboard_scenes ADD COLUMN IF NOT EXISTS sequence VARCHAR(100);
+
+-- 依赖升级字段的索引
+CREATE INDEX IF NOT EXISTS idx_history_candidate_batch ON generation_history(candidate_batch_id) WHERE candidate_batch_id IS NOT NULL;
+CREATE INDEX IF NOT EXISTS idx_projects_description_trgm ON projects USING GIN (description gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS idx_projects_deleted_at ON projects(deleted_at) WHERE deleted_at IS NOT NULL;
 
 -- ========================================
 -- 注释说明
@@ -157,6 +318,14 @@ COMMENT ON TABLE scenes IS '视频场景表';
 COMMENT ON TABLE projects IS '用户项目表';
 COMMENT ON TABLE storyboard_scenes IS '项目分镜表';
 COMMENT ON TABLE generation_history IS '图片/视频生成历史记录表';
+COMMENT ON TABLE video_tasks IS '视频生成任务表';
 COMMENT ON TABLE composite_videos IS '合成视频记录表';
+COMMENT ON TABLE storyboard_drafts IS '分镜草稿表';
+COMMENT ON TABLE scene_review_notes IS '分镜审核备注表';
+COMMENT ON TABLE prompt_edits IS '提示词编辑历史表';
+COMMENT ON TABLE generation_batches IS '批量生成任务表';
+COMMENT ON TABLE prompt_translations IS '提示词翻译缓存表';
+COMMENT ON TABLE project_snapshots IS '项目快照表';
 COMMENT ON TABLE characters IS '统一角色库表';
 COMMENT ON TABLE project_characters IS '项目角色关联表';
+COMMENT ON TABLE scene_characters IS '分镜出场角色表';
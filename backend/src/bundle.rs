@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+/// 项目包中清单文件的名称
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+/// 打包时下载单个外链文件的超时时间
+const DOWNLOAD_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(120);
+/// 打包时单个外链文件的大小上限
+const MAX_DOWNLOAD_BYTES: u64 = 500 * 1024 * 1024;
+
+/// 打包条目的数据来源
+pub enum BundleSource {
+    /// 本地文件（按流复制，避免整体读入内存）
+    File(PathBuf),
+    /// 已下载到内存中的数据（例如 R2 外链）
+    Bytes(Vec<u8>),
+}
+
+pub struct BundleEntry {
+    pub archive_path: String,
+    pub source: BundleSource,
+}
+
+/// 收集需要打包的媒体文件，同一 URL 只打包一次
+#[derive(Default)]
+pub struct BundleBuilder {
+    entries: Vec<BundleEntry>,
+    by_url: HashMap<String, String>,
+}
+
+impl BundleBuilder {
+    /// 添加媒体文件，返回其在包内的路径；文件不可用时返回 None
+    pub async fn add_media(&mut self, url: &str, folder: &str) -> Option<String> {
+        if let Some(archive_path) = self.by_url.get(url) {
+            return Some(archive_path.clone());
+        }
+
+        let source = if url.starts_with("/data/") {
+            let local_path = PathBuf::from(format!(".{}", url));
+            if !local_path.exists() {
+                println!("⚠️  打包时文件不存在: {}", url);
+                return None;
+            }
+            BundleSource::File(local_path)
+        } else if url.starts_with("http://") || url.starts_with("https://") {
+            match download_bytes(url).await {
+                Ok(bytes) => BundleSource::Bytes(bytes),
+                Err(e) => {
+                    println!("⚠️  打包时下载失败: {} - {}", url, e);
+                    return None;
+                }
+            }
+        } else {
+            println!("⚠️  打包时跳过无法识别的URL: {}", url);
+            return None;
+        };
+
+        let basename = url
+            .split('?')
+            .next()
+            .and_then(|u| u.rsplit('/').next())
+            .filter(|name| !name.is_empty())
+            .unwrap_or("file");
+        let archive_path = format!("{}/{}_{}", folder, self.entries.len(), basename);
+
+        self.entries.push(BundleEntry {
+            archive_path: archive_path.clone(),
+            source,
+        });
+        self.by_url.insert(url.to_string(), archive_path.clone());
+        Some(archive_path)
+    }
+
+    pub fn into_entries(self) -> Vec<BundleEntry> {
+        self.entries
+    }
+}
+
+/// 下载外链媒体，超时或超过大小上限时放弃该文件
+async fn download_bytes(url: &str) -> io::Result<Vec<u8>> {
+    let client = reqwest::Client::builder()
+        .timeout(DOWNLOAD_TIMEOUT)
+        .build()
+        .map_err(to_io_error_from_reqwest)?;
+    let mut response = client
+        .get(url)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(to_io_error_from_reqwest)?;
+
+    if response.content_length().is_some_and(|len| len > MAX_DOWNLOAD_BYTES) {
+        return Err(too_large_error());
+    }
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(to_io_error_from_reqwest)? {
+        if (bytes.len() + chunk.len()) as u64 > MAX_DOWNLOAD_BYTES {
+            return Err(too_large_error());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+fn too_large_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("文件超过 {} MB", MAX_DOWNLOAD_BYTES / 1024 / 1024),
+    )
+}
+
+/// 将清单和所有媒体文件写入 ZIP 文件
+pub fn write_bundle(output: &Path, manifest_json: &str, entries: &[BundleEntry]) -> io::Result<()> {
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut zip = ZipWriter::new(File::create(output)?);
+
+    let json_options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    zip.start_file(MANIFEST_NAME, json_options).map_err(to_io_error)?;
+    zip.write_all(manifest_json.as_bytes())?;
+
+    // 图片和视频本身已压缩，直接存储即可
+    let media_options = FileOptions::default()
+        .compression_method(CompressionMethod::Stored)
+        .large_file(true);
+
+    for entry in entries {
+        zip.start_file(entry.archive_path.as_str(), media_options).map_err(to_io_error)?;
+        match &entry.source {
+            BundleSource::File(path) => {
+                let mut file = File::open(path)?;
+                io::copy(&mut file, &mut zip)?;
+            }
+            BundleSource::Bytes(bytes) => zip.write_all(bytes)?,
+        }
+    }
+
+    zip.finish().map_err(to_io_error)?;
+    Ok(())
+}
+
+/// 打开内存中的 ZIP 并读取清单内容
+pub fn open_bundle(bytes: Vec<u8>) -> io::Result<(ZipArchive<Cursor<Vec<u8>>>, String)> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).map_err(to_io_error)?;
+
+    let mut manifest = String::new();
+    archive
+        .by_name(MANIFEST_NAME)
+        .map_err(to_io_error)?
+        .read_to_string(&mut manifest)?;
+
+    Ok((archive, manifest))
+}
+
+/// 将 ZIP 中的条目解压到指定路径
+pub fn extract_entry(
+    archive: &mut ZipArchive<Cursor<Vec<u8>>>,
+    archive_path: &str,
+    dest: &Path,
+) -> io::Result<()> {
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut entry = archive.by_name(archive_path).map_err(to_io_error)?;
+    let mut file = File::create(dest)?;
+    io::copy(&mut entry, &mut file)?;
+    Ok(())
+}
+
+fn to_io_error(e: zip::result::ZipError) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+fn to_io_error_from_reqwest(e: reqwest::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
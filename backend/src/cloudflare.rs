@@ -8,12 +8,21 @@ use crate::models::UploadedFile;
 pub struct CloudflareStorage {
     bucket_name: String,
     public_url: String,
-    s3_client: aws_sdk_s3::Client,
+    s3_client: Option<aws_sdk_s3::Client>,  // None 表示模拟模式（MOCK_AI），不实际上传
 }
 
 impl CloudflareStorage {
     /// 从环境变量创建 Cloudflare R2 存储客户端
     pub async fn new() -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        if crate::mock::enabled() {
+            return Ok(Self {
+                bucket_name: String::new(),
+                // mock::install_env 把 R2_PUBLIC_URL 设为本服务地址
+                public_url: env::var("R2_PUBLIC_URL").unwrap_or_default(),
+                s3_client: None,
+            });
+        }
+
         let account_id = env::var("R2_ACCOUNT_ID")
             .map_err(|_| "R2_ACCOUNT_ID 环境变量未设置")?;
         let access_key_id = env::var("R2_ACCESS_KEY_ID")
@@ -49,7 +58,7 @@ impl CloudflareStorage {
         Ok(Self {
             bucket_name,
             public_url,
-            s3_client,
+            s3_client: Some(s3_client),
         })
     }
 
@@ -60,6 +69,16 @@ impl CloudflareStorage {
         remote_folder: Option<&str>,
         custom_name: Option<&str>,
     ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        // 模拟模式：文件已由本服务的 /data 路由提供，直接返回本地地址
+        let Some(s3_client) = &self.s3_client else {
+            if !std::path::Path::new(local_path).exists() {
+                return Err(format!("文件不存在: {}", local_path).into());
+            }
+            let public_url = format!("{}/{}", self.public_url, local_path.trim_start_matches("./"));
+            println!("🧪 模拟上传: {}", public_url);
+            return Ok(public_url);
+        };
+
         // 读取文件 (Async)
         println!("📂 读取本地文件: {}", local_path);
         let mut file = tokio::fs::File::open(local_path).await?;
@@ -98,7 +117,7 @@ impl CloudflareStorage {
         // 使用 AWS SDK 上传
         let body = aws_sdk_s3::primitives::ByteStream::from(buffer);
         
-        self.s3_client
+        s3_client
             .put_object()
             .bucket(&self.bucket_name)
             .key(&remote_path)
@@ -123,6 +142,11 @@ impl CloudflareStorage {
         local_path: &str,
         file_type: &str,
     ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        // 模拟模式不写入上传缓存，避免切回真实存储后复用本地地址
+        if self.s3_client.is_none() {
+            return self.upload_file(local_path, None, None).await;
+        }
+
         // 计算文件哈希
         let file_hash = calculate_file_hash(local_path)?;
         let file_size = std::fs::metadata(local_path)?.len() as i64;
@@ -176,7 +200,7 @@ impl CloudflareStorage {
 }
 
 /// 计算文件的 SHA256 哈希值
-fn calculate_file_hash(path: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+pub fn calculate_file_hash(path: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
     let mut file = File::open(path)?;
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer)?;
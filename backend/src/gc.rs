@@ -0,0 +1,237 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+use uuid::Uuid;
+
+/// 扫描到的孤立文件或目录
+#[derive(Debug, Clone, Serialize)]
+pub struct Orphan {
+    pub path: String,
+    pub size_bytes: u64,
+    pub is_dir: bool,
+    pub reason: &'static str,
+}
+
+/// 数据库中仍然有效的引用
+pub struct LiveReferences {
+    /// 所有被引用的本地 URL（形如 /data/...，已去掉查询参数）
+    pub urls: HashSet<String>,
+    pub project_ids: HashSet<Uuid>,
+    pub job_ids: HashSet<Uuid>,
+}
+
+impl LiveReferences {
+    pub fn new(urls: Vec<String>, project_ids: Vec<Uuid>, job_ids: Vec<Uuid>) -> Self {
+        let urls = urls
+            .iter()
+            .filter_map(|url| normalize_url(url))
+            .collect();
+
+        LiveReferences {
+            urls,
+            project_ids: project_ids.into_iter().collect(),
+            job_ids: job_ids.into_iter().collect(),
+        }
+    }
+}
+
+// 统一为 /data/... 形式，忽略外链
+fn normalize_url(url: &str) -> Option<String> {
+    let url = url.split('?').next()?.trim();
+    let url = url.strip_prefix('.').unwrap_or(url);
+    if url.starts_with("/data/") {
+        Some(url.to_string())
+    } else if url.starts_with("data/") {
+        Some(format!("/{}", url))
+    } else {
+        None
+    }
+}
+
+/// 扫描 data 目录，找出数据库中不再引用的文件
+///
+/// 最近 `min_age` 内修改过的文件会被跳过，避免误删正在生成或导出的文件。
+pub fn scan(data_dir: &Path, refs: &LiveReferences, min_age: Duration) -> io::Result<Vec<Orphan>> {
+    let mut orphans = Vec::new();
+
+    // data/projects/{project_id}/...
+    for entry in read_dir_sorted(&data_dir.join("projects"))? {
+        let path = entry;
+        let name = file_name(&path);
+        match Uuid::parse_str(&name) {
+            Ok(id) if refs.project_ids.contains(&id) => {
+                if path.is_dir() {
+                    scan_project_dir(data_dir, &path, refs, min_age, &mut orphans)?;
+                }
+            }
+            Ok(_) => push_orphan(data_dir, &path, "project_deleted", min_age, &mut orphans)?,
+            Err(_) => {}
+        }
+    }
+
+    // data/analysis/{job_id}/...
+    for path in read_dir_sorted(&data_dir.join("analysis"))? {
+        if let Ok(id) = Uuid::parse_str(&file_name(&path)) {
+            if !refs.job_ids.contains(&id) {
+                push_orphan(data_dir, &path, "job_deleted", min_age, &mut orphans)?;
+            }
+        }
+    }
+
+    // data/characters/*
+    for path in read_dir_sorted(&data_dir.join("characters"))? {
+        if path.is_file() && !refs.urls.contains(&to_url(data_dir, &path)) {
+            push_orphan(data_dir, &path, "unreferenced", min_age, &mut orphans)?;
+        }
+    }
+
+    Ok(orphans)
+}
+
+fn scan_project_dir(
+    data_dir: &Path,
+    dir: &Path,
+    refs: &LiveReferences,
+    min_age: Duration,
+    orphans: &mut Vec<Orphan>,
+) -> io::Result<()> {
+    for path in read_dir_sorted(dir)? {
+        let name = file_name(&path);
+
+        // 导出临时目录、合成视频的文件列表、下载的临时角色图
+        let is_temp = name.starts_with("temp_export") || name.starts_with("concat_list_") || name.starts_with("temp_");
+        if is_temp {
+            push_orphan(data_dir, &path, "temp", min_age, orphans)?;
+            continue;
+        }
+
+        if path.is_dir() {
+            scan_project_dir(data_dir, &path, refs, min_age, orphans)?;
+        } else if !refs.urls.contains(&to_url(data_dir, &path)) {
+            push_orphan(data_dir, &path, "unreferenced", min_age, orphans)?;
+        }
+    }
+    Ok(())
+}
+
+fn push_orphan(
+    data_dir: &Path,
+    path: &Path,
+    reason: &'static str,
+    min_age: Duration,
+    orphans: &mut Vec<Orphan>,
+) -> io::Result<()> {
+    if modified_within(path, min_age)? {
+        return Ok(());
+    }
+
+    orphans.push(Orphan {
+        path: to_url(data_dir, path).trim_start_matches('/').to_string(),
+        size_bytes: disk_usage(path)?,
+        is_dir: path.is_dir(),
+        reason,
+    });
+    Ok(())
+}
+
+/// 删除孤立文件，返回 (删除数量, 释放字节数, 错误列表)
+pub fn delete(data_dir: &Path, orphans: &[Orphan]) -> (usize, u64, Vec<String>) {
+    let mut deleted = 0;
+    let mut freed = 0;
+    let mut errors = Vec::new();
+
+    for orphan in orphans {
+        // path 形如 data/...，只允许删除 data 目录下的内容
+        let Some(relative) = orphan.path.strip_prefix("data/") else {
+            errors.push(format!("{}: 路径不在 data 目录下", orphan.path));
+            continue;
+        };
+        if relative.split('/').any(|part| part == "..") {
+            errors.push(format!("{}: 非法路径", orphan.path));
+            continue;
+        }
+
+        let path = data_dir.join(relative);
+        let result = if orphan.is_dir {
+            fs::remove_dir_all(&path)
+        } else {
+            fs::remove_file(&path)
+        };
+
+        match result {
+            Ok(_) => {
+                deleted += 1;
+                freed += orphan.size_bytes;
+            }
+            Err(e) => errors.push(format!("{}: {}", orphan.path, e)),
+        }
+    }
+
+    (deleted, freed, errors)
+}
+
+// 目录或文件的 mtime 是否在 min_age 之内（目录取其中最新的文件）
+fn modified_within(path: &Path, min_age: Duration) -> io::Result<bool> {
+    if min_age.is_zero() {
+        return Ok(false);
+    }
+
+    let modified = fs::metadata(path)?.modified()?;
+    let recent = SystemTime::now()
+        .duration_since(modified)
+        .map(|age| age < min_age)
+        .unwrap_or(true);
+    if recent {
+        return Ok(true);
+    }
+
+    if path.is_dir() {
+        for child in read_dir_sorted(path)? {
+            if modified_within(&child, min_age)? {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+fn disk_usage(path: &Path) -> io::Result<u64> {
+    let metadata = fs::symlink_metadata(path)?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+
+    let mut total = 0;
+    for child in read_dir_sorted(path)? {
+        total += disk_usage(&child)?;
+    }
+    Ok(total)
+}
+
+fn read_dir_sorted(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+// 本地路径转换为 /data/... 形式的 URL
+fn to_url(data_dir: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(data_dir).unwrap_or(path);
+    format!("/data/{}", relative.to_string_lossy().replace('\\', "/"))
+}
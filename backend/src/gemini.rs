@@ -0,0 +1,475 @@
+use std::fmt;
+use std::time::Duration;
+
+use base64::Engine as _;
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::logger;
+
+// ========================================
+// 请求 / 响应结构
+// ========================================
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerateContentRequest {
+    pub contents: Vec<Content>,
+    pub generation_config: GenerationConfig,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Content {
+    #[serde(default)]
+    pub parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Part {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inline_data: Option<InlineData>,
+}
+
+impl Part {
+    pub fn text(text: impl Into<String>) -> Self {
+        Part { text: Some(text.into()), inline_data: None }
+    }
+
+    /// base64 编码的图片
+    pub fn image(mime_type: impl Into<String>, data: impl Into<String>) -> Self {
+        Part {
+            text: None,
+            inline_data: Some(InlineData { mime_type: mime_type.into(), data: data.into() }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlineData {
+    pub mime_type: String,
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerationConfig {
+    pub temperature: f32,
+    pub top_k: u32,
+    pub top_p: f32,
+    pub max_output_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub candidate_count: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_config: Option<ImageConfig>,
+}
+
+impl Default for GenerationConfig {
+    fn default() -> Self {
+        GenerationConfig {
+            temperature: 0.4,
+            top_k: 32,
+            top_p: 1.0,
+            max_output_tokens: 8192,
+            candidate_count: None,
+            image_config: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImageConfig {
+    pub aspect_ratio: String,
+    pub image_size: String,
+}
+
+impl GenerateContentRequest {
+    /// 单轮请求
+    pub fn new(parts: Vec<Part>, generation_config: GenerationConfig) -> Self {
+        GenerateContentRequest {
+            contents: vec![Content { parts }],
+            generation_config,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerateContentResponse {
+    #[serde(default)]
+    pub candidates: Vec<Candidate>,
+    pub prompt_feedback: Option<PromptFeedback>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Candidate {
+    pub content: Option<Content>,
+    pub finish_reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PromptFeedback {
+    pub block_reason: Option<String>,
+}
+
+// 因安全策略终止生成的 finishReason
+const SAFETY_FINISH_REASONS: &[&str] = &["SAFETY", "PROHIBITED_CONTENT", "IMAGE_SAFETY", "BLOCKLIST", "SPII"];
+
+// ========================================
+// 错误分类
+// ========================================
+
+#[derive(Debug)]
+pub enum GeminiError {
+    /// 缺少环境变量等配置问题
+    Config(String),
+    /// 网络连接失败
+    Network(String),
+    /// 请求超时
+    Timeout,
+    /// 配额用尽或触发限流（HTTP 429）
+    Quota(String),
+    /// 提示词或生成结果被安全策略拦截
+    SafetyBlock(String),
+    /// 模型返回了空结果
+    EmptyResponse,
+    /// 其他 HTTP 错误
+    Api { status: u16, message: String },
+    /// 响应无法解析
+    InvalidResponse(String),
+}
+
+impl GeminiError {
+    /// 分类标识，返回给前端用于区分错误类型
+    pub fn kind(&self) -> &'static str {
+        match self {
+            GeminiError::Config(_) => "config",
+            GeminiError::Network(_) => "network",
+            GeminiError::Timeout => "timeout",
+            GeminiError::Quota(_) => "quota",
+            GeminiError::SafetyBlock(_) => "safety_block",
+            GeminiError::EmptyResponse => "empty_response",
+            GeminiError::Api { .. } => "api",
+            GeminiError::InvalidResponse(_) => "invalid_response",
+        }
+    }
+
+    // 临时性错误才重试；安全拦截和配置错误重试也没有意义
+    fn is_retryable(&self) -> bool {
+        match self {
+            GeminiError::Network(_) | GeminiError::Timeout | GeminiError::Quota(_) | GeminiError::EmptyResponse => true,
+            GeminiError::Api { status, .. } => *status >= 500,
+            _ => false,
+        }
+    }
+
+    fn from_status(status: StatusCode, body: String) -> Self {
+        // 部分第三方供应商在内容被过滤时返回 empty_response
+        if body.contains("empty response") || body.contains("empty_response") {
+            return GeminiError::EmptyResponse;
+        }
+        if status == StatusCode::TOO_MANY_REQUESTS || body.contains("RESOURCE_EXHAUSTED") {
+            return GeminiError::Quota(body);
+        }
+        if body.contains("SAFETY") || body.contains("PROHIBITED_CONTENT") {
+            return GeminiError::SafetyBlock(body);
+        }
+        GeminiError::Api { status: status.as_u16(), message: body }
+    }
+
+    fn from_reqwest(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            GeminiError::Timeout
+        } else {
+            GeminiError::Network(e.to_string())
+        }
+    }
+}
+
+impl fmt::Display for GeminiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GeminiError::Config(msg) => write!(f, "Gemini 配置错误: {}", msg),
+            GeminiError::Network(msg) => write!(f, "无法连接 Gemini API: {}", msg),
+            GeminiError::Timeout => write!(f, "Gemini API 请求超时"),
+            GeminiError::Quota(msg) => write!(f, "Gemini API 配额不足或请求过于频繁，请稍后重试: {}", msg),
+            GeminiError::SafetyBlock(reason) => write!(
+                f,
+                "提示词或生成结果触发了安全过滤 ({})，请修改描述，避免使用可能引起歧义的词汇",
+                reason
+            ),
+            GeminiError::EmptyResponse => write!(
+                f,
+                "AI 模型返回了空结果，可能是提示词触发了内容审核或描述不够清晰，请尝试使用更具体、更中性的描述"
+            ),
+            GeminiError::Api { status, message } => write!(f, "Gemini API 错误 (status {}): {}", status, message),
+            GeminiError::InvalidResponse(msg) => write!(f, "无法解析 Gemini 响应: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for GeminiError {}
+
+// ========================================
+// 客户端
+// ========================================
+
+// 最多重试次数和单次重试的最长等待时间
+const MAX_RETRIES_LIMIT: u32 = 5;
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Gemini 客户端配置，从环境变量读取
+#[derive(Debug, Clone)]
+pub struct GeminiConfig {
+    pub base_url: String,
+    pub api_key: String,
+    pub model: String,
+    pub endpoint: String,
+    pub timeout: Duration,
+    pub max_retries: u32,
+    pub retry_backoff: Duration,
+}
+
+impl GeminiConfig {
+    /// 必填：GEMINI_BASE_URL、GEMINI_API_KEY、GEMINI_MODEL、GEMINI_ENDPOINT
+    /// 可选：GEMINI_TIMEOUT_SECS（默认 120）、GEMINI_MAX_RETRIES（默认 2，最多 5）、
+    /// GEMINI_RETRY_BACKOFF_MS（默认 2000，每次重试翻倍，最长 60 秒）
+    pub fn from_env() -> Result<Self, GeminiError> {
+        fn required(name: &str) -> Result<String, GeminiError> {
+            std::env::var(name)
+                .ok()
+                .filter(|v| !v.is_empty())
+                .ok_or_else(|| GeminiError::Config(format!("{} not set", name)))
+        }
+        fn optional(name: &str, default: u64) -> u64 {
+            std::env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+        }
+
+        Ok(GeminiConfig {
+            base_url: required("GEMINI_BASE_URL")?,
+            api_key: required("GEMINI_API_KEY")?,
+            model: required("GEMINI_MODEL")?,
+            endpoint: required("GEMINI_ENDPOINT")?,
+            timeout: Duration::from_secs(optional("GEMINI_TIMEOUT_SECS", 120)),
+            max_retries: optional("GEMINI_MAX_RETRIES", 2).min(MAX_RETRIES_LIMIT as u64) as u32,
+            retry_backoff: Duration::from_millis(optional("GEMINI_RETRY_BACKOFF_MS", 2000)),
+        })
+    }
+
+    fn url(&self) -> String {
+        format!("{}{}", self.base_url, self.endpoint.replace("{model}", &self.model))
+    }
+
+    // 第 attempt 次重试（从 0 开始）前的等待时间：按指数翻倍，不超过 MAX_RETRY_DELAY
+    fn retry_delay(&self, attempt: u32) -> Duration {
+        self.retry_backoff
+            .checked_mul(2u32.saturating_pow(attempt))
+            .map_or(MAX_RETRY_DELAY, |delay| delay.min(MAX_RETRY_DELAY))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GeminiClient {
+    http: Client,
+    config: GeminiConfig,
+}
+
+impl GeminiClient {
+    pub fn new(config: GeminiConfig) -> Self {
+        GeminiClient { http: Client::new(), config }
+    }
+
+    /// 发送请求，临时性错误按指数退避重试
+    pub async fn generate_content(&self, request: &GenerateContentRequest) -> Result<GenerateContentResponse, GeminiError> {
+        let mut attempt = 0;
+        loop {
+            match self.send(request).await {
+                Ok(response) => return Ok(response),
+                Err(e) if e.is_retryable() && attempt < self.config.max_retries.min(MAX_RETRIES_LIMIT) => {
+                    let delay = self.config.retry_delay(attempt);
+                    attempt += 1;
+                    println!(
+                        "   ⚠️  Gemini 请求失败 ({})，{}ms 后第 {} 次重试",
+                        e.kind(),
+                        delay.as_millis(),
+                        attempt
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// 生成图片，返回每个候选中的第一张图片数据
+    pub async fn generate_images(&self, request: &GenerateContentRequest) -> Result<Vec<Vec<u8>>, GeminiError> {
+        let response = self.generate_content(request).await?;
+        extract_images(&response)
+    }
+
+    async fn send(&self, request: &GenerateContentRequest) -> Result<GenerateContentResponse, GeminiError> {
+        let request_log = serde_json::to_string_pretty(request).unwrap_or_default();
+
+        let res = self.http
+            .post(self.config.url())
+            .header("Content-Type", "application/json")
+            .header("x-goog-api-key", &self.config.api_key)
+            .timeout(self.config.timeout)
+            .json(request)
+            .send()
+            .await
+            .map_err(GeminiError::from_reqwest)?;
+
+        let status = res.status();
+        let body = res.text().await.map_err(GeminiError::from_reqwest)?;
+        let _ = logger::log_model_interaction(
+            &self.config.model,
+            &request_log,
+            &format!("Status: {}\n{}", status.as_u16(), body),
+        );
+
+        if !status.is_success() {
+            return Err(GeminiError::from_status(status, body));
+        }
+
+        serde_json::from_str(&body).map_err(|e| GeminiError::InvalidResponse(e.to_string()))
+    }
+}
+
+/// 从响应中提取图片；没有图片时区分安全拦截和空结果
+pub fn extract_images(response: &GenerateContentResponse) -> Result<Vec<Vec<u8>>, GeminiError> {
+    if let Some(reason) = response.prompt_feedback.as_ref().and_then(|f| f.block_reason.clone()) {
+        return Err(GeminiError::SafetyBlock(reason));
+    }
+
+    let mut images = Vec::new();
+    for candidate in &response.candidates {
+        let data = candidate
+            .content
+            .as_ref()
+            .and_then(|c| c.parts.iter().find_map(|p| p.inline_data.as_ref()))
+            .map(|d| d.data.as_str());
+        if let Some(data) = data {
+            let bytes = base64::engine::general_purpose::STANDARD
+                .decode(data)
+                .map_err(|e| GeminiError::InvalidResponse(format!("Failed to decode base64: {}", e)))?;
+            images.push(bytes);
+        }
+    }
+
+    if images.is_empty() {
+        let blocked = response
+            .candidates
+            .iter()
+            .filter_map(|c| c.finish_reason.as_deref())
+            .find(|reason| SAFETY_FINISH_REASONS.contains(reason));
+        return Err(match blocked {
+            Some(reason) => GeminiError::SafetyBlock(reason.to_string()),
+            None => GeminiError::EmptyResponse,
+        });
+    }
+    Ok(images)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config(max_retries: u32, retry_backoff: Duration) -> GeminiConfig {
+        GeminiConfig {
+            base_url: "http://localhost".to_string(),
+            api_key: "key".to_string(),
+            model: "model".to_string(),
+            endpoint: "/models/{model}:generateContent".to_string(),
+            timeout: Duration::from_secs(1),
+            max_retries,
+            retry_backoff,
+        }
+    }
+
+    fn response(json: serde_json::Value) -> GenerateContentResponse {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn retry_delay_doubles_and_is_capped() {
+        let config = test_config(2, Duration::from_millis(2000));
+        assert_eq!(config.retry_delay(0), Duration::from_secs(2));
+        assert_eq!(config.retry_delay(1), Duration::from_secs(4));
+        assert_eq!(config.retry_delay(5), MAX_RETRY_DELAY);
+        assert_eq!(config.retry_delay(40), MAX_RETRY_DELAY);
+        assert_eq!(config.retry_delay(u32::MAX), MAX_RETRY_DELAY);
+
+        let huge = test_config(2, Duration::MAX);
+        assert_eq!(huge.retry_delay(3), MAX_RETRY_DELAY);
+        assert_eq!(config.url(), "http://localhost/models/model:generateContent");
+    }
+
+    #[test]
+    fn from_status_classifies_errors() {
+        let kind = |status: u16, body: &str| {
+            GeminiError::from_status(StatusCode::from_u16(status).unwrap(), body.to_string()).kind()
+        };
+        assert_eq!(kind(429, "slow down"), "quota");
+        assert_eq!(kind(400, r#"{"error":{"status":"RESOURCE_EXHAUSTED"}}"#), "quota");
+        assert_eq!(kind(400, "blocked: SAFETY"), "safety_block");
+        assert_eq!(kind(400, "PROHIBITED_CONTENT"), "safety_block");
+        assert_eq!(kind(500, "upstream returned empty response"), "empty_response");
+        assert_eq!(kind(200, "empty_response"), "empty_response");
+        assert_eq!(kind(401, "invalid key"), "api");
+
+        assert!(GeminiError::from_status(StatusCode::BAD_GATEWAY, String::new()).is_retryable());
+        assert!(!GeminiError::from_status(StatusCode::BAD_REQUEST, String::new()).is_retryable());
+        assert!(GeminiError::from_status(StatusCode::TOO_MANY_REQUESTS, String::new()).is_retryable());
+        assert!(!GeminiError::from_status(StatusCode::BAD_REQUEST, "SAFETY".to_string()).is_retryable());
+    }
+
+    #[test]
+    fn extract_images_decodes_candidates() {
+        let images = extract_images(&response(serde_json::json!({
+            "candidates": [
+                { "content": { "parts": [{ "text": "here" }, { "inlineData": { "mimeType": "image/png", "data": "aGVsbG8=" } }] } },
+                { "content": { "parts": [{ "inlineData": { "mimeType": "image/png", "data": "d29ybGQ=" } }] } },
+                { "content": { "parts": [{ "text": "no image" }] } }
+            ]
+        })))
+        .unwrap();
+        assert_eq!(images, [b"hello".to_vec(), b"world".to_vec()]);
+
+        let invalid = extract_images(&response(serde_json::json!({
+            "candidates": [{ "content": { "parts": [{ "inlineData": { "mimeType": "image/png", "data": "not base64!" } }] } }]
+        })));
+        assert_eq!(invalid.unwrap_err().kind(), "invalid_response");
+    }
+
+    #[test]
+    fn extract_images_reports_safety_blocks() {
+        let blocked = extract_images(&response(serde_json::json!({
+            "candidates": [],
+            "promptFeedback": { "blockReason": "SAFETY" }
+        })));
+        assert!(matches!(blocked, Err(GeminiError::SafetyBlock(reason)) if reason == "SAFETY"));
+
+        let finished = extract_images(&response(serde_json::json!({
+            "candidates": [{ "content": { "parts": [] }, "finishReason": "IMAGE_SAFETY" }]
+        })));
+        assert!(matches!(finished, Err(GeminiError::SafetyBlock(reason)) if reason == "IMAGE_SAFETY"));
+    }
+
+    #[test]
+    fn extract_images_reports_empty_responses() {
+        let empty = extract_images(&response(serde_json::json!({})));
+        assert!(matches!(empty, Err(GeminiError::EmptyResponse)));
+
+        let text_only = extract_images(&response(serde_json::json!({
+            "candidates": [{ "content": { "parts": [{ "text": "sorry" }] }, "finishReason": "STOP" }]
+        })));
+        assert!(matches!(text_only, Err(GeminiError::EmptyResponse)));
+    }
+}
@@ -0,0 +1,1036 @@
+use std::fmt;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use base64::Engine as _;
+use futures_util::future::BoxFuture;
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::gemini::{self, GeminiClient, GeminiConfig, GeminiError};
+use crate::logger;
+
+/// 可选的图片生成服务
+pub const IMAGE_PROVIDERS: &[&str] = &["gemini", "openai", "sd_webui", "comfyui"];
+
+// ========================================
+// 请求
+// ========================================
+
+/// 参考图（base64 编码）
+#[derive(Debug, Clone)]
+pub struct ReferenceImage {
+    pub mime_type: String,
+    pub data: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImageRequest {
+    pub prompt: String,
+    pub references: Vec<ReferenceImage>,
+    pub mask: Option<ReferenceImage>,  // 局部重绘蒙版（白色为重绘区域），需配合第一张参考图使用
+    pub count: usize,                  // 生成张数
+    pub aspect_ratio: Option<String>,  // 画面比例，如 "9:16"；None 表示由服务决定
+    pub resolution: Option<String>,    // "720p"、"1080p" 或 "4k"
+}
+
+impl ImageRequest {
+    /// 单张纯文本生成
+    pub fn new(prompt: impl Into<String>) -> Self {
+        ImageRequest {
+            prompt: prompt.into(),
+            references: Vec::new(),
+            mask: None,
+            count: 1,
+            aspect_ratio: None,
+            resolution: None,
+        }
+    }
+}
+
+// ========================================
+// 错误分类
+// ========================================
+
+#[derive(Debug)]
+pub enum ImageError {
+    /// 缺少环境变量等配置问题
+    Config(String),
+    /// 网络连接失败
+    Network(String),
+    /// 请求超时
+    Timeout,
+    /// 配额用尽或触发限流
+    Quota(String),
+    /// 提示词或生成结果被安全策略拦截
+    SafetyBlock(String),
+    /// 服务返回了空结果
+    EmptyResponse,
+    /// 其他 HTTP 错误
+    Api { status: u16, message: String },
+    /// 响应无法解析
+    InvalidResponse(String),
+    /// 未知服务或服务不支持该请求（如未配置参考图工作流）
+    Unsupported(String),
+}
+
+impl ImageError {
+    /// 分类标识，返回给前端用于区分错误类型
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ImageError::Config(_) => "config",
+            ImageError::Network(_) => "network",
+            ImageError::Timeout => "timeout",
+            ImageError::Quota(_) => "quota",
+            ImageError::SafetyBlock(_) => "safety_block",
+            ImageError::EmptyResponse => "empty_response",
+            ImageError::Api { .. } => "api",
+            ImageError::InvalidResponse(_) => "invalid_response",
+            ImageError::Unsupported(_) => "unsupported",
+        }
+    }
+
+    fn from_status(status: StatusCode, body: String) -> Self {
+        if status == StatusCode::TOO_MANY_REQUESTS || body.contains("insufficient_quota") {
+            return ImageError::Quota(body);
+        }
+        // OpenAI 内容审核拦截
+        if body.contains("content_policy_violation") || body.contains("moderation_blocked") {
+            return ImageError::SafetyBlock(body);
+        }
+        ImageError::Api { status: status.as_u16(), message: body }
+    }
+
+    fn from_reqwest(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            ImageError::Timeout
+        } else {
+            ImageError::Network(e.to_string())
+        }
+    }
+}
+
+impl From<GeminiError> for ImageError {
+    fn from(e: GeminiError) -> Self {
+        match e {
+            GeminiError::Config(msg) => ImageError::Config(msg),
+            GeminiError::Network(msg) => ImageError::Network(msg),
+            GeminiError::Timeout => ImageError::Timeout,
+            GeminiError::Quota(msg) => ImageError::Quota(msg),
+            GeminiError::SafetyBlock(reason) => ImageError::SafetyBlock(reason),
+            GeminiError::EmptyResponse => ImageError::EmptyResponse,
+            GeminiError::Api { status, message } => ImageError::Api { status, message },
+            GeminiError::InvalidResponse(msg) => ImageError::InvalidResponse(msg),
+        }
+    }
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::Config(msg) => write!(f, "图片生成服务配置错误: {}", msg),
+            ImageError::Network(msg) => write!(f, "无法连接图片生成服务: {}", msg),
+            ImageError::Timeout => write!(f, "图片生成服务请求超时"),
+            ImageError::Quota(msg) => write!(f, "图片生成服务配额不足或请求过于频繁，请稍后重试: {}", msg),
+            ImageError::SafetyBlock(reason) => write!(
+                f,
+                "提示词或生成结果触发了安全过滤 ({})，请修改描述，避免使用可能引起歧义的词汇",
+                reason
+            ),
+            ImageError::EmptyResponse => write!(
+                f,
+                "AI 模型返回了空结果，可能是提示词触发了内容审核或描述不够清晰，请尝试使用更具体、更中性的描述"
+            ),
+            ImageError::Api { status, message } => write!(f, "图片生成服务错误 (status {}): {}", status, message),
+            ImageError::InvalidResponse(msg) => write!(f, "无法解析图片生成服务响应: {}", msg),
+            ImageError::Unsupported(msg) => write!(f, "图片生成服务不支持该请求: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+// ========================================
+// 服务接口
+// ========================================
+
+/// 图片生成服务
+pub trait ImageProvider: Send + Sync {
+    /// 服务标识，取值见 IMAGE_PROVIDERS
+    fn name(&self) -> &'static str;
+
+    /// 实际使用的模型
+    fn model(&self) -> &str;
+
+    /// 生成 request.count 张图片；有参考图时以参考图为基础生成，有蒙版时只重绘第一张参考图的蒙版区域。
+    /// 部分失败时返回成功的图片
+    fn generate<'a>(&'a self, request: &'a ImageRequest) -> BoxFuture<'a, Result<Vec<Vec<u8>>, ImageError>>;
+}
+
+/// 按名称创建图片生成服务
+///
+/// name 为空时使用 IMAGE_PROVIDER 环境变量（默认 gemini），model 为空时使用该服务配置的默认模型。
+pub fn create_provider(name: Option<&str>, model: Option<&str>) -> Result<Box<dyn ImageProvider>, ImageError> {
+    let name = match name.filter(|n| !n.is_empty()) {
+        Some(name) => name.to_string(),
+        None => std::env::var("IMAGE_PROVIDER").ok().filter(|n| !n.is_empty()).unwrap_or_else(|| "gemini".to_string()),
+    };
+    let model = model.filter(|m| !m.is_empty());
+
+    Ok(match name.as_str() {
+        "gemini" => Box::new(GeminiProvider::from_env(model)?),
+        "openai" => Box::new(OpenAiProvider::from_env(model)?),
+        "sd_webui" => Box::new(SdWebUiProvider::from_env(model)),
+        "comfyui" => Box::new(ComfyUiProvider::from_env(model)?),
+        other => {
+            return Err(ImageError::Unsupported(format!(
+                "未知的图片生成服务: {}，可选值: {}",
+                other,
+                IMAGE_PROVIDERS.join(", ")
+            )))
+        }
+    })
+}
+
+fn env_or(name: &str, default: &str) -> String {
+    std::env::var(name).ok().filter(|v| !v.is_empty()).unwrap_or_else(|| default.to_string())
+}
+
+fn env_number<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+fn decode_base64(data: &str) -> Result<Vec<u8>, ImageError> {
+    // 兼容 data URL
+    let data = data.split_once("base64,").map(|(_, d)| d).unwrap_or(data);
+    base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .map_err(|e| ImageError::InvalidResponse(format!("Failed to decode base64: {}", e)))
+}
+
+// 服务不支持一次生成多张时，并行发起多次单张请求；部分失败时保留成功的图片
+async fn generate_parallel<F, Fut>(count: usize, generate_one: F) -> Result<Vec<Vec<u8>>, ImageError>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<Vec<Vec<u8>>, ImageError>>,
+{
+    if count <= 1 {
+        return generate_one().await;
+    }
+
+    println!("   ⏳ 并行发起 {} 次生成请求...", count);
+    let results = futures_util::future::join_all((0..count).map(|_| generate_one())).await;
+
+    let mut images = Vec::new();
+    let mut first_error = None;
+    for result in results {
+        match result {
+            Ok(mut batch) => images.append(&mut batch),
+            Err(e) => {
+                println!("   ⚠️  候选图生成失败: {}", e);
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    match first_error {
+        Some(e) if images.is_empty() => Err(e),
+        _ => Ok(images),
+    }
+}
+
+// 解析 "w:h" 形式的画面比例
+fn parse_aspect_ratio(aspect_ratio: Option<&str>) -> Option<(f64, f64)> {
+    let (w, h) = aspect_ratio?.split_once(':')?;
+    let (w, h): (f64, f64) = (w.trim().parse().ok()?, h.trim().parse().ok()?);
+    (w > 0.0 && h > 0.0).then_some((w, h))
+}
+
+// 本地 Stable Diffusion 的出图尺寸：长边按分辨率取值，短边按比例缩放并对齐到 64
+fn sd_dimensions(aspect_ratio: Option<&str>, resolution: Option<&str>) -> (u32, u32) {
+    let long_side: u32 = match resolution {
+        Some("720p") => 768,
+        Some("4k") => 1536,
+        _ => 1024,
+    };
+    let Some((w, h)) = parse_aspect_ratio(aspect_ratio) else {
+        return (long_side, long_side);
+    };
+    let short_side = ((long_side as f64 * w.min(h) / w.max(h) / 64.0).round() as u32).max(1) * 64;
+    if w >= h {
+        (long_side, short_side)
+    } else {
+        (short_side, long_side)
+    }
+}
+
+// 本地服务的 img2img 只接受一张初始图，多张参考图时直接报错而不是悄悄丢弃
+fn single_reference<'a>(request: &'a ImageRequest, provider: &str) -> Result<Option<&'a ReferenceImage>, ImageError> {
+    if request.references.len() > 1 {
+        return Err(ImageError::Unsupported(format!(
+            "{} 只支持一张参考图，收到 {} 张",
+            provider,
+            request.references.len()
+        )));
+    }
+    Ok(request.references.first())
+}
+
+// ========================================
+// Gemini
+// ========================================
+
+pub struct GeminiProvider {
+    client: GeminiClient,
+    model: String,
+    supports_candidate_count: bool,
+}
+
+impl GeminiProvider {
+    /// 使用 GEMINI_* 环境变量；GEMINI_SUPPORTS_CANDIDATE_COUNT=true 时多张图一次请求生成
+    pub fn from_env(model: Option<&str>) -> Result<Self, ImageError> {
+        let mut config = GeminiConfig::from_env()?;
+        if let Some(model) = model {
+            config.model = model.to_string();
+        }
+        Ok(GeminiProvider {
+            model: config.model.clone(),
+            client: GeminiClient::new(config),
+            supports_candidate_count: std::env::var("GEMINI_SUPPORTS_CANDIDATE_COUNT")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+        })
+    }
+
+    async fn generate_images(&self, request: &ImageRequest) -> Result<Vec<Vec<u8>>, ImageError> {
+        let mut parts: Vec<gemini::Part> = request
+            .references
+            .iter()
+            .map(|r| gemini::Part::image(r.mime_type.clone(), r.data.clone()))
+            .collect();
+
+        // Gemini 没有蒙版参数，蒙版作为原图之后的一张图片并在提示词中说明
+        let prompt = match &request.mask {
+            Some(mask) => {
+                parts.push(gemini::Part::image(mask.mime_type.clone(), mask.data.clone()));
+                format!(
+                    "The first image is the source image and the last image is a mask. \
+Edit only the region that is white in the mask and keep everything else exactly unchanged, \
+returning the full image at the same size. Edit: {}",
+                    request.prompt
+                )
+            }
+            None => request.prompt.clone(),
+        };
+        parts.push(gemini::Part::text(prompt));
+
+        let use_candidate_count = request.count > 1 && self.supports_candidate_count;
+        let gemini_request = gemini::GenerateContentRequest::new(
+            parts,
+            gemini::GenerationConfig {
+                candidate_count: if use_candidate_count { Some(request.count as u32) } else { None },
+                image_config: request.aspect_ratio.as_ref().map(|aspect_ratio| gemini::ImageConfig {
+                    aspect_ratio: aspect_ratio.clone(),
+                    image_size: match request.resolution.as_deref() {
+                        Some("720p") => "1K",
+                        Some("4k") => "4K",
+                        _ => "2K",
+                    }
+                    .to_string(),
+                }),
+                ..Default::default()
+            },
+        );
+
+        if use_candidate_count {
+            return Ok(self.client.generate_images(&gemini_request).await?);
+        }
+        generate_parallel(request.count, || async {
+            Ok(self.client.generate_images(&gemini_request).await?)
+        })
+        .await
+    }
+}
+
+impl ImageProvider for GeminiProvider {
+    fn name(&self) -> &'static str {
+        "gemini"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn generate<'a>(&'a self, request: &'a ImageRequest) -> BoxFuture<'a, Result<Vec<Vec<u8>>, ImageError>> {
+        Box::pin(self.generate_images(request))
+    }
+}
+
+// ========================================
+// OpenAI 兼容的 Images API
+// ========================================
+
+pub struct OpenAiProvider {
+    http: Client,
+    base_url: String,
+    api_key: String,
+    model: String,
+    timeout: Duration,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAiImagesResponse {
+    #[serde(default)]
+    data: Vec<OpenAiImage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAiImage {
+    b64_json: Option<String>,
+    url: Option<String>,
+}
+
+impl OpenAiProvider {
+    /// 必填：OPENAI_IMAGE_API_KEY
+    /// 可选：OPENAI_IMAGE_BASE_URL（默认 https://api.openai.com/v1）、OPENAI_IMAGE_MODEL（默认 gpt-image-1）、OPENAI_IMAGE_TIMEOUT_SECS（默认 180）
+    pub fn from_env(model: Option<&str>) -> Result<Self, ImageError> {
+        let api_key = std::env::var("OPENAI_IMAGE_API_KEY")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .ok_or_else(|| ImageError::Config("OPENAI_IMAGE_API_KEY not set".to_string()))?;
+        Ok(OpenAiProvider {
+            http: Client::new(),
+            base_url: env_or("OPENAI_IMAGE_BASE_URL", "https://api.openai.com/v1").trim_end_matches('/').to_string(),
+            api_key,
+            model: model.map(str::to_string).unwrap_or_else(|| env_or("OPENAI_IMAGE_MODEL", "gpt-image-1")),
+            timeout: Duration::from_secs(env_number("OPENAI_IMAGE_TIMEOUT_SECS", 180)),
+        })
+    }
+
+    // dall-e 系列需要显式要求返回 base64，且 dall-e-3 每次只能生成一张
+    fn is_dalle(&self) -> bool {
+        self.model.starts_with("dall-e")
+    }
+
+    // 各模型支持的尺寸有限，按画面比例取最接近的一档
+    fn size(&self, aspect_ratio: Option<&str>) -> &'static str {
+        let orientation = match parse_aspect_ratio(aspect_ratio) {
+            Some((w, h)) if w > h => std::cmp::Ordering::Greater,
+            Some((w, h)) if w < h => std::cmp::Ordering::Less,
+            _ => std::cmp::Ordering::Equal,
+        };
+        match (self.model.as_str(), orientation) {
+            ("dall-e-2", _) | (_, std::cmp::Ordering::Equal) => "1024x1024",
+            ("dall-e-3", std::cmp::Ordering::Greater) => "1792x1024",
+            ("dall-e-3", std::cmp::Ordering::Less) => "1024x1792",
+            (_, std::cmp::Ordering::Greater) => "1536x1024",
+            (_, std::cmp::Ordering::Less) => "1024x1536",
+        }
+    }
+
+    async fn generate_images(&self, request: &ImageRequest) -> Result<Vec<Vec<u8>>, ImageError> {
+        if self.model == "dall-e-3" {
+            let single = ImageRequest { count: 1, ..request.clone() };
+            return generate_parallel(request.count, || self.request_images(&single)).await;
+        }
+        self.request_images(request).await
+    }
+
+    async fn request_images(&self, request: &ImageRequest) -> Result<Vec<Vec<u8>>, ImageError> {
+        let size = self.size(request.aspect_ratio.as_deref());
+
+        if request.mask.is_some() && request.references.is_empty() {
+            return Err(ImageError::Unsupported("局部重绘需要原图".to_string()));
+        }
+
+        let (builder, request_log) = if request.references.is_empty() {
+            let mut payload = serde_json::json!({
+                "model": self.model,
+                "prompt": request.prompt,
+                "n": request.count,
+                "size": size,
+            });
+            if self.is_dalle() {
+                payload["response_format"] = serde_json::json!("b64_json");
+            }
+            let request_log = serde_json::to_string_pretty(&payload).unwrap_or_default();
+            (self.http.post(format!("{}/images/generations", self.base_url)).json(&payload), request_log)
+        } else {
+            // 参考图通过 edits 接口以 multipart 上传
+            let mut form = reqwest::multipart::Form::new()
+                .text("model", self.model.clone())
+                .text("prompt", request.prompt.clone())
+                .text("n", request.count.to_string())
+                .text("size", size);
+            if self.is_dalle() {
+                form = form.text("response_format", "b64_json");
+            }
+            let field = if request.references.len() > 1 { "image[]" } else { "image" };
+            for (i, reference) in request.references.iter().enumerate() {
+                let extension = reference.mime_type.rsplit('/').next().unwrap_or("png");
+                let part = reqwest::multipart::Part::bytes(decode_base64(&reference.data)?)
+                    .file_name(format!("reference_{}.{}", i + 1, extension))
+                    .mime_str(&reference.mime_type)
+                    .map_err(|e| ImageError::Config(format!("Invalid mime type: {}", e)))?;
+                form = form.part(field, part);
+            }
+            // OpenAI 的蒙版以透明区域表示重绘区域
+            if let Some(mask) = &request.mask {
+                let part = reqwest::multipart::Part::bytes(alpha_mask_png(&mask.data)?)
+                    .file_name("mask.png")
+                    .mime_str("image/png")
+                    .map_err(|e| ImageError::Config(format!("Invalid mime type: {}", e)))?;
+                form = form.part("mask", part);
+            }
+            let request_log = format!(
+                "POST /images/edits\nmodel: {}\nprompt: {}\nn: {}\nsize: {}\nreferences: {}\nmask: {}",
+                self.model,
+                request.prompt,
+                request.count,
+                size,
+                request.references.len(),
+                request.mask.is_some()
+            );
+            (self.http.post(format!("{}/images/edits", self.base_url)).multipart(form), request_log)
+        };
+
+        let res = builder
+            .bearer_auth(&self.api_key)
+            .timeout(self.timeout)
+            .send()
+            .await
+            .map_err(ImageError::from_reqwest)?;
+
+        let status = res.status();
+        let body = res.text().await.map_err(ImageError::from_reqwest)?;
+        let _ = logger::log_model_interaction(&self.model, &request_log, &format!("Status: {}\n{}", status.as_u16(), body));
+
+        if !status.is_success() {
+            return Err(ImageError::from_status(status, body));
+        }
+
+        let response: OpenAiImagesResponse =
+            serde_json::from_str(&body).map_err(|e| ImageError::InvalidResponse(e.to_string()))?;
+
+        let mut images = Vec::new();
+        for image in response.data {
+            if let Some(data) = image.b64_json {
+                images.push(decode_base64(&data)?);
+            } else if let Some(url) = image.url {
+                let bytes = self.http
+                    .get(&url)
+                    .timeout(self.timeout)
+                    .send()
+                    .await
+                    .and_then(|r| r.error_for_status())
+                    .map_err(ImageError::from_reqwest)?
+                    .bytes()
+                    .await
+                    .map_err(ImageError::from_reqwest)?;
+                images.push(bytes.to_vec());
+            }
+        }
+
+        if images.is_empty() {
+            return Err(ImageError::EmptyResponse);
+        }
+        Ok(images)
+    }
+}
+
+impl ImageProvider for OpenAiProvider {
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn generate<'a>(&'a self, request: &'a ImageRequest) -> BoxFuture<'a, Result<Vec<Vec<u8>>, ImageError>> {
+        Box::pin(self.generate_images(request))
+    }
+}
+
+// ========================================
+// Stable Diffusion WebUI (AUTOMATIC1111 API)
+// ========================================
+
+pub struct SdWebUiProvider {
+    http: Client,
+    base_url: String,
+    model: Option<String>,  // None 表示使用 WebUI 当前加载的模型
+    steps: u32,
+    denoising_strength: f32,
+    negative_prompt: String,
+    timeout: Duration,
+}
+
+#[derive(Debug, Deserialize)]
+struct SdWebUiResponse {
+    #[serde(default)]
+    images: Vec<String>,
+}
+
+impl SdWebUiProvider {
+    /// 可选：SD_WEBUI_BASE_URL（默认 http://127.0.0.1:7860）、SD_WEBUI_MODEL、SD_WEBUI_STEPS（默认 30）、
+    /// SD_WEBUI_DENOISING_STRENGTH（参考图重绘幅度，默认 0.6）、SD_WEBUI_NEGATIVE_PROMPT、SD_WEBUI_TIMEOUT_SECS（默认 300）
+    pub fn from_env(model: Option<&str>) -> Self {
+        SdWebUiProvider {
+            http: Client::new(),
+            base_url: env_or("SD_WEBUI_BASE_URL", "http://127.0.0.1:7860").trim_end_matches('/').to_string(),
+            model: model
+                .map(str::to_string)
+                .or_else(|| std::env::var("SD_WEBUI_MODEL").ok().filter(|m| !m.is_empty())),
+            steps: env_number("SD_WEBUI_STEPS", 30),
+            denoising_strength: env_number("SD_WEBUI_DENOISING_STRENGTH", 0.6),
+            negative_prompt: std::env::var("SD_WEBUI_NEGATIVE_PROMPT").unwrap_or_default(),
+            timeout: Duration::from_secs(env_number("SD_WEBUI_TIMEOUT_SECS", 300)),
+        }
+    }
+
+    async fn generate_images(&self, request: &ImageRequest) -> Result<Vec<Vec<u8>>, ImageError> {
+        let (width, height) = sd_dimensions(request.aspect_ratio.as_deref(), request.resolution.as_deref());
+
+        let mut payload = serde_json::json!({
+            "prompt": request.prompt,
+            "negative_prompt": self.negative_prompt,
+            "steps": self.steps,
+            "width": width,
+            "height": height,
+            "batch_size": request.count,
+            "n_iter": 1,
+        });
+        if let Some(model) = &self.model {
+            payload["override_settings"] = serde_json::json!({ "sd_model_checkpoint": model });
+        }
+
+        // img2img 只接受一张初始图
+        let endpoint = if let Some(reference) = single_reference(request, "SD WebUI")? {
+            payload["init_images"] = serde_json::json!([reference.data]);
+            payload["denoising_strength"] = serde_json::json!(self.denoising_strength);
+            // 局部重绘：白色为重绘区域，未遮罩区域保持原图
+            if let Some(mask) = &request.mask {
+                payload["mask"] = serde_json::json!(mask.data);
+                payload["inpainting_fill"] = serde_json::json!(1);
+                payload["inpaint_full_res"] = serde_json::json!(false);
+                payload["mask_blur"] = serde_json::json!(4);
+            }
+            "img2img"
+        } else if request.mask.is_some() {
+            return Err(ImageError::Unsupported("局部重绘需要原图".to_string()));
+        } else {
+            "txt2img"
+        };
+
+        let mut request_log = payload.clone();
+        if request_log.get("init_images").is_some() {
+            request_log["init_images"] = serde_json::json!(["<base64>"]);
+        }
+        if request_log.get("mask").is_some() {
+            request_log["mask"] = serde_json::json!("<base64>");
+        }
+
+        let res = self.http
+            .post(format!("{}/sdapi/v1/{}", self.base_url, endpoint))
+            .timeout(self.timeout)
+            .json(&payload)
+            .send()
+            .await
+            .map_err(ImageError::from_reqwest)?;
+
+        let status = res.status();
+        let body = res.text().await.map_err(ImageError::from_reqwest)?;
+        let _ = logger::log_model_interaction(
+            self.model(),
+            &serde_json::to_string_pretty(&request_log).unwrap_or_default(),
+            &format!("Status: {}\n{}", status.as_u16(), body),
+        );
+
+        if !status.is_success() {
+            return Err(ImageError::from_status(status, body));
+        }
+
+        let response: SdWebUiResponse =
+            serde_json::from_str(&body).map_err(|e| ImageError::InvalidResponse(e.to_string()))?;
+
+        // 部分扩展（如 ControlNet）会在结果后追加预处理图，只取前 count 张
+        let images = response
+            .images
+            .iter()
+            .take(request.count)
+            .map(|data| decode_base64(data))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if images.is_empty() {
+            return Err(ImageError::EmptyResponse);
+        }
+        Ok(images)
+    }
+}
+
+impl ImageProvider for SdWebUiProvider {
+    fn name(&self) -> &'static str {
+        "sd_webui"
+    }
+
+    fn model(&self) -> &str {
+        self.model.as_deref().unwrap_or("sd_webui")
+    }
+
+    fn generate<'a>(&'a self, request: &'a ImageRequest) -> BoxFuture<'a, Result<Vec<Vec<u8>>, ImageError>> {
+        Box::pin(self.generate_images(request))
+    }
+}
+
+// ========================================
+// ComfyUI
+// ========================================
+
+/// 通过 API 格式的工作流调用 ComfyUI
+///
+/// 工作流中值为 `{{prompt}}`、`{{seed}}`、`{{width}}`、`{{height}}`、`{{batch_size}}`、`{{model}}`、`{{image}}`、`{{mask}}`
+/// 的字段会在提交前替换（整段占位符替换后保留数字类型），`{{image}}` 和 `{{mask}}` 为上传后的参考图和蒙版文件名。
+pub struct ComfyUiProvider {
+    http: Client,
+    base_url: String,
+    model: Option<String>,
+    workflow: serde_json::Value,
+    img2img_workflow: Option<serde_json::Value>,
+    timeout: Duration,
+}
+
+#[derive(Debug, Deserialize)]
+struct ComfyUiQueued {
+    prompt_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ComfyUiUploaded {
+    name: String,
+    #[serde(default)]
+    subfolder: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ComfyUiHistoryEntry {
+    #[serde(default)]
+    outputs: std::collections::HashMap<String, ComfyUiNodeOutput>,
+    status: Option<ComfyUiStatus>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ComfyUiNodeOutput {
+    #[serde(default)]
+    images: Vec<ComfyUiImage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ComfyUiImage {
+    filename: String,
+    #[serde(default)]
+    subfolder: String,
+    #[serde(rename = "type", default)]
+    kind: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ComfyUiStatus {
+    #[serde(default)]
+    status_str: String,
+}
+
+impl ComfyUiProvider {
+    /// 必填：COMFYUI_WORKFLOW（文生图工作流 JSON 文件路径）
+    /// 可选：COMFYUI_BASE_URL（默认 http://127.0.0.1:8188）、COMFYUI_IMG2IMG_WORKFLOW（参考图工作流）、COMFYUI_MODEL、COMFYUI_TIMEOUT_SECS（默认 300）
+    pub fn from_env(model: Option<&str>) -> Result<Self, ImageError> {
+        fn load_workflow(path: &str) -> Result<serde_json::Value, ImageError> {
+            let content = std::fs::read_to_string(path)
+                .map_err(|e| ImageError::Config(format!("无法读取 ComfyUI 工作流 {}: {}", path, e)))?;
+            serde_json::from_str(&content)
+                .map_err(|e| ImageError::Config(format!("ComfyUI 工作流 {} 不是有效的 JSON: {}", path, e)))
+        }
+
+        let workflow_path = std::env::var("COMFYUI_WORKFLOW")
+            .ok()
+            .filter(|v| !v.is_empty())
+            .ok_or_else(|| ImageError::Config("COMFYUI_WORKFLOW not set".to_string()))?;
+        let img2img_workflow = match std::env::var("COMFYUI_IMG2IMG_WORKFLOW").ok().filter(|v| !v.is_empty()) {
+            Some(path) => Some(load_workflow(&path)?),
+            None => None,
+        };
+
+        Ok(ComfyUiProvider {
+            http: Client::new(),
+            base_url: env_or("COMFYUI_BASE_URL", "http://127.0.0.1:8188").trim_end_matches('/').to_string(),
+            model: model
+                .map(str::to_string)
+                .or_else(|| std::env::var("COMFYUI_MODEL").ok().filter(|m| !m.is_empty())),
+            workflow: load_workflow(&workflow_path)?,
+            img2img_workflow,
+            timeout: Duration::from_secs(env_number("COMFYUI_TIMEOUT_SECS", 300)),
+        })
+    }
+
+    async fn generate_images(&self, request: &ImageRequest) -> Result<Vec<Vec<u8>>, ImageError> {
+        let deadline = Instant::now() + self.timeout;
+        let (width, height) = sd_dimensions(request.aspect_ratio.as_deref(), request.resolution.as_deref());
+
+        let reference = single_reference(request, "ComfyUI")?;
+        let mut workflow = match reference {
+            None => self.workflow.clone(),
+            Some(_) => self.img2img_workflow.clone().ok_or_else(|| {
+                ImageError::Unsupported("未配置 COMFYUI_IMG2IMG_WORKFLOW，无法使用参考图生成".to_string())
+            })?,
+        };
+
+        let mut variables = vec![
+            ("prompt", serde_json::json!(request.prompt)),
+            ("seed", serde_json::json!(Uuid::new_v4().as_u128() as u32)),
+            ("width", serde_json::json!(width)),
+            ("height", serde_json::json!(height)),
+            ("batch_size", serde_json::json!(request.count)),
+            ("model", serde_json::json!(self.model.clone().unwrap_or_default())),
+        ];
+        if let Some(reference) = reference {
+            variables.push(("image", serde_json::json!(self.upload_image(reference).await?)));
+        }
+        if let Some(mask) = &request.mask {
+            variables.push(("mask", serde_json::json!(self.upload_image(mask).await?)));
+        }
+        fill_placeholders(&mut workflow, &variables);
+
+        let res = self.http
+            .post(format!("{}/prompt", self.base_url))
+            .timeout(self.timeout)
+            .json(&serde_json::json!({ "prompt": workflow, "client_id": Uuid::new_v4().to_string() }))
+            .send()
+            .await
+            .map_err(ImageError::from_reqwest)?;
+        let status = res.status();
+        let body = res.text().await.map_err(ImageError::from_reqwest)?;
+        let _ = logger::log_model_interaction(
+            self.model(),
+            &serde_json::to_string_pretty(&workflow).unwrap_or_default(),
+            &format!("Status: {}\n{}", status.as_u16(), body),
+        );
+        if !status.is_success() {
+            return Err(ImageError::from_status(status, body));
+        }
+        let queued: ComfyUiQueued =
+            serde_json::from_str(&body).map_err(|e| ImageError::InvalidResponse(e.to_string()))?;
+
+        // 轮询执行结果
+        let entry = loop {
+            if Instant::now() >= deadline {
+                return Err(ImageError::Timeout);
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+
+            let mut history: std::collections::HashMap<String, ComfyUiHistoryEntry> = self.http
+                .get(format!("{}/history/{}", self.base_url, queued.prompt_id))
+                .timeout(self.timeout)
+                .send()
+                .await
+                .map_err(ImageError::from_reqwest)?
+                .json()
+                .await
+                .map_err(|e| ImageError::InvalidResponse(e.to_string()))?;
+            if let Some(entry) = history.remove(&queued.prompt_id) {
+                break entry;
+            }
+        };
+
+        if entry.status.as_ref().map(|s| s.status_str == "error").unwrap_or(false) {
+            return Err(ImageError::Api {
+                status: 500,
+                message: format!("ComfyUI 工作流执行失败: {}", queued.prompt_id),
+            });
+        }
+
+        // 只取输出节点的图片，跳过预览图
+        let mut images = Vec::new();
+        for image in entry.outputs.values().flat_map(|o| &o.images).filter(|i| i.kind == "output") {
+            let bytes = self.http
+                .get(format!("{}/view", self.base_url))
+                .query(&[("filename", &image.filename), ("subfolder", &image.subfolder), ("type", &image.kind)])
+                .timeout(self.timeout)
+                .send()
+                .await
+                .and_then(|r| r.error_for_status())
+                .map_err(ImageError::from_reqwest)?
+                .bytes()
+                .await
+                .map_err(ImageError::from_reqwest)?;
+            images.push(bytes.to_vec());
+        }
+
+        if images.is_empty() {
+            return Err(ImageError::EmptyResponse);
+        }
+        images.truncate(request.count);
+        Ok(images)
+    }
+
+    // 上传参考图，返回工作流 LoadImage 节点可用的文件名
+    async fn upload_image(&self, reference: &ReferenceImage) -> Result<String, ImageError> {
+        let extension = reference.mime_type.rsplit('/').next().unwrap_or("png");
+        let part = reqwest::multipart::Part::bytes(decode_base64(&reference.data)?)
+            .file_name(format!("reference_{}.{}", Uuid::new_v4(), extension))
+            .mime_str(&reference.mime_type)
+            .map_err(|e| ImageError::Config(format!("Invalid mime type: {}", e)))?;
+        let form = reqwest::multipart::Form::new()
+            .part("image", part)
+            .text("overwrite", "true");
+
+        let res = self.http
+            .post(format!("{}/upload/image", self.base_url))
+            .timeout(self.timeout)
+            .multipart(form)
+            .send()
+            .await
+            .map_err(ImageError::from_reqwest)?;
+        let status = res.status();
+        if !status.is_success() {
+            let body = res.text().await.unwrap_or_default();
+            return Err(ImageError::from_status(status, body));
+        }
+        let uploaded: ComfyUiUploaded = res.json().await.map_err(|e| ImageError::InvalidResponse(e.to_string()))?;
+        Ok(if uploaded.subfolder.is_empty() {
+            uploaded.name
+        } else {
+            format!("{}/{}", uploaded.subfolder, uploaded.name)
+        })
+    }
+}
+
+impl ImageProvider for ComfyUiProvider {
+    fn name(&self) -> &'static str {
+        "comfyui"
+    }
+
+    fn model(&self) -> &str {
+        self.model.as_deref().unwrap_or("comfyui")
+    }
+
+    fn generate<'a>(&'a self, request: &'a ImageRequest) -> BoxFuture<'a, Result<Vec<Vec<u8>>, ImageError>> {
+        Box::pin(self.generate_images(request))
+    }
+}
+
+// 白色为重绘区域的蒙版转为 OpenAI 使用的透明蒙版（透明为重绘区域）
+fn alpha_mask_png(data: &str) -> Result<Vec<u8>, ImageError> {
+    let mask = image::load_from_memory(&decode_base64(data)?)
+        .map_err(|e| ImageError::InvalidResponse(format!("Invalid mask image: {}", e)))?
+        .to_luma8();
+    let alpha = image::RgbaImage::from_fn(mask.width(), mask.height(), |x, y| {
+        image::Rgba([0, 0, 0, 255 - mask.get_pixel(x, y)[0]])
+    });
+
+    let mut bytes = std::io::Cursor::new(Vec::new());
+    image::DynamicImage::ImageRgba8(alpha)
+        .write_to(&mut bytes, image::ImageOutputFormat::Png)
+        .map_err(|e| ImageError::InvalidResponse(format!("Failed to encode mask: {}", e)))?;
+    Ok(bytes.into_inner())
+}
+
+// 递归替换工作流中的 {{name}} 占位符
+fn fill_placeholders(value: &mut serde_json::Value, variables: &[(&str, serde_json::Value)]) {
+    match value {
+        serde_json::Value::String(s) => {
+            // 整段是占位符时直接替换为变量值，保留数字类型
+            if let Some((_, replacement)) = variables.iter().find(|(name, _)| *s == format!("{{{{{}}}}}", name)) {
+                *value = replacement.clone();
+                return;
+            }
+            for (name, replacement) in variables {
+                let placeholder = format!("{{{{{}}}}}", name);
+                if s.contains(&placeholder) {
+                    let text = match replacement {
+                        serde_json::Value::String(t) => t.clone(),
+                        other => other.to_string(),
+                    };
+                    *s = s.replace(&placeholder, &text);
+                }
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(|v| fill_placeholders(v, variables)),
+        serde_json::Value::Object(map) => map.values_mut().for_each(|v| fill_placeholders(v, variables)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png_base64(image: image::DynamicImage) -> String {
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        image.write_to(&mut bytes, image::ImageOutputFormat::Png).expect("encode png");
+        base64::engine::general_purpose::STANDARD.encode(bytes.into_inner())
+    }
+
+    #[test]
+    fn sd_dimensions_scale_short_side() {
+        assert_eq!(sd_dimensions(None, None), (1024, 1024));
+        assert_eq!(sd_dimensions(Some("16:9"), Some("1080p")), (1024, 576));
+        assert_eq!(sd_dimensions(Some("9:16"), Some("720p")), (448, 768));
+        assert_eq!(sd_dimensions(Some("1:1"), Some("4k")), (1536, 1536));
+        assert_eq!(sd_dimensions(Some("0:9"), Some("4k")), (1536, 1536));
+        assert_eq!(sd_dimensions(Some("bad"), None), (1024, 1024));
+    }
+
+    #[test]
+    fn fill_placeholders_replaces_nested_values() {
+        let mut workflow = serde_json::json!({
+            "3": { "inputs": { "seed": "{{seed}}", "text": "masterpiece, {{prompt}}", "size": ["{{width}}", "{{height}}"] } },
+            "4": { "inputs": { "ckpt_name": "{{model}}", "other": "{{unknown}}", "steps": 20 } }
+        });
+        fill_placeholders(&mut workflow, &[
+            ("prompt", serde_json::json!("a cat")),
+            ("seed", serde_json::json!(42)),
+            ("width", serde_json::json!(512)),
+            ("height", serde_json::json!(768)),
+            ("model", serde_json::json!("sdxl.safetensors")),
+        ]);
+
+        assert_eq!(workflow["3"]["inputs"]["seed"], serde_json::json!(42));
+        assert_eq!(workflow["3"]["inputs"]["text"], serde_json::json!("masterpiece, a cat"));
+        assert_eq!(workflow["3"]["inputs"]["size"], serde_json::json!([512, 768]));
+        assert_eq!(workflow["4"]["inputs"]["ckpt_name"], serde_json::json!("sdxl.safetensors"));
+        assert_eq!(workflow["4"]["inputs"]["other"], serde_json::json!("{{unknown}}"));
+        assert_eq!(workflow["4"]["inputs"]["steps"], serde_json::json!(20));
+    }
+
+    #[test]
+    fn alpha_mask_png_inverts_luma_into_alpha() {
+        let mask = image::GrayImage::from_fn(2, 1, |x, _| image::Luma([if x == 0 { 255 } else { 0 }]));
+        let data = format!("data:image/png;base64,{}", png_base64(image::DynamicImage::ImageLuma8(mask)));
+
+        let alpha = image::load_from_memory(&alpha_mask_png(&data).unwrap()).unwrap().to_rgba8();
+        assert_eq!(alpha.dimensions(), (2, 1));
+        // 白色（重绘区域）变为透明，黑色保持不透明
+        assert_eq!(alpha.get_pixel(0, 0)[3], 0);
+        assert_eq!(alpha.get_pixel(1, 0)[3], 255);
+    }
+
+    #[test]
+    fn alpha_mask_png_rejects_invalid_image() {
+        let data = base64::engine::general_purpose::STANDARD.encode(b"not an image");
+        assert!(matches!(alpha_mask_png(&data), Err(ImageError::InvalidResponse(_))));
+        assert!(matches!(alpha_mask_png("%%%"), Err(ImageError::InvalidResponse(_))));
+    }
+
+    #[test]
+    fn single_reference_rejects_extra_references() {
+        let reference = ReferenceImage { mime_type: "image/png".to_string(), data: String::new() };
+        let mut request = ImageRequest::new("prompt");
+        assert!(single_reference(&request, "ComfyUI").unwrap().is_none());
+
+        request.references.push(reference.clone());
+        assert!(single_reference(&request, "ComfyUI").unwrap().is_some());
+
+        request.references.push(reference);
+        assert!(matches!(single_reference(&request, "ComfyUI"), Err(ImageError::Unsupported(_))));
+    }
+}
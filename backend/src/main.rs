@@ -1,6 +1,7 @@
 use actix_cors::Cors;
 use actix_multipart::Multipart;
 use actix_web::{web, App, HttpResponse, HttpServer, Result};
+use actix_web::http::header::{Charset, ContentDisposition, DispositionParam, DispositionType, ExtendedValue};
 
 use futures_util::stream::StreamExt as _;
 use futures_util::TryStreamExt;
@@ -18,8 +19,20 @@ mod db;
 mod models;
 mod cloudflare;
 mod logger;
+mod bundle;
+mod prompt_template;
+mod gc;
+mod word_diff;
+mod gemini;
+mod image_provider;
+mod mock;
+mod postprocess;
+mod text;
 
-use models::{Job, Scene as DbScene, Project, StoryboardScene, GenerationHistory, CompositeVideo, ProjectCharacter, Character};
+use models::{Job, Scene as DbScene, Project, ProjectVisibility, StoryboardScene, SceneReviewNote, SCENE_STATUSES, GenerationHistory, CompositeVideo, ProjectCharacter, Character, StoryboardDraft, DraftScene, SceneCharacter, SearchHit, ProjectSnapshot, SnapshotData, SnapshotScene, PromptEdit, SCENE_PROMPT_FIELDS, PROJECT_PROMPT_FIELDS, PromptTranslation, GenerationBatch, BatchItem, VideoTask};
+use prompt_template::PromptContext;
+use image_provider::{ImageError, ImageProvider, ImageRequest, ReferenceImage, IMAGE_PROVIDERS};
+use postprocess::PostProcessConfig;
 
 
 
@@ -94,8 +107,8 @@ struct YouTubeDownloadRequest {
 // 我的项目功能 - 请求/响应结构体
 // ========================================
 
-// 项目导入请求（JSON格式）
-#[derive(Debug, Deserialize)]
+// 项目导入请求（JSON格式），导出时使用同一结构
+#[derive(Debug, Serialize, Deserialize)]
 struct ProjectImport {
     #[serde(rename = "标题")]
     title: String,
@@ -109,21 +122,33 @@ struct ProjectImport {
     global_video_prompt: Option<String>,
     #[serde(rename = "项目类型", default = "default_project_type", skip_serializing_if = "Option::is_none")]
     project_type: Option<String>,
+    #[serde(rename = "项目简介", default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(rename = "画面比例", default, skip_serializing_if = "Option::is_none")]
+    aspect_ratio: Option<String>,
+    #[serde(rename = "分辨率", default, skip_serializing_if = "Option::is_none")]
+    resolution: Option<String>,
+    #[serde(rename = "帧率", default, skip_serializing_if = "Option::is_none")]
+    fps: Option<i32>,
+    #[serde(rename = "变量", default, skip_serializing_if = "Option::is_none")]
+    variables: Option<std::collections::HashMap<String, String>>,
     #[serde(rename = "角色", skip_serializing_if = "Option::is_none")]
     characters: Option<Vec<CharacterImport>>,
     #[serde(rename = "分镜")]
     scenes: Vec<SceneImport>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 struct SceneImport {
     id: i32,
-    #[serde(rename = "时长")]
+    #[serde(rename = "时长", skip_serializing_if = "Option::is_none")]
     duration: Option<serde_json::Value>,  // 支持数字或字符串，漫画可选
     #[serde(rename = "首帧图提示词", alias = "图提示词")]
     first_frame_prompt: String,  // 支持"首帧图提示词"或"图提示词"
-    #[serde(rename = "视频提示词", default)]
+    #[serde(rename = "视频提示词", default, skip_serializing_if = "Option::is_none")]
     video_prompt: Option<String>,  // 视频项目必需，漫画可选
+    #[serde(rename = "角色", default, skip_serializing_if = "Option::is_none")]
+    characters: Option<Vec<String>>,  // 出场角色名称（可选）
 }
 
 // 解析时长，支持多种格式：3, "3", "3秒", "3s", "3.5秒" 等
@@ -148,14 +173,159 @@ fn parse_duration(value: &serde_json::Value) -> Option<f64> {
     }
 }
 
+// 验证分镜时长范围：1-30秒（VEO API 限制，实际使用时取 1-8）
+fn clamp_scene_duration(scene_id: i32, d: f64) -> f64 {
+    if d < 1.0 {
+        println!("⚠️ 分镜 {} 时长 {} 小于1秒，使用默认值8秒", scene_id, d);
+        8.0
+    } else if d > 30.0 {
+        println!("⚠️ 分镜 {} 时长 {} 超过30秒，使用30秒", scene_id, d);
+        30.0
+    } else {
+        d
+    }
+}
 
-#[derive(Debug, Deserialize)]
+// 支持的画面比例（与 Gemini 图片生成支持的比例一致）
+const SUPPORTED_ASPECT_RATIOS: &[&str] = &["1:1", "2:3", "3:2", "3:4", "4:3", "4:5", "5:4", "9:16", "16:9", "21:9"];
+// 支持的分辨率
+const SUPPORTED_RESOLUTIONS: &[&str] = &["720p", "1080p", "4k"];
+
+// 项目输出格式（画面比例、分辨率、帧率）
+#[derive(Debug, Clone)]
+struct OutputFormat {
+    aspect_ratio: String,
+    resolution: String,
+    fps: i32,
+}
+
+impl OutputFormat {
+    fn from_project(project: &Project) -> Self {
+        OutputFormat {
+            aspect_ratio: project.aspect_ratio.clone().unwrap_or_else(|| "9:16".to_string()),
+            resolution: project.resolution.clone().unwrap_or_else(|| "1080p".to_string()),
+            fps: project.fps.unwrap_or(24),
+        }
+    }
+
+    // 画面比例对应的宽高比数值
+    fn ratio(&self) -> Option<f64> {
+        let (w, h) = self.aspect_ratio.split_once(':')?;
+        let w: f64 = w.trim().parse().ok()?;
+        let h: f64 = h.trim().parse().ok()?;
+        if w > 0.0 && h > 0.0 { Some(w / h) } else { None }
+    }
+
+    // 分辨率对应的短边像素数
+    fn short_side(&self) -> u32 {
+        match self.resolution.as_str() {
+            "720p" => 720,
+            "4k" => 2160,
+            _ => 1080,
+        }
+    }
+
+    // 项目输出尺寸（宽, 高）：短边为分辨率像素数，取偶数
+    fn target_size(&self) -> Option<(u32, u32)> {
+        let ratio = self.ratio()?;
+        let short_side = self.short_side() as f64;
+        let (width, height) = if ratio >= 1.0 {
+            (short_side * ratio, short_side)
+        } else {
+            (short_side, short_side / ratio)
+        };
+        let even = |v: f64| (((v / 2.0).round() as u32) * 2).max(2);
+        Some((even(width), even(height)))
+    }
+
+    // 检查上传媒体的尺寸/帧率是否与项目设置一致，返回不一致的提示
+    fn check_media(&self, width: u32, height: u32, fps: Option<f64>) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if width == 0 || height == 0 {
+            return warnings;
+        }
+
+        if let Some(expected) = self.ratio() {
+            let actual = width as f64 / height as f64;
+            if (actual - expected).abs() / expected > 0.02 {
+                warnings.push(format!(
+                    "画面比例不一致: 文件为 {}x{}，项目设置为 {}",
+                    width, height, self.aspect_ratio
+                ));
+            }
+        }
+
+        if width.min(height) < self.short_side() {
+            warnings.push(format!(
+                "分辨率低于项目设置: 文件为 {}x{}，项目设置为 {}",
+                width, height, self.resolution
+            ));
+        }
+
+        if let Some(fps) = fps {
+            if (fps - self.fps as f64).abs() > 0.5 {
+                warnings.push(format!("帧率不一致: 文件为 {:.2}fps，项目设置为 {}fps", fps, self.fps));
+            }
+        }
+
+        warnings
+    }
+}
+
+// 校验输出格式参数
+fn validate_output_format(
+    aspect_ratio: Option<&str>,
+    resolution: Option<&str>,
+    fps: Option<i32>,
+) -> std::result::Result<(), String> {
+    if let Some(ratio) = aspect_ratio {
+        if !SUPPORTED_ASPECT_RATIOS.contains(&ratio) {
+            return Err(format!("不支持的画面比例: {}，可选值: {}", ratio, SUPPORTED_ASPECT_RATIOS.join(", ")));
+        }
+    }
+    if let Some(resolution) = resolution {
+        if !SUPPORTED_RESOLUTIONS.contains(&resolution) {
+            return Err(format!("不支持的分辨率: {}，可选值: {}", resolution, SUPPORTED_RESOLUTIONS.join(", ")));
+        }
+    }
+    if let Some(fps) = fps {
+        if !(1..=60).contains(&fps) {
+            return Err(format!("帧率必须在 1-60 之间: {}", fps));
+        }
+    }
+    Ok(())
+}
+
+// 校验提示词变量名，返回去除首尾空白后的变量表
+fn normalize_prompt_variables(
+    variables: std::collections::HashMap<String, String>,
+) -> std::result::Result<std::collections::HashMap<String, String>, String> {
+    let mut normalized = std::collections::HashMap::new();
+    for (name, value) in variables {
+        if !prompt_template::is_valid_variable_name(&name) {
+            return Err(format!("无效的变量名: {}", name));
+        }
+        normalized.insert(name.trim().to_string(), value);
+    }
+    Ok(normalized)
+}
+
+// 校验导入数据中的输出格式和提示词变量（JSON 导入和项目包导入共用）
+fn validate_project_import(import: &ProjectImport) -> std::result::Result<(), String> {
+    validate_output_format(import.aspect_ratio.as_deref(), import.resolution.as_deref(), import.fps)?;
+    if let Some(variables) = &import.variables {
+        normalize_prompt_variables(variables.clone())?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 struct CharacterImport {
     #[serde(rename = "角色名称")]
     name: String,
-    #[serde(rename = "分类")]
+    #[serde(rename = "分类", skip_serializing_if = "Option::is_none")]
     category: Option<String>,
-    #[serde(rename = "标签")]
+    #[serde(rename = "标签", skip_serializing_if = "Option::is_none")]
     tags: Option<String>,  // 逗号分隔
     #[serde(rename = "提示词")]
     prompt: String,
@@ -167,11 +337,37 @@ struct UpdatePromptsRequest {
     first_frame_prompt: Option<String>,
     video_prompt: Option<String>,
     duration: Option<f64>,
+    author: Option<String>,  // 修改人（可选，记录到编辑历史）
+}
+
+// 设置分镜出场角色请求
+#[derive(Debug, Deserialize)]
+struct UpdateSceneCharactersRequest {
+    character_ids: Vec<Uuid>,
 }
 
 #[derive(Debug, Deserialize)]
 struct UpdateScriptRequest {
     script: String,
+    author: Option<String>,
+}
+
+// 项目基本信息更新请求（未提供的字段保持不变）
+#[derive(Debug, Deserialize)]
+struct UpdateProjectRequest {
+    title: Option<String>,
+    project_type: Option<String>,
+    description: Option<String>,      // 传空字符串清空简介
+    cover_image_url: Option<String>,  // 传空字符串恢复为自动封面
+    aspect_ratio: Option<String>,     // 画面比例，如 "9:16"、"16:9"
+    resolution: Option<String>,       // "720p"、"1080p" 或 "4k"
+    fps: Option<i32>,
+    target_runtime: Option<f64>,      // 目标总时长（秒），传 0 清除
+    prompt_translation: Option<String>, // "original"、"translated" 或 "both"
+    image_provider: Option<String>,     // 图片生成服务，传空字符串恢复为服务器默认
+    image_model: Option<String>,        // 图片生成模型，传空字符串使用服务默认模型
+    continuity_mode: Option<String>,    // 首帧图连贯模式: "off"、"sequence" 或 "always"
+    image_postprocess: Option<serde_json::Value>, // 首帧图后处理配置，传空字符串恢复服务器默认
 }
 
 // 角色分析请求/响应结构体
@@ -852,6 +1048,16 @@ fn format_timestamp(seconds: f64) -> String {
     format!("{:02}:{:02}:{:02}.{:03}", hours, minutes, secs, millis)
 }
 
+// 解析 format_timestamp 生成的 "HH:MM:SS.mmm"
+fn parse_timestamp(timestamp: &str) -> Option<f64> {
+    let mut parts = timestamp.trim().split(':');
+    let (hours, minutes, seconds) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(hours.parse::<f64>().ok()? * 3600.0 + minutes.parse::<f64>().ok()? * 60.0 + seconds.parse::<f64>().ok()?)
+}
+
 fn get_video_info(video_path: &PathBuf) -> Result<VideoInfo> {
     let output = Command::new("ffprobe")
         .args(&[
@@ -977,19 +1183,32 @@ async fn get_projects(
         .and_then(|s| s.parse::<i64>().ok())
         .unwrap_or(0);
 
+    // 根据status参数过滤归档/回收站项目，默认只返回正常项目
+    let visibility = match query.get("status") {
+        Some(status) => match ProjectVisibility::parse(status) {
+            Some(v) => v,
+            None => {
+                return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": "status 必须是 active、archived、trash 或 all"
+                })));
+            }
+        },
+        None => ProjectVisibility::Active,
+    };
+
     // 根据type参数过滤项目类型
     let mut projects = if let Some(project_type) = query.get("type") {
-        Project::list_by_type(pool.as_ref(), project_type, limit, offset)
+        Project::list_by_type(pool.as_ref(), project_type, visibility, limit, offset)
             .await
             .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
     } else {
-        Project::list_all(pool.as_ref(), limit, offset)
+        Project::list_all(pool.as_ref(), visibility, limit, offset)
             .await
             .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
     };
 
-    // 为每个项目自动填充封面图（使用第一个分镜的首帧图）
-    for project in &mut projects {
+    // 未手动指定封面的项目自动填充封面图（使用第一个分镜的首帧图）
+    for project in projects.iter_mut().filter(|p| p.cover_image_url.is_none()) {
         // 查询第一个分镜场景
         if let Ok(scenes) = StoryboardScene::find_by_project_id(pool.as_ref(), project.id).await {
             if let Some(first_scene) = scenes.first() {
@@ -1003,41 +1222,296 @@ async fn get_projects(
     Ok(HttpResponse::Ok().json(projects))
 }
 
-// 创建项目（导入JSON）
-async fn create_project(
-    req_body: web::Json<ProjectImport>,
+// 转义 LIKE 模式中的特殊字符
+fn escape_like(term: &str) -> String {
+    term.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+// 截取命中关键词附近的文本片段
+fn search_snippet(content: &str, term: &str, radius: usize) -> String {
+    let chars: Vec<char> = content.chars().collect();
+    let lower = content.to_lowercase();
+    let center = lower
+        .find(&term.to_lowercase())
+        .map(|byte_pos| lower[..byte_pos].chars().count())
+        .unwrap_or(0)
+        .min(chars.len());
+
+    let start = center.saturating_sub(radius);
+    let end = (center + term.chars().count() + radius).min(chars.len());
+
+    let mut snippet: String = chars[start..end].iter().collect::<String>().replace('\n', " ");
+    if start > 0 {
+        snippet.insert(0, '…');
+    }
+    if end < chars.len() {
+        snippet.push('…');
+    }
+    snippet
+}
+
+// 全文搜索：项目标题/简介/剧本/全局提示词、分镜提示词和生成历史提示词
+async fn search_projects(
     pool: web::Data<sqlx::PgPool>,
+    query: web::Query<std::collections::HashMap<String, String>>,
 ) -> Result<HttpResponse> {
+    let q = query.get("q").map(|s| s.trim().to_string()).unwrap_or_default();
+    let limit = query.get("limit")
+        .and_then(|s| s.parse::<i64>().ok())
+        .unwrap_or(20)
+        .clamp(1, 100);
+
+    if q.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "搜索关键词不能为空"
+        })));
+    }
+
+    // 按空白拆分关键词，所有关键词都需要命中
+    let terms: Vec<&str> = q.split_whitespace().collect();
+    let patterns: Vec<String> = terms.iter().map(|t| format!("%{}%", escape_like(t))).collect();
+
+    let hits = SearchHit::search(pool.as_ref(), &q, &patterns, limit)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    let results: Vec<serde_json::Value> = hits
+        .into_iter()
+        .map(|hit| serde_json::json!({
+            "kind": hit.kind,
+            "field": hit.field,
+            "project_id": hit.project_id,
+            "project_title": hit.project_title,
+            "scene_id": hit.scene_id,
+            "scene_index": hit.scene_index,
+            "history_id": hit.history_id,
+            "snippet": search_snippet(&hit.content, terms[0], 40),
+            "thumbnail_url": hit.thumbnail_url,
+            "rank": hit.rank,
+        }))
+        .collect();
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "query": q,
+        "total": results.len(),
+        "results": results
+    })))
+}
+
+// 根据导入数据创建项目记录、全局提示词和分镜（不处理角色）
+async fn create_project_from_import(
+    conn: &mut sqlx::PgConnection,
+    import: &ProjectImport,
+) -> Result<Project, sqlx::Error> {
     // 判断项目类型并获取相应的全局图提示词
-    let project_type = req_body.project_type.clone().unwrap_or_else(|| "video".to_string());
+    let project_type = import.project_type.clone().unwrap_or_else(|| "video".to_string());
     let final_global_image_prompt = if project_type == "comic" {
-        req_body.comic_global_image_prompt.clone()
+        import.comic_global_image_prompt.clone()
     } else {
-        req_body.global_image_prompt.clone()
+        import.global_image_prompt.clone()
     };
 
     // 创建项目记录
-    let project = Project::create(
-        pool.as_ref(),
-        req_body.title.clone(),
-        Some(req_body.script.clone()),
-        Some(project_type.clone()),
+    let mut project = Project::create(
+        &mut *conn,
+        import.title.clone(),
+        Some(import.script.clone()),
+        Some(project_type),
     )
-    .await
-    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    .await?;
 
     // 更新全局提示词（如果提供）
-    if final_global_image_prompt.is_some() || req_body.global_video_prompt.is_some() {
+    if final_global_image_prompt.is_some() || import.global_video_prompt.is_some() {
         sqlx::query(
             "UPDATE projects SET global_image_prompt = $1, global_video_prompt = $2, updated_at = NOW() WHERE id = $3"
         )
         .bind(&final_global_image_prompt)
-        .bind(&req_body.global_video_prompt)
+        .bind(&import.global_video_prompt)
         .bind(project.id)
-        .execute(pool.get_ref())
+        .execute(&mut *conn)
+        .await?;
+        project.global_image_prompt = final_global_image_prompt;
+        project.global_video_prompt = import.global_video_prompt.clone();
+    }
+
+    // 更新项目简介（如果提供）
+    if let Some(description) = import.description.as_ref().filter(|d| !d.trim().is_empty()) {
+        sqlx::query("UPDATE projects SET description = $1 WHERE id = $2")
+            .bind(description)
+            .bind(project.id)
+            .execute(&mut *conn)
+            .await?;
+        project.description = Some(description.clone());
+    }
+
+    // 更新输出格式（如果提供）
+    if import.aspect_ratio.is_some() || import.resolution.is_some() || import.fps.is_some() {
+        Project::update_output_format(
+            &mut *conn,
+            project.id,
+            import.aspect_ratio.clone(),
+            import.resolution.clone(),
+            import.fps,
+        )
+        .await?;
+        if let Some(aspect_ratio) = &import.aspect_ratio {
+            project.aspect_ratio = Some(aspect_ratio.clone());
+        }
+        if let Some(resolution) = &import.resolution {
+            project.resolution = Some(resolution.clone());
+        }
+        if let Some(fps) = import.fps {
+            project.fps = Some(fps);
+        }
+    }
+
+    // 更新提示词变量（如果提供，变量名已由 validate_project_import 校验）
+    if let Some(variables) = import.variables.as_ref().filter(|v| !v.is_empty()) {
+        let variables = variables.iter().map(|(name, value)| (name.trim().to_string(), value.clone())).collect();
+        if let Some(updated) = Project::update_prompt_variables(&mut *conn, project.id, variables).await? {
+            project = updated;
+        }
+    }
+
+    // 批量创建分镜记录
+    let scenes_data: Vec<(i32, Option<f64>, Option<String>, Option<String>)> = import
+        .scenes
+        .iter()
+        .map(|s| {
+            // 解析时长，支持多种格式
+            let duration = s.duration.as_ref()
+                .and_then(|v| parse_duration(v))
+                .map(|d| clamp_scene_duration(s.id, d));
+            
+            (
+                s.id,
+                duration,
+                Some(s.first_frame_prompt.clone()),
+                s.video_prompt.clone(),  // 现在是 Option<String>
+            )
+        })
+        .collect();
+
+    StoryboardScene::batch_create(&mut *conn, project.id, scenes_data).await?;
+    recalculate_timeline(&mut *conn, project.id).await?;
+
+    Ok(project)
+}
+
+// 根据提示词中出现的角色名推断出场角色（长名称优先，避免短名称误匹配）
+fn infer_cast(text: &str, candidates: &[(Uuid, String)]) -> Vec<Uuid> {
+    let mut sorted: Vec<&(Uuid, String)> = candidates.iter().filter(|(_, name)| !name.trim().is_empty()).collect();
+    sorted.sort_by_key(|(_, name)| std::cmp::Reverse(name.chars().count()));
+
+    let mut remaining = text.to_string();
+    let mut cast = Vec::new();
+    for (id, name) in sorted {
+        if remaining.contains(name.as_str()) {
+            remaining = remaining.replace(name.as_str(), " ");
+            if !cast.contains(id) {
+                cast.push(*id);
+            }
+        }
+    }
+    cast
+}
+
+// 按分镜序号设置出场角色（角色名称），返回项目中找不到的角色名
+async fn apply_scene_casting<'a>(
+    conn: impl sqlx::Acquire<'a, Database = sqlx::Postgres>,
+    project_id: Uuid,
+    casting: &[(i32, Vec<String>)],
+) -> Result<Vec<String>, sqlx::Error> {
+    let mut unknown: Vec<String> = Vec::new();
+    if casting.iter().all(|(_, names)| names.is_empty()) {
+        return Ok(unknown);
+    }
+
+    let mut conn = conn.acquire().await?;
+    let scenes = StoryboardScene::find_by_project_id(&mut *conn, project_id).await?;
+    let candidates = Character::list_castable(&mut *conn, project_id).await?;
+
+    for (scene_index, names) in casting {
+        let Some(scene) = scenes.iter().find(|s| s.scene_index == *scene_index) else {
+            continue;
+        };
+
+        let mut character_ids = Vec::new();
+        for name in names {
+            match candidates.iter().find(|c| c.name.trim() == name.trim()) {
+                Some(c) => character_ids.push(c.id),
+                None => {
+                    if !unknown.contains(name) {
+                        unknown.push(name.clone());
+                    }
+                }
+            }
+        }
+        SceneCharacter::set_for_scene(&mut *conn, scene.id, &character_ids).await?;
+    }
+
+    if !unknown.is_empty() {
+        println!("⚠️  出场角色中有项目不存在的角色: {}", unknown.join(", "));
+    }
+    Ok(unknown)
+}
+
+// 用于推断出场角色的分镜文本（首帧图提示词 + 视频提示词）
+fn scene_cast_text(scene: &StoryboardScene) -> String {
+    format!(
+        "{} {}",
+        scene.first_frame_prompt.as_deref().unwrap_or_default(),
+        scene.video_prompt.as_deref().unwrap_or_default()
+    )
+}
+
+// 导入数据中的分镜出场角色
+fn import_casting(scenes: &[SceneImport]) -> Vec<(i32, Vec<String>)> {
+    scenes
+        .iter()
+        .filter_map(|s| s.characters.as_ref().map(|names| (s.id, names.clone())))
+        .collect()
+}
+
+// 获取分镜出场角色：优先使用已设置的选角，未设置时根据提示词中的角色名推断
+// 返回 (出场角色, 是否为推断结果)
+async fn resolve_scene_cast(
+    pool: &sqlx::PgPool,
+    scene: &StoryboardScene,
+    characters: &[ProjectCharacter],
+) -> Result<(Vec<ProjectCharacter>, bool), sqlx::Error> {
+    let cast = SceneCharacter::get_for_scene(pool, scene.id).await?;
+    if !cast.is_empty() {
+        return Ok((cast, false));
+    }
+
+    let text = scene_cast_text(scene);
+    let candidates: Vec<(Uuid, String)> = characters.iter().map(|c| (c.id, c.name.clone())).collect();
+    let inferred = infer_cast(&text, &candidates);
+    let cast = characters.iter().filter(|c| inferred.contains(&c.id)).cloned().collect();
+    Ok((cast, true))
+}
+
+// 创建项目（导入JSON）
+async fn create_project(
+    req_body: web::Json<ProjectImport>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    if let Err(e) = validate_project_import(&req_body) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": e
+        })));
+    }
+
+    // 项目、分镜和角色在同一个事务中创建，失败时不留下不完整的项目
+    let mut tx = pool
+        .begin()
         .await
         .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-    }
+    let project = create_project_from_import(&mut tx, &req_body)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let project_type = project.project_type.clone().unwrap_or_else(|| "video".to_string());
 
     // 导入角色到系统角色库（如果提供）
     if let Some(characters) = &req_body.characters {
@@ -1050,7 +1524,7 @@ async fn create_project(
 
             // 创建待生成角色
             Character::create_pending(
-                pool.as_ref(),
+                &mut *tx,
                 char_import.name.clone(),
                 Some(char_import.prompt.clone()),
                 char_import.category.clone(),
@@ -1063,54 +1537,29 @@ async fn create_project(
         println!("✅ 导入 {} 个角色到系统角色库", characters.len());
     }
 
-    // 批量创建分镜记录
-    let scenes_data: Vec<(i32, Option<f64>, Option<String>, Option<String>)> = req_body
-        .scenes
-        .iter()
-        .map(|s| {
-            // 解析时长，支持多种格式
-            let duration = s.duration.as_ref()
-                .and_then(|v| parse_duration(v))
-                .map(|d| {
-                    // 验证范围：1-30秒（VEO API 限制，实际使用时取 1-8）
-                    if d < 1.0 {
-                        println!("⚠️ 分镜 {} 时长 {} 小于1秒，使用默认值8秒", s.id, d);
-                        8.0
-                    } else if d > 30.0 {
-                        println!("⚠️ 分镜 {} 时长 {} 超过30秒，使用30秒", s.id, d);
-                        30.0
-                    } else {
-                        d
-                    }
-                });
-            
-            (
-                s.id,
-                duration,
-                Some(s.first_frame_prompt.clone()),
-                s.video_prompt.clone(),  // 现在是 Option<String>
-            )
-        })
-        .collect();
-
-    StoryboardScene::batch_create(pool.as_ref(), project.id, scenes_data)
+    // 设置分镜出场角色（如果提供）
+    let unknown_characters = apply_scene_casting(&mut *tx, project.id, &import_casting(&req_body.scenes))
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    tx.commit()
         .await
         .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-
 
     println!("✅ 项目创建成功: {} ({} 个分镜)", project.id, req_body.scenes.len());
 
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "project_id": project.id,
         "project_type": project_type,
+        "unknown_characters": unknown_characters,
         "message": "项目创建成功"
     })))
 }
 
-// 获取项目详情（包含分镜列表）
+// 获取项目详情（包含分镜列表），支持 ?status=approved,locked 按审核状态过滤分镜
 async fn get_project_detail(
     path: web::Path<String>,
     pool: web::Data<sqlx::PgPool>,
+    query: web::Query<std::collections::HashMap<String, String>>,
 ) -> Result<HttpResponse> {
     let project_id_str = path.into_inner();
     let project_id = Uuid::parse_str(&project_id_str)
@@ -1123,17 +1572,27 @@ async fn get_project_detail(
         .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Project not found"))?;
 
     // 查询分镜
-    let scenes = StoryboardScene::find_by_project_id(pool.as_ref(), project_id)
+    let mut scenes = StoryboardScene::find_by_project_id(pool.as_ref(), project_id)
         .await
         .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 
+    if let Some(status_filter) = query.get("status").filter(|s| !s.is_empty()) {
+        let statuses: Vec<&str> = status_filter.split(',').map(|s| s.trim()).collect();
+        if let Some(invalid) = statuses.iter().find(|s| !SCENE_STATUSES.contains(s)) {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("无效的分镜状态: {}，可选值: {}", invalid, SCENE_STATUSES.join(", "))
+            })));
+        }
+        scenes.retain(|s| statuses.contains(&s.status.as_str()));
+    }
+
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "project": project,
         "scenes": scenes,
     })))
 }
 
-// 删除项目
+// 删除项目（移入回收站，可恢复）
 async fn delete_project(
     path: web::Path<String>,
     pool: web::Data<sqlx::PgPool>,
@@ -1142,3004 +1601,6851 @@ async fn delete_project(
     let project_id = Uuid::parse_str(&project_id_str)
         .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
 
-    match Project::delete(pool.as_ref(), project_id).await {
-        Ok(_) => {
-            println!("🗑️ 项目已删除: {}", project_id);
+    match Project::move_to_trash(pool.as_ref(), project_id).await {
+        Ok(Some(project)) => {
+            println!("🗑️ 项目已移入回收站: {}", project_id);
             Ok(HttpResponse::Ok().json(serde_json::json!({
                 "success": true,
-                "message": "项目删除成功"
+                "message": "项目已移入回收站",
+                "project": project
             })))
         }
+        Ok(None) => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "项目不存在"
+        }))),
         Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
             "error": e.to_string()
         }))),
     }
 }
 
+// 归档项目
+async fn archive_project(
+    path: web::Path<String>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    set_project_archived(path, pool, true).await
+}
 
-// 下载JSON模板（视频）
-async fn download_video_template() -> Result<HttpResponse> {
-    let template = serde_json::json!({
-        "标题": "",
-        "剧本": "",
-        "项目类型": "video",
-        "首帧图全局提示词": "",
-        "视频全局提示词": "",
-        "角色": [
-            {
-                "角色名称": "",
-                "分类": "",
-                "标签": "",
-                "提示词": ""
-            }
-        ],
-        "分镜": [
-            {
-                "id": 1,
-                "时长": "5秒",
-                "首帧图提示词": "",
-                "视频提示词": ""
-            },
-            {
-                "id": 2,
-                "时长": "3s",
-                "首帧图提示词": "",
-                "视频提示词": ""
-            }
-        ]
-    });
-
-    let template_json = serde_json::to_string_pretty(&template)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-
-    Ok(HttpResponse::Ok()
-        .content_type("application/json")
-        .insert_header(("Content-Disposition", "attachment; filename=\"项目模板（视频）.json\""))
-        .body(template_json))
+// 取消归档
+async fn unarchive_project(
+    path: web::Path<String>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    set_project_archived(path, pool, false).await
 }
 
-// 下载JSON模板（漫画）
-async fn download_comic_template() -> Result<HttpResponse> {
-    let template = serde_json::json!({
-        "标题": "",
-        "剧本": "",
-        "项目类型": "comic",
-        "图全局提示词": "",
-        "角色": [
-            {
-                "角色名称": "",
-                "分类": "",
-                "标签": "",
-                "提示词": ""
-            }
-        ],
-        "分镜": [
-            {
-                "id": 1,
-                "图提示词": ""
-            },
-            {
-                "id": 2,
-                "图提示词": ""
-            }
-        ]
-    });
-
-    let template_json = serde_json::to_string_pretty(&template)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+async fn set_project_archived(
+    path: web::Path<String>,
+    pool: web::Data<sqlx::PgPool>,
+    archived: bool,
+) -> Result<HttpResponse> {
+    let project_id_str = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
 
-    Ok(HttpResponse::Ok()
-        .content_type("application/json")
-        .insert_header(("Content-Disposition", "attachment; filename=\"项目模板（漫画）.json\""))
-        .body(template_json))
+    match Project::set_archived(pool.as_ref(), project_id, archived).await {
+        Ok(Some(project)) => {
+            println!("📦 项目已{}: {}", if archived { "归档" } else { "取消归档" }, project_id);
+            Ok(HttpResponse::Ok().json(project))
+        }
+        Ok(None) => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "项目不存在"
+        }))),
+        Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": e.to_string()
+        }))),
+    }
 }
 
-// 更新分镜提示词
-async fn update_scene_prompts(
-    path: web::Path<(String, i32)>,
-    req_body: web::Json<UpdatePromptsRequest>,
+// 从回收站恢复项目
+async fn restore_project(
+    path: web::Path<String>,
     pool: web::Data<sqlx::PgPool>,
 ) -> Result<HttpResponse> {
-    let (_project_id_str, scene_id) = path.into_inner();
-
-    StoryboardScene::update_prompts(
-        pool.as_ref(),
-        scene_id,
-        req_body.first_frame_prompt.clone(),
-        req_body.video_prompt.clone(),
-        req_body.duration,
-    )
-    .await
-    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let project_id_str = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
 
-    Ok(HttpResponse::Ok().json(serde_json::json!({
-        "message": "提示词已更新"
-    })))
+    match Project::restore(pool.as_ref(), project_id).await {
+        Ok(Some(project)) => {
+            println!("♻️  项目已从回收站恢复: {}", project_id);
+            Ok(HttpResponse::Ok().json(project))
+        }
+        Ok(None) => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "项目不存在"
+        }))),
+        Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": e.to_string()
+        }))),
+    }
 }
 
-// 生成首帧图（使用Gemini API + 角色图片参考）
-async fn generate_first_frame(
-    path: web::Path<(String, i32)>,
+// 彻底删除回收站中的项目（不可恢复）
+async fn purge_project(
+    path: web::Path<String>,
     pool: web::Data<sqlx::PgPool>,
 ) -> Result<HttpResponse> {
-    let (project_id_str, scene_id) = path.into_inner();
+    let project_id_str = path.into_inner();
     let project_id = Uuid::parse_str(&project_id_str)
         .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
 
-    // Fetch Project (Global Prompt)
-    let project = Project::find_by_id(pool.as_ref(), project_id)
-        .await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
-        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Project not found"))?;
-
-    // Fetch All Characters
-    let characters = ProjectCharacter::get_all_for_project(pool.as_ref(), project_id)
-        .await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-
-    // 查询分镜信息
-    let scene = StoryboardScene::find_by_id(pool.as_ref(), scene_id)
+    let project = match Project::find_by_id(pool.as_ref(), project_id)
         .await
         .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
-        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Scene not found"))?;
-
-    println!("🎨 开始生成首帧图: Scene ID {}", scene_id);
-    println!("   > 项目角色数量: {}", characters.len());
-
-    // 构建完整提示词：全局提示词 + 分镜提示词
-    let mut full_prompt = String::new();
-    if let Some(global_prompt) = &project.global_image_prompt {
-        if !global_prompt.is_empty() {
-            full_prompt.push_str(global_prompt);
-            full_prompt.push_str(". ");
+    {
+        Some(p) => p,
+        None => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "项目不存在"
+            })));
         }
-    }
-    if let Some(scene_prompt) = &scene.first_frame_prompt {
-        full_prompt.push_str(scene_prompt);
-    }
+    };
 
-    if full_prompt.is_empty() {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            "首帧图提示词不能为空"
-        ).into());
+    if project.deleted_at.is_none() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "只能彻底删除回收站中的项目，请先删除项目"
+        })));
     }
 
-    println!("   > 完整提示词: {}", full_prompt);
-
-    // 获取 API Key 和 URL 配置
-    let api_key = std::env::var("GEMINI_API_KEY")
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "GEMINI_API_KEY not set"))?;
-    
-    let client = Client::new();
-    
-    // 从环境变量读取 Gemini API 配置（必须设置）
-    let gemini_base_url = std::env::var("GEMINI_BASE_URL")
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "GEMINI_BASE_URL not set"))?;
-    let gemini_model = std::env::var("GEMINI_MODEL")
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "GEMINI_MODEL not set"))?;
-    let gemini_endpoint = std::env::var("GEMINI_ENDPOINT")
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "GEMINI_ENDPOINT not set"))?;
-    let url = format!("{}{}", gemini_base_url, gemini_endpoint.replace("{model}", &gemini_model));
+    match Project::delete(pool.as_ref(), project_id).await {
+        Ok(_) => {
+            println!("🔥 项目已彻底删除: {}", project_id);
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "success": true,
+                "message": "项目已彻底删除"
+            })))
+        }
+        Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": e.to_string()
+        }))),
+    }
+}
 
-    // 构建请求 parts
-    let mut parts: Vec<serde_json::Value> = Vec::new();
+// 定期清理回收站中超过保留天数的项目
+async fn auto_purge_trash(pool: sqlx::PgPool, retention_days: i64) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(60 * 60));
+    loop {
+        interval.tick().await;
 
-    // 根据角色数量决定使用拼接图还是单独传递
-    let character_count = characters.len();
-    
-    if character_count > 3 {
-        // 超过 3 张角色图，使用拼接图
-        println!("   > 角色数量 {} > 3，使用拼接角色图", character_count);
-        
-        let final_image_url = if let Some(url) = project.combined_characters_image {
-            // 检查文件是否存在
-            let local_path = format!("./data/{}", url.trim_start_matches("/data/"));
-            if std::path::Path::new(&local_path).exists() {
-                println!("   > 使用现有拼接角色图: {}", url);
-                url
-            } else {
-                println!("   ⚠️  拼接图文件不存在: {}", url);
-                "".to_string() 
+        let expired = match Project::find_expired_trash(&pool, retention_days).await {
+            Ok(ids) => ids,
+            Err(e) => {
+                eprintln!("❌ 查询过期回收站项目失败: {}", e);
+                continue;
             }
-        } else {
-            println!("   ⚠️  未找到拼接图，将使用单张角色图");
-            "".to_string()
         };
 
-        if !final_image_url.is_empty() {
-            // 下载并编码拼接图
-            match download_and_encode_image(&final_image_url).await {
-                Ok((base64_data, mime_type)) => {
-                    parts.push(serde_json::json!({
-                        "inlineData": {
-                            "mimeType": mime_type,
-                            "data": base64_data
-                        }
-                    }));
-                    println!("   ✅ 添加参考图(拼接图): {}", final_image_url);
-                }
-                Err(e) => {
-                    println!("   ⚠️  无法加载拼接图 {}: {}", final_image_url, e);
-                }
-            }
-        } else {
-            // 拼接图不存在，降级使用单张角色图
-            println!("   ⚠️  拼接图不可用，回退到使用单张角色图片");
-            for character in &characters {
-                match download_and_encode_image(&character.image_url).await {
-                    Ok((base64_data, mime_type)) => {
-                        parts.push(serde_json::json!({
-                            "inlineData": {
-                                "mimeType": mime_type,
-                                "data": base64_data
-                            }
-                        }));
-                        println!("   ✅ 添加角色图: {}", character.name);
-                    }
-                    Err(e) => {
-                        println!("   ⚠️  无法加载角色图片 {}: {}", character.name, e);
-                    }
-                }
-            }
-        }
-    } else if character_count > 0 {
-        // 3 张及以下角色图，直接传递每张图
-        println!("   > 角色数量 {} <= 3，直接传递每张角色图", character_count);
-        
-        for character in &characters {
-            match download_and_encode_image(&character.image_url).await {
-                Ok((base64_data, mime_type)) => {
-                    parts.push(serde_json::json!({
-                        "inlineData": {
-                            "mimeType": mime_type,
-                            "data": base64_data
-                        }
-                    }));
-                    println!("   ✅ 添加角色图: {}", character.name);
-                }
-                Err(e) => {
-                    println!("   ⚠️  无法加载角色图片 {}: {}", character.name, e);
-                }
+        for project_id in expired {
+            match Project::delete(&pool, project_id).await {
+                Ok(_) => println!("🔥 回收站项目已自动清理: {}", project_id),
+                Err(e) => eprintln!("❌ 自动清理项目失败 {}: {}", project_id, e),
             }
         }
-    } else {
-        println!("   ℹ️  项目无角色图，将仅使用文本提示词生成");
     }
+}
 
-    // 添加文本提示词
-    let text_instruction = if parts.is_empty() {
-        // 没有参考图片，使用纯文本生成
-        full_prompt.clone()
-    } else {
-        // 有参考图片，添加指令
-        format!("Based on this character reference image, generate an image for: {}", full_prompt)
-    };
-    parts.push(serde_json::json!({"text": text_instruction}));
-
-    // 调用 Gemini API
-    let payload = serde_json::json!({
-        "contents": [{
-            "parts": parts
-        }],
-        "generationConfig": {
-            "temperature": 0.4,
-            "topK": 32,
-            "topP": 1,
-            "maxOutputTokens": 8192,
-        }
-    });
-
-    println!("   ⏳ 正在调用 Gemini API...");
-    
-    // Log Request
-    let request_log = serde_json::to_string_pretty(&payload).unwrap_or_default();
-    
-    let res = client.post(url)
-        .header("Content-Type", "application/json")
-        .header("x-goog-api-key", api_key)
-        .json(&payload)
-        .send()
-        .await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to call Gemini API: {}", e)))?;
+// 将项目转换为导入格式（与 create_project 接收的 JSON 结构一致）
+async fn build_project_export(pool: &sqlx::PgPool, project: &Project) -> Result<ProjectImport, sqlx::Error> {
+    let scenes = StoryboardScene::find_by_project_id(pool, project.id).await?;
+    let characters = ProjectCharacter::get_all_for_project(pool, project.id).await?;
+
+    let project_type = project.project_type.clone().unwrap_or_else(|| "video".to_string());
+    let is_comic = project_type == "comic";
+
+    let characters: Vec<CharacterImport> = characters
+        .into_iter()
+        .map(|c| CharacterImport {
+            name: c.name,
+            category: c.category,
+            tags: if c.tags.is_empty() { None } else { Some(c.tags.join(",")) },
+            prompt: c.prompt.unwrap_or_default(),
+        })
+        .collect();
 
-    if !res.status().is_success() {
-        let error_text = res.text().await.unwrap_or_default();
-        // Log Error Response
-        let _ = logger::log_model_interaction("gemini-3-pro-image-preview", &request_log, &format!("Error: {}", error_text));
-        
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("Gemini API error: {}", error_text)
-        ).into());
+    let mut scene_imports: Vec<SceneImport> = Vec::with_capacity(scenes.len());
+    for s in scenes {
+        let cast = SceneCharacter::get_for_scene(pool, s.id).await?;
+        scene_imports.push(SceneImport {
+            id: s.scene_index,
+            duration: s.duration.map(|d| serde_json::json!(d)),
+            first_frame_prompt: s.first_frame_prompt.unwrap_or_default(),
+            video_prompt: s.video_prompt,
+            characters: if cast.is_empty() { None } else { Some(cast.into_iter().map(|c| c.name).collect()) },
+        });
     }
 
-    let result: serde_json::Value = res.json().await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to parse response: {}", e)))?;
+    Ok(ProjectImport {
+        title: project.title.clone(),
+        script: project.script.clone().unwrap_or_default(),
+        global_image_prompt: if is_comic { None } else { project.global_image_prompt.clone() },
+        comic_global_image_prompt: if is_comic { project.global_image_prompt.clone() } else { None },
+        global_video_prompt: project.global_video_prompt.clone(),
+        project_type: Some(project_type),
+        description: project.description.clone(),
+        aspect_ratio: project.aspect_ratio.clone(),
+        resolution: project.resolution.clone(),
+        fps: project.fps,
+        variables: if project.prompt_variables.is_empty() { None } else { Some(project.prompt_variables.0.clone()) },
+        characters: if characters.is_empty() { None } else { Some(characters) },
+        scenes: scene_imports,
+    })
+}
 
-    // Log Success Response
-    let response_log = serde_json::to_string_pretty(&result).unwrap_or_default();
-    let _ = logger::log_model_interaction("gemini-3-pro-image-preview", &request_log, &response_log);
-
-    // 提取图片数据
-    let candidates = result["candidates"].as_array()
-        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Other, "No candidates in response"))?;
-    let content = candidates.get(0).and_then(|c| c["content"].as_object())
-        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Other, "No content in candidate"))?;
-    let result_parts = content["parts"].as_array()
-        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Other, "No parts in content"))?;
-
-    let mut image_data_base64 = None;
-    for part in result_parts {
-        if let Some(inline_data) = part["inlineData"].as_object() {
-            if let Some(data) = inline_data["data"].as_str() {
-                image_data_base64 = Some(data);
-                break;
-            }
-        }
+// 生成带 UTF-8 文件名的下载响应头
+fn attachment_header(filename: &str) -> ContentDisposition {
+    ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![DispositionParam::FilenameExt(ExtendedValue {
+            charset: Charset::Ext("UTF-8".to_string()),
+            language_tag: None,
+            value: filename.as_bytes().to_vec(),
+        })],
     }
+}
 
-    let image_data_base64 = image_data_base64
-        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Other, "No image data in response"))?;
+// 导出项目为 JSON（可重新导入）
+async fn export_project_json(
+    path: web::Path<String>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let project_id_str = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
 
-    // 解码并保存图片
-    let image_bytes = base64::engine::general_purpose::STANDARD.decode(image_data_base64)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to decode base64: {}", e)))?;
+    let project = Project::find_by_id(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Project not found"))?;
 
-    let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S").to_string();
-    let filename = format!("first_frame_{}_{}.png", scene_id, timestamp);
-    let project_dir = format!("data/projects/{}/first_frames", project_id);
-    fs::create_dir_all(&project_dir)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to create directory: {}", e)))?;
-    let filepath = format!("{}/{}", project_dir, filename);
-    let image_url = format!("/data/projects/{}/first_frames/{}", project_id, filename);
+    let export = build_project_export(pool.as_ref(), &project)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 
-    let mut file = File::create(&filepath)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to create file: {}", e)))?;
-    file.write_all(&image_bytes)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to write file: {}", e)))?;
+    let export_json = serde_json::to_string_pretty(&export)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 
-    println!("   ✅ 首帧图已保存到本地: {}", filepath);
+    println!("📦 项目已导出为 JSON: {} ({} 个分镜)", project_id, export.scenes.len());
 
-    // 更新场景：使用本地 URL
-    StoryboardScene::update_latest_image(pool.as_ref(), scene_id, image_url.clone())
-        .await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let filename = format!("{}.json", sanitize_filename::sanitize(&project.title));
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .insert_header(attachment_header(&filename))
+        .body(export_json))
+}
 
-    // 历史记录：使用本地 URL（视频生成时会自动上传到 Cloudflare）
-    GenerationHistory::create(
-        pool.as_ref(),
-        scene_id,
-        "image".to_string(),
-        full_prompt.clone(),
-        image_url.clone(),
-    )
-    .await
-    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+// ========================================
+// 项目包（ZIP）导出/导入
+// ========================================
 
-    println!("🎉 首帧图生成成功!");
+// 项目包清单：项目 JSON + 媒体文件在包内的路径
+#[derive(Debug, Serialize, Deserialize)]
+struct BundleManifest {
+    version: u32,
+    project: ProjectImport,
+    #[serde(default)]
+    combined_characters_image: Option<String>,
+    #[serde(default)]
+    scenes: Vec<BundleScene>,
+    #[serde(default)]
+    characters: Vec<BundleCharacter>,
+    #[serde(default)]
+    composites: Vec<BundleComposite>,
+}
 
-    Ok(HttpResponse::Ok().json(serde_json::json!({
-        "message": "首帧图生成成功",
-        "image_url": image_url,
-        "prompt": full_prompt,
-    })))
+#[derive(Debug, Serialize, Deserialize)]
+struct BundleScene {
+    scene_index: i32,
+    latest_image: Option<String>,
+    latest_video: Option<String>,
+    #[serde(default)]
+    history: Vec<BundleHistory>,
+}
 
+#[derive(Debug, Serialize, Deserialize)]
+struct BundleHistory {
+    generation_type: String,
+    prompt: String,
+    file: String,
+    created_at: chrono::DateTime<chrono::Utc>,
 }
 
-// 辅助函数：下载并编码图片为 base64
-async fn download_and_encode_image(image_url: &str) -> Result<(String, String)> {
-    // 判断是本地路径还是外部URL
-    let image_bytes = if image_url.starts_with("http://") || image_url.starts_with("https://") {
-        // 外部URL，下载
-        let client = Client::new();
-        let response = client.get(image_url)
-            .send()
-            .await
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to download image: {}", e)))?;
-        
-        response.bytes()
-            .await
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to read image bytes: {}", e)))?
-            .to_vec()
-    } else {
-        // 本地路径（例如 /uploads/xxx.png）
-        let local_path = if image_url.starts_with("/data/") {
-            format!(".{}", image_url)  // ./uploads/xxx.png
-        } else {
-            image_url.to_string()
-        };
-        
-        fs::read(&local_path)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to read local file: {}", e)))?
-    };
-
-    // 编码为 base64
-    let base64_data = base64::engine::general_purpose::STANDARD.encode(&image_bytes);
-
-    // 判断 MIME 类型（简单根据URL后缀判断）
-    let mime_type = if image_url.ends_with(".png") {
-        "image/png"
-    } else if image_url.ends_with(".jpg") || image_url.ends_with(".jpeg") {
-        "image/jpeg"
-    } else if image_url.ends_with(".webp") {
-        "image/webp"
-    } else {
-        "image/png"  // 默认
-    };
+#[derive(Debug, Serialize, Deserialize)]
+struct BundleCharacter {
+    name: String,
+    prompt: Option<String>,
+    category: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    display_order: i32,
+    image: Option<String>,
+}
 
-    Ok((base64_data, mime_type.to_string()))
+#[derive(Debug, Serialize, Deserialize)]
+struct BundleComposite {
+    file: String,
+    scene_count: i32,
 }
 
-// 生成分镜视频（使用VEO API）
-async fn generate_storyboard_video(
-    path: web::Path<(String, i32)>,
+const BUNDLE_VERSION: u32 = 1;
+
+// 导出项目包（ZIP，包含项目 JSON 和媒体文件）
+// 查询参数 include_history=true 时打包全部生成历史文件，否则只打包每个分镜的最新图片和视频
+async fn export_project_bundle(
+    path: web::Path<String>,
+    query: web::Query<std::collections::HashMap<String, String>>,
     pool: web::Data<sqlx::PgPool>,
-) -> Result<HttpResponse> {
-    let (project_id_str, scene_id) = path.into_inner();
+) -> Result<actix_files::NamedFile> {
+    let project_id_str = path.into_inner();
     let project_id = Uuid::parse_str(&project_id_str)
-        .map_err(|e| {
-            eprintln!("❌ 无效的项目ID: {} - {}", project_id_str, e);
-            std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Invalid project ID: {}", e))
-        })?;
-
-    println!("🎥 开始生成分镜视频: Project ID {}, Scene ID {}", project_id, scene_id);
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+    let include_history = query.get("include_history")
+        .map(|v| v == "true" || v == "1")
+        .unwrap_or(false);
 
-    // Fetch Project (Global Prompt)
     let project = Project::find_by_id(pool.as_ref(), project_id)
         .await
-        .map_err(|e| {
-            eprintln!("❌ 查询项目失败: {}", e);
-            std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to find project: {}", e))
-        })?
-        .ok_or_else(|| {
-            eprintln!("❌ 项目不存在: {}", project_id);
-            std::io::Error::new(std::io::ErrorKind::NotFound, "Project not found")
-        })?;
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Project not found"))?;
 
-    // Fetch All Characters
+    println!("📦 开始打包项目: {} (包含历史: {})", project_id, include_history);
+
+    let export = build_project_export(pool.as_ref(), &project)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let scenes = StoryboardScene::find_by_project_id(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let mut history = GenerationHistory::find_by_project_id(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    // 按时间正序打包，导入时按相同顺序重建
+    history.reverse();
     let characters = ProjectCharacter::get_all_for_project(pool.as_ref(), project_id)
         .await
-        .map_err(|e| {
-            eprintln!("❌ 查询角色失败: {}", e);
-            std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to get characters: {}", e))
-        })?;
-
-    // 查询分镜信息
-    let scene = StoryboardScene::find_by_id(pool.as_ref(), scene_id)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let composites = CompositeVideo::find_by_project_id(pool.as_ref(), project_id)
         .await
-        .map_err(|e| {
-            eprintln!("❌ 查询分镜失败: {}", e);
-            std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to find scene: {}", e))
-        })?
-        .ok_or_else(|| {
-            eprintln!("❌ 分镜不存在: {}", scene_id);
-            std::io::Error::new(std::io::ErrorKind::NotFound, "Scene not found")
-        })?;
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 
-    println!("   > 项目角色数量: {}", characters.len());
-    println!("   > 首帧图: {:?}", scene.latest_image_url);
-    println!("   > 视频提示词: {:?}", scene.video_prompt);
-    println!("   > 分镜时长: {:?}秒", scene.duration);
+    let mut builder = bundle::BundleBuilder::default();
 
-    // 构建完整提示词：全局提示词 + 分镜提示词 + 图片使用指示
-    let mut full_prompt = String::new();
-    if let Some(global_prompt) = &project.global_video_prompt {
-        if !global_prompt.is_empty() {
-            full_prompt.push_str(global_prompt);
-            full_prompt.push_str(". ");
+    let mut bundle_scenes = Vec::new();
+    for scene in &scenes {
+        let folder = format!("scenes/{}", scene.scene_index);
+        let latest_image = match &scene.latest_image_url {
+            Some(url) => builder.add_media(url, &folder).await,
+            None => None,
+        };
+        let latest_video = match &scene.latest_video_url {
+            Some(url) => builder.add_media(url, &folder).await,
+            None => None,
+        };
+
+        let mut scene_history = Vec::new();
+        for h in history.iter().filter(|h| h.scene_id == scene.id) {
+            let is_latest = scene.latest_image_url.as_deref() == Some(h.result_url.as_str())
+                || scene.latest_video_url.as_deref() == Some(h.result_url.as_str());
+            if !include_history && !is_latest {
+                continue;
+            }
+            if let Some(file) = builder.add_media(&h.result_url, &format!("{}/history", folder)).await {
+                scene_history.push(BundleHistory {
+                    generation_type: h.generation_type.clone(),
+                    prompt: h.prompt.clone(),
+                    file,
+                    created_at: h.created_at,
+                });
+            }
         }
-    }
-    if let Some(scene_prompt) = &scene.video_prompt {
-        full_prompt.push_str(scene_prompt);
+
+        bundle_scenes.push(BundleScene {
+            scene_index: scene.scene_index,
+            latest_image,
+            latest_video,
+            history: scene_history,
+        });
     }
 
-    if full_prompt.is_empty() {
-        eprintln!("❌ 视频提示词为空");
-        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "视频提示词不能为空"
-        })));
+    let mut bundle_characters = Vec::new();
+    for character in &characters {
+        let image = if character.image_url.is_empty() {
+            None
+        } else {
+            builder.add_media(&character.image_url, "characters").await
+        };
+        bundle_characters.push(BundleCharacter {
+            name: character.name.clone(),
+            prompt: character.prompt.clone(),
+            category: character.category.clone(),
+            tags: character.tags.clone(),
+            display_order: character.display_order,
+            image,
+        });
     }
-    
-    // 不再添加图片使用指示，只传首帧图
-    // let has_characters = !characters.is_empty();
-    // if has_characters {
-    //     full_prompt.push_str(". ");
-    //     full_prompt.push_str("重要提示：第1张参考图片是场景的首帧构图，请严格按照该图的场景、构图和氛围生成视频。");
-    //     full_prompt.push_str("第2张参考图片是角色外观参考，仅用于了解角色的服装、发型和特征，不要在视频中直接显示该图片内容。");
-    // }
 
-    println!("   > 完整提示词: {}", full_prompt);
+    let combined_characters_image = match &project.combined_characters_image {
+        Some(url) => builder.add_media(url, "characters").await,
+        None => None,
+    };
 
-    // 获取 VEO API Key
-    let veo_api_key = std::env::var("VEO_API_KEY")
-        .map_err(|e| {
-            eprintln!("❌ VEO_API_KEY 环境变量未设置: {}", e);
-            std::io::Error::new(std::io::ErrorKind::Other, "VEO_API_KEY not set")
-        })?;
-    
-    let client = Client::new();
+    let mut bundle_composites = Vec::new();
+    for composite in &composites {
+        if let Some(file) = builder.add_media(&composite.video_url, "composite").await {
+            bundle_composites.push(BundleComposite {
+                file,
+                scene_count: composite.scene_count,
+            });
+        }
+    }
 
-    // 创建 Cloudflare 存储客户端
-    let cloudflare_storage = cloudflare::CloudflareStorage::new()
-        .await
-        .map_err(|e| {
-            eprintln!("❌ Cloudflare 存储初始化失败: {}", e);
-            std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to create Cloudflare storage: {}", e))
-        })?;
+    let manifest = BundleManifest {
+        version: BUNDLE_VERSION,
+        project: export,
+        combined_characters_image,
+        scenes: bundle_scenes,
+        characters: bundle_characters,
+        composites: bundle_composites,
+    };
+    let manifest_json = serde_json::to_string_pretty(&manifest)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 
-    // 验证必须有首帧图
-    if scene.latest_image_url.is_none() || scene.latest_image_url.as_ref().unwrap().is_empty() {
-        eprintln!("❌ 没有首帧图，无法生成视频");
-        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "请先生成首帧图，再生成分镜视频"
-        })));
-    }
+    // 每次导出使用独立的临时目录，并发导出互不覆盖
+    let export_dir = PathBuf::from(format!("data/projects/{}/temp_export_bundle_{}", project_id, Uuid::new_v4()));
+    let filename = format!("{}.zip", sanitize_filename::sanitize(&project.title));
+    let output_path = export_dir.join(format!("bundle_{}.zip", project_id));
 
-    // 收集需要上传的图片URL
-    let mut image_urls: Vec<String> = Vec::new();
-    
-    // 添加首帧图
-    if let Some(ref first_frame_url) = scene.latest_image_url {
-        if first_frame_url.starts_with("http") {
-            // 已经是公网URL（R2链接），直接使用
-            image_urls.push(first_frame_url.clone());
-            println!("   ✅ 使用已缓存的 R2 链接: {}", first_frame_url);
-        } else if first_frame_url.starts_with("/data/") {
-            // 本地图片，需要上传到 Cloudflare
-            let local_path = format!(".{}", first_frame_url);
-            println!("   > 首帧图为本地路径，正在上传到 Cloudflare: {}", first_frame_url);
-            
-            match cloudflare_storage.get_or_upload(pool.as_ref(), &local_path, "image").await {
-                Ok(cdn_url) => {
-                    image_urls.push(cdn_url.clone());
-                    println!("   ✅ 首帧图上传成功: {}", cdn_url);
-                    
-                    // 重要：将 R2 链接更新到数据库，下次可以直接使用
-                    match StoryboardScene::update_latest_image(pool.as_ref(), scene_id, cdn_url.clone()).await {
-                        Ok(_) => {
-                            println!("   📝 已更新数据库为 R2 链接，下次将直接使用缓存");
-                        }
-                        Err(e) => {
-                            eprintln!("   ⚠️  更新数据库失败: {}，但不影响视频生成", e);
-                        }
-                    }
-                }
-                Err(e) => {
-                    eprintln!("   ❌ 首帧图上传失败: {}", e);
-                    // 继续执行，使用空图片列表
-                }
-            }
+    let entries = builder.into_entries();
+    let media_count = entries.len();
+    let zip_path = output_path.clone();
+    let written = web::block(move || bundle::write_bundle(&zip_path, &manifest_json, &entries))
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+        .and_then(|r| r);
+    let file = written.and_then(|_| actix_files::NamedFile::open(&output_path));
+
+    // 无论成功与否都删除临时目录；已打开的文件在响应发送完之前仍可读取
+    if let Err(e) = fs::remove_dir_all(&export_dir) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            println!("⚠️  删除临时导出目录失败: {:?} - {}", export_dir, e);
         }
     }
 
+    let file = file?;
+    println!("✅ 项目包已生成: {} ({} 个媒体文件)", filename, media_count);
 
-    // 注释：VEO API 只使用首帧图，角色图已经在首帧图生成时使用过了
-    // 不再需要拼接角色图传递给 VEO
+    Ok(file.set_content_disposition(attachment_header(&filename)))
+}
 
-    if image_urls.is_empty() {
-        eprintln!("   ⚠️  警告：没有可用的公网图片，将使用纯文本生成视频");
+// 导入项目包的大小上限（整个包需读入内存解压）
+const MAX_BUNDLE_UPLOAD_BYTES: usize = 1024 * 1024 * 1024;
+
+// 项目包导入时待解压的文件
+struct BundleExtraction {
+    archive_path: String,
+    dest: PathBuf,
+}
+
+// 为包内文件分配新的本地路径，返回对应的 /data/... URL
+fn plan_bundle_file(
+    plan: &mut Vec<BundleExtraction>,
+    urls: &mut std::collections::HashMap<String, String>,
+    archive_path: &str,
+    dest_dir: &str,
+) -> String {
+    if let Some(url) = urls.get(archive_path) {
+        return url.clone();
     }
 
+    let basename = archive_path.rsplit('/').next().unwrap_or(archive_path);
+    let filename = sanitize_filename::sanitize(basename);
+    let dest = format!("{}/{}", dest_dir, filename);
+    let url = format!("/{}", dest);
 
-    // 调用 VEO API 创建视频
-    // 获取视频时长并转换为整数（1-8范围）
-    let video_duration_float = scene.duration.unwrap_or(8.0);
-    let video_duration = video_duration_float.round().max(1.0).min(8.0) as i32;
-    println!("   > 使用视频时长: {}秒", video_duration);
-    
-    // 从环境变量读取 VEO 配置
-    let veo_model = std::env::var("VEO_MODEL")
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "VEO_MODEL not set"))?;
-    let veo_base_url = std::env::var("VEO_BASE_URL")
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "VEO_BASE_URL not set"))?;
-    let veo_create_endpoint = std::env::var("VEO_CREATE_ENDPOINT")
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "VEO_CREATE_ENDPOINT not set"))?;
-    
-    let create_payload = serde_json::json!({
-        "prompt": full_prompt,
-        "model": veo_model,
-        "images": image_urls,
-        "duration": video_duration,
-        "enhance_prompt": true,
-        "enable_upsample": true,
-        "aspect_ratio": "9:16"
+    plan.push(BundleExtraction {
+        archive_path: archive_path.to_string(),
+        dest: PathBuf::from(dest),
     });
+    urls.insert(archive_path.to_string(), url.clone());
+    url
+}
 
-    println!("   ⏳ 正在调用 VEO API 创建视频...");
-    
-    let create_url = format!("{}{}", veo_base_url, veo_create_endpoint);
-    let create_res = client.post(&create_url)
-        .header("Content-Type", "application/json")
-        .header("Accept", "application/json")
-        .header("Authorization", format!("Bearer {}", veo_api_key))
-        .json(&create_payload)
-        .timeout(std::time::Duration::from_secs(30))
-        .send()
-        .await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to call VEO API: {}", e)))?;
+// 导入项目包（multipart，字段名 file），使用新的ID重建项目
+async fn import_project_bundle(
+    mut payload: Multipart,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let mut bundle_bytes: Vec<u8> = Vec::new();
 
-    if !create_res.status().is_success() {
-        let error_text = create_res.text().await.unwrap_or_default();
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("VEO API error: {}", error_text)
-        ).into());
+    while let Some(item) = payload.next().await {
+        let mut field = item?;
+        let field_name = field.content_disposition().get_name().unwrap_or("").to_string();
+
+        while let Some(chunk) = field.next().await {
+            let data = chunk?;
+            if field_name == "file" {
+                if bundle_bytes.len() + data.len() > MAX_BUNDLE_UPLOAD_BYTES {
+                    return Ok(HttpResponse::PayloadTooLarge().json(serde_json::json!({
+                        "error": format!("项目包不能超过 {} MB", MAX_BUNDLE_UPLOAD_BYTES / 1024 / 1024)
+                    })));
+                }
+                bundle_bytes.extend_from_slice(&data);
+            }
+        }
     }
 
-    let create_result: serde_json::Value = create_res.json().await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to parse response: {}", e)))?;
-
-    // 提取 video_id 和状态
-    let video_id = create_result["id"].as_str()
-        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Other, "No video ID in response"))?
-        .to_string();
-    
-    let status = create_result["status"].as_str().unwrap_or("unknown").to_string();
-    let progress = create_result["progress"].as_i64().unwrap_or(0);
-    
-    println!("   ✅ 视频创建请求已提交");
-    println!("   📹 Video ID: {}", video_id);
-    println!("   📊 初始状态: {} ({}%)", status, progress);
-
-    // 返回video_id和状态，让前端轮询
-    Ok(HttpResponse::Ok().json(serde_json::json!({
-        "video_id": video_id,
-        "status": status,
-        "progress": progress,
-        "message": "视频生成已启动，请通过轮询接口查询进度"
-    })))
-}
+    if bundle_bytes.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "No file uploaded"
+        })));
+    }
 
-// 已移除 upload_image_to_github 函数，改用 Cloudflare R2 存储
+    println!("📥 开始导入项目包 ({} bytes)", bundle_bytes.len());
 
-// 轮询查询视频生成状态
-async fn poll_video_status(
-    path: web::Path<(String, i32, String)>,
-    pool: web::Data<sqlx::PgPool>,
-) -> Result<HttpResponse> {
-    let (project_id_str, scene_id, video_id) = path.into_inner();
-    let project_id = Uuid::parse_str(&project_id_str)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Invalid project ID: {}", e)))?;
+    let (mut archive, manifest_json) = match web::block(move || bundle::open_bundle(bundle_bytes)).await? {
+        Ok(r) => r,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("无效的项目包: {}", e)
+            })));
+        }
+    };
 
-    println!("📡 轮询视频状态: Video ID {}, Scene ID {}", video_id, scene_id);
+    let manifest: BundleManifest = match serde_json::from_str(&manifest_json) {
+        Ok(m) => m,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("无法解析项目包清单: {}", e)
+            })));
+        }
+    };
 
-    // 获取 VEO API Key
-    let veo_api_key = std::env::var("VEO_API_KEY")
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "VEO_API_KEY not set"))?;
-    
-    let client = Client::new();
-    
-    // 从环境变量读取 VEO 配置
-    let veo_base_url = std::env::var("VEO_BASE_URL")
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "VEO_BASE_URL not set"))?;
-    let veo_query_endpoint = std::env::var("VEO_QUERY_ENDPOINT")
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "VEO_QUERY_ENDPOINT not set"))?;
-    
-    // 查询 VEO API 获取视频状态
-    let query_url = format!("{}{}?id={}", veo_base_url, veo_query_endpoint, video_id);
-    let query_res = client.get(&query_url)
-        .header("Accept", "application/json")
-        .header("Authorization", format!("Bearer {}", veo_api_key))
-        .send()
-        .await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to query video status: {}", e)))?;
+    if manifest.version > BUNDLE_VERSION {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("不支持的项目包版本: {}", manifest.version)
+        })));
+    }
 
-    if !query_res.status().is_success() {
-        let error_text = query_res.text().await.unwrap_or_default();
-        return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": format!("VEO API error: {}", error_text)
+    if let Err(e) = validate_project_import(&manifest.project) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": e
         })));
     }
 
-    let query_result: serde_json::Value = query_res.json().await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to parse query response: {}", e)))?;
+    // 所有数据库写入在同一个事务中完成，任一步失败都不会留下不完整的项目或角色
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 
-    let status = query_result["status"].as_str().unwrap_or("unknown");
-    let progress = query_result["progress"].as_i64().unwrap_or(0);
-    
-    println!("   > 当前状态: {} ({}%)", status, progress);
+    // 1. 创建项目和分镜
+    let project = create_project_from_import(&mut tx, &manifest.project)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let project_id = project.id;
 
-    // 如果状态是 completed，下载视频并保存
-    if status == "completed" {
-        let video_url = query_result["video_url"].as_str()
-            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Other, "Video completed but no URL in response"))?;
+    let scene_ids: std::collections::HashMap<i32, i32> = StoryboardScene::find_by_project_id(&mut *tx, project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+        .into_iter()
+        .map(|s| (s.scene_index, s.id))
+        .collect();
 
-        println!("   📥 开始下载视频: {}", video_url);
+    // 2. 为所有媒体文件分配新的本地路径
+    let mut plan: Vec<BundleExtraction> = Vec::new();
+    let mut urls: std::collections::HashMap<String, String> = std::collections::HashMap::new();
+    let image_dir = format!("data/projects/{}/first_frames", project_id);
+    let video_dir = format!("data/projects/{}/videos", project_id);
+    let dir_for_type = |generation_type: &str| {
+        if generation_type == "video" { video_dir.clone() } else { image_dir.clone() }
+    };
 
-        // 下载视频
-        let video_response = client.get(video_url)
-            .send()
-            .await
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to download video: {}", e)))?;
+    // (分镜ID, 最新图片, 最新视频, 历史[(类型, 提示词, URL)])
+    let mut scene_updates: Vec<(i32, Option<String>, Option<String>, Vec<(String, String, String)>)> = Vec::new();
+    for bundle_scene in &manifest.scenes {
+        let Some(&scene_id) = scene_ids.get(&bundle_scene.scene_index) else {
+            println!("⚠️  项目包中的分镜 {} 不在项目 JSON 中，已跳过", bundle_scene.scene_index);
+            continue;
+        };
 
-        let video_bytes = video_response.bytes()
-            .await
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to read video bytes: {}", e)))?;
+        let mut history = Vec::new();
+        for h in &bundle_scene.history {
+            let url = plan_bundle_file(&mut plan, &mut urls, &h.file, &dir_for_type(&h.generation_type));
+            history.push((h.generation_type.clone(), h.prompt.clone(), url));
+        }
+        let latest_image = bundle_scene.latest_image.as_ref()
+            .map(|f| plan_bundle_file(&mut plan, &mut urls, f, &image_dir));
+        let latest_video = bundle_scene.latest_video.as_ref()
+            .map(|f| plan_bundle_file(&mut plan, &mut urls, f, &video_dir));
 
-        // 保存视频
-        let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S").to_string();
-        let filename = format!("video_{}_{}.mp4", scene_id, timestamp);
-        let project_dir = format!("data/projects/{}/videos", project_id);
-        fs::create_dir_all(&project_dir)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to create directory: {}", e)))?;
-        let filepath = format!("{}/{}", project_dir, filename);
-        let local_video_url = format!("/data/projects/{}/videos/{}", project_id, filename);
+        scene_updates.push((scene_id, latest_image, latest_video, history));
+    }
 
-        let mut file = File::create(&filepath)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to create file: {}", e)))?;
-        file.write_all(&video_bytes)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to write file: {}", e)))?;
+    let combined_url = manifest.combined_characters_image.as_ref()
+        .map(|f| plan_bundle_file(&mut plan, &mut urls, f, &format!("data/projects/{}", project_id)));
 
-        println!("   ✅ 视频已保存: {}", local_video_url);
+    let composite_dir = format!("data/projects/{}/composite", project_id);
+    let composite_updates: Vec<(String, i32)> = manifest.composites.iter()
+        .map(|c| (plan_bundle_file(&mut plan, &mut urls, &c.file, &composite_dir), c.scene_count))
+        .collect();
 
-        // 更新数据库
-        StoryboardScene::update_latest_video(pool.as_ref(), scene_id, local_video_url.clone())
+    // 角色使用新ID创建，图片按角色ID命名
+    let mut character_updates: Vec<(Uuid, Option<String>)> = Vec::new();
+    for bundle_char in &manifest.characters {
+        let character = Character::create(
+            &mut *tx,
+            bundle_char.name.clone(),
+            "".to_string(),
+            bundle_char.prompt.clone(),
+            Some(project_id),
+        )
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+        Character::update(
+            &mut *tx,
+            character.id,
+            character.name.clone(),
+            character.prompt.clone(),
+            bundle_char.category.clone(),
+            Some(bundle_char.tags.clone()),
+        )
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+        ProjectCharacter::link(&mut *tx, project_id, character.id, bundle_char.display_order)
             .await
             .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 
-        // 查询分镜信息以获取提示词
-        let scene = StoryboardScene::find_by_id(pool.as_ref(), scene_id)
-            .await
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
-            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Scene not found"))?;
+        let image_url = bundle_char.image.as_ref().map(|f| {
+            let ext = Path::new(f).extension().and_then(|e| e.to_str()).unwrap_or("png");
+            let dest = format!("data/characters/{}.{}", character.id, ext);
+            plan.push(BundleExtraction {
+                archive_path: f.clone(),
+                dest: PathBuf::from(&dest),
+            });
+            format!("/{}", dest)
+        });
+        character_updates.push((character.id, image_url));
+    }
 
-        // 查询项目信息以获取全局提示词
-        let project = Project::find_by_id(pool.as_ref(), project_id)
-            .await
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
-            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Project not found"))?;
+    // 3. 解压媒体文件（失败时事务随 tx 丢弃回滚，只需删除已解压的文件）
+    let media_count = plan.len();
+    let extracted: Vec<PathBuf> = plan.iter().map(|item| item.dest.clone()).collect();
+    let project_dir = PathBuf::from(format!("data/projects/{}", project_id));
+    let remove_extracted = || {
+        for path in &extracted {
+            let _ = fs::remove_file(path);
+        }
+        let _ = fs::remove_dir_all(&project_dir);
+    };
+
+    let extract_result = web::block(move || -> std::io::Result<()> {
+        for item in &plan {
+            bundle::extract_entry(&mut archive, &item.archive_path, &item.dest)?;
+        }
+        Ok(())
+    })
+    .await
+    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+    .and_then(|r| r);
 
-        // 构建完整提示词
-        let mut full_prompt = String::new();
-        if let Some(global_prompt) = &project.global_video_prompt {
-            if !global_prompt.is_empty() {
-                full_prompt.push_str(global_prompt);
-                full_prompt.push_str(". ");
+    if let Err(e) = extract_result {
+        eprintln!("❌ 解压项目包失败: {}", e);
+        remove_extracted();
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("解压项目包失败: {}", e)
+        })));
+    }
+
+    // 4. 写回新的 URL，恢复分镜出场角色（按角色名称关联到新建的角色），全部成功后提交
+    let written: std::result::Result<(), sqlx::Error> = async {
+        for (scene_id, latest_image, latest_video, history) in scene_updates {
+            for (generation_type, prompt, url) in history {
+                GenerationHistory::create(&mut *tx, scene_id, generation_type, prompt, url).await?;
+            }
+            if let Some(url) = latest_image {
+                StoryboardScene::update_latest_image(&mut *tx, scene_id, url).await?;
+            }
+            if let Some(url) = latest_video {
+                StoryboardScene::update_latest_video(&mut *tx, scene_id, url).await?;
             }
         }
-        if let Some(scene_prompt) = &scene.video_prompt {
-            full_prompt.push_str(scene_prompt);
+
+        if let Some(url) = combined_url {
+            sqlx::query(
+                "UPDATE projects SET combined_characters_image = $1, updated_at = NOW() WHERE id = $2"
+            )
+            .bind(&url)
+            .bind(project_id)
+            .execute(&mut *tx)
+            .await?;
         }
 
-        // 记录历史
-        GenerationHistory::create(
-            pool.as_ref(),
-            scene_id,
-            "video".to_string(),
-            full_prompt,
-            local_video_url.clone(),
-        )
-        .await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        for (url, scene_count) in composite_updates {
+            CompositeVideo::create(&mut *tx, project_id, url, scene_count).await?;
+        }
 
-        println!("🎬 分镜视频生成完成: Scene ID {}", scene_id);
+        for (char_id, image_url) in character_updates {
+            if let Some(url) = image_url {
+                Character::update_status(&mut *tx, char_id, url).await?;
+            }
+        }
 
-        // 返回完成状态和视频URL
-        return Ok(HttpResponse::Ok().json(serde_json::json!({
-            "status": status,
-            "progress": progress,
-            "video_url": local_video_url,
-            "message": "视频生成完成"
+        apply_scene_casting(&mut *tx, project_id, &import_casting(&manifest.project.scenes)).await?;
+        tx.commit().await
+    }
+    .await;
+
+    if let Err(e) = written {
+        eprintln!("❌ 导入项目包失败: {}", e);
+        remove_extracted();
+        return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("导入项目包失败: {}", e)
         })));
     }
 
-    // 返回当前状态（queued, processing, failed等）
-    let response = serde_json::json!({
-        "status": status,
-        "progress": progress,
-    });
+    println!("✅ 项目包导入成功: {} ({} 个分镜, {} 个媒体文件)", project_id, manifest.project.scenes.len(), media_count);
 
-    // 如果失败，包含错误信息
-    if status == "failed" || status == "error" {
-        let error_msg = query_result["error"].as_str().unwrap_or("Unknown error");
-        return Ok(HttpResponse::Ok().json(serde_json::json!({
-            "status": status,
-            "progress": progress,
-            "error": error_msg
-        })));
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "project_id": project_id,
+        "project_type": project.project_type,
+        "media_count": media_count,
+        "message": "项目包导入成功"
+    })))
+}
+
+// 复制项目请求（字段均可省略）
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+struct DuplicateProjectRequest {
+    title: Option<String>,
+    copy_media: bool,    // 复制每个分镜的最新图片和视频
+    copy_history: bool,  // 复制全部生成历史（包含 copy_media）
+}
+
+// 将源项目目录下的文件复制到新项目目录，返回新的 URL；其他 URL（角色图、R2 链接）原样保留
+fn copy_project_file(url: &str, from_project_id: Uuid, to_project_id: Uuid) -> std::io::Result<String> {
+    let source_prefix = format!("/data/projects/{}/", from_project_id);
+    let Some(relative) = url.strip_prefix(&source_prefix) else {
+        return Ok(url.to_string());
+    };
+
+    let source_path = format!("./data/projects/{}/{}", from_project_id, relative);
+    let dest_path = PathBuf::from(format!("./data/projects/{}/{}", to_project_id, relative));
+    if let Some(parent) = dest_path.parent() {
+        fs::create_dir_all(parent)?;
     }
+    fs::copy(&source_path, &dest_path)?;
 
-    Ok(HttpResponse::Ok().json(response))
+    Ok(format!("/data/projects/{}/{}", to_project_id, relative))
 }
 
-// 拼接角色图片
-async fn stitch_character_images(
+// 在阻塞线程池中复制文件，同一 URL 只复制一次（最新图片和视频通常也在生成历史中）
+async fn copy_project_file_once(
+    copied: &mut std::collections::HashMap<String, String>,
+    url: &str,
+    from_project_id: Uuid,
+    to_project_id: Uuid,
+) -> std::io::Result<String> {
+    if let Some(new_url) = copied.get(url) {
+        return Ok(new_url.clone());
+    }
+    let source_url = url.to_string();
+    let new_url = web::block(move || copy_project_file(&source_url, from_project_id, to_project_id))
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))??;
+    copied.insert(url.to_string(), new_url.clone());
+    Ok(new_url)
+}
+
+// 复制项目（分镜、角色关联，可选复制媒体和生成历史）
+async fn duplicate_project(
     path: web::Path<String>,
+    req: web::Json<DuplicateProjectRequest>,
     pool: web::Data<sqlx::PgPool>,
 ) -> Result<HttpResponse> {
-    use image::{ImageBuffer, Rgba, DynamicImage, imageops};
-    
     let project_id_str = path.into_inner();
-    let project_id = match Uuid::parse_str(&project_id_str) {
-        Ok(id) => id,
-        Err(e) => {
-            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-                "error": format!("无效的项目ID: {}", e)
-            })));
+    let source_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+    let req = req.into_inner();
+    let copy_media = req.copy_media || req.copy_history;
+
+    let source = Project::find_by_id(pool.as_ref(), source_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Project not found"))?;
+
+    println!("📑 开始复制项目: {} (媒体: {}, 历史: {})", source_id, copy_media, req.copy_history);
+
+    let title = req.title
+        .filter(|t| !t.trim().is_empty())
+        .unwrap_or_else(|| format!("{} (副本)", source.title));
+
+    let project = Project::create_fork(pool.as_ref(), &source, title)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let project_id = project.id;
+
+    // 复制角色关联
+    let linked = ProjectCharacter::copy_links(pool.as_ref(), source_id, project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    let scenes = StoryboardScene::find_by_project_id(pool.as_ref(), source_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    let mut copied_files = 0;
+    let mut copied_urls = std::collections::HashMap::new();
+    for scene in &scenes {
+        let new_scene = StoryboardScene::copy_to_project(pool.as_ref(), scene, project_id)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+        // 角色关联沿用同一批角色，出场角色可直接复制
+        SceneCharacter::copy(pool.as_ref(), scene.id, new_scene.id)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+        if !copy_media {
+            continue;
         }
-    };
-    
-    println!("🎨 开始拼接角色图片: Project ID {}", project_id);
-    
-    // 获取项目的所有角色
-    let characters = match ProjectCharacter::get_all_for_project(pool.as_ref(), project_id).await {
-        Ok(chars) => chars,
-        Err(e) => {
-            eprintln!("❌ 获取角色失败: {}", e);
-            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": format!("获取角色失败: {}", e)
-            })));
+
+        // 按时间正序复制历史，保持最新记录的顺序
+        let mut history = GenerationHistory::find_by_scene_id(pool.as_ref(), scene.id)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        history.reverse();
+
+        for h in &history {
+            let is_latest = scene.latest_image_url.as_deref() == Some(h.result_url.as_str())
+                || scene.latest_video_url.as_deref() == Some(h.result_url.as_str());
+            if !req.copy_history && !is_latest {
+                continue;
+            }
+            match copy_project_file_once(&mut copied_urls, &h.result_url, source_id, project_id).await {
+                Ok(url) => {
+                    GenerationHistory::create_with_translation(
+                        pool.as_ref(),
+                        new_scene.id,
+                        h.generation_type.clone(),
+                        h.prompt.clone(),
+                        h.translated_prompt.clone(),
+                        url,
+                    )
+                        .await
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+                    copied_files += 1;
+                }
+                Err(e) => println!("⚠️  复制历史文件失败: {} - {}", h.result_url, e),
+            }
         }
-    };
-    
-    if characters.is_empty() {
-        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "项目没有角色，无法拼接图片"
-        })));
-    }
-    
-    println!("   > 角色数量: {}", characters.len());
-    
-    // 加载所有角色图片
-    let mut images: Vec<DynamicImage> = Vec::new();
-    let client = Client::new();
-    
-    for character in &characters {
-        let local_path = if character.image_url.starts_with("/data/") {
-            format!(".{}", character.image_url)
-        } else if character.image_url.starts_with("http") {
-            // 如果是远程URL，需要下载
-            let response = match client.get(&character.image_url).send().await {
-                Ok(r) => r,
-                Err(e) => {
-                    eprintln!("❌ 下载图片失败: {}", e);
-                    return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                        "error": format!("下载图片失败: {}", e)
-                    })));
+
+        if let Some(ref url) = scene.latest_image_url {
+            match copy_project_file_once(&mut copied_urls, url, source_id, project_id).await {
+                Ok(new_url) => {
+                    StoryboardScene::update_latest_image(pool.as_ref(), new_scene.id, new_url)
+                        .await
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
                 }
-            };
-            
-            let bytes = match response.bytes().await {
-                Ok(b) => b,
-                Err(e) => {
-                    eprintln!("❌ 读取图片失败: {}", e);
-                    return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                        "error": format!("读取图片失败: {}", e)
-                    })));
+                Err(e) => println!("⚠️  复制首帧图失败: {} - {}", url, e),
+            }
+        }
+        if let Some(ref url) = scene.latest_video_url {
+            match copy_project_file_once(&mut copied_urls, url, source_id, project_id).await {
+                Ok(new_url) => {
+                    StoryboardScene::update_latest_video(pool.as_ref(), new_scene.id, new_url)
+                        .await
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
                 }
-            };
-            
-            // 创建临时文件
-            let temp_path = format!("data/projects/{}/temp_{}.jpg", project_id, character.id);
-            if let Err(e) = fs::create_dir_all(format!("data/projects/{}", project_id)) {
-                eprintln!("❌ 创建目录失败: {}", e);
-                return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                    "error": format!("创建目录失败: {}", e)
-                })));
+                Err(e) => println!("⚠️  复制视频失败: {} - {}", url, e),
             }
-            if let Err(e) = fs::write(&temp_path, bytes) {
-                eprintln!("❌ 保存临时文件失败: {}", e);
-                return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                    "error": format!("保存临时文件失败: {}", e)
-                })));
+        }
+    }
+
+    // 复制拼接角色图
+    if copy_media {
+        if let Some(ref url) = source.combined_characters_image {
+            if let Ok(new_url) = copy_project_file_once(&mut copied_urls, url, source_id, project_id).await {
+                sqlx::query(
+                    "UPDATE projects SET combined_characters_image = $1, updated_at = NOW() WHERE id = $2"
+                )
+                .bind(&new_url)
+                .bind(project_id)
+                .execute(pool.as_ref())
+                .await
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
             }
-            temp_path
-        } else {
-            character.image_url.clone()
-        };
-        
-        println!("   > 加载图片: {} - {}", character.name, local_path);
-        
-        // 使用自动格式检测来加载图片，不依赖扩展名
-        let img = match image::io::Reader::open(&local_path) {
-            Ok(reader) => {
-                match reader.with_guessed_format() {
-                    Ok(reader_with_format) => {
-                        match reader_with_format.decode() {
-                            Ok(i) => i,
-                            Err(e) => {
-                                eprintln!("❌ 解码图片失败");
-                                eprintln!("   角色: {}", character.name);
-                                eprintln!("   路径: {}", local_path);
-                                eprintln!("   原始URL: {}", character.image_url);
-                                eprintln!("   错误: {}", e);
-                                return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                                    "error": format!("解码图片失败 (角色: {}): {}", character.name, e)
-                                })));
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("❌ 识别图片格式失败");
-                        eprintln!("   角色: {}", character.name);
-                        eprintln!("   路径: {}", local_path);
-                        eprintln!("   原始URL: {}", character.image_url);
-                        eprintln!("   错误: {}", e);
-                        return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                            "error": format!("识别图片格式失败 (角色: {}): {}", character.name, e)
-                        })));
-                    }
-                }
+        }
+    }
+
+    println!("✅ 项目复制成功: {} -> {} ({} 个分镜, {} 个角色, {} 个历史文件)", source_id, project_id, scenes.len(), linked, copied_files);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "project_id": project_id,
+        "forked_from": source_id,
+        "project_type": project.project_type,
+        "message": "项目复制成功"
+    })))
+}
+
+
+// 下载JSON模板（视频）
+async fn download_video_template() -> Result<HttpResponse> {
+    let template = serde_json::json!({
+        "标题": "",
+        "剧本": "",
+        "项目类型": "video",
+        "首帧图全局提示词": "",
+        "视频全局提示词": "",
+        "角色": [
+            {
+                "角色名称": "",
+                "分类": "",
+                "标签": "",
+                "提示词": ""
             }
-            Err(e) => {
-                eprintln!("❌ 打开图片文件失败");
-                eprintln!("   角色: {}", character.name);
-                eprintln!("   路径: {}", local_path);
-                eprintln!("   原始URL: {}", character.image_url);
-                eprintln!("   错误: {}", e);
-                return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                    "error": format!("打开图片文件失败 (角色: {}): {}", character.name, e)
-                })));
+        ],
+        "分镜": [
+            {
+                "id": 1,
+                "时长": "5秒",
+                "首帧图提示词": "",
+                "视频提示词": ""
+            },
+            {
+                "id": 2,
+                "时长": "3s",
+                "首帧图提示词": "",
+                "视频提示词": ""
             }
-        };
-        images.push(img);
+        ]
+    });
+
+    let template_json = serde_json::to_string_pretty(&template)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .insert_header(("Content-Disposition", "attachment; filename=\"项目模板（视频）.json\""))
+        .body(template_json))
+}
+
+// 下载JSON模板（漫画）
+async fn download_comic_template() -> Result<HttpResponse> {
+    let template = serde_json::json!({
+        "标题": "",
+        "剧本": "",
+        "项目类型": "comic",
+        "图全局提示词": "",
+        "角色": [
+            {
+                "角色名称": "",
+                "分类": "",
+                "标签": "",
+                "提示词": ""
+            }
+        ],
+        "分镜": [
+            {
+                "id": 1,
+                "图提示词": ""
+            },
+            {
+                "id": 2,
+                "图提示词": ""
+            }
+        ]
+    });
+
+    let template_json = serde_json::to_string_pretty(&template)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .insert_header(("Content-Disposition", "attachment; filename=\"项目模板（漫画）.json\""))
+        .body(template_json))
+}
+
+// 更新分镜提示词
+async fn update_scene_prompts(
+    path: web::Path<(String, i32)>,
+    req_body: web::Json<UpdatePromptsRequest>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let (_project_id_str, scene_id) = path.into_inner();
+
+    let scene = StoryboardScene::find_by_id(pool.as_ref(), scene_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Scene not found"))?;
+
+    if scene.is_locked() {
+        return Ok(scene_locked_response(scene_id));
     }
-    
-    // 等比例缩放布局算法
-    let count = images.len();
-    let max_width = 768u32; // 最大宽度
-    
-    // 等比例缩放所有图片，宽度统一为768px，高度按比例调整
-    let mut resized_images: Vec<(DynamicImage, u32, u32)> = Vec::new(); // (图片, 宽度, 高度)
-    
-    for img in &images {
-        let (orig_width, orig_height) = (img.width(), img.height());
-        
-        // 计算缩放后的高度（保持比例）
-        let scale = max_width as f64 / orig_width as f64;
-        let new_height = (orig_height as f64 * scale) as u32;
-        
-        // 使用resize_exact确保缩放到精确尺寸
-        let resized = img.resize_exact(max_width, new_height, imageops::FilterType::Lanczos3);
-        resized_images.push((resized, max_width, new_height));
+
+    // 提示词、编辑记录和时间轴一起提交
+    let mut tx = pool.begin()
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    StoryboardScene::update_prompts(
+        &mut *tx,
+        scene_id,
+        req_body.first_frame_prompt.clone(),
+        req_body.video_prompt.clone(),
+        req_body.duration,
+    )
+    .await
+    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    let author = edit_author(&req_body.author);
+    for (field, old_value, new_value) in [
+        ("first_frame_prompt", &scene.first_frame_prompt, &req_body.first_frame_prompt),
+        ("video_prompt", &scene.video_prompt, &req_body.video_prompt),
+    ] {
+        PromptEdit::record(
+            &mut *tx,
+            scene.project_id,
+            Some(scene_id),
+            field,
+            old_value.clone(),
+            new_value.clone(),
+            author.clone(),
+        )
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
     }
-    
-    // 计算布局 - 自适应网格算法
-    // 目标：使最终画板的长宽比尽可能接近 1:1 (正方形)
-    
-    let avg_height: f64 = resized_images.iter().map(|(_, _, h)| *h as f64).sum::<f64>() / count as f64;
-    let mut best_cols = 1;
-    let mut best_score = f64::MAX; // Score越接近1越好 (max(ratio, 1/ratio))
-    
-    // 遍历所有可能的列数配置 (1 到 count)
-    for cols in 1..=count {
-        let rows = (count as f64 / cols as f64).ceil();
-        
-        let est_width = cols as f64 * max_width as f64;
-        let est_height = rows * avg_height;
-        
-        let aspect_ratio = est_width / est_height;
-        
-        // 计算偏离度 score >= 1.0
-        let score = if aspect_ratio >= 1.0 {
-            aspect_ratio
-        } else {
-            1.0 / aspect_ratio
-        };
-        
-        // 倾向于更少的行数（如果接近），或者严格按照score
-        // 这里简单比较score
+
+    // 时长可能变化，重新计算时间轴
+    let total_runtime = recalculate_timeline(&mut *tx, scene.project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    tx.commit()
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "提示词已更新",
+        "total_runtime": total_runtime
+    })))
+}
+
+// 未设置时长的分镜按 8 秒计算（与视频生成的默认时长一致）
+const DEFAULT_SCENE_DURATION: f64 = 8.0;
+
+// 按分镜顺序计算每个分镜的起止时间（秒）
+// 锁定且已有时间戳的分镜保持原时间，后续分镜从它的结束时间继续排
+fn scene_timeline(scenes: &[StoryboardScene]) -> Vec<(f64, f64)> {
+    let mut cursor = 0.0;
+    scenes
+        .iter()
+        .map(|scene| {
+            let locked_timing = if scene.is_locked() {
+                let start = scene.start_time.as_deref().and_then(parse_timestamp);
+                start.zip(scene.end_time.as_deref().and_then(parse_timestamp))
+            } else {
+                None
+            };
+            let (start, end) = locked_timing
+                .unwrap_or_else(|| (cursor, cursor + scene.duration.unwrap_or(DEFAULT_SCENE_DURATION)));
+            cursor = end;
+            (start, end)
+        })
+        .collect()
+}
+
+// 根据分镜时长计算时间轴并写回 start_time/end_time（锁定分镜不改写），返回总时长（秒）
+async fn recalculate_timeline<'a>(
+    conn: impl sqlx::Acquire<'a, Database = sqlx::Postgres>,
+    project_id: Uuid,
+) -> Result<f64, sqlx::Error> {
+    let mut conn = conn.acquire().await?;
+    let scenes = StoryboardScene::find_by_project_id(&mut *conn, project_id).await?;
+    let timeline = scene_timeline(&scenes);
+
+    for (scene, (start, end)) in scenes.iter().zip(&timeline) {
+        if scene.is_locked() {
+            continue;
+        }
+        let start_time = format_timestamp(*start);
+        let end_time = format_timestamp(*end);
+        if scene.start_time.as_deref() != Some(start_time.as_str())
+            || scene.end_time.as_deref() != Some(end_time.as_str())
+        {
+            StoryboardScene::update_timing(&mut *conn, scene.id, start_time, end_time).await?;
+        }
+    }
+
+    Ok(timeline.last().map(|(_, end)| *end).unwrap_or(0.0))
+}
+
+// 分镜排序请求
+#[derive(Debug, Deserialize)]
+struct ReorderScenesRequest {
+    scene_ids: Vec<i32>,  // 项目全部分镜ID，按新顺序排列
+}
+
+// 调整分镜顺序并重新计算时间轴
+async fn reorder_scenes(
+    path: web::Path<String>,
+    req_body: web::Json<ReorderScenesRequest>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let project_id_str = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+
+    let scenes = StoryboardScene::find_by_project_id(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    let mut requested = req_body.scene_ids.clone();
+    requested.sort_unstable();
+    requested.dedup();
+    let mut existing: Vec<i32> = scenes.iter().map(|s| s.id).collect();
+    existing.sort_unstable();
+
+    if requested.len() != req_body.scene_ids.len() || requested != existing {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "scene_ids 必须包含项目的全部分镜且不能重复"
+        })));
+    }
+
+    // 锁定的分镜必须保持原位置（scenes 按当前顺序排列）
+    if let Some(locked) = scenes
+        .iter()
+        .zip(&req_body.scene_ids)
+        .find(|(scene, new_id)| scene.is_locked() && scene.id != **new_id)
+        .map(|(scene, _)| scene)
+    {
+        return Ok(scene_locked_response(locked.id));
+    }
+
+    StoryboardScene::reorder(pool.as_ref(), project_id, &req_body.scene_ids)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let total_runtime = recalculate_timeline(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    println!("🔀 分镜顺序已更新: Project ID {} ({} 个分镜)", project_id, req_body.scene_ids.len());
+
+    let scenes = StoryboardScene::find_by_project_id(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "scenes": scenes,
+        "total_runtime": total_runtime
+    })))
+}
+
+// 获取项目时间轴：每个分镜的起止时间、总时长与目标时长，并标记视频实际时长与计划不符的分镜
+async fn get_project_timeline(
+    path: web::Path<String>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let project_id_str = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+
+    let project = Project::find_by_id(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Project not found"))?;
+
+    // 只读计算时间轴，不回写分镜（锁定分镜的时间戳保持不变）
+    let scenes = StoryboardScene::find_by_project_id(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    // 读取本地视频的实际时长（远程视频跳过）
+    let video_paths: Vec<Option<PathBuf>> = scenes
+        .iter()
+        .map(|s| {
+            s.latest_video_url
+                .as_deref()
+                .filter(|url| url.starts_with("/data/"))
+                .map(|url| PathBuf::from(format!(".{}", url.split('?').next().unwrap_or(url))))
+        })
+        .collect();
+    let video_durations: Vec<Option<f64>> = web::block(move || {
+        video_paths
+            .iter()
+            .map(|p| {
+                p.as_ref()
+                    .filter(|p| p.exists())
+                    .and_then(|p| get_video_duration(p).ok())
+                    .filter(|d| *d > 0.0)
+            })
+            .collect()
+    })
+    .await
+    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    let planned = scene_timeline(&scenes);
+    let total_runtime = planned.last().map(|(_, end)| *end).unwrap_or(0.0);
+    let mut mismatched = 0;
+    let timeline: Vec<serde_json::Value> = scenes
+        .iter()
+        .zip(planned)
+        .zip(video_durations)
+        .map(|((scene, (start, end)), video_duration)| {
+            let duration = scene.duration.unwrap_or(DEFAULT_SCENE_DURATION);
+
+            let duration_mismatch = video_duration.is_some_and(|d| (d - duration).abs() > 0.5);
+            if duration_mismatch {
+                mismatched += 1;
+            }
+
+            serde_json::json!({
+                "scene_id": scene.id,
+                "scene_index": scene.scene_index,
+                "start": start,
+                "end": end,
+                "start_time": format_timestamp(start),
+                "end_time": format_timestamp(end),
+                "duration": duration,
+                "duration_is_default": scene.duration.is_none(),
+                "video_duration": video_duration,
+                "duration_mismatch": duration_mismatch,
+            })
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "scenes": timeline,
+        "total_runtime": total_runtime,
+        "total_runtime_time": format_timestamp(total_runtime),
+        "target_runtime": project.target_runtime,
+        "runtime_delta": project.target_runtime.map(|t| total_runtime - t),
+        "mismatched_scene_count": mismatched,
+    })))
+}
+
+// 查询分镜并校验其属于指定项目
+async fn find_project_scene(
+    pool: &sqlx::PgPool,
+    project_id: Uuid,
+    scene_id: i32,
+) -> std::io::Result<Option<StoryboardScene>> {
+    let scene = StoryboardScene::find_by_id(pool, scene_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    Ok(scene.filter(|s| s.project_id == project_id))
+}
+
+// 获取分镜出场角色（未设置时返回根据提示词推断的结果）
+async fn get_scene_characters(
+    path: web::Path<(String, i32)>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let (project_id_str, scene_id) = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+
+    let Some(scene) = find_project_scene(pool.as_ref(), project_id, scene_id).await? else {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "分镜不存在"
+        })));
+    };
+
+    let characters = ProjectCharacter::get_all_for_project(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let (cast, inferred) = resolve_scene_cast(pool.as_ref(), &scene, &characters)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "characters": cast,
+        "inferred": inferred
+    })))
+}
+
+// 设置分镜出场角色（整体替换）
+async fn update_scene_characters(
+    path: web::Path<(String, i32)>,
+    req_body: web::Json<UpdateSceneCharactersRequest>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let (project_id_str, scene_id) = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+
+    match find_project_scene(pool.as_ref(), project_id, scene_id).await? {
+        Some(scene) if scene.is_locked() => return Ok(scene_locked_response(scene_id)),
+        Some(_) => {}
+        None => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "分镜不存在"
+            })));
+        }
+    }
+
+    // 只允许选择项目中的角色
+    let candidates = Character::list_castable(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    if let Some(id) = req_body.character_ids.iter().find(|id| !candidates.iter().any(|c| &c.id == *id)) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("角色不属于该项目: {}", id)
+        })));
+    }
+
+    SceneCharacter::set_for_scene(pool.as_ref(), scene_id, &req_body.character_ids)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    let cast = SceneCharacter::get_for_scene(pool.as_ref(), scene_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "characters": cast,
+        "inferred": false
+    })))
+}
+
+// 根据提示词中的角色名推断并保存分镜出场角色
+async fn infer_scene_characters(
+    path: web::Path<(String, i32)>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let (project_id_str, scene_id) = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+
+    let Some(scene) = find_project_scene(pool.as_ref(), project_id, scene_id).await? else {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "分镜不存在"
+        })));
+    };
+
+    if scene.is_locked() {
+        return Ok(scene_locked_response(scene_id));
+    }
+
+    let candidates: Vec<(Uuid, String)> = Character::list_castable(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+        .into_iter()
+        .map(|c| (c.id, c.name))
+        .collect();
+
+    let character_ids = infer_cast(&scene_cast_text(&scene), &candidates);
+
+    SceneCharacter::set_for_scene(pool.as_ref(), scene_id, &character_ids)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    let cast = SceneCharacter::get_for_scene(pool.as_ref(), scene_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    println!("🎭 已推断分镜出场角色: Scene ID {} ({} 个)", scene_id, cast.len());
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "characters": cast,
+        "inferred": false
+    })))
+}
+
+// 分镜已锁定时的错误响应
+fn scene_locked_response(scene_id: i32) -> HttpResponse {
+    println!("🔒 分镜已锁定，拒绝修改: Scene ID {}", scene_id);
+    HttpResponse::Locked().json(serde_json::json!({
+        "error": "分镜已锁定，请先解锁后再修改",
+        "scene_id": scene_id
+    }))
+}
+
+// 更新分镜审核状态请求
+#[derive(Debug, Deserialize)]
+struct UpdateSceneStatusRequest {
+    status: String,
+    author: Option<String>,
+    note: Option<String>,  // 可选：同时记录一条审核备注
+}
+
+// 添加审核备注请求
+#[derive(Debug, Deserialize)]
+struct CreateReviewNoteRequest {
+    author: String,
+    note: String,
+}
+
+// 更新分镜审核状态（draft / in_review / approved / locked）
+async fn update_scene_status(
+    path: web::Path<(String, i32)>,
+    req_body: web::Json<UpdateSceneStatusRequest>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let (project_id_str, scene_id) = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+    let req = req_body.into_inner();
+
+    if !SCENE_STATUSES.contains(&req.status.as_str()) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("无效的分镜状态: {}，可选值: {}", req.status, SCENE_STATUSES.join(", "))
+        })));
+    }
+
+    let Some(previous) = find_project_scene(pool.as_ref(), project_id, scene_id).await? else {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "分镜不存在"
+        })));
+    };
+
+    let scene = StoryboardScene::update_status(pool.as_ref(), scene_id, &req.status)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Scene not found"))?;
+
+    // 记录状态变更（附带备注时一并保存）
+    let author = req.author
+        .map(|a| a.trim().to_string())
+        .filter(|a| !a.is_empty())
+        .unwrap_or_else(|| "匿名".to_string());
+    let note = req.note
+        .map(|n| n.trim().to_string())
+        .filter(|n| !n.is_empty())
+        .unwrap_or_else(|| format!("状态变更: {} → {}", previous.status, scene.status));
+    let review_note = SceneReviewNote::create(pool.as_ref(), scene_id, author, note, Some(scene.status.clone()))
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    println!("📝 分镜状态已更新: Scene ID {} ({} → {})", scene_id, previous.status, scene.status);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "scene": scene,
+        "note": review_note
+    })))
+}
+
+// 设置分镜所属场次请求
+#[derive(Debug, Deserialize)]
+struct UpdateSceneSequenceRequest {
+    sequence: Option<String>,  // 传空字符串或 null 清空
+}
+
+// 分镜场次名称最大长度
+const MAX_SEQUENCE_LENGTH: usize = 100;
+
+// 设置分镜所属场次（连贯模式为 sequence 时，同一场次的相邻分镜互相参考）
+async fn update_scene_sequence(
+    path: web::Path<(String, i32)>,
+    req_body: web::Json<UpdateSceneSequenceRequest>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let (project_id_str, scene_id) = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+
+    let sequence = req_body.into_inner().sequence
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty());
+    if sequence.as_ref().is_some_and(|s| s.chars().count() > MAX_SEQUENCE_LENGTH) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("场次名称不能超过 {} 个字符", MAX_SEQUENCE_LENGTH)
+        })));
+    }
+
+    match find_project_scene(pool.as_ref(), project_id, scene_id).await? {
+        None => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "分镜不存在"
+            })));
+        }
+        Some(scene) if scene.is_locked() => return Ok(scene_locked_response(scene_id)),
+        Some(_) => {}
+    }
+
+    let scene = StoryboardScene::update_sequence(pool.as_ref(), scene_id, sequence)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Scene not found"))?;
+
+    println!("🎬 分镜场次已更新: Scene ID {} ({})", scene_id, scene.sequence.as_deref().unwrap_or("无"));
+
+    Ok(HttpResponse::Ok().json(scene))
+}
+
+// 获取分镜审核备注（最新在前）
+async fn get_scene_notes(
+    path: web::Path<(String, i32)>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let (project_id_str, scene_id) = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+
+    if find_project_scene(pool.as_ref(), project_id, scene_id).await?.is_none() {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "分镜不存在"
+        })));
+    }
+
+    let notes = SceneReviewNote::find_by_scene_id(pool.as_ref(), scene_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    Ok(HttpResponse::Ok().json(notes))
+}
+
+// 添加分镜审核备注（锁定的分镜也可以添加备注）
+async fn create_scene_note(
+    path: web::Path<(String, i32)>,
+    req_body: web::Json<CreateReviewNoteRequest>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let (project_id_str, scene_id) = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+
+    let author = req_body.author.trim().to_string();
+    let note = req_body.note.trim().to_string();
+    if author.is_empty() || note.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "作者和备注内容不能为空"
+        })));
+    }
+
+    if find_project_scene(pool.as_ref(), project_id, scene_id).await?.is_none() {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "分镜不存在"
+        })));
+    }
+
+    let review_note = SceneReviewNote::create(pool.as_ref(), scene_id, author, note, None)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    Ok(HttpResponse::Ok().json(review_note))
+}
+
+// 构建提示词模板上下文（项目角色提示词 + 项目变量）
+fn prompt_context(project: &Project, characters: &[ProjectCharacter]) -> PromptContext {
+    PromptContext::new(
+        characters.iter().map(|c| (c.name.clone(), c.prompt.clone())),
+        project.prompt_variables.0.clone(),
+    )
+}
+
+// 构建完整提示词：全局提示词 + 分镜提示词，并展开其中的占位符
+fn build_scene_prompt(
+    global_prompt: Option<&str>,
+    scene_prompt: Option<&str>,
+    ctx: &PromptContext,
+) -> std::result::Result<String, Vec<String>> {
+    let mut full_prompt = String::new();
+    if let Some(global_prompt) = global_prompt {
+        if !global_prompt.is_empty() {
+            full_prompt.push_str(global_prompt);
+            full_prompt.push_str(". ");
+        }
+    }
+    if let Some(scene_prompt) = scene_prompt {
+        full_prompt.push_str(scene_prompt);
+    }
+    prompt_template::render(&full_prompt, ctx)
+}
+
+// 提示词中存在无法解析的占位符时的错误响应
+fn unresolved_placeholders_response(unresolved: Vec<String>) -> HttpResponse {
+    println!("❌ 提示词中存在无法解析的占位符: {}", unresolved.join(", "));
+    HttpResponse::BadRequest().json(serde_json::json!({
+        "error": format!("提示词中存在无法解析的占位符: {}", unresolved.join(", ")),
+        "unresolved": unresolved
+    }))
+}
+
+// 提示词翻译模式：发送原文 / 发送英文翻译 / 翻译在前、原文在后一起发送
+const PROMPT_TRANSLATION_MODES: &[&str] = &["original", "translated", "both"];
+
+// 使用文本模型把提示词翻译成英文，按原文哈希缓存
+async fn translate_prompt(pool: &sqlx::PgPool, text: &str) -> std::io::Result<String> {
+    use sha2::{Digest, Sha256};
+
+    let prompt_hash = hex::encode(Sha256::digest(text.as_bytes()));
+    if let Some(cached) = PromptTranslation::find_by_hash(pool, &prompt_hash)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+    {
+        println!("   🌐 使用缓存的提示词翻译");
+        return Ok(cached.translated_text);
+    }
+
+    println!("   🌐 正在翻译提示词...");
+    let input = format!(
+        "Translate the following image/video generation prompt into natural English. \
+Keep character names, proper nouns, numbers and any English text unchanged. \
+Output only the translated prompt, without explanations or quotes.\n\n{}",
+        text
+    );
+    let translated = call_gpt_nano(mock::GPT_NANO_TASK_TRANSLATE, input, 0.2, 4000).await?.trim().to_string();
+    if translated.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::Other, "翻译结果为空"));
+    }
+
+    PromptTranslation::save(pool, &prompt_hash, text, &translated, GPT_NANO_MODEL)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    Ok(translated)
+}
+
+// 按项目的翻译模式生成发送给模型的提示词，返回 (发送文本, 英文翻译)
+// 提示词不含中日韩文字或翻译失败时发送原文
+async fn prepare_model_prompt(
+    pool: &sqlx::PgPool,
+    project: &Project,
+    full_prompt: &str,
+) -> (String, Option<String>) {
+    if project.prompt_translation == "original" || !full_prompt.chars().any(text::is_cjk) {
+        return (full_prompt.to_string(), None);
+    }
+
+    match translate_prompt(pool, full_prompt).await {
+        Ok(translated) => {
+            println!("   > 英文提示词: {}", translated);
+            let model_prompt = if project.prompt_translation == "both" {
+                format!("{}\n\n{}", translated, full_prompt)
+            } else {
+                translated.clone()
+            };
+            (model_prompt, Some(translated))
+        }
+        Err(e) => {
+            eprintln!("⚠️  提示词翻译失败，使用原文: {}", e);
+            (full_prompt.to_string(), None)
+        }
+    }
+}
+
+// 生成首帧图（使用项目选择的图片生成服务 + 角色图片参考）
+async fn generate_first_frame(
+    path: web::Path<(String, i32)>,
+    req: web::Json<GenerateFirstFrameRequest>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let (project_id_str, scene_id) = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+
+    // Fetch Project (Global Prompt)
+    let project = Project::find_by_id(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Project not found"))?;
+
+    // Fetch All Characters
+    let characters = ProjectCharacter::get_all_for_project(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    // 查询分镜信息
+    let scene = StoryboardScene::find_by_id(pool.as_ref(), scene_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Scene not found"))?;
+
+    let req = req.into_inner();
+    let candidates = req.candidates.unwrap_or(1);
+    if candidates == 0 || candidates > MAX_IMAGE_CANDIDATES {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("候选图数量必须在 1 到 {} 之间", MAX_IMAGE_CANDIDATES)
+        })));
+    }
+
+    let provider = match image_provider_for(Some(&project), req.provider.as_deref(), req.model.as_deref()) {
+        Ok(provider) => provider,
+        Err(e) => return Ok(image_error_response(&e)),
+    };
+
+    match render_first_frame(pool.as_ref(), &project, &characters, &scene, provider.as_ref(), candidates, req.continuity).await {
+        Ok(result) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "message": "首帧图生成成功",
+            "image_url": result.image_url,
+            "prompt": result.prompt,
+            "translated_prompt": result.translated_prompt,
+            "candidate_batch_id": result.candidate_batch_id,
+            "candidates": result.candidates,
+            "continuity_source": result.continuity_source,
+        }))),
+        Err(FirstFrameError::Locked) => Ok(scene_locked_response(scene_id)),
+        Err(FirstFrameError::Unresolved(unresolved)) => Ok(unresolved_placeholders_response(unresolved)),
+        Err(FirstFrameError::Image(e)) => Ok(image_error_response(&e)),
+        Err(FirstFrameError::Failed(e)) => Err(e.into()),
+    }
+}
+
+// 选择图片生成服务：请求指定的优先，否则使用项目设置，都未设置时使用服务器默认
+// 请求只指定服务时，仅在与项目服务相同时沿用项目的模型
+fn image_provider_for(
+    project: Option<&Project>,
+    provider: Option<&str>,
+    model: Option<&str>,
+) -> std::result::Result<Box<dyn ImageProvider>, ImageError> {
+    let project_provider = project.and_then(|p| p.image_provider.as_deref());
+    let project_model = project.and_then(|p| p.image_model.as_deref());
+
+    let provider = provider.filter(|p| !p.is_empty());
+    let (provider, model) = match provider {
+        Some(provider) if Some(provider) != project_provider => (Some(provider), model),
+        _ => (provider.or(project_provider), model.or(project_model)),
+    };
+
+    let provider = image_provider::create_provider(provider, model)?;
+    println!("   > 图片生成服务: {} / {}", provider.name(), provider.model());
+    Ok(provider)
+}
+
+// 图片生成失败时的错误响应（按错误类型返回状态码）
+fn image_error_response(e: &ImageError) -> HttpResponse {
+    eprintln!("❌ 图片生成失败 ({}): {}", e.kind(), e);
+    let mut response = match e {
+        ImageError::Unsupported(_) => HttpResponse::BadRequest(),
+        ImageError::SafetyBlock(_) => HttpResponse::UnprocessableEntity(),
+        ImageError::Quota(_) => HttpResponse::TooManyRequests(),
+        ImageError::Timeout => HttpResponse::GatewayTimeout(),
+        ImageError::Config(_) => HttpResponse::InternalServerError(),
+        ImageError::Network(_)
+        | ImageError::EmptyResponse
+        | ImageError::Api { .. }
+        | ImageError::InvalidResponse(_) => HttpResponse::BadGateway(),
+    };
+    response.json(serde_json::json!({
+        "error": e.to_string(),
+        "error_kind": e.kind()
+    }))
+}
+
+// 首帧图生成结果
+struct FirstFrameResult {
+    image_url: String,  // 第一张图
+    prompt: String,
+    translated_prompt: Option<String>,
+    candidate_batch_id: Option<Uuid>,  // 多个候选图时的批次ID
+    candidates: Vec<GenerationHistory>,
+    continuity_source: Option<ContinuitySource>,  // 连贯模式使用的上一分镜画面
+}
+
+// 单次请求最多生成的候选图数量
+const MAX_IMAGE_CANDIDATES: usize = 4;
+
+// 生成首帧图请求（字段均可省略，发送 {} 即使用默认设置）
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct GenerateFirstFrameRequest {
+    candidates: Option<usize>,  // 候选图数量，默认 1
+    provider: Option<String>,   // 图片生成服务，默认使用项目设置
+    model: Option<String>,
+    continuity: Option<bool>,   // 是否参考上一分镜画面，默认按项目连贯模式
+}
+
+// 首帧图连贯模式：关闭 / 仅同一场次的相邻分镜 / 总是参考上一分镜
+const CONTINUITY_MODES: &[&str] = &["off", "sequence", "always"];
+
+// 连贯参考图来源
+#[derive(Debug, Serialize)]
+struct ContinuitySource {
+    scene_id: i32,
+    scene_index: i32,
+    kind: &'static str,  // "video_last_frame" 或 "image"
+    url: String,
+}
+
+// 查找连贯参考图：优先使用上一分镜视频的最后一帧，没有视频时使用其首帧图
+// requested 为请求指定的开关，未指定时按项目连贯模式判断
+async fn find_continuity_reference(
+    pool: &sqlx::PgPool,
+    project: &Project,
+    scene: &StoryboardScene,
+    requested: Option<bool>,
+) -> std::io::Result<Option<(ContinuitySource, ReferenceImage)>> {
+    let mode = project.continuity_mode.as_str();
+    if !requested.unwrap_or(mode != "off") {
+        return Ok(None);
+    }
+
+    let Some(previous) = StoryboardScene::find_previous(pool, project.id, scene.scene_index)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+    else {
+        println!("   ℹ️  没有上一分镜，跳过连贯参考");
+        return Ok(None);
+    };
+
+    // sequence 模式只延续同一场次的分镜（请求显式开启时不限制）
+    if requested.is_none() && mode == "sequence" {
+        let same_sequence = scene.sequence.as_deref().is_some_and(|s| !s.is_empty())
+            && scene.sequence == previous.sequence;
+        if !same_sequence {
+            println!("   ℹ️  上一分镜不属于同一场次，跳过连贯参考");
+            return Ok(None);
+        }
+    }
+
+    if let Some(video_url) = previous.latest_video_url.as_deref() {
+        match extract_last_frame(project.id, previous.id, video_url).await {
+            Ok(frame_url) => match download_and_encode_image(&frame_url).await {
+                Ok((data, mime_type)) => {
+                    println!("   ✅ 添加连贯参考图(上一分镜视频尾帧): Scene #{}", previous.scene_index);
+                    let source = ContinuitySource {
+                        scene_id: previous.id,
+                        scene_index: previous.scene_index,
+                        kind: "video_last_frame",
+                        url: frame_url,
+                    };
+                    return Ok(Some((source, ReferenceImage { mime_type, data })));
+                }
+                Err(e) => println!("   ⚠️  无法加载视频尾帧: {}", e),
+            },
+            Err(e) => println!("   ⚠️  提取上一分镜视频尾帧失败，改用首帧图: {}", e),
+        }
+    }
+
+    if let Some(image_url) = previous.latest_image_url.as_deref() {
+        match download_and_encode_image(image_url).await {
+            Ok((data, mime_type)) => {
+                println!("   ✅ 添加连贯参考图(上一分镜首帧图): Scene #{}", previous.scene_index);
+                let source = ContinuitySource {
+                    scene_id: previous.id,
+                    scene_index: previous.scene_index,
+                    kind: "image",
+                    url: image_url.to_string(),
+                };
+                return Ok(Some((source, ReferenceImage { mime_type, data })));
+            }
+            Err(e) => println!("   ⚠️  无法加载上一分镜首帧图 {}: {}", image_url, e),
+        }
+    }
+
+    println!("   ℹ️  上一分镜还没有可用画面，跳过连贯参考");
+    Ok(None)
+}
+
+// 用 ffmpeg 提取视频最后一帧，按视频地址哈希缓存到项目的 continuity 目录
+async fn extract_last_frame(project_id: Uuid, scene_id: i32, video_url: &str) -> std::io::Result<String> {
+    use sha2::{Digest, Sha256};
+
+    let video_hash = hex::encode(Sha256::digest(video_url.as_bytes()));
+    let filename = format!("last_frame_{}_{}.png", scene_id, &video_hash[..16]);
+    let frame_dir = format!("data/projects/{}/continuity", project_id);
+    let frame_path = format!("{}/{}", frame_dir, filename);
+    let frame_url = format!("/data/projects/{}/continuity/{}", project_id, filename);
+
+    if std::path::Path::new(&frame_path).exists() {
+        return Ok(frame_url);
+    }
+
+    fs::create_dir_all(&frame_dir)?;
+
+    // 本地视频使用相对路径，外部 URL 由 ffmpeg 直接读取
+    let input = if video_url.starts_with("/data/") {
+        format!(".{}", video_url)
+    } else {
+        video_url.to_string()
+    };
+
+    let output = AsyncCommand::new("ffmpeg")
+        .args([
+            "-sseof", "-0.1",  // 从结尾前 0.1 秒开始读取
+            "-i", &input,
+            "-frames:v", "1",
+            "-update", "1",
+            "-y",
+            &frame_path,
+        ])
+        .output()
+        .await?;
+
+    if !output.status.success() || !std::path::Path::new(&frame_path).exists() {
+        let error_msg = String::from_utf8_lossy(&output.stderr);
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            format!("ffmpeg 提取尾帧失败: {}", error_msg.lines().last().unwrap_or("")),
+        ));
+    }
+
+    Ok(frame_url)
+}
+
+// 首帧图生成失败原因
+enum FirstFrameError {
+    Locked,
+    Unresolved(Vec<String>),
+    Image(ImageError),
+    Failed(std::io::Error),
+}
+
+impl From<std::io::Error> for FirstFrameError {
+    fn from(e: std::io::Error) -> Self {
+        FirstFrameError::Failed(e)
+    }
+}
+
+impl From<ImageError> for FirstFrameError {
+    fn from(e: ImageError) -> Self {
+        FirstFrameError::Image(e)
+    }
+}
+
+impl std::fmt::Display for FirstFrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FirstFrameError::Locked => write!(f, "分镜已锁定"),
+            FirstFrameError::Unresolved(unresolved) => {
+                write!(f, "提示词中存在无法解析的占位符: {}", unresolved.join(", "))
+            }
+            FirstFrameError::Image(e) => write!(f, "{}", e),
+            FirstFrameError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+// 为单个分镜生成首帧图：保存图片、更新分镜并记录生成历史
+async fn render_first_frame(
+    pool: &sqlx::PgPool,
+    project: &Project,
+    characters: &[ProjectCharacter],
+    scene: &StoryboardScene,
+    provider: &dyn ImageProvider,
+    candidates: usize,
+    continuity: Option<bool>,
+) -> std::result::Result<FirstFrameResult, FirstFrameError> {
+    let project_id = project.id;
+    let scene_id = scene.id;
+    let output_format = OutputFormat::from_project(project);
+
+    if scene.is_locked() {
+        return Err(FirstFrameError::Locked);
+    }
+
+    // 获取该分镜的出场角色，只附带这些角色的参考图
+    let (cast, inferred) = resolve_scene_cast(pool, scene, characters)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    println!("🎨 开始生成首帧图: Scene ID {}", scene_id);
+    println!("   > 项目角色数量: {}", characters.len());
+    println!(
+        "   > 出场角色{}: {}",
+        if inferred { "(根据提示词推断)" } else { "" },
+        cast.iter().map(|c| c.name.as_str()).collect::<Vec<_>>().join(", ")
+    );
+
+    // 构建完整提示词：全局提示词 + 分镜提示词（展开角色和变量占位符）
+    let full_prompt = match build_scene_prompt(
+        project.global_image_prompt.as_deref(),
+        scene.first_frame_prompt.as_deref(),
+        &prompt_context(project, characters),
+    ) {
+        Ok(p) => p,
+        Err(unresolved) => return Err(FirstFrameError::Unresolved(unresolved)),
+    };
+
+    if full_prompt.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "首帧图提示词不能为空"
+        ).into());
+    }
+
+    println!("   > 完整提示词: {}", full_prompt);
+    let (model_prompt, translated_prompt) = prepare_model_prompt(pool, project, &full_prompt).await;
+
+    // 参考图
+    let mut references: Vec<ReferenceImage> = Vec::new();
+
+    // 根据出场角色数量决定使用拼接图还是单独传递
+    let character_count = cast.len();
+    
+    // 拼接图包含项目全部角色，只有全部角色都出场时才使用
+    if character_count > 3 && character_count == characters.len() {
+        // 超过 3 张角色图，使用拼接图
+        println!("   > 角色数量 {} > 3，使用拼接角色图", character_count);
+        
+        let final_image_url = if let Some(url) = project.combined_characters_image.clone() {
+            // 检查文件是否存在
+            let local_path = format!("./data/{}", url.trim_start_matches("/data/"));
+            if std::path::Path::new(&local_path).exists() {
+                println!("   > 使用现有拼接角色图: {}", url);
+                url
+            } else {
+                println!("   ⚠️  拼接图文件不存在: {}", url);
+                "".to_string() 
+            }
+        } else {
+            println!("   ⚠️  未找到拼接图，将使用单张角色图");
+            "".to_string()
+        };
+
+        if !final_image_url.is_empty() {
+            // 下载并编码拼接图
+            match download_and_encode_image(&final_image_url).await {
+                Ok((base64_data, mime_type)) => {
+                    references.push(ReferenceImage { mime_type, data: base64_data });
+                    println!("   ✅ 添加参考图(拼接图): {}", final_image_url);
+                }
+                Err(e) => {
+                    println!("   ⚠️  无法加载拼接图 {}: {}", final_image_url, e);
+                }
+            }
+        } else {
+            // 拼接图不存在，降级使用单张角色图
+            println!("   ⚠️  拼接图不可用，回退到使用单张角色图片");
+            for character in &cast {
+                match download_and_encode_image(&character.image_url).await {
+                    Ok((base64_data, mime_type)) => {
+                        references.push(ReferenceImage { mime_type, data: base64_data });
+                        println!("   ✅ 添加角色图: {}", character.name);
+                    }
+                    Err(e) => {
+                        println!("   ⚠️  无法加载角色图片 {}: {}", character.name, e);
+                    }
+                }
+            }
+        }
+    } else if character_count > 0 {
+        // 直接传递每个出场角色的图
+        println!("   > 出场角色数量 {}，直接传递每张角色图", character_count);
+        
+        for character in &cast {
+            match download_and_encode_image(&character.image_url).await {
+                Ok((base64_data, mime_type)) => {
+                    references.push(ReferenceImage { mime_type, data: base64_data });
+                    println!("   ✅ 添加角色图: {}", character.name);
+                }
+                Err(e) => {
+                    println!("   ⚠️  无法加载角色图片 {}: {}", character.name, e);
+                }
+            }
+        }
+    } else {
+        println!("   ℹ️  分镜无出场角色，将仅使用文本提示词生成");
+    }
+    let has_character_references = !references.is_empty();
+
+    // 连贯模式：上一分镜的画面放在最后一张参考图
+    let continuity_source = match find_continuity_reference(pool, project, scene, continuity).await? {
+        Some((source, reference)) => {
+            references.push(reference);
+            Some(source)
+        }
+        None => None,
+    };
+
+    // 添加文本提示词
+    let mut text_instruction = if has_character_references {
+        // 有角色参考图片，添加指令
+        format!("Based on this character reference image, generate an image for: {}", model_prompt)
+    } else {
+        // 没有角色参考图片，使用纯文本生成
+        model_prompt.clone()
+    };
+    if continuity_source.is_some() {
+        text_instruction.push_str(
+            "\n\nThe last reference image is the previous shot of the same scene. Keep the environment, \
+             lighting, color palette and set dressing consistent with it, but follow the description above \
+             for composition, camera angle and character poses instead of copying them."
+        );
+    }
+
+    // 按项目输出格式设置图片比例和尺寸
+    println!("   > 输出格式: {} / {}", output_format.aspect_ratio, output_format.resolution);
+
+    let request = ImageRequest {
+        prompt: text_instruction,
+        references,
+        mask: None,
+        count: candidates,
+        aspect_ratio: Some(output_format.aspect_ratio.clone()),
+        resolution: Some(output_format.resolution.clone()),
+    };
+
+    println!("   ⏳ 正在调用 {} ({})...", provider.name(), provider.model());
+    let images = provider.generate(&request).await?;
+    let images: Vec<Vec<u8>> = images.into_iter().take(candidates).collect();
+
+    let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S").to_string();
+    let project_dir = format!("data/projects/{}/first_frames", project_id);
+    fs::create_dir_all(&project_dir)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to create directory: {}", e)))?;
+
+    // 多个候选图归入同一批次
+    let candidate_batch_id = if candidates > 1 { Some(Uuid::new_v4()) } else { None };
+    let mut histories = Vec::with_capacity(images.len());
+
+    for (i, image_bytes) in images.into_iter().enumerate() {
+        let stem = if candidate_batch_id.is_some() {
+            format!("first_frame_{}_{}_{}", scene_id, timestamp, i + 1)
+        } else {
+            format!("first_frame_{}_{}", scene_id, timestamp)
+        };
+        let saved = save_processed_image(project, &project_dir, &stem, image_bytes).await?;
+
+        println!("   ✅ 首帧图已保存到本地: {}", saved.url);
+
+        // 历史记录：使用本地 URL（视频生成时会自动上传到 Cloudflare）
+        let history = GenerationHistory::create_candidate(
+            pool,
+            scene_id,
+            full_prompt.clone(),
+            translated_prompt.clone(),
+            saved.url,
+            saved.original_url,
+            candidate_batch_id,
+        )
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        histories.push(history);
+    }
+
+    let image_url = histories[0].result_url.clone();
+
+    // 单张图直接作为当前首帧图；多个候选图由用户选择
+    if candidate_batch_id.is_none() {
+        StoryboardScene::update_latest_image(pool, scene_id, image_url.clone())
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    }
+
+    println!("🎉 首帧图生成成功! ({} 张)", histories.len());
+
+    Ok(FirstFrameResult {
+        image_url,
+        prompt: full_prompt,
+        translated_prompt,
+        candidate_batch_id,
+        candidates: histories,
+        continuity_source,
+    })
+}
+
+// 批量生成首帧图请求
+#[derive(Debug, Deserialize)]
+struct BatchGenerateRequest {
+    #[serde(default = "default_batch_mode")]
+    mode: String,                 // "all"、"missing"（只生成没有首帧图的分镜）或 "scenes"
+    scene_ids: Option<Vec<i32>>,  // mode 为 "scenes" 时指定分镜
+    concurrency: Option<usize>,   // 并发数，默认取 BATCH_CONCURRENCY 环境变量（默认 3），项目开启连贯模式时固定为 1
+    provider: Option<String>,     // 图片生成服务，默认使用项目设置
+    model: Option<String>,
+}
+
+fn default_batch_mode() -> String {
+    "missing".to_string()
+}
+
+const MAX_BATCH_CONCURRENCY: usize = 10;
+
+// 批量生成首帧图：立即返回任务，后台按并发数逐个生成，通过批量任务接口查询进度
+async fn batch_generate_first_frames(
+    path: web::Path<String>,
+    req_body: web::Json<BatchGenerateRequest>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let project_id = Uuid::parse_str(&path.into_inner())
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+    let req = req_body.into_inner();
+
+    let Some(project) = Project::find_by_id(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+    else {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "项目不存在"
+        })));
+    };
+
+    let all_scenes = StoryboardScene::find_by_project_id(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    let scenes: Vec<StoryboardScene> = match req.mode.as_str() {
+        "all" => all_scenes,
+        "missing" => all_scenes
+            .into_iter()
+            .filter(|s| s.latest_image_url.as_deref().unwrap_or("").is_empty())
+            .collect(),
+        "scenes" => {
+            let scene_ids = req.scene_ids.unwrap_or_default();
+            let unknown: Vec<i32> = scene_ids
+                .iter()
+                .filter(|id| !all_scenes.iter().any(|s| s.id == **id))
+                .copied()
+                .collect();
+            if scene_ids.is_empty() || !unknown.is_empty() {
+                return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": "scene_ids 不能为空且必须属于该项目",
+                    "unknown_scene_ids": unknown
+                })));
+            }
+            all_scenes.into_iter().filter(|s| scene_ids.contains(&s.id)).collect()
+        }
+        other => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("无效的批量模式: {}，可选值: all, missing, scenes", other)
+            })));
+        }
+    };
+
+    let concurrency = req.concurrency
+        .or_else(|| std::env::var("BATCH_CONCURRENCY").ok().and_then(|s| s.parse().ok()))
+        .unwrap_or(3)
+        .clamp(1, MAX_BATCH_CONCURRENCY);
+
+    // 连贯模式下分镜要参考上一分镜的新首帧图，按分镜顺序逐个生成
+    let sequential = project.continuity_mode != "off";
+    let concurrency = if sequential { 1 } else { concurrency };
+
+    let provider = match image_provider_for(Some(&project), req.provider.as_deref(), req.model.as_deref()) {
+        Ok(provider) => provider,
+        Err(e) => return Ok(image_error_response(&e)),
+    };
+
+    // 锁定的分镜直接跳过
+    let items: Vec<BatchItem> = scenes
+        .iter()
+        .map(|s| if s.is_locked() { BatchItem::skipped(s.id, "分镜已锁定") } else { BatchItem::pending(s.id) })
+        .collect();
+
+    let batch = GenerationBatch::create(pool.as_ref(), project_id, "image", concurrency as i32, items)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    println!(
+        "🎨 批量生成首帧图: {} 个分镜（跳过 {} 个），并发 {}{}",
+        batch.total,
+        batch.skipped,
+        concurrency,
+        if sequential { "（连贯模式，按分镜顺序生成）" } else { "" }
+    );
+
+    let mut pending: Vec<StoryboardScene> = scenes.into_iter().filter(|s| !s.is_locked()).collect();
+    pending.sort_by_key(|s| s.scene_index);
+    if !pending.is_empty() {
+        let pool = pool.get_ref().clone();
+        let batch_id = batch.id;
+        let handle = actix_web::rt::spawn(run_first_frame_batch(
+            pool.clone(),
+            project,
+            provider,
+            batch_id,
+            batch.items.0.clone(),
+            pending.iter().map(|s| s.id).collect(),
+            concurrency,
+        ));
+
+        // 后台任务 panic 时标记批量任务失败，避免一直停留在 running
+        actix_web::rt::spawn(async move {
+            if let Err(e) = handle.await {
+                eprintln!("❌ 批量生成任务异常退出: {} - {}", batch_id, e);
+                if let Err(e) = GenerationBatch::mark_failed(&pool, batch_id, "批量任务异常中止").await {
+                    eprintln!("⚠️  更新批量任务状态失败: {}", e);
+                }
+            }
+        });
+    }
+
+    Ok(HttpResponse::Accepted().json(batch))
+}
+
+// 后台执行批量首帧图生成，每完成一个分镜写回一次进度
+async fn run_first_frame_batch(
+    pool: sqlx::PgPool,
+    project: Project,
+    provider: Box<dyn ImageProvider>,
+    batch_id: Uuid,
+    mut items: Vec<BatchItem>,
+    scene_ids: Vec<i32>,
+    concurrency: usize,
+) {
+    let characters = match ProjectCharacter::get_all_for_project(&pool, project.id).await {
+        Ok(characters) => characters,
+        Err(e) => {
+            eprintln!("❌ 批量生成失败，无法读取项目角色: {}", e);
+            for item in items.iter_mut().filter(|i| i.status == "pending") {
+                item.status = "failed".to_string();
+                item.error = Some(e.to_string());
+            }
+            let _ = GenerationBatch::update_items(&pool, batch_id, &items).await;
+            return;
+        }
+    };
+
+    // 各分镜并发更新同一份进度，写库时持有锁保证后写入的总是最新状态
+    let items = tokio::sync::Mutex::new(items);
+    let items_ref = &items;
+    let pool_ref = &pool;
+    let project_ref = &project;
+    let characters_ref = &characters;
+    let provider_ref = provider.as_ref();
+    let mut results = futures_util::stream::iter(scene_ids)
+        .map(|scene_id| async move {
+            // 开始生成前重新读取分镜，提交任务后被锁定、修改或删除的分镜以最新状态为准
+            let scene = match StoryboardScene::find_by_id(pool_ref, scene_id).await {
+                Ok(Some(scene)) if scene.project_id == project_ref.id => scene,
+                Ok(_) => {
+                    let e = std::io::Error::new(std::io::ErrorKind::NotFound, "分镜已删除");
+                    return (scene_id, Err(FirstFrameError::Failed(e)));
+                }
+                Err(e) => {
+                    let e = std::io::Error::new(std::io::ErrorKind::Other, e);
+                    return (scene_id, Err(FirstFrameError::Failed(e)));
+                }
+            };
+            if scene.is_locked() {
+                return (scene_id, Err(FirstFrameError::Locked));
+            }
+
+            update_batch_item(pool_ref, batch_id, items_ref, scene_id, |item| {
+                item.status = "running".to_string();
+            })
+            .await;
+            let result = render_first_frame(pool_ref, project_ref, characters_ref, &scene, provider_ref, 1, None).await;
+            (scene_id, result)
+        })
+        .buffer_unordered(concurrency);
+
+    while let Some((scene_id, result)) = results.next().await {
+        update_batch_item(pool_ref, batch_id, items_ref, scene_id, |item| match result {
+            Ok(r) => {
+                item.status = "succeeded".to_string();
+                item.result_url = Some(r.image_url);
+            }
+            Err(FirstFrameError::Locked) => {
+                item.status = "skipped".to_string();
+                item.error = Some(FirstFrameError::Locked.to_string());
+            }
+            Err(e) => {
+                eprintln!("❌ 分镜 {} 首帧图生成失败: {}", scene_id, e);
+                item.status = "failed".to_string();
+                item.error = Some(e.to_string());
+            }
+        })
+        .await;
+    }
+
+    println!("✅ 批量生成首帧图完成: {}", batch_id);
+}
+
+// 修改批量任务中某个分镜的状态并保存进度
+async fn update_batch_item(
+    pool: &sqlx::PgPool,
+    batch_id: Uuid,
+    items: &tokio::sync::Mutex<Vec<BatchItem>>,
+    scene_id: i32,
+    update: impl FnOnce(&mut BatchItem),
+) {
+    let mut items = items.lock().await;
+    if let Some(item) = items.iter_mut().find(|i| i.scene_id == scene_id) {
+        update(item);
+    }
+    if let Err(e) = GenerationBatch::update_items(pool, batch_id, &items).await {
+        eprintln!("⚠️  更新批量任务进度失败: {}", e);
+    }
+}
+
+// 查询批量任务进度
+async fn get_generation_batch(
+    path: web::Path<(String, String)>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let (project_id_str, batch_id_str) = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+    let batch_id = Uuid::parse_str(&batch_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid batch ID"))?;
+
+    match GenerationBatch::find_by_id(pool.as_ref(), batch_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+    {
+        Some(batch) if batch.project_id == project_id => Ok(HttpResponse::Ok().json(batch)),
+        _ => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "批量任务不存在"
+        }))),
+    }
+}
+
+// 项目最近的批量任务
+async fn get_generation_batches(
+    path: web::Path<String>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let project_id = Uuid::parse_str(&path.into_inner())
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+
+    let batches = GenerationBatch::find_by_project_id(pool.as_ref(), project_id, 20)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    Ok(HttpResponse::Ok().json(batches))
+}
+
+// 辅助函数：下载并编码图片为 base64
+async fn download_and_encode_image(image_url: &str) -> Result<(String, String)> {
+    let image_bytes = load_image_bytes(image_url).await?;
+
+    // 编码为 base64
+    let base64_data = base64::engine::general_purpose::STANDARD.encode(&image_bytes);
+
+    // 判断 MIME 类型（简单根据URL后缀判断）
+    let mime_type = if image_url.ends_with(".png") {
+        "image/png"
+    } else if image_url.ends_with(".jpg") || image_url.ends_with(".jpeg") {
+        "image/jpeg"
+    } else if image_url.ends_with(".webp") {
+        "image/webp"
+    } else {
+        "image/png"  // 默认
+    };
+
+    Ok((base64_data, mime_type.to_string()))
+}
+
+// 读取图片内容（本地 /data 路径或外部URL）
+async fn load_image_bytes(image_url: &str) -> Result<Vec<u8>> {
+    // 判断是本地路径还是外部URL
+    let image_bytes = if image_url.starts_with("http://") || image_url.starts_with("https://") {
+        // 外部URL，下载
+        let client = Client::new();
+        let response = client.get(image_url)
+            .send()
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to download image: {}", e)))?;
+        
+        response.bytes()
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to read image bytes: {}", e)))?
+            .to_vec()
+    } else {
+        // 本地路径（例如 /uploads/xxx.png）
+        let local_path = if image_url.starts_with("/data/") {
+            format!(".{}", image_url)  // ./uploads/xxx.png
+        } else {
+            image_url.to_string()
+        };
+        
+        fs::read(&local_path)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to read local file: {}", e)))?
+    };
+
+    Ok(image_bytes)
+}
+
+// 生成分镜视频（使用VEO API）
+async fn generate_storyboard_video(
+    path: web::Path<(String, i32)>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let (project_id_str, scene_id) = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|e| {
+            eprintln!("❌ 无效的项目ID: {} - {}", project_id_str, e);
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Invalid project ID: {}", e))
+        })?;
+
+    println!("🎥 开始生成分镜视频: Project ID {}, Scene ID {}", project_id, scene_id);
+
+    // Fetch Project (Global Prompt)
+    let project = Project::find_by_id(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| {
+            eprintln!("❌ 查询项目失败: {}", e);
+            std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to find project: {}", e))
+        })?
+        .ok_or_else(|| {
+            eprintln!("❌ 项目不存在: {}", project_id);
+            std::io::Error::new(std::io::ErrorKind::NotFound, "Project not found")
+        })?;
+
+    // Fetch All Characters
+    let characters = ProjectCharacter::get_all_for_project(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| {
+            eprintln!("❌ 查询角色失败: {}", e);
+            std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to get characters: {}", e))
+        })?;
+
+    // 查询分镜信息
+    let scene = StoryboardScene::find_by_id(pool.as_ref(), scene_id)
+        .await
+        .map_err(|e| {
+            eprintln!("❌ 查询分镜失败: {}", e);
+            std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to find scene: {}", e))
+        })?
+        .ok_or_else(|| {
+            eprintln!("❌ 分镜不存在: {}", scene_id);
+            std::io::Error::new(std::io::ErrorKind::NotFound, "Scene not found")
+        })?;
+
+    if scene.is_locked() {
+        return Ok(scene_locked_response(scene_id));
+    }
+
+    println!("   > 项目角色数量: {}", characters.len());
+    println!("   > 首帧图: {:?}", scene.latest_image_url);
+    println!("   > 视频提示词: {:?}", scene.video_prompt);
+    println!("   > 分镜时长: {:?}秒", scene.duration);
+
+    // 构建完整提示词：全局提示词 + 分镜提示词（展开角色和变量占位符）
+    let full_prompt = match build_scene_prompt(
+        project.global_video_prompt.as_deref(),
+        scene.video_prompt.as_deref(),
+        &prompt_context(&project, &characters),
+    ) {
+        Ok(p) => p,
+        Err(unresolved) => return Ok(unresolved_placeholders_response(unresolved)),
+    };
+
+    if full_prompt.is_empty() {
+        eprintln!("❌ 视频提示词为空");
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "视频提示词不能为空"
+        })));
+    }
+    
+    // 不再添加图片使用指示，只传首帧图
+    // let has_characters = !characters.is_empty();
+    // if has_characters {
+    //     full_prompt.push_str(". ");
+    //     full_prompt.push_str("重要提示：第1张参考图片是场景的首帧构图，请严格按照该图的场景、构图和氛围生成视频。");
+    //     full_prompt.push_str("第2张参考图片是角色外观参考，仅用于了解角色的服装、发型和特征，不要在视频中直接显示该图片内容。");
+    // }
+
+    println!("   > 完整提示词: {}", full_prompt);
+    let (model_prompt, translated_prompt) = prepare_model_prompt(pool.as_ref(), &project, &full_prompt).await;
+
+    // 获取 VEO API Key
+    let veo_api_key = std::env::var("VEO_API_KEY")
+        .map_err(|e| {
+            eprintln!("❌ VEO_API_KEY 环境变量未设置: {}", e);
+            std::io::Error::new(std::io::ErrorKind::Other, "VEO_API_KEY not set")
+        })?;
+    
+    let client = Client::new();
+
+    // 创建 Cloudflare 存储客户端
+    let cloudflare_storage = cloudflare::CloudflareStorage::new()
+        .await
+        .map_err(|e| {
+            eprintln!("❌ Cloudflare 存储初始化失败: {}", e);
+            std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to create Cloudflare storage: {}", e))
+        })?;
+
+    // 验证必须有首帧图
+    if scene.latest_image_url.is_none() || scene.latest_image_url.as_ref().unwrap().is_empty() {
+        eprintln!("❌ 没有首帧图，无法生成视频");
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "请先生成首帧图，再生成分镜视频"
+        })));
+    }
+
+    // 收集需要上传的图片URL
+    let mut image_urls: Vec<String> = Vec::new();
+    
+    // 添加首帧图
+    if let Some(ref first_frame_url) = scene.latest_image_url {
+        if first_frame_url.starts_with("http") {
+            // 已经是公网URL（R2链接），直接使用
+            image_urls.push(first_frame_url.clone());
+            println!("   ✅ 使用已缓存的 R2 链接: {}", first_frame_url);
+        } else if first_frame_url.starts_with("/data/") {
+            // 本地图片，需要上传到 Cloudflare
+            let local_path = format!(".{}", first_frame_url);
+            println!("   > 首帧图为本地路径，正在上传到 Cloudflare: {}", first_frame_url);
+            
+            match cloudflare_storage.get_or_upload(pool.as_ref(), &local_path, "image").await {
+                Ok(cdn_url) => {
+                    image_urls.push(cdn_url.clone());
+                    println!("   ✅ 首帧图上传成功: {}", cdn_url);
+                    
+                    // 重要：将 R2 链接更新到数据库，下次可以直接使用
+                    match StoryboardScene::update_latest_image(pool.as_ref(), scene_id, cdn_url.clone()).await {
+                        Ok(_) => {
+                            println!("   📝 已更新数据库为 R2 链接，下次将直接使用缓存");
+                        }
+                        Err(e) => {
+                            eprintln!("   ⚠️  更新数据库失败: {}，但不影响视频生成", e);
+                        }
+                    }
+                }
+                Err(e) => {
+                    eprintln!("   ❌ 首帧图上传失败: {}", e);
+                    // 继续执行，使用空图片列表
+                }
+            }
+        }
+    }
+
+
+    // 注释：VEO API 只使用首帧图，角色图已经在首帧图生成时使用过了
+    // 不再需要拼接角色图传递给 VEO
+
+    if image_urls.is_empty() {
+        eprintln!("   ⚠️  警告：没有可用的公网图片，将使用纯文本生成视频");
+    }
+
+
+    // 调用 VEO API 创建视频
+    // 获取视频时长并转换为整数（1-8范围）
+    let video_duration_float = scene.duration.unwrap_or(8.0);
+    let video_duration = video_duration_float.round().max(1.0).min(8.0) as i32;
+    println!("   > 使用视频时长: {}秒", video_duration);
+    
+    // 从环境变量读取 VEO 配置
+    let veo_model = std::env::var("VEO_MODEL")
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "VEO_MODEL not set"))?;
+    let veo_base_url = std::env::var("VEO_BASE_URL")
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "VEO_BASE_URL not set"))?;
+    let veo_create_endpoint = std::env::var("VEO_CREATE_ENDPOINT")
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "VEO_CREATE_ENDPOINT not set"))?;
+    
+    // 按项目输出格式设置视频比例，720p 不需要放大
+    // 创建接口只提供 aspect_ratio 和 enable_upsample 参数，分辨率通过是否放大控制，帧率由服务决定
+    let output_format = OutputFormat::from_project(&project);
+    println!("   > 输出格式: {} / {}", output_format.aspect_ratio, output_format.resolution);
+
+    let create_payload = serde_json::json!({
+        "prompt": model_prompt,
+        "model": veo_model,
+        "images": image_urls,
+        "duration": video_duration,
+        "enhance_prompt": true,
+        "enable_upsample": output_format.resolution != "720p",
+        "aspect_ratio": output_format.aspect_ratio
+    });
+
+    println!("   ⏳ 正在调用 VEO API 创建视频...");
+    
+    let create_url = format!("{}{}", veo_base_url, veo_create_endpoint);
+    let create_res = client.post(&create_url)
+        .header("Content-Type", "application/json")
+        .header("Accept", "application/json")
+        .header("Authorization", format!("Bearer {}", veo_api_key))
+        .json(&create_payload)
+        .timeout(std::time::Duration::from_secs(30))
+        .send()
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to call VEO API: {}", e)))?;
+
+    if !create_res.status().is_success() {
+        let error_text = create_res.text().await.unwrap_or_default();
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            format!("VEO API error: {}", error_text)
+        ).into());
+    }
+
+    let create_result: serde_json::Value = create_res.json().await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to parse response: {}", e)))?;
+
+    // 提取 video_id 和状态
+    let video_id = create_result["id"].as_str()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Other, "No video ID in response"))?
+        .to_string();
+    
+    let status = create_result["status"].as_str().unwrap_or("unknown").to_string();
+    let progress = create_result["progress"].as_i64().unwrap_or(0);
+    
+    // 保存本次实际使用的提示词，轮询完成时写入历史
+    VideoTask::create(pool.as_ref(), &video_id, scene_id, &full_prompt, translated_prompt.as_deref())
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    println!("   ✅ 视频创建请求已提交");
+    println!("   📹 Video ID: {}", video_id);
+    println!("   📊 初始状态: {} ({}%)", status, progress);
+
+    // 返回video_id和状态，让前端轮询
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "video_id": video_id,
+        "status": status,
+        "progress": progress,
+        "translated_prompt": translated_prompt,
+        "message": "视频生成已启动，请通过轮询接口查询进度"
+    })))
+}
+
+// 已移除 upload_image_to_github 函数，改用 Cloudflare R2 存储
+
+// VEO 查询接口配置
+// 未通过 app_data 注入时读取环境变量（测试中注入模拟服务地址，避免修改全局环境变量）
+#[derive(Debug, Clone)]
+struct VeoQueryConfig {
+    base_url: String,
+    query_endpoint: String,
+    api_key: String,
+}
+
+impl VeoQueryConfig {
+    fn from_env() -> std::io::Result<Self> {
+        let env = |name: &str| {
+            std::env::var(name)
+                .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, format!("{} not set", name)))
+        };
+        Ok(VeoQueryConfig {
+            base_url: env("VEO_BASE_URL")?,
+            query_endpoint: env("VEO_QUERY_ENDPOINT")?,
+            api_key: env("VEO_API_KEY")?,
+        })
+    }
+}
+
+// 轮询查询视频生成状态
+async fn poll_video_status(
+    path: web::Path<(String, i32, String)>,
+    pool: web::Data<sqlx::PgPool>,
+    veo_config: Option<web::Data<VeoQueryConfig>>,
+) -> Result<HttpResponse> {
+    let (project_id_str, scene_id, video_id) = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Invalid project ID: {}", e)))?;
+
+    println!("📡 轮询视频状态: Video ID {}, Scene ID {}", video_id, scene_id);
+
+    if find_project_scene(pool.as_ref(), project_id, scene_id).await?.is_none() {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "分镜不存在"
+        })));
+    }
+    // 任务记录中的分镜必须与路径一致，避免把视频写到其他分镜
+    let task = VideoTask::find(pool.as_ref(), &video_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    if task.as_ref().is_some_and(|t| t.scene_id != scene_id) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "视频任务不属于该分镜"
+        })));
+    }
+
+    let veo_config = match veo_config {
+        Some(config) => config.get_ref().clone(),
+        None => VeoQueryConfig::from_env()?,
+    };
+    let client = Client::new();
+
+    // 查询 VEO API 获取视频状态
+    let query_url = format!("{}{}?id={}", veo_config.base_url, veo_config.query_endpoint, video_id);
+    let query_res = client.get(&query_url)
+        .header("Accept", "application/json")
+        .header("Authorization", format!("Bearer {}", veo_config.api_key))
+        .send()
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to query video status: {}", e)))?;
+
+    if !query_res.status().is_success() {
+        let error_text = query_res.text().await.unwrap_or_default();
+        return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("VEO API error: {}", error_text)
+        })));
+    }
+
+    let query_result: serde_json::Value = query_res.json().await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to parse query response: {}", e)))?;
+
+    let status = query_result["status"].as_str().unwrap_or("unknown");
+    let progress = query_result["progress"].as_i64().unwrap_or(0);
+    
+    println!("   > 当前状态: {} ({}%)", status, progress);
+
+    // 如果状态是 completed，下载视频并保存
+    if status == "completed" {
+        let video_url = query_result["video_url"].as_str()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Other, "Video completed but no URL in response"))?;
+
+        println!("   📥 开始下载视频: {}", video_url);
+
+        // 下载视频
+        let video_response = client.get(video_url)
+            .send()
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to download video: {}", e)))?;
+
+        let video_bytes = video_response.bytes()
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to read video bytes: {}", e)))?;
+
+        // 保存视频
+        let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S").to_string();
+        let filename = format!("video_{}_{}.mp4", scene_id, timestamp);
+        let project_dir = format!("data/projects/{}/videos", project_id);
+        fs::create_dir_all(&project_dir)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to create directory: {}", e)))?;
+        let filepath = format!("{}/{}", project_dir, filename);
+        let local_video_url = format!("/data/projects/{}/videos/{}", project_id, filename);
+
+        let mut file = File::create(&filepath)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to create file: {}", e)))?;
+        file.write_all(&video_bytes)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to write file: {}", e)))?;
+
+        println!("   ✅ 视频已保存: {}", local_video_url);
+
+        // 更新数据库
+        StoryboardScene::update_latest_video(pool.as_ref(), scene_id, local_video_url.clone())
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+        // 使用创建任务时保存的提示词和翻译（提示词或翻译模式之后可能已修改）
+        let (full_prompt, translated_prompt) = match task {
+            Some(task) => (task.prompt, task.translated_prompt),
+            None => (rebuild_video_prompt(pool.as_ref(), project_id, scene_id).await?, None),
+        };
+
+        // 记录历史
+        GenerationHistory::create_with_translation(
+            pool.as_ref(),
+            scene_id,
+            "video".to_string(),
+            full_prompt,
+            translated_prompt,
+            local_video_url.clone(),
+        )
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        VideoTask::delete(pool.as_ref(), &video_id)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+        println!("🎬 分镜视频生成完成: Scene ID {}", scene_id);
+
+        // 返回完成状态和视频URL
+        return Ok(HttpResponse::Ok().json(serde_json::json!({
+            "status": status,
+            "progress": progress,
+            "video_url": local_video_url,
+            "message": "视频生成完成"
+        })));
+    }
+
+    // 返回当前状态（queued, processing, failed等）
+    let response = serde_json::json!({
+        "status": status,
+        "progress": progress,
+    });
+
+    // 如果失败，包含错误信息
+    if status == "failed" || status == "error" {
+        let error_msg = query_result["error"].as_str().unwrap_or("Unknown error");
+        VideoTask::delete(pool.as_ref(), &video_id)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        return Ok(HttpResponse::Ok().json(serde_json::json!({
+            "status": status,
+            "progress": progress,
+            "error": error_msg
+        })));
+    }
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+// 没有任务记录时（旧任务）按当前分镜和项目重新构建视频提示词
+async fn rebuild_video_prompt(pool: &sqlx::PgPool, project_id: Uuid, scene_id: i32) -> std::io::Result<String> {
+    // 查询分镜信息以获取提示词
+    let scene = StoryboardScene::find_by_id(pool, scene_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Scene not found"))?;
+
+    // 查询项目信息以获取全局提示词
+    let project = Project::find_by_id(pool, project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Project not found"))?;
+
+    // 构建完整提示词（占位符在创建任务时已校验，这里解析失败时保留原文）
+    let characters = ProjectCharacter::get_all_for_project(pool, project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let full_prompt = build_scene_prompt(
+        project.global_video_prompt.as_deref(),
+        scene.video_prompt.as_deref(),
+        &prompt_context(&project, &characters),
+    )
+    .unwrap_or_else(|_| {
+        let mut raw = String::new();
+        if let Some(global_prompt) = project.global_video_prompt.as_deref().filter(|g| !g.is_empty()) {
+            raw.push_str(global_prompt);
+            raw.push_str(". ");
+        }
+        raw.push_str(scene.video_prompt.as_deref().unwrap_or_default());
+        raw
+    });
+
+    Ok(full_prompt)
+}
+
+// 拼接角色图片
+async fn stitch_character_images(
+    path: web::Path<String>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    use image::{ImageBuffer, Rgba, DynamicImage, imageops};
+    
+    let project_id_str = path.into_inner();
+    let project_id = match Uuid::parse_str(&project_id_str) {
+        Ok(id) => id,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("无效的项目ID: {}", e)
+            })));
+        }
+    };
+    
+    println!("🎨 开始拼接角色图片: Project ID {}", project_id);
+    
+    // 获取项目的所有角色
+    let characters = match ProjectCharacter::get_all_for_project(pool.as_ref(), project_id).await {
+        Ok(chars) => chars,
+        Err(e) => {
+            eprintln!("❌ 获取角色失败: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("获取角色失败: {}", e)
+            })));
+        }
+    };
+    
+    if characters.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "项目没有角色，无法拼接图片"
+        })));
+    }
+    
+    println!("   > 角色数量: {}", characters.len());
+    
+    // 加载所有角色图片
+    let mut images: Vec<DynamicImage> = Vec::new();
+    let client = Client::new();
+    
+    for character in &characters {
+        let local_path = if character.image_url.starts_with("/data/") {
+            format!(".{}", character.image_url)
+        } else if character.image_url.starts_with("http") {
+            // 如果是远程URL，需要下载
+            let response = match client.get(&character.image_url).send().await {
+                Ok(r) => r,
+                Err(e) => {
+                    eprintln!("❌ 下载图片失败: {}", e);
+                    return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                        "error": format!("下载图片失败: {}", e)
+                    })));
+                }
+            };
+            
+            let bytes = match response.bytes().await {
+                Ok(b) => b,
+                Err(e) => {
+                    eprintln!("❌ 读取图片失败: {}", e);
+                    return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                        "error": format!("读取图片失败: {}", e)
+                    })));
+                }
+            };
+            
+            // 创建临时文件
+            let temp_path = format!("data/projects/{}/temp_{}.jpg", project_id, character.id);
+            if let Err(e) = fs::create_dir_all(format!("data/projects/{}", project_id)) {
+                eprintln!("❌ 创建目录失败: {}", e);
+                return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": format!("创建目录失败: {}", e)
+                })));
+            }
+            if let Err(e) = fs::write(&temp_path, bytes) {
+                eprintln!("❌ 保存临时文件失败: {}", e);
+                return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": format!("保存临时文件失败: {}", e)
+                })));
+            }
+            temp_path
+        } else {
+            character.image_url.clone()
+        };
+        
+        println!("   > 加载图片: {} - {}", character.name, local_path);
+        
+        // 使用自动格式检测来加载图片，不依赖扩展名
+        let img = match image::io::Reader::open(&local_path) {
+            Ok(reader) => {
+                match reader.with_guessed_format() {
+                    Ok(reader_with_format) => {
+                        match reader_with_format.decode() {
+                            Ok(i) => i,
+                            Err(e) => {
+                                eprintln!("❌ 解码图片失败");
+                                eprintln!("   角色: {}", character.name);
+                                eprintln!("   路径: {}", local_path);
+                                eprintln!("   原始URL: {}", character.image_url);
+                                eprintln!("   错误: {}", e);
+                                return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                                    "error": format!("解码图片失败 (角色: {}): {}", character.name, e)
+                                })));
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("❌ 识别图片格式失败");
+                        eprintln!("   角色: {}", character.name);
+                        eprintln!("   路径: {}", local_path);
+                        eprintln!("   原始URL: {}", character.image_url);
+                        eprintln!("   错误: {}", e);
+                        return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                            "error": format!("识别图片格式失败 (角色: {}): {}", character.name, e)
+                        })));
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("❌ 打开图片文件失败");
+                eprintln!("   角色: {}", character.name);
+                eprintln!("   路径: {}", local_path);
+                eprintln!("   原始URL: {}", character.image_url);
+                eprintln!("   错误: {}", e);
+                return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": format!("打开图片文件失败 (角色: {}): {}", character.name, e)
+                })));
+            }
+        };
+        images.push(img);
+    }
+    
+    // 等比例缩放布局算法
+    let count = images.len();
+    let max_width = 768u32; // 最大宽度
+    
+    // 等比例缩放所有图片，宽度统一为768px，高度按比例调整
+    let mut resized_images: Vec<(DynamicImage, u32, u32)> = Vec::new(); // (图片, 宽度, 高度)
+    
+    for img in &images {
+        let (orig_width, orig_height) = (img.width(), img.height());
+        
+        // 计算缩放后的高度（保持比例）
+        let scale = max_width as f64 / orig_width as f64;
+        let new_height = (orig_height as f64 * scale) as u32;
+        
+        // 使用resize_exact确保缩放到精确尺寸
+        let resized = img.resize_exact(max_width, new_height, imageops::FilterType::Lanczos3);
+        resized_images.push((resized, max_width, new_height));
+    }
+    
+    // 计算布局 - 自适应网格算法
+    // 目标：使最终画板的长宽比尽可能接近 1:1 (正方形)
+    
+    let avg_height: f64 = resized_images.iter().map(|(_, _, h)| *h as f64).sum::<f64>() / count as f64;
+    let mut best_cols = 1;
+    let mut best_score = f64::MAX; // Score越接近1越好 (max(ratio, 1/ratio))
+    
+    // 遍历所有可能的列数配置 (1 到 count)
+    for cols in 1..=count {
+        let rows = (count as f64 / cols as f64).ceil();
+        
+        let est_width = cols as f64 * max_width as f64;
+        let est_height = rows * avg_height;
+        
+        let aspect_ratio = est_width / est_height;
+        
+        // 计算偏离度 score >= 1.0
+        let score = if aspect_ratio >= 1.0 {
+            aspect_ratio
+        } else {
+            1.0 / aspect_ratio
+        };
+        
+        // 倾向于更少的行数（如果接近），或者严格按照score
+        // 这里简单比较score
         if score < best_score {
             best_score = score;
             best_cols = cols;
         }
     }
 
-    let max_images_per_row = best_cols;
-    
-    // 计算需要多少行以及每行的高度
-    let rows_needed = ((count as f64) / (max_images_per_row as f64)).ceil() as usize;
-    
-    let mut row_heights: Vec<u32> = Vec::new();
-    for row_idx in 0..rows_needed {
-        let start_idx = row_idx * max_images_per_row;
-        let end_idx = ((row_idx + 1) * max_images_per_row).min(count);
-        
-        // 这一行的最大高度
-        let max_height = resized_images[start_idx..end_idx]
-            .iter()
-            .map(|(_, _, h)| *h)
-            .max()
-            .unwrap_or(max_width); // fallback, shouldn't happen
-        
-        row_heights.push(max_height);
-    }
-    
-    let canvas_width = (max_images_per_row as u32) * max_width;
-    let canvas_height: u32 = row_heights.iter().sum();
-    
-    println!("   > 布局优化: 选中 {} 列 (Score: {:.2})", max_images_per_row, best_score);
-    println!("   > 最终布局: {} 行 × {} 列", rows_needed, max_images_per_row);
-    println!("   > 画布尺寸: {}x{}", canvas_width, canvas_height);
-    
-    // 创建白色背景的画布
-    let mut canvas: ImageBuffer<Rgba<u8>, Vec<u8>> = ImageBuffer::new(canvas_width, canvas_height);
-    
-    // 填充白色背景
-    for pixel in canvas.pixels_mut() {
-        *pixel = Rgba([255, 255, 255, 255]); // 白色，完全不透明
+    let max_images_per_row = best_cols;
+    
+    // 计算需要多少行以及每行的高度
+    let rows_needed = ((count as f64) / (max_images_per_row as f64)).ceil() as usize;
+    
+    let mut row_heights: Vec<u32> = Vec::new();
+    for row_idx in 0..rows_needed {
+        let start_idx = row_idx * max_images_per_row;
+        let end_idx = ((row_idx + 1) * max_images_per_row).min(count);
+        
+        // 这一行的最大高度
+        let max_height = resized_images[start_idx..end_idx]
+            .iter()
+            .map(|(_, _, h)| *h)
+            .max()
+            .unwrap_or(max_width); // fallback, shouldn't happen
+        
+        row_heights.push(max_height);
+    }
+    
+    let canvas_width = (max_images_per_row as u32) * max_width;
+    let canvas_height: u32 = row_heights.iter().sum();
+    
+    println!("   > 布局优化: 选中 {} 列 (Score: {:.2})", max_images_per_row, best_score);
+    println!("   > 最终布局: {} 行 × {} 列", rows_needed, max_images_per_row);
+    println!("   > 画布尺寸: {}x{}", canvas_width, canvas_height);
+    
+    // 创建白色背景的画布
+    let mut canvas: ImageBuffer<Rgba<u8>, Vec<u8>> = ImageBuffer::new(canvas_width, canvas_height);
+    
+    // 填充白色背景
+    for pixel in canvas.pixels_mut() {
+        *pixel = Rgba([255, 255, 255, 255]); // 白色，完全不透明
+    }
+    
+    // 拼接图片
+    let mut img_idx = 0;
+    let mut y_offset = 0u32;
+    
+    for row_idx in 0..rows_needed {
+        let row_height = row_heights[row_idx];
+        let images_in_row = if row_idx == rows_needed - 1 {
+            count - (row_idx * max_images_per_row)
+        } else {
+            max_images_per_row
+        };
+        
+        for col in 0..images_in_row {
+            let (ref img, width, height) = resized_images[img_idx];
+            let rgba_img = img.to_rgba8();
+            
+            let x_offset = (col as u32) * max_width;
+            
+            // 垂直居中对齐
+            let vertical_padding = (row_height - height) / 2;
+            let final_y_offset = y_offset + vertical_padding;
+            
+            // 将图片复制到画布
+            for y in 0..height {
+                for x in 0..width {
+                    if x_offset + x < canvas_width && final_y_offset + y < canvas_height {
+                        canvas.put_pixel(x_offset + x, final_y_offset + y, *rgba_img.get_pixel(x, y));
+                    }
+                }
+            }
+            
+            println!("   > 拼接图片 {}: 位置 ({}, {}), 尺寸 {}x{}", img_idx + 1, x_offset, final_y_offset, width, height);
+            img_idx += 1;
+        }
+        
+        y_offset += row_height;
+    }
+    
+    let final_image = DynamicImage::ImageRgba8(canvas);
+    
+    // 保存拼接后的图片
+    let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S").to_string();
+    let filename = format!("combined_characters_{}.jpg", timestamp);
+    let project_dir = format!("data/projects/{}", project_id);
+    if let Err(e) = fs::create_dir_all(&project_dir) {
+        eprintln!("❌ 创建目录失败: {}", e);
+        return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("创建目录失败: {}", e)
+        })));
+    }
+    let filepath = format!("{}/{}", project_dir, filename);
+    let image_url = format!("/data/projects/{}/{}", project_id, filename);
+    
+    if let Err(e) = final_image.save(&filepath) {
+        eprintln!("❌ 保存图片失败: {}", e);
+        return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("保存图片失败: {}", e)
+        })));
+    }
+    
+    println!("   ✅ 拼接图片已保存: {}", image_url);
+    
+    // 更新项目的 combined_characters_image 字段
+    if let Err(e) = sqlx::query(
+        "UPDATE projects SET combined_characters_image = $1, updated_at = NOW() WHERE id = $2"
+    )
+    .bind(&image_url)
+    .bind(project_id)
+    .execute(pool.as_ref())
+    .await {
+        eprintln!("❌ 更新数据库失败: {}", e);
+        return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("更新数据库失败: {}", e)
+        })));
+    }
+    
+    println!("🎉 角色图片拼接成功!");
+    
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "角色图片拼接成功",
+        "image_url": image_url,
+    })))
+}
+
+// 删除拼接的角色图片
+async fn delete_combined_characters_image(
+    path: web::Path<String>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let project_id_str = path.into_inner();
+    let project_id = match Uuid::parse_str(&project_id_str) {
+        Ok(id) => id,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("无效的项目ID: {}", e)
+            })));
+        }
+    };
+    
+    // 获取项目信息
+    let project = match Project::find_by_id(pool.as_ref(), project_id).await {
+        Ok(Some(p)) => p,
+        Ok(None) => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "项目不存在"
+            })));
+        }
+        Err(e) => {
+            eprintln!("❌ 查询项目失败: {}", e);
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("查询项目失败: {}", e)
+            })));
+        }
+    };
+    
+    // 删除文件（如果存在）
+    if let Some(ref image_url) = project.combined_characters_image {
+        if image_url.starts_with("/data/") {
+            let local_path = format!(".{}", image_url);
+            if Path::new(&local_path).exists() {
+                fs::remove_file(&local_path)
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to delete file: {}", e)))?;
+                println!("   🗑️  已删除文件: {}", local_path);
+            }
+        }
+    }
+    
+    // 更新数据库，将字段设为 NULL
+    sqlx::query(
+        "UPDATE projects SET combined_characters_image = NULL, updated_at = NOW() WHERE id = $1"
+    )
+    .bind(project_id)
+    .execute(pool.as_ref())
+    .await
+    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to update project: {}", e)))?;
+    
+    println!("✅ 拼接图片已删除");
+    
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "拼接图片已删除"
+    })))
+}
+
+
+
+
+
+// 获取项目所有历史记录（画布模式）
+async fn get_project_history(
+    path: web::Path<String>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let project_id_str = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+
+    let history = GenerationHistory::find_by_project_id(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    Ok(HttpResponse::Ok().json(history))
+}
+
+// 获取历史记录
+async fn get_generation_history(
+    path: web::Path<(String, i32)>,
+    query: web::Query<std::collections::HashMap<String, String>>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let (_project_id_str, scene_id) = path.into_inner();
+    let generation_type = query.get("type");
+
+    let history = if let Some(gen_type) = generation_type {
+        GenerationHistory::find_by_scene_and_type(pool.as_ref(), scene_id, gen_type)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+    } else {
+        GenerationHistory::find_by_scene_id(pool.as_ref(), scene_id)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+    };
+
+    Ok(HttpResponse::Ok().json(history))
+}
+
+// 删除历史记录
+async fn delete_generation_history(
+    path: web::Path<(String, i32, i32)>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let (_project_id, _scene_id, history_id) = path.into_inner();
+
+    GenerationHistory::delete(pool.as_ref(), history_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "History deleted"
+    })))
+}
+
+// 更新历史记录的创建时间（设置为最新）
+async fn update_generation_history_time(
+    path: web::Path<(String, i32, i32)>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let (_project_id, _scene_id, history_id) = path.into_inner();
+
+    let updated_history = GenerationHistory::update_created_at(pool.as_ref(), history_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "History time updated",
+        "history": updated_history
+    })))
+}
+
+// 获取同一批次的候选图
+async fn get_image_candidates(
+    path: web::Path<(String, i32, String)>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let (project_id_str, scene_id, batch_id_str) = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+    let candidate_batch_id = Uuid::parse_str(&batch_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid batch ID"))?;
+
+    if find_project_scene(pool.as_ref(), project_id, scene_id).await?.is_none() {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "分镜不存在"
+        })));
+    }
+
+    let candidates = GenerationHistory::find_by_candidate_batch(pool.as_ref(), scene_id, candidate_batch_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    Ok(HttpResponse::Ok().json(candidates))
+}
+
+// 选择一张候选图作为分镜当前首帧图
+async fn select_image_candidate(
+    path: web::Path<(String, i32, i32)>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let (project_id_str, scene_id, history_id) = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+
+    let Some(scene) = find_project_scene(pool.as_ref(), project_id, scene_id).await? else {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "分镜不存在"
+        })));
+    };
+    if scene.is_locked() {
+        return Ok(scene_locked_response(scene_id));
+    }
+
+    let history = match GenerationHistory::find_by_scene_and_type(pool.as_ref(), scene_id, "image")
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+        .into_iter()
+        .find(|h| h.id == history_id)
+    {
+        Some(history) => history,
+        None => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "候选图不存在"
+            })));
+        }
+    };
+
+    StoryboardScene::update_latest_image(pool.as_ref(), scene_id, history.result_url.clone())
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    // 选中的图排到历史记录最前
+    let history = GenerationHistory::update_created_at(pool.as_ref(), history_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    println!("✅ 已选择候选图: Scene ID {} -> {}", scene_id, history.result_url);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "image_url": history.result_url,
+        "history": history
+    })))
+}
+
+// 局部重绘请求
+#[derive(Debug, Deserialize)]
+struct RegionEditRequest {
+    mask_base64: String,       // 蒙版图片（白色为重绘区域，支持 data URL），尺寸与原图不同时自动缩放
+    instruction: String,       // 修改说明
+    feather: Option<u32>,      // 蒙版边缘羽化半径（像素，0-64），默认 4
+    provider: Option<String>,  // 图片生成服务，默认使用项目设置
+    model: Option<String>,
+}
+
+// 蒙版边缘默认羽化半径
+const DEFAULT_MASK_FEATHER: u32 = 4;
+// 蒙版边缘最大羽化半径（模糊耗时随半径增长）
+const MAX_MASK_FEATHER: u32 = 64;
+
+// 局部重绘：只修改当前首帧图中蒙版覆盖的区域，结果作为新的历史版本
+async fn edit_scene_region(
+    path: web::Path<(String, i32)>,
+    req: web::Json<RegionEditRequest>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let (project_id_str, scene_id) = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+    let req = req.into_inner();
+
+    let instruction = req.instruction.trim().to_string();
+    if instruction.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "修改说明不能为空"
+        })));
+    }
+    let feather = req.feather.unwrap_or(DEFAULT_MASK_FEATHER);
+    if feather > MAX_MASK_FEATHER {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("羽化半径不能超过 {} 像素", MAX_MASK_FEATHER)
+        })));
+    }
+
+    let Some(project) = Project::find_by_id(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+    else {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "项目不存在"
+        })));
+    };
+    let Some(scene) = find_project_scene(pool.as_ref(), project_id, scene_id).await? else {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "分镜不存在"
+        })));
+    };
+    if scene.is_locked() {
+        return Ok(scene_locked_response(scene_id));
+    }
+    let Some(source_url) = scene.latest_image_url.clone().filter(|u| !u.is_empty()) else {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "分镜还没有首帧图，无法局部重绘"
+        })));
+    };
+
+    println!("🖌️  开始局部重绘: Scene ID {} ({})", scene_id, source_url);
+
+    let source_bytes = load_image_bytes(&source_url).await?;
+    let mask_data = req.mask_base64.split_once("base64,").map(|(_, d)| d).unwrap_or(&req.mask_base64);
+    let Ok(mask_bytes) = base64::engine::general_purpose::STANDARD.decode(mask_data.trim()) else {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "蒙版图片无效"
+        })));
+    };
+
+    // 解码、缩放和编码整张首帧图比较耗时，放到阻塞线程池执行
+    let inputs = match web::block(move || prepare_region_edit(&source_bytes, &mask_bytes)).await?? {
+        Ok(inputs) => inputs,
+        Err(message) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": message
+            })));
+        }
+    };
+
+    let provider = match image_provider_for(Some(&project), req.provider.as_deref(), req.model.as_deref()) {
+        Ok(provider) => provider,
+        Err(e) => return Ok(image_error_response(&e)),
+    };
+
+    let (model_prompt, translated_prompt) = prepare_model_prompt(pool.as_ref(), &project, &instruction).await;
+    let RegionEditInputs { source, mask, source_png, mask_png } = inputs;
+
+    let output_format = OutputFormat::from_project(&project);
+    let mut request = ImageRequest::new(model_prompt);
+    request.references.push(ReferenceImage {
+        mime_type: "image/png".to_string(),
+        data: base64::engine::general_purpose::STANDARD.encode(&source_png),
+    });
+    request.mask = Some(ReferenceImage {
+        mime_type: "image/png".to_string(),
+        data: base64::engine::general_purpose::STANDARD.encode(&mask_png),
+    });
+    request.aspect_ratio = Some(output_format.aspect_ratio.clone());
+    request.resolution = Some(output_format.resolution.clone());
+
+    println!("   ⏳ 正在调用 {} ({})...", provider.name(), provider.model());
+    let generated = match provider.generate(&request).await {
+        Ok(images) => images.into_iter().next().unwrap_or_default(),
+        Err(e) => return Ok(image_error_response(&e)),
+    };
+
+    // 服务返回整张图，只取蒙版区域合成回原图
+    let result_png = web::block(move || -> std::io::Result<Vec<u8>> {
+        let generated = image::load_from_memory(&generated)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("无法解析生成结果: {}", e)))?
+            .to_rgba8();
+        let result = composite_masked(&source, &generated, &mask, feather);
+        encode_png(&image::DynamicImage::ImageRgba8(result))
+    })
+    .await??;
+
+    let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S").to_string();
+    let project_dir = format!("data/projects/{}/first_frames", project_id);
+    fs::create_dir_all(&project_dir)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to create directory: {}", e)))?;
+
+    // 合成结果和生成的首帧图一样按项目配置后处理（蒙版对应后处理前的原图）
+    let stem = format!("edit_{}_{}", scene_id, timestamp);
+    let saved = save_processed_image(&project, &project_dir, &stem, result_png).await?;
+    let mask_filename = format!("mask_{}_{}.png", scene_id, timestamp);
+    fs::write(format!("{}/{}", project_dir, mask_filename), &mask_png)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to write file: {}", e)))?;
+    let image_url = saved.url;
+    let mask_url = format!("/data/projects/{}/first_frames/{}", project_id, mask_filename);
+
+    // 关联原图版本（首帧图来自外部链接时可能没有对应记录）
+    let source_history_id = find_source_history_id(pool.as_ref(), scene_id, &source_url).await?;
+
+    let mut history = GenerationHistory::create_edit(
+        pool.as_ref(),
+        scene_id,
+        instruction,
+        translated_prompt,
+        image_url.clone(),
+        source_history_id,
+        Some(mask_url),
+    )
+    .await
+    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    if let Some(original_url) = saved.original_url {
+        history = GenerationHistory::set_original_url(pool.as_ref(), history.id, original_url)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    }
+
+    StoryboardScene::update_latest_image(pool.as_ref(), scene_id, image_url.clone())
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    println!("🎉 局部重绘完成: {}", image_url);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "局部重绘完成",
+        "image_url": image_url,
+        "source_history_id": source_history_id,
+        "history": history
+    })))
+}
+
+// 局部重绘的输入：原图和原图尺寸的灰度蒙版
+struct RegionEditInputs {
+    source: image::RgbaImage,
+    mask: image::GrayImage,
+    source_png: Vec<u8>,
+    mask_png: Vec<u8>,
+}
+
+// 解码原图和蒙版，蒙版统一为原图尺寸；蒙版无效时返回错误提示（内层 Err）
+fn prepare_region_edit(
+    source_bytes: &[u8],
+    mask_bytes: &[u8],
+) -> std::io::Result<std::result::Result<RegionEditInputs, &'static str>> {
+    let source = image::load_from_memory(source_bytes)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("无法读取首帧图: {}", e)))?
+        .to_rgba8();
+    let (width, height) = source.dimensions();
+
+    let Ok(mask) = image::load_from_memory(mask_bytes) else {
+        return Ok(Err("蒙版图片无效"));
+    };
+    let mut mask = mask.to_luma8();
+    if mask.dimensions() != (width, height) {
+        mask = image::imageops::resize(&mask, width, height, image::imageops::FilterType::Triangle);
+    }
+    if !mask.pixels().any(|p| p[0] >= 128) {
+        return Ok(Err("蒙版为空，请涂抹需要修改的区域"));
+    }
+
+    let source_png = encode_png(&image::DynamicImage::ImageRgba8(source.clone()))?;
+    let mask_png = encode_png(&image::DynamicImage::ImageLuma8(mask.clone()))?;
+    Ok(Ok(RegionEditInputs { source, mask, source_png, mask_png }))
+}
+
+// 查找首帧图对应的生成历史
+// 生成视频后首帧图会被替换为 R2 链接，此时通过上传缓存的文件哈希找回本地历史记录
+async fn find_source_history_id(pool: &sqlx::PgPool, scene_id: i32, image_url: &str) -> std::io::Result<Option<i32>> {
+    if let Some(history) = GenerationHistory::find_latest_by_result_url(pool, scene_id, image_url)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+    {
+        return Ok(Some(history.id));
+    }
+
+    if !image_url.starts_with("http") {
+        return Ok(None);
+    }
+    let Some(uploaded) = models::UploadedFile::find_by_url(pool, image_url)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+    else {
+        return Ok(None);
+    };
+
+    let histories = GenerationHistory::find_by_scene_and_type(pool, scene_id, "image")
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    for history in histories.iter().filter(|h| h.result_url.starts_with("/data/")) {
+        let local_path = format!(".{}", history.result_url);
+        if cloudflare::calculate_file_hash(&local_path).ok().as_deref() == Some(uploaded.file_hash.as_str()) {
+            return Ok(Some(history.id));
+        }
+    }
+    Ok(None)
+}
+
+// 把生成结果按蒙版合成回原图：蒙版白色处取生成结果，黑色处保留原图，边缘按羽化半径过渡
+fn composite_masked(
+    source: &image::RgbaImage,
+    generated: &image::RgbaImage,
+    mask: &image::GrayImage,
+    feather: u32,
+) -> image::RgbaImage {
+    let (width, height) = source.dimensions();
+    let generated = if generated.dimensions() != (width, height) {
+        image::imageops::resize(generated, width, height, image::imageops::FilterType::Lanczos3)
+    } else {
+        generated.clone()
+    };
+    let mask = if feather > 0 {
+        image::imageops::blur(mask, feather as f32)
+    } else {
+        mask.clone()
+    };
+
+    image::RgbaImage::from_fn(width, height, |x, y| {
+        let alpha = mask.get_pixel(x, y)[0] as f32 / 255.0;
+        let (s, g) = (source.get_pixel(x, y), generated.get_pixel(x, y));
+        image::Rgba(std::array::from_fn(|i| {
+            (s[i] as f32 * (1.0 - alpha) + g[i] as f32 * alpha).round() as u8
+        }))
+    })
+}
+
+fn encode_png(image: &image::DynamicImage) -> std::io::Result<Vec<u8>> {
+    let mut bytes = std::io::Cursor::new(Vec::new());
+    image
+        .write_to(&mut bytes, image::ImageOutputFormat::Png)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to encode image: {}", e)))?;
+    Ok(bytes.into_inner())
+}
+
+// 后处理保存结果
+struct ProcessedImage {
+    url: String,
+    original_url: Option<String>,  // 未后处理时为空
+}
+
+// 按图片内容识别扩展名（提供方可能返回 JPEG/WebP），无法识别时按 PNG 处理
+fn image_extension(bytes: &[u8]) -> &'static str {
+    image::guess_format(bytes)
+        .ok()
+        .and_then(|f| f.extensions_str().first().copied())
+        .unwrap_or("png")
+}
+
+// 保存图片并按项目配置后处理：原图保存为 {stem}_original.{ext}，结果保存为 {stem}.{format}
+// 未启用后处理或处理失败时直接保存原图为 {stem}.{ext}
+async fn save_processed_image(
+    project: &Project,
+    dir: &str,
+    stem: &str,
+    original: Vec<u8>,
+) -> std::io::Result<ProcessedImage> {
+    let config = project
+        .image_postprocess
+        .as_ref()
+        .map(|c| c.0.clone())
+        .unwrap_or_else(PostProcessConfig::from_env);
+    let original_ext = image_extension(&original);
+    fs::create_dir_all(dir)?;
+
+    // 旧版本保存的配置可能未经校验（例如外部水印 URL），无效时不做后处理
+    if config.enabled
+        && config.validate().map_err(|e| eprintln!("   ⚠️  图片后处理配置无效，保留原图: {}", e)).is_ok()
+    {
+        let watermark = match &config.watermark {
+            Some(settings) => match load_image_bytes(&settings.image_url).await {
+                Ok(bytes) => image::load_from_memory(&bytes)
+                    .map_err(|e| eprintln!("   ⚠️  水印图片无法解码，跳过水印: {}", e))
+                    .ok(),
+                Err(e) => {
+                    eprintln!("   ⚠️  无法加载水印图片 {}，跳过水印: {}", settings.image_url, e);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let target = OutputFormat::from_project(project).target_size();
+        let input = original.clone();
+        let process_config = config.clone();
+        let processed = web::block(move || postprocess::process(&input, &process_config, target, watermark.as_ref()))
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+        match processed {
+            Ok(bytes) => {
+                let original_name = format!("{}_original.{}", stem, original_ext);
+                let processed_name = format!("{}.{}", stem, config.extension());
+                fs::write(format!("{}/{}", dir, original_name), &original)?;
+                fs::write(format!("{}/{}", dir, processed_name), &bytes)?;
+                println!(
+                    "   🖼️  图片后处理完成: {} ({}{})",
+                    processed_name,
+                    target.map(|(w, h)| format!("{}x{}, ", w, h)).unwrap_or_default(),
+                    config.format
+                );
+                return Ok(ProcessedImage {
+                    url: format!("/{}/{}", dir, processed_name),
+                    original_url: Some(format!("/{}/{}", dir, original_name)),
+                });
+            }
+            Err(e) => eprintln!("   ⚠️  图片后处理失败，保留原图: {}", e),
+        }
+    }
+
+    let filename = format!("{}.{}", stem, original_ext);
+    fs::write(format!("{}/{}", dir, filename), &original)?;
+    Ok(ProcessedImage {
+        url: format!("/{}/{}", dir, filename),
+        original_url: None,
+    })
+}
+
+// 上传文件到历史记录
+async fn upload_scene_media(
+    path: web::Path<(String, i32)>,
+    mut payload: Multipart,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let (project_id_str, scene_id) = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+    
+    println!("📤 开始上传文件: 项目ID={}, 分镜ID={}", project_id, scene_id);
+    
+    // 验证场景是否存在
+    let scene = StoryboardScene::find_by_id(pool.as_ref(), scene_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Scene not found"))?;
+    
+    if scene.project_id != project_id {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Scene does not belong to this project"
+        })));
+    }
+
+    if scene.is_locked() {
+        return Ok(scene_locked_response(scene_id));
+    }
+    
+    // 创建上传目录
+    let upload_dir = PathBuf::from(format!("./data/projects/{}/scenes/{}/uploads", project_id, scene_id));
+    fs::create_dir_all(&upload_dir)?;
+    
+    let mut file_path = PathBuf::new();
+    let mut prompt = String::new();
+    let mut generation_type = String::new();
+    let mut file_extension = String::new();
+    
+    // 处理 multipart 表单
+    while let Some(item) = payload.next().await {
+        let mut field = item?;
+        let content_disposition = field.content_disposition();
+        let field_name = content_disposition.get_name().unwrap_or("");
+        
+        match field_name {
+            "file" => {
+                if let Some(filename) = content_disposition.get_filename() {
+                    // 提取文件扩展名
+                    file_extension = Path::new(filename)
+                        .extension()
+                        .and_then(|s| s.to_str())
+                        .unwrap_or("bin")
+                        .to_string();
+                    
+                    // 生成唯一文件名
+                    let timestamp = chrono::Utc::now().timestamp();
+                    let new_filename = format!("{}_{}.{}", timestamp, scene_id, file_extension);
+                    let file_path_clone = upload_dir.join(&new_filename);
+                    file_path = file_path_clone.clone();
+                    
+                    // 保存文件
+                    let mut f = web::block(move || File::create(file_path_clone))
+                        .await??;
+                    
+                    while let Some(chunk) = field.next().await {
+                        let data = chunk?;
+                        f = web::block(move || f.write_all(&data).map(|_| f)).await??;
+                    }
+                    
+                    println!("✅ 文件已保存: {:?}", file_path);
+                }
+            },
+            "prompt" => {
+                // 读取提示词
+                while let Some(chunk) = field.next().await {
+                    let data = chunk?;
+                    prompt.push_str(&String::from_utf8_lossy(&data));
+                }
+            },
+            "generation_type" => {
+                // 读取生成类型
+                while let Some(chunk) = field.next().await {
+                    let data = chunk?;
+                    generation_type.push_str(&String::from_utf8_lossy(&data));
+                }
+            },
+            _ => {
+                // 忽略其他字段
+                while let Some(_chunk) = field.next().await {}
+            }
+        }
+    }
+    
+    // 验证文件是否上传
+    if file_path.as_os_str().is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "No file uploaded"
+        })));
+    }
+    
+    // 验证生成类型
+    if generation_type != "image" && generation_type != "video" {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Invalid generation_type, must be 'image' or 'video'"
+        })));
+    }
+    
+    // 验证文件类型
+    let valid_image_exts = vec!["jpg", "jpeg", "png", "webp"];
+    let valid_video_exts = vec!["mp4", "mov", "webm"];
+    
+    let is_valid_image = valid_image_exts.contains(&file_extension.to_lowercase().as_str());
+    let is_valid_video = valid_video_exts.contains(&file_extension.to_lowercase().as_str());
+    
+    if generation_type == "image" && !is_valid_image {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Invalid image file format"
+        })));
+    }
+    
+    if generation_type == "video" && !is_valid_image && !is_valid_video {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Invalid file format for video generation"
+        })));
+    }
+    
+    let project = Project::find_by_id(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    // 上传的首帧图按项目配置后处理（原图保留）
+    let mut original_url = None;
+    if generation_type == "image" {
+        if let Some(project) = &project {
+            let original = fs::read(&file_path)?;
+            let stem = file_path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or_default()
+                .to_string();
+            let upload_dir = format!("data/projects/{}/scenes/{}/uploads", project_id, scene_id);
+            let saved = save_processed_image(project, &upload_dir, &stem, original).await?;
+            let saved_path = PathBuf::from(format!(".{}", saved.url));
+            if saved_path != file_path {
+                fs::remove_file(&file_path)?;
+            }
+            file_path = saved_path;
+            original_url = saved.original_url;
+        }
+    }
+
+    // 生成相对路径URL
+    let relative_path = file_path.strip_prefix("./")
+        .unwrap_or(&file_path)
+        .to_string_lossy()
+        .to_string();
+    let result_url = format!("/{}", relative_path.replace("\\", "/"));
+
+    // 检查上传文件是否与项目输出格式一致（仅提示，不阻止上传）
+    let mut warnings = Vec::new();
+    if let Some(project) = &project {
+        let output_format = OutputFormat::from_project(project);
+        let media_path = file_path.clone();
+        let media_info = web::block(move || {
+            if is_valid_image {
+                image::image_dimensions(&media_path).ok().map(|(w, h)| (w, h, None))
+            } else {
+                get_video_info(&media_path).ok().map(|info| (info.width, info.height, Some(info.fps)))
+            }
+        })
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+        if let Some((width, height, fps)) = media_info {
+            warnings = output_format.check_media(width, height, fps);
+            for warning in &warnings {
+                println!("⚠️  {}", warning);
+            }
+        }
+    }
+    
+    // 创建历史记录
+    let mut history = GenerationHistory::create(
+        pool.as_ref(),
+        scene_id,
+        generation_type.clone(),
+        prompt.clone(),
+        result_url.clone(),
+    )
+    .await
+    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    if let Some(original_url) = original_url {
+        history = GenerationHistory::set_original_url(pool.as_ref(), history.id, original_url)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    }
+    
+    // 更新场景的最新URL
+    if generation_type == "image" {
+        StoryboardScene::update_latest_image(pool.as_ref(), scene_id, result_url.clone())
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+            
+        // 如果有提示词，同步更新场景的首帧图提示词
+        if !prompt.is_empty() {
+            StoryboardScene::update_prompts(pool.as_ref(), scene_id, Some(prompt.clone()), None, None)
+                .await
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        }
+    } else {
+        StoryboardScene::update_latest_video(pool.as_ref(), scene_id, result_url.clone())
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+            
+        // 如果有提示词，同步更新场景的视频提示词
+        if !prompt.is_empty() {
+            StoryboardScene::update_prompts(pool.as_ref(), scene_id, None, Some(prompt.clone()), None)
+                .await
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        }
+    }
+    
+    println!("✅ 上传成功: ID={}, URL={}", history.id, result_url);
+    
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "Upload successful",
+        "history": history,
+        "result_url": result_url,
+        "warnings": warnings
+    })))
+}
+
+// 在Finder中显示文件
+async fn reveal_file_in_finder(
+    req_body: web::Json<RevealFileRequest>,
+) -> Result<HttpResponse> {
+    let file_path = &req_body.file_path;
+    
+    println!("📁 尝试在Finder中显示文件: {}", file_path);
+    
+    // 转换为绝对路径
+    let absolute_path = if file_path.starts_with("/") {
+        // 已经是绝对路径，去掉开头的 /
+        PathBuf::from(format!(".{}", file_path))
+    } else if file_path.starts_with("./") {
+        PathBuf::from(file_path)
+    } else {
+        PathBuf::from(format!("./{}", file_path))
+    };
+    
+    // 验证文件存在
+    if !absolute_path.exists() {
+        println!("❌ 文件不存在: {:?}", absolute_path);
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "文件不存在"
+        })));
+    }
+    
+    // 验证路径在data目录内（安全检查）
+    let canonical_path = match fs::canonicalize(&absolute_path) {
+        Ok(path) => path,
+        Err(e) => {
+            println!("❌ 无法解析路径: {:?} - {}", absolute_path, e);
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "无效的文件路径"
+            })));
+        }
+    };
+    
+    let data_dir = match fs::canonicalize("./data") {
+        Ok(path) => path,
+        Err(_) => {
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "数据目录不存在"
+            })));
+        }
+    };
+    
+    if !canonical_path.starts_with(&data_dir) {
+        println!("❌ 路径不在数据目录内: {:?}", canonical_path);
+        return Ok(HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "只能访问数据目录内的文件"
+        })));
+    }
+    
+    // 使用 open -R 命令在Finder中显示文件（macOS）
+    let output = AsyncCommand::new("open")
+        .args(&["-R", canonical_path.to_str().unwrap()])
+        .output()
+        .await;
+    
+    match output {
+        Ok(result) => {
+            if result.status.success() {
+                println!("✅ 已在Finder中打开: {:?}", canonical_path);
+                Ok(HttpResponse::Ok().json(serde_json::json!({
+                    "success": true,
+                    "message": "文件已在Finder中显示"
+                })))
+            } else {
+                let error_msg = String::from_utf8_lossy(&result.stderr);
+                println!("❌ 打开Finder失败: {}", error_msg);
+                Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": format!("打开Finder失败: {}", error_msg)
+                })))
+            }
+        },
+        Err(e) => {
+            println!("❌ 执行open命令失败: {}", e);
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("执行open命令失败: {}", e)
+            })))
+        }
+    }
+}
+
+// 合成视频（模拟）
+async fn composite_video(
+    path: web::Path<String>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let project_id_str = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+
+    println!("🎬 开始合成项目视频: {}", project_id);
+
+    // 1. 获取所有分镜场景，按顺序排列
+    let scenes = StoryboardScene::find_by_project_id(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    // 2. 过滤出有视频的分镜
+    let video_scenes: Vec<_> = scenes.iter()
+        .filter(|s| s.latest_video_url.is_some())
+        .collect();
+
+    if video_scenes.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "没有可用的分镜视频，请先生成分镜视频"
+        })));
+    }
+
+    println!("📊 找到 {} 个分镜视频", video_scenes.len());
+
+    // 3. 创建输出目录
+    let project_dir = format!("data/projects/{}/composite", project_id);
+    fs::create_dir_all(&project_dir)?;
+
+    // 4. 创建临时文件列表
+    let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S").to_string();
+    let concat_list_path = format!("{}/concat_list_{}.txt", project_dir, timestamp);
+    let output_filename = format!("composite_{}.mp4", timestamp);
+    let output_path = format!("{}/{}", project_dir, output_filename);
+    let video_url = format!("/data/projects/{}/composite/{}", project_id, output_filename);
+
+    // 5. 写入视频文件列表
+    let mut concat_content = String::new();
+    for scene in &video_scenes {
+        if let Some(ref url) = scene.latest_video_url {
+            // 转换 URL 为本地路径
+            let local_path = if url.starts_with("/data/") {
+                format!(".{}", url)
+            } else if url.starts_with("http") {
+                // 如果是外部URL，跳过（在真实场景中可能需要下载）
+                println!("⚠️  跳过外部URL: {}", url);
+                continue;
+            } else {
+                url.clone()
+            };
+
+            // 检查文件是否存在
+            if !Path::new(&local_path).exists() {
+                println!("⚠️  视频文件不存在: {}", local_path);
+                continue;
+            }
+
+            concat_content.push_str(&format!("file '{}'\n", 
+                Path::new(&local_path).canonicalize()
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+                    .to_str()
+                    .unwrap()
+            ));
+        }
+    }
+
+    if concat_content.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "没有找到有效的本地视频文件"
+        })));
+    }
+
+    fs::write(&concat_list_path, concat_content)?;
+    println!("📝 创建文件列表: {}", concat_list_path);
+
+    // 6. 执行 ffmpeg 合成
+    println!("🎬 开始使用 ffmpeg 合成视频...");
+    let output = std::process::Command::new("ffmpeg")
+        .args(&[
+            "-f", "concat",
+            "-safe", "0",
+            "-i", &concat_list_path,
+            "-c", "copy",
+            "-y", // 覆盖已存在的文件
+            &output_path,
+        ])
+        .output()
+        .map_err(|e| {
+            println!("❌ ffmpeg 执行失败: {}", e);
+            std::io::Error::new(std::io::ErrorKind::Other, format!("ffmpeg execution failed: {}", e))
+        })?;
+
+    if !output.status.success() {
+        let error_msg = String::from_utf8_lossy(&output.stderr);
+        println!("❌ ffmpeg 合成失败: {}", error_msg);
+        
+        // 清理临时文件
+        let _ = fs::remove_file(&concat_list_path);
+        
+        return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("视频合成失败: {}", error_msg)
+        })));
+    }
+
+    println!("✅ 视频合成成功: {}", output_path);
+
+    // 7. 清理临时文件
+    let _ = fs::remove_file(&concat_list_path);
+
+    // 8. 记录合成历史到数据库
+    let composite = CompositeVideo::create(
+        pool.as_ref(), 
+        project_id, 
+        video_url.clone(),
+        video_scenes.len() as i32
+    )
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    println!("🎞️ 视频合成记录已保存: ID {}", composite.id);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "视频合成成功",
+        "composite_id": composite.id,
+        "video_url": video_url,
+        "scene_count": composite.scene_count,
+        "created_at": composite.created_at,
+    })))
+}
+
+// 获取合成历史
+async fn get_composite_history(
+    path: web::Path<String>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let project_id_str = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+
+    let composites = CompositeVideo::find_by_project_id(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    Ok(HttpResponse::Ok().json(composites))
+}
+
+// 导出项目首帧图
+async fn export_project_images(
+    path: web::Path<String>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let project_id_str = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+
+    println!("📦 开始导出项目首帧图到临时目录: {}", project_id);
+
+    // 1. 获取所有分镜场景，按顺序排列
+    let scenes = StoryboardScene::find_by_project_id(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    // 2. 过滤出有首帧图的分镜
+    let image_scenes: Vec<_> = scenes.iter()
+        .filter(|s| s.latest_image_url.is_some())
+        .collect();
+
+    if image_scenes.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "没有可导出的首帧图，请先生成首帧图"
+        })));
+    }
+
+    println!("📊 找到 {} 个首帧图", image_scenes.len());
+
+    // 3. 创建临时导出目录（固定路径）
+    let export_path = format!("data/projects/{}/temp_export_images", project_id);
+    
+    // 4. 如果目录已存在，先清空
+    if Path::new(&export_path).exists() {
+        println!("🗑️  清空现有临时目录: {}", export_path);
+        fs::remove_dir_all(&export_path)?;
+    }
+    
+    // 5. 创建空的导出目录
+    println!("📁 创建临时导出目录: {}", export_path);
+    fs::create_dir_all(&export_path)?;
+
+    // 6. 复制所有首帧图文件
+    for scene in &image_scenes {
+        if let Some(ref url) = scene.latest_image_url {
+            // 转换 URL 为本地路径
+            let local_path = if url.starts_with("/data/") {
+                format!(".{}", url)
+            } else if url.starts_with("http") {
+                println!("⚠️  跳过外部URL: {}", url);
+                continue;
+            } else {
+                url.clone()
+            };
+
+            // 检查文件是否存在
+            if !Path::new(&local_path).exists() {
+                println!("⚠️  首帧图文件不存在: {}", local_path);
+                continue;
+            }
+
+            // 获取文件扩展名
+            let ext = Path::new(&local_path)
+                .extension()
+                .and_then(|s| s.to_str())
+                .unwrap_or("png");
+
+            // 确定输出文件名
+            let output_filename = format!("scene_{}.{}", scene.scene_index, ext);
+            let output_path = format!("{}/{}", export_path, output_filename);
+
+            // 复制文件
+            fs::copy(&local_path, &output_path)
+                .map_err(|e| {
+                    println!("❌ 复制文件失败: {} -> {}: {}", local_path, output_path, e);
+                    std::io::Error::new(std::io::ErrorKind::Other, format!("复制文件失败: {}", e))
+                })?;
+
+            println!("✅ 已复制: {} -> {}", local_path, output_filename);
+        }
+    }
+
+    println!("✅ 所有首帧图已复制到临时目录: {}", export_path);
+
+    // 7. 打开导出目录
+    let canonical_path = fs::canonicalize(&export_path)
+        .map_err(|e| {
+            println!("❌ 无法解析路径: {:?} - {}", export_path, e);
+            std::io::Error::new(std::io::ErrorKind::Other, "无法解析路径")
+        })?;
+
+    println!("📂 打开目录: {:?}", canonical_path);
+
+    #[cfg(target_os = "macos")]
+    {
+        let output = Command::new("open")
+            .arg(&canonical_path)
+            .output()
+            .map_err(|e| {
+                println!("❌ 执行open命令失败: {}", e);
+                std::io::Error::new(std::io::ErrorKind::Other, format!("执行open命令失败: {}", e))
+            })?;
+
+        if !output.status.success() {
+            let error_msg = String::from_utf8_lossy(&output.stderr);
+            println!("❌ open命令执行失败: {}", error_msg);
+        } else {
+            println!("✅ 已在Finder中打开目录");
+        }
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    {
+        println!("⚠️  当前系统不支持自动打开文件管理器");
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "首帧图已导出到临时目录",
+        "export_path": export_path,
+        "image_count": image_scenes.len(),
+    })))
+}
+
+// 导出项目视频
+async fn export_project_videos(
+    path: web::Path<String>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let project_id_str = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+
+    println!("📦 开始导出项目视频到临时目录: {}", project_id);
+
+    // 1. 获取所有分镜场景，按顺序排列
+    let scenes = StoryboardScene::find_by_project_id(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    // 2. 过滤出有视频的分镜
+    let video_scenes: Vec<_> = scenes.iter()
+        .filter(|s| s.latest_video_url.is_some())
+        .collect();
+
+    if video_scenes.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "没有可导出的视频，请先生成分镜视频"
+        })));
+    }
+
+    println!("📊 找到 {} 个分镜视频", video_scenes.len());
+
+    // 3. 创建临时导出目录（固定路径）
+    let export_path = format!("data/projects/{}/temp_export", project_id);
+    
+    // 4. 如果目录已存在，先清空
+    if Path::new(&export_path).exists() {
+        println!("🗑️  清空现有临时目录: {}", export_path);
+        fs::remove_dir_all(&export_path)?;
+    }
+    
+    // 5. 创建空的导出目录
+    println!("📁 创建临时导出目录: {}", export_path);
+    fs::create_dir_all(&export_path)?;
+
+    // 6. 复制所有视频文件
+    for scene in &video_scenes {
+        if let Some(ref url) = scene.latest_video_url {
+            // 转换 URL 为本地路径
+            let local_path = if url.starts_with("/data/") {
+                format!(".{}", url)
+            } else if url.starts_with("http") {
+                println!("⚠️  跳过外部URL: {}", url);
+                continue;
+            } else {
+                url.clone()
+            };
+
+            // 检查文件是否存在
+            if !Path::new(&local_path).exists() {
+                println!("⚠️  视频文件不存在: {}", local_path);
+                continue;
+            }
+
+            // 确定输出文件名
+            let output_filename = format!("scene_{}.mp4", scene.scene_index);
+            let output_path = format!("{}/{}", export_path, output_filename);
+
+            // 复制文件
+            fs::copy(&local_path, &output_path)
+                .map_err(|e| {
+                    println!("❌ 复制文件失败: {} -> {}: {}", local_path, output_path, e);
+                    std::io::Error::new(std::io::ErrorKind::Other, format!("复制文件失败: {}", e))
+                })?;
+
+            println!("✅ 已复制: {} -> {}", local_path, output_filename);
+        }
+    }
+
+    println!("✅ 所有视频已复制到临时目录: {}", export_path);
+
+    // 7. 打开导出目录
+    let canonical_path = fs::canonicalize(&export_path)
+        .map_err(|e| {
+            println!("❌ 无法解析路径: {:?} - {}", export_path, e);
+            std::io::Error::new(std::io::ErrorKind::Other, "无法解析路径")
+        })?;
+
+    println!("📂 打开目录: {:?}", canonical_path);
+
+    #[cfg(target_os = "macos")]
+    {
+        let output = Command::new("open")
+            .arg(&canonical_path)
+            .output()
+            .map_err(|e| {
+                println!("❌ 执行open命令失败: {}", e);
+                std::io::Error::new(std::io::ErrorKind::Other, format!("执行open命令失败: {}", e))
+            })?;
+
+        if !output.status.success() {
+            let error_msg = String::from_utf8_lossy(&output.stderr);
+            println!("❌ open命令执行失败: {}", error_msg);
+        } else {
+            println!("✅ 已在Finder中打开目录");
+        }
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    {
+        println!("⚠️  当前系统不支持自动打开文件管理器");
     }
-    
-    // 拼接图片
-    let mut img_idx = 0;
-    let mut y_offset = 0u32;
-    
-    for row_idx in 0..rows_needed {
-        let row_height = row_heights[row_idx];
-        let images_in_row = if row_idx == rows_needed - 1 {
-            count - (row_idx * max_images_per_row)
-        } else {
-            max_images_per_row
-        };
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "视频已导出到临时目录",
+        "export_path": export_path,
+        "video_count": video_scenes.len(),
+        "is_new_export": true,
+    })))
+}
+
+// 上传合成视频
+async fn upload_composite_video(
+    path: web::Path<String>,
+    mut payload: Multipart,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let project_id_str = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+
+    println!("📤 开始上传合成视频: {}", project_id);
+
+    // 创建合成目录
+    let composite_dir = format!("data/projects/{}/composite", project_id);
+    fs::create_dir_all(&composite_dir)?;
+
+    let mut video_path = PathBuf::new();
+
+    // 接收上传的视频文件
+    while let Some(item) = payload.next().await {
+        let mut field = item?;
+        let content_disposition = field.content_disposition();
         
-        for col in 0..images_in_row {
-            let (ref img, width, height) = resized_images[img_idx];
-            let rgba_img = img.to_rgba8();
+        if let Some(filename) = content_disposition.get_filename() {
             
-            let x_offset = (col as u32) * max_width;
+            // 生成时间戳文件名
+            let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S").to_string();
+            let ext = Path::new(filename)
+                .extension()
+                .and_then(|s| s.to_str())
+                .unwrap_or("mp4");
+            let new_filename = format!("upload_{}.{}", timestamp, ext);
             
-            // 垂直居中对齐
-            let vertical_padding = (row_height - height) / 2;
-            let final_y_offset = y_offset + vertical_padding;
+            let filepath = PathBuf::from(&composite_dir).join(&new_filename);
+            video_path = filepath.clone();
             
-            // 将图片复制到画布
-            for y in 0..height {
-                for x in 0..width {
-                    if x_offset + x < canvas_width && final_y_offset + y < canvas_height {
-                        canvas.put_pixel(x_offset + x, final_y_offset + y, *rgba_img.get_pixel(x, y));
-                    }
+            println!("📝 保存文件: {:?}", filepath);
+            
+            let mut f = web::block(move || std::fs::File::create(filepath))
+                .await??;
+
+            while let Some(chunk) = field.next().await {
+                let data = chunk?;
+                f = web::block(move || f.write_all(&data).map(|_| f)).await??;
+            }
+            
+            println!("✅ 文件上传完成: {}", new_filename);
+        }
+    }
+
+    if video_path.as_os_str().is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "未收到视频文件"
+        })));
+    }
+
+    // 构建视频 URL
+    let video_url = format!(
+        "/data/projects/{}/composite/{}", 
+        project_id, 
+        video_path.file_name().unwrap().to_str().unwrap()
+    );
+
+    println!("🔗 视频URL: {}", video_url);
+
+    // 保存到数据库（scene_count=0 表示手动上传）
+    let composite = CompositeVideo::create(
+        pool.as_ref(), 
+        project_id, 
+        video_url.clone(),
+        0  // 0 表示手动上传
+    )
+    .await
+    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    println!("💾 合成视频记录已保存: ID {}", composite.id);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "message": "视频上传成功",
+        "composite": composite,
+    })))
+}
+
+
+
+
+
+
+
+
+
+
+
+// ========================================
+// 角色管理 API handlers
+// ========================================
+
+#[derive(Deserialize)]
+struct CreateCharacterRequest {
+    name: String,
+    prompt: Option<String>,
+    display_order: Option<i32>,
+}
+
+#[derive(Deserialize)]
+struct UpdateCharacterRequest {
+    name: String,
+    prompt: Option<String>,
+    category: Option<String>,
+    tags: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+struct UpdateGlobalPromptRequest {
+    global_image_prompt: Option<String>,
+    global_video_prompt: Option<String>,
+    author: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct UpdatePromptVariablesRequest {
+    variables: std::collections::HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct SearchSystemCharactersRequest {
+    query: Option<String>,
+    limit: Option<i64>,
+}
+
+async fn get_system_characters(
+    pool: web::Data<sqlx::PgPool>,
+    query: web::Query<SearchSystemCharactersRequest>,
+) -> Result<HttpResponse> {
+    let characters = Character::search(pool.as_ref(), query.query.clone(), query.limit)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    Ok(HttpResponse::Ok().json(characters))
+}
+
+// 获取待生成角色列表
+async fn get_pending_characters(
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let characters = Character::list_pending(pool.as_ref())
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    
+    Ok(HttpResponse::Ok().json(characters))
+}
+
+// AI生成角色图片
+#[derive(Debug, Deserialize)]
+struct GenerateCharacterRequest {
+    prompt: Option<String>,
+    provider: Option<String>,  // 图片生成服务，默认使用角色来源项目的设置
+    model: Option<String>,
+}
+
+async fn generate_character_image(
+    path: web::Path<String>,
+    pool: web::Data<sqlx::PgPool>,
+    req_body: Option<web::Json<GenerateCharacterRequest>>,
+) -> Result<HttpResponse> {
+    let char_id_str = path.into_inner();
+    log_api_request(
+        &format!("/api/characters/{}/generate", char_id_str),
+        "POST",
+        &format!("prompt: {:?}", req_body.as_ref().and_then(|r| r.prompt.as_ref()))
+    );
+    
+    let char_id = match Uuid::parse_str(&char_id_str) {
+        Ok(id) => id,
+        Err(e) => {
+            log_api_error("generate_character_image", &e.to_string(), &format!("Invalid UUID: {}", char_id_str));
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Invalid character ID"
+            })));
+        }
+    };
+    
+    // 查询角色信息
+    let character = match sqlx::query_as::<_, Character>("SELECT * FROM characters WHERE id = $1")
+        .bind(char_id)
+        .fetch_optional(pool.as_ref())
+        .await {
+        Ok(Some(c)) => c,
+        Ok(None) => {
+            log_api_error("generate_character_image", "Character not found", &format!("ID: {}", char_id));
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "Character not found"
+            })));
+        },
+        Err(e) => {
+            log_api_error("generate_character_image", &e.to_string(), &format!("Database query failed for ID: {}", char_id));
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("Database error: {}", e)
+            })));
+        }
+    };
+    
+    let requested_provider = req_body.as_ref().and_then(|r| r.provider.clone());
+    let requested_model = req_body.as_ref().and_then(|r| r.model.clone());
+
+    // 优先使用请求体中的prompt，否则使用数据库中的prompt
+    let prompt = if let Some(req) = req_body {
+        if let Some(p) = req.prompt.clone() {
+            if !p.trim().is_empty() {
+                p
+            } else {
+                match character.prompt {
+                    Some(p) => p,
+                    None => return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                        "error": "Character has no prompt"
+                    })))
                 }
             }
-            
-            println!("   > 拼接图片 {}: 位置 ({}, {}), 尺寸 {}x{}", img_idx + 1, x_offset, final_y_offset, width, height);
-            img_idx += 1;
+        } else {
+            match character.prompt {
+                Some(p) => p,
+                None => return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": "Character has no prompt"
+                })))
+            }
         }
-        
-        y_offset += row_height;
-    }
+    } else {
+        match character.prompt {
+            Some(p) => p,
+            None => return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Character has no prompt"
+            })))
+        }
+    };
     
-    let final_image = DynamicImage::ImageRgba8(canvas);
+    // 项目角色默认使用来源项目的图片生成服务
+    let project = match character.source_project_id {
+        Some(project_id) => Project::find_by_id(pool.as_ref(), project_id)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?,
+        None => None,
+    };
+    let provider = match image_provider_for(project.as_ref(), requested_provider.as_deref(), requested_model.as_deref()) {
+        Ok(provider) => provider,
+        Err(e) => {
+            log_api_error("generate_character_image", &e.to_string(), "Image provider configuration");
+            return Ok(image_error_response(&e));
+        }
+    };
+
+    let image_bytes = match provider.generate(&ImageRequest::new(prompt)).await {
+        Ok(images) => images.into_iter().next().unwrap_or_default(),
+        Err(e) => {
+            log_api_error("generate_character_image", &e.to_string(), &format!("{} API call failed, char_id: {}", provider.name(), char_id));
+            return Ok(image_error_response(&e));
+        }
+    };
     
-    // 保存拼接后的图片
     let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S").to_string();
-    let filename = format!("combined_characters_{}.jpg", timestamp);
-    let project_dir = format!("data/projects/{}", project_id);
-    if let Err(e) = fs::create_dir_all(&project_dir) {
-        eprintln!("❌ 创建目录失败: {}", e);
-        return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": format!("创建目录失败: {}", e)
-        })));
-    }
-    let filepath = format!("{}/{}", project_dir, filename);
-    let image_url = format!("/data/projects/{}/{}", project_id, filename);
+    let filename = format!("gemini_{}_{}.{}", timestamp, char_id, image_extension(&image_bytes));
+    let filepath = format!("data/characters/{}", filename);
+    let image_url = format!("/data/characters/{}", filename);
     
-    if let Err(e) = final_image.save(&filepath) {
-        eprintln!("❌ 保存图片失败: {}", e);
+    let mut file = match File::create(&filepath) {
+        Ok(f) => f,
+        Err(e) => return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("Failed to create file: {}", e)
+        })))
+    };
+    if let Err(e) = file.write_all(&image_bytes) {
         return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": format!("保存图片失败: {}", e)
+            "error": format!("Failed to write file: {}", e)
         })));
     }
-    
-    println!("   ✅ 拼接图片已保存: {}", image_url);
-    
-    // 更新项目的 combined_characters_image 字段
-    if let Err(e) = sqlx::query(
-        "UPDATE projects SET combined_characters_image = $1, updated_at = NOW() WHERE id = $2"
+        
+    // Update image_url and updated_at to change timestamp for cache busting
+    let updated_character = match sqlx::query_as::<_, Character>(
+        "UPDATE characters SET image_url = $1, updated_at = NOW() WHERE id = $2 RETURNING *"
     )
-    .bind(&image_url)
-    .bind(project_id)
-    .execute(pool.as_ref())
+    .bind(image_url.clone())
+    .bind(char_id)
+    .fetch_one(pool.as_ref())
     .await {
-        eprintln!("❌ 更新数据库失败: {}", e);
-        return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": format!("更新数据库失败: {}", e)
-        })));
-    }
-    
-    println!("🎉 角色图片拼接成功!");
+        Ok(c) => c,
+        Err(e) => return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("Failed to update character: {}", e)
+        })))
+    };
     
+    println!("🎨 角色图片生成成功: {} (ID: {})", character.name, char_id);
+
     Ok(HttpResponse::Ok().json(serde_json::json!({
-        "message": "角色图片拼接成功",
+        "success": true,
         "image_url": image_url,
+        "character": updated_character,
     })))
 }
 
-// 删除拼接的角色图片
-async fn delete_combined_characters_image(
-    path: web::Path<String>,
+fn default_project_type() -> Option<String> {
+    Some("video".to_string())
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Img2ImgRequest {
+    // 源图（四选一）
+    image_base64: Option<String>,
+    mime_type: Option<String>,      // 与 image_base64 一起使用
+    image_url: Option<String>,      // 已有的 /data/... 图片
+    character_id: Option<Uuid>,     // 使用角色当前图片
+    history_id: Option<i32>,        // 使用某条分镜生成历史的结果
+    prompt: String,
+    save_to: Option<String>,        // 结果保存位置（必填），见 IMG2IMG_SAVE_TARGETS
+    target_character_id: Option<Uuid>, // save_to 为 character / derived_character 时的角色，默认使用源角色
+    name: Option<String>,           // 衍生角色名称，默认 "原角色名 (衍生)"
+    scene_id: Option<i32>,          // save_to 为 scene 时的分镜，默认使用源历史记录所属分镜
+    provider: Option<String>,       // 图片生成服务，默认使用项目设置
+    model: Option<String>,
+}
+
+// 图生图结果保存位置：预览 / 替换角色图片 / 新建衍生角色 / 记录到分镜生成历史
+// preview 只保存文件不写数据库，之后没有被角色等引用的文件会被孤立文件清理删除
+const IMG2IMG_SAVE_TARGETS: &[&str] = &["preview", "character", "derived_character", "scene"];
+
+#[derive(Debug, PartialEq)]
+enum Img2ImgSource {
+    Upload { data: String, mime_type: String },
+    Url(String),
+    Character(Uuid),
+    History(i32),
+}
+
+#[derive(Debug, PartialEq)]
+enum Img2ImgTarget {
+    Preview,
+    Character(Uuid),
+    DerivedCharacter(Uuid),
+    Scene(i32),
+}
+
+// 解析源图，要求有且只有一个来源
+fn img2img_source(req: &Img2ImgRequest) -> std::result::Result<Img2ImgSource, String> {
+    let source_count = [
+        req.image_base64.is_some(),
+        req.image_url.is_some(),
+        req.character_id.is_some(),
+        req.history_id.is_some(),
+    ]
+    .iter()
+    .filter(|s| **s)
+    .count();
+    if source_count != 1 {
+        return Err("请指定且只指定一个源图: image_base64、image_url、character_id 或 history_id".to_string());
+    }
+
+    if let Some(data) = &req.image_base64 {
+        let Some(mime_type) = req.mime_type.clone().filter(|m| !m.is_empty()) else {
+            return Err("使用 image_base64 时必须提供 mime_type".to_string());
+        };
+        return Ok(Img2ImgSource::Upload { data: data.clone(), mime_type });
+    }
+    if let Some(url) = &req.image_url {
+        if !url.starts_with("/data/") || url.contains("..") {
+            return Err("image_url 必须是 /data/ 下的图片路径".to_string());
+        }
+        return Ok(Img2ImgSource::Url(url.clone()));
+    }
+    match (req.character_id, req.history_id) {
+        (Some(id), _) => Ok(Img2ImgSource::Character(id)),
+        (_, Some(id)) => Ok(Img2ImgSource::History(id)),
+        _ => unreachable!("source_count == 1"),
+    }
+}
+
+// 解析保存位置；源历史记录所属分镜作为 scene 的默认值
+fn img2img_target(req: &Img2ImgRequest, source_scene_id: Option<i32>) -> std::result::Result<Img2ImgTarget, String> {
+    let character_id = req.target_character_id.or(req.character_id);
+    match req.save_to.as_deref() {
+        Some("preview") => Ok(Img2ImgTarget::Preview),
+        Some("character" | "derived_character") => {
+            let Some(id) = character_id else {
+                return Err("保存到角色时需要 target_character_id 或 character_id".to_string());
+            };
+            if req.save_to.as_deref() == Some("character") {
+                Ok(Img2ImgTarget::Character(id))
+            } else {
+                Ok(Img2ImgTarget::DerivedCharacter(id))
+            }
+        }
+        Some("scene") => req
+            .scene_id
+            .or(source_scene_id)
+            .map(Img2ImgTarget::Scene)
+            .ok_or_else(|| "保存到分镜时需要 scene_id 或 history_id".to_string()),
+        Some(target) => Err(format!(
+            "无效的保存位置: {}，可选值: {}",
+            target,
+            IMG2IMG_SAVE_TARGETS.join(", ")
+        )),
+        None => Err(format!(
+            "请指定保存位置 save_to，可选值: {}",
+            IMG2IMG_SAVE_TARGETS.join(", ")
+        )),
+    }
+}
+
+fn img2img_bad_request(error: impl Into<String>) -> HttpResponse {
+    HttpResponse::BadRequest().json(serde_json::json!({
+        "error": error.into()
+    }))
+}
+
+// Image-to-image generation
+async fn img2img_generate(
+    req: web::Json<Img2ImgRequest>,
     pool: web::Data<sqlx::PgPool>,
 ) -> Result<HttpResponse> {
-    let project_id_str = path.into_inner();
-    let project_id = match Uuid::parse_str(&project_id_str) {
-        Ok(id) => id,
-        Err(e) => {
-            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-                "error": format!("无效的项目ID: {}", e)
-            })));
+    let req = req.into_inner();
+    log_api_request(
+        "/api/characters/img2img",
+        "POST",
+        &format!(
+            "prompt: {}, image_url: {:?}, character_id: {:?}, history_id: {:?}, save_to: {:?}",
+            req.prompt, req.image_url, req.character_id, req.history_id, req.save_to
+        ),
+    );
+
+    let source = match img2img_source(&req) {
+        Ok(source) => source,
+        Err(e) => return Ok(img2img_bad_request(e)),
+    };
+
+    // 源角色 / 源历史记录
+    let source_character = match source {
+        Img2ImgSource::Character(id) => match Character::find_by_id(pool.as_ref(), id)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+        {
+            Some(c) if c.image_url.is_empty() => return Ok(img2img_bad_request("该角色还没有图片")),
+            Some(c) => Some(c),
+            None => {
+                return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                    "error": "Character not found"
+                })));
+            }
+        },
+        _ => None,
+    };
+    let source_history = match source {
+        Img2ImgSource::History(id) => match GenerationHistory::find_by_id(pool.as_ref(), id)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+        {
+            Some(h) if h.generation_type != "image" => return Ok(img2img_bad_request("只能使用图片类型的生成历史")),
+            Some(h) => Some(h),
+            None => {
+                return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                    "error": "History not found"
+                })));
+            }
+        },
+        _ => None,
+    };
+
+    let target = match img2img_target(&req, source_history.as_ref().map(|h| h.scene_id)) {
+        Ok(target) => target,
+        Err(e) => return Ok(img2img_bad_request(e)),
+    };
+
+    // 读取源图
+    let (image_base64, mime_type) = if let Img2ImgSource::Upload { data, mime_type } = source {
+        (data, mime_type)
+    } else {
+        let source_url = match (&source, &source_character, &source_history) {
+            (Img2ImgSource::Url(url), _, _) => url.clone(),
+            (_, Some(character), _) => character.image_url.clone(),
+            (_, _, Some(history)) => history.result_url.clone(),
+            _ => String::new(),
+        };
+
+        let bytes = match load_image_bytes(&source_url).await {
+            Ok(bytes) => bytes,
+            Err(e) => return Ok(img2img_bad_request(format!("无法读取源图 {}: {}", source_url, e))),
+        };
+        let mime_type = image::guess_format(&bytes)
+            .map(|f| f.to_mime_type().to_string())
+            .unwrap_or_else(|_| "image/png".to_string());
+        (base64::engine::general_purpose::STANDARD.encode(&bytes), mime_type)
+    };
+
+    // 保存目标
+    let target_character = match target {
+        Img2ImgTarget::Character(id) | Img2ImgTarget::DerivedCharacter(id) => {
+            if Some(id) == source_character.as_ref().map(|c| c.id) {
+                source_character.clone()
+            } else {
+                match Character::find_by_id(pool.as_ref(), id)
+                    .await
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+                {
+                    Some(c) => Some(c),
+                    None => {
+                        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                            "error": "Character not found"
+                        })));
+                    }
+                }
+            }
         }
+        _ => None,
     };
-    
-    // 获取项目信息
-    let project = match Project::find_by_id(pool.as_ref(), project_id).await {
-        Ok(Some(p)) => p,
-        Ok(None) => {
-            return Ok(HttpResponse::NotFound().json(serde_json::json!({
-                "error": "项目不存在"
-            })));
+    let target_scene = if let Img2ImgTarget::Scene(scene_id) = target {
+        match StoryboardScene::find_by_id(pool.as_ref(), scene_id)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+        {
+            Some(scene) if scene.is_locked() => return Ok(scene_locked_response(scene.id)),
+            Some(scene) => Some(scene),
+            None => {
+                return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                    "error": "Scene not found"
+                })));
+            }
         }
+    } else {
+        None
+    };
+
+    // 分镜结果使用分镜所属项目的设置，角色结果使用角色来源项目的设置
+    let project_id = target_scene
+        .as_ref()
+        .map(|s| s.project_id)
+        .or_else(|| target_character.as_ref().and_then(|c| c.source_project_id))
+        .or_else(|| source_character.as_ref().and_then(|c| c.source_project_id));
+    let project = match project_id {
+        Some(id) => Project::find_by_id(pool.as_ref(), id)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?,
+        None => None,
+    };
+
+    let provider = match image_provider_for(project.as_ref(), req.provider.as_deref(), req.model.as_deref()) {
+        Ok(provider) => provider,
+        Err(e) => return Ok(image_error_response(&e)),
+    };
+
+    // Build request with image and text
+    let mut request = ImageRequest::new(format!("Based on this reference image, {}", req.prompt));
+    request.references.push(ReferenceImage {
+        mime_type,
+        data: image_base64,
+    });
+
+    let image_bytes = match provider.generate(&request).await {
+        Ok(images) => images.into_iter().next().unwrap_or_default(),
         Err(e) => {
-            eprintln!("❌ 查询项目失败: {}", e);
-            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": format!("查询项目失败: {}", e)
-            })));
+            log_api_error("img2img_generate", &e.to_string(), &format!("prompt: {}", req.prompt));
+            return Ok(image_error_response(&e));
         }
     };
-    
-    // 删除文件（如果存在）
-    if let Some(ref image_url) = project.combined_characters_image {
-        if image_url.starts_with("/data/") {
-            let local_path = format!(".{}", image_url);
-            if Path::new(&local_path).exists() {
-                fs::remove_file(&local_path)
-                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to delete file: {}", e)))?;
-                println!("   🗑️  已删除文件: {}", local_path);
-            }
+
+    // 同一秒内可能有多次请求，文件名附加随机 ID 避免互相覆盖
+    let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S").to_string();
+    let unique_id = Uuid::new_v4().simple().to_string();
+
+    // 分镜结果保存到项目首帧图目录，其余保存到角色目录；已知项目时按项目配置后处理
+    if let (Some(scene), Some(project)) = (&target_scene, &project) {
+        let project_dir = format!("data/projects/{}/first_frames", project.id);
+        let stem = format!("img2img_{}_{}_{}", scene.id, timestamp, unique_id);
+        let saved = save_processed_image(project, &project_dir, &stem, image_bytes).await?;
+
+        let mut history = GenerationHistory::create_edit(
+            pool.as_ref(),
+            scene.id,
+            req.prompt.clone(),
+            None,
+            saved.url.clone(),
+            source_history.as_ref().map(|h| h.id),
+            None,
+        )
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        if let Some(original_url) = saved.original_url {
+            history = GenerationHistory::set_original_url(pool.as_ref(), history.id, original_url)
+                .await
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
         }
+
+        println!("🎨 图生图结果已记录到分镜生成历史: Scene ID {} ({})", scene.id, saved.url);
+
+        return Ok(HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "image_url": saved.url,
+            "history": history,
+        })));
     }
-    
-    // 更新数据库，将字段设为 NULL
-    sqlx::query(
-        "UPDATE projects SET combined_characters_image = NULL, updated_at = NOW() WHERE id = $1"
+
+    let stem = match &target_character {
+        Some(character) => format!("gemini_img2img_{}_{}_{}", timestamp, character.id, unique_id),
+        None => format!("gemini_img2img_{}_{}", timestamp, unique_id),
+    };
+    let image_url = match &project {
+        Some(project) => save_processed_image(project, "data/characters", &stem, image_bytes).await?.url,
+        None => {
+            fs::create_dir_all("data/characters")?;
+            let filename = format!("{}.{}", stem, image_extension(&image_bytes));
+            let mut file = File::create(format!("data/characters/{}", filename))
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to create file: {}", e)))?;
+            file.write_all(&image_bytes)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to write file: {}", e)))?;
+            format!("/data/characters/{}", filename)
+        }
+    };
+
+    let character = match (target, target_character) {
+        (Img2ImgTarget::Character(_), Some(character)) => {
+            let updated = Character::update_image(pool.as_ref(), character.id, image_url.clone())
+                .await
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+            println!("🎨 角色图片已替换为图生图结果: {} (ID: {})", updated.name, updated.id);
+            Some(updated)
+        }
+        (Img2ImgTarget::DerivedCharacter(_), Some(base)) => {
+            let name = req
+                .name
+                .as_deref()
+                .map(str::trim)
+                .filter(|n| !n.is_empty())
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("{} (衍生)", base.name));
+            let derived = Character::create_derived(pool.as_ref(), &base, name, image_url.clone(), Some(req.prompt.clone()))
+                .await
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+            println!("🎨 已创建衍生角色: {} (ID: {}, 来源: {})", derived.name, derived.id, base.id);
+            Some(derived)
+        }
+        _ => None,
+    };
+
+    println!("🎨 Image-to-image generation completed: {}", image_url);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "image_url": image_url,
+        "character": character,
+    })))
+}
+
+// 录用角色 (Pending -> Generated)
+async fn adopt_character(
+    path: web::Path<String>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let char_id_str = path.into_inner();
+    let char_id = Uuid::parse_str(&char_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid character ID"))?;
+        
+    let updated_character = sqlx::query_as::<_, Character>(
+        "UPDATE characters SET status = 1 WHERE id = $1 RETURNING *"
     )
-    .bind(project_id)
-    .execute(pool.as_ref())
+    .bind(char_id)
+    .fetch_one(pool.as_ref())
     .await
-    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to update project: {}", e)))?;
-    
-    println!("✅ 拼接图片已删除");
+    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
     
     Ok(HttpResponse::Ok().json(serde_json::json!({
-        "message": "拼接图片已删除"
+        "success": true,
+        "character": updated_character,
     })))
 }
 
-
-
-
-
-// 获取项目所有历史记录（画布模式）
-async fn get_project_history(
+async fn get_project_characters(
     path: web::Path<String>,
     pool: web::Data<sqlx::PgPool>,
 ) -> Result<HttpResponse> {
     let project_id_str = path.into_inner();
     let project_id = Uuid::parse_str(&project_id_str)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
-
-    let history = GenerationHistory::find_by_project_id(pool.as_ref(), project_id)
-        .await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-
-    Ok(HttpResponse::Ok().json(history))
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+    
+    match ProjectCharacter::get_all_for_project(pool.as_ref(), project_id).await {
+        Ok(characters) => Ok(HttpResponse::Ok().json(characters)),
+        Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": e.to_string()
+        }))),
+    }
 }
 
-// 获取历史记录
-async fn get_generation_history(
-    path: web::Path<(String, i32)>,
-    query: web::Query<std::collections::HashMap<String, String>>,
+
+async fn create_project_character(
+    path: web::Path<String>,
     pool: web::Data<sqlx::PgPool>,
+    req: web::Json<CreateCharacterRequest>,
 ) -> Result<HttpResponse> {
-    let (_project_id_str, scene_id) = path.into_inner();
-    let generation_type = query.get("type");
-
-    let history = if let Some(gen_type) = generation_type {
-        GenerationHistory::find_by_scene_and_type(pool.as_ref(), scene_id, gen_type)
-            .await
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
-    } else {
-        GenerationHistory::find_by_scene_id(pool.as_ref(), scene_id)
-            .await
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
-    };
-
-    Ok(HttpResponse::Ok().json(history))
+    let project_id_str = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+    
+    // Create new character in global library
+    match Character::create(
+        pool.as_ref(), 
+        req.name.clone(), 
+        "".to_string(), 
+        req.prompt.clone(), 
+        Some(project_id)
+    ).await {
+        Ok(character) => {
+            // Link to project
+            match ProjectCharacter::link(pool.as_ref(), project_id, character.id, req.display_order.unwrap_or(0)).await {
+                Ok(_) => {
+                    // Return the view struct
+                     Ok(HttpResponse::Ok().json(ProjectCharacter { 
+                        id: character.id,
+                        name: character.name,
+                        image_url: character.image_url,
+                        prompt: character.prompt,
+                        category: character.category,
+                        tags: character.tags,
+                        display_order: req.display_order.unwrap_or(0)
+                    }))
+                },
+                Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": format!("Failed to link character: {}", e)
+                })))
+            }
+        },
+        Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": e.to_string()
+        }))),
+    }
 }
 
-// 删除历史记录
-async fn delete_generation_history(
-    path: web::Path<(String, i32, i32)>,
+
+async fn update_project_character(
     pool: web::Data<sqlx::PgPool>,
+    path: web::Path<(String, String)>,
+    req: web::Json<UpdateCharacterRequest>,
 ) -> Result<HttpResponse> {
-    let (_project_id, _scene_id, history_id) = path.into_inner();
-
-    GenerationHistory::delete(pool.as_ref(), history_id)
-        .await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-
-    Ok(HttpResponse::Ok().json(serde_json::json!({
-        "success": true,
-        "message": "History deleted"
-    })))
+    let (_, char_id_str) = path.into_inner();
+    let char_id = Uuid::parse_str(&char_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid character ID"))?;
+    
+    match Character::update(
+        pool.as_ref(), 
+        char_id, 
+        req.name.clone(), 
+        req.prompt.clone(),
+        req.category.clone(),
+        req.tags.clone()
+    ).await {
+        Ok(character) => Ok(HttpResponse::Ok().json(character)),
+        Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": e.to_string()
+        }))),
+    }
 }
 
-// 更新历史记录的创建时间（设置为最新）
-async fn update_generation_history_time(
-    path: web::Path<(String, i32, i32)>,
+async fn get_character_detail(
+    path: web::Path<String>,
     pool: web::Data<sqlx::PgPool>,
 ) -> Result<HttpResponse> {
-    let (_project_id, _scene_id, history_id) = path.into_inner();
+    let char_id_str = path.into_inner();
+    let char_id = Uuid::parse_str(&char_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid character ID"))?;
 
-    let updated_history = GenerationHistory::update_created_at(pool.as_ref(), history_id)
+    let char = sqlx::query_as::<_, Character>("SELECT * FROM characters WHERE id = $1")
+        .bind(char_id)
+        .fetch_optional(pool.as_ref())
         .await
         .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 
-    Ok(HttpResponse::Ok().json(serde_json::json!({
-        "success": true,
-        "message": "History time updated",
-        "history": updated_history
-    })))
+    match char {
+        Some(c) => Ok(HttpResponse::Ok().json(c)),
+        None => Ok(HttpResponse::NotFound().json(serde_json::json!({"error": "Character not found"}))),
+    }
 }
 
-// 上传文件到历史记录
-async fn upload_scene_media(
-    path: web::Path<(String, i32)>,
-    mut payload: Multipart,
+async fn update_character_global(
     pool: web::Data<sqlx::PgPool>,
+    path: web::Path<String>,
+    req: web::Json<UpdateCharacterRequest>,
 ) -> Result<HttpResponse> {
-    let (project_id_str, scene_id) = path.into_inner();
-    let project_id = Uuid::parse_str(&project_id_str)
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
-    
-    println!("📤 开始上传文件: 项目ID={}, 分镜ID={}", project_id, scene_id);
-    
-    // 验证场景是否存在
-    let scene = StoryboardScene::find_by_id(pool.as_ref(), scene_id)
-        .await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
-        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Scene not found"))?;
-    
-    if scene.project_id != project_id {
-        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "Scene does not belong to this project"
-        })));
-    }
-    
-    // 创建上传目录
-    let upload_dir = PathBuf::from(format!("./data/projects/{}/scenes/{}/uploads", project_id, scene_id));
-    fs::create_dir_all(&upload_dir)?;
-    
-    let mut file_path = PathBuf::new();
-    let mut prompt = String::new();
-    let mut generation_type = String::new();
-    let mut file_extension = String::new();
-    
-    // 处理 multipart 表单
-    while let Some(item) = payload.next().await {
-        let mut field = item?;
-        let content_disposition = field.content_disposition();
-        let field_name = content_disposition.get_name().unwrap_or("");
-        
-        match field_name {
-            "file" => {
-                if let Some(filename) = content_disposition.get_filename() {
-                    // 提取文件扩展名
-                    file_extension = Path::new(filename)
-                        .extension()
-                        .and_then(|s| s.to_str())
-                        .unwrap_or("bin")
-                        .to_string();
-                    
-                    // 生成唯一文件名
-                    let timestamp = chrono::Utc::now().timestamp();
-                    let new_filename = format!("{}_{}.{}", timestamp, scene_id, file_extension);
-                    let file_path_clone = upload_dir.join(&new_filename);
-                    file_path = file_path_clone.clone();
-                    
-                    // 保存文件
-                    let mut f = web::block(move || File::create(file_path_clone))
-                        .await??;
-                    
-                    while let Some(chunk) = field.next().await {
-                        let data = chunk?;
-                        f = web::block(move || f.write_all(&data).map(|_| f)).await??;
-                    }
-                    
-                    println!("✅ 文件已保存: {:?}", file_path);
-                }
-            },
-            "prompt" => {
-                // 读取提示词
-                while let Some(chunk) = field.next().await {
-                    let data = chunk?;
-                    prompt.push_str(&String::from_utf8_lossy(&data));
-                }
-            },
-            "generation_type" => {
-                // 读取生成类型
-                while let Some(chunk) = field.next().await {
-                    let data = chunk?;
-                    generation_type.push_str(&String::from_utf8_lossy(&data));
-                }
-            },
-            _ => {
-                // 忽略其他字段
-                while let Some(_chunk) = field.next().await {}
-            }
-        }
-    }
+    let char_id_str = path.into_inner();
+    let char_id = Uuid::parse_str(&char_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid character ID"))?;
     
-    // 验证文件是否上传
-    if file_path.as_os_str().is_empty() {
-        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "No file uploaded"
-        })));
+    match Character::update(
+        pool.as_ref(), 
+        char_id, 
+        req.name.clone(), 
+        req.prompt.clone(),
+        req.category.clone(),
+        req.tags.clone()
+    ).await {
+        Ok(character) => Ok(HttpResponse::Ok().json(character)),
+        Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": e.to_string()
+        }))),
     }
-    
-    // 验证生成类型
-    if generation_type != "image" && generation_type != "video" {
-        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "Invalid generation_type, must be 'image' or 'video'"
-        })));
     }
+
+async fn create_character_global(
+    pool: web::Data<sqlx::PgPool>,
+    req: web::Json<CreateCharacterRequest>,
+) -> Result<HttpResponse> {
+    log_api_request("/api/characters", "POST", &format!("name: {}, prompt: {:?}", req.name, req.prompt));
     
-    // 验证文件类型
-    let valid_image_exts = vec!["jpg", "jpeg", "png", "webp"];
-    let valid_video_exts = vec!["mp4", "mov", "webm"];
-    
-    let is_valid_image = valid_image_exts.contains(&file_extension.to_lowercase().as_str());
-    let is_valid_video = valid_video_exts.contains(&file_extension.to_lowercase().as_str());
-    
-    if generation_type == "image" && !is_valid_image {
-        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "Invalid image file format"
-        })));
+    match Character::create(
+        pool.as_ref(), 
+        req.name.clone(), 
+        "".to_string(), 
+        req.prompt.clone(), 
+        None
+    ).await {
+        Ok(character) => Ok(HttpResponse::Ok().json(character)),
+        Err(e) => {
+            log_api_error("create_character_global", &e.to_string(), &format!("name: {}", req.name));
+            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": e.to_string()
+            })))
+        },
     }
+}
+
+async fn upload_character_image_global(
+    path: web::Path<String>,
+    pool: web::Data<sqlx::PgPool>,
+    mut payload: Multipart,
+) -> Result<HttpResponse> {
+    let char_id_str = path.into_inner();
+    let char_id = Uuid::parse_str(&char_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid character ID"))?;
+
+    let mut filename = String::new();
     
-    if generation_type == "video" && !is_valid_image && !is_valid_video {
+    while let Ok(Some(mut field)) = payload.try_next().await {
+        let content_type = field.content_disposition();
+        
+        if let Some(name) = content_type.get_name() {
+            if name == "file" {
+                let ext = Path::new(content_type.get_filename().unwrap_or("image.png"))
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .unwrap_or("png");
+                
+                filename = format!("{}.{}", char_id, ext);
+                let filepath = format!("data/characters/{}", filename);
+                
+                // Ensure directory exists
+                fs::create_dir_all("data/characters")?;
+                
+                let mut f = File::create(filepath)?;
+                
+                while let Ok(Some(chunk)) = field.try_next().await {
+                    f.write_all(&chunk)?;
+                }
+            }
+        }
+    }
+
+    if filename.is_empty() {
         return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "Invalid file format for video generation"
+            "error": "No file uploaded"
         })));
     }
+
+    let filepath = format!("data/characters/{}", filename);
     
-    // 生成相对路径URL
-    let relative_path = file_path.strip_prefix("./")
-        .unwrap_or(&file_path)
-        .to_string_lossy()
-        .to_string();
-    let result_url = format!("/{}", relative_path.replace("\\", "/"));
+    // 上传到 Cloudflare R2
+    let cloudflare_storage = cloudflare::CloudflareStorage::new()
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to create Cloudflare storage: {}", e)))?;
     
-    // 创建历史记录
-    let history = GenerationHistory::create(
-        pool.as_ref(),
-        scene_id,
-        generation_type.clone(),
-        prompt.clone(),
-        result_url.clone(),
-    )
-    .await
-    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let cloudflare_url = cloudflare_storage.get_or_upload(pool.as_ref(), &filepath, "image")
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to upload to Cloudflare: {}", e)))?;
+
+    println!("   ✅ 角色图片已上传到 Cloudflare: {}", cloudflare_url);
     
-    // 更新场景的最新URL
-    if generation_type == "image" {
-        StoryboardScene::update_latest_image(pool.as_ref(), scene_id, result_url.clone())
-            .await
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-            
-        // 如果有提示词，同步更新场景的首帧图提示词
-        if !prompt.is_empty() {
-            StoryboardScene::update_prompts(pool.as_ref(), scene_id, Some(prompt.clone()), None, None)
-                .await
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-        }
-    } else {
-        StoryboardScene::update_latest_video(pool.as_ref(), scene_id, result_url.clone())
-            .await
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-            
-        // 如果有提示词，同步更新场景的视频提示词
-        if !prompt.is_empty() {
-            StoryboardScene::update_prompts(pool.as_ref(), scene_id, None, Some(prompt.clone()), None)
-                .await
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-        }
+    // Update character image_url with Cloudflare URL
+    match sqlx::query("UPDATE characters SET image_url = $1 WHERE id = $2")
+        .bind(&cloudflare_url)
+        .bind(char_id)
+        .execute(pool.as_ref())
+        .await
+    {
+        Ok(_) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "image_url": cloudflare_url
+        }))),
+        Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": e.to_string()
+        }))),
     }
-    
-    println!("✅ 上传成功: ID={}, URL={}", history.id, result_url);
-    
-    Ok(HttpResponse::Ok().json(serde_json::json!({
-        "success": true,
-        "message": "Upload successful",
-        "history": history,
-        "result_url": result_url
-    })))
 }
 
-// 在Finder中显示文件
-async fn reveal_file_in_finder(
-    req_body: web::Json<RevealFileRequest>,
+
+#[derive(Deserialize)]
+struct LinkCharacterRequest {
+    char_id: Uuid,
+    display_order: Option<i32>,
+}
+
+async fn link_project_character(
+    path: web::Path<String>,
+    pool: web::Data<sqlx::PgPool>,
+    req: web::Json<LinkCharacterRequest>,
 ) -> Result<HttpResponse> {
-    let file_path = &req_body.file_path;
-    
-    println!("📁 尝试在Finder中显示文件: {}", file_path);
-    
-    // 转换为绝对路径
-    let absolute_path = if file_path.starts_with("/") {
-        // 已经是绝对路径，去掉开头的 /
-        PathBuf::from(format!(".{}", file_path))
-    } else if file_path.starts_with("./") {
-        PathBuf::from(file_path)
-    } else {
-        PathBuf::from(format!("./{}", file_path))
-    };
-    
-    // 验证文件存在
-    if !absolute_path.exists() {
-        println!("❌ 文件不存在: {:?}", absolute_path);
-        return Ok(HttpResponse::NotFound().json(serde_json::json!({
-            "error": "文件不存在"
-        })));
-    }
-    
-    // 验证路径在data目录内（安全检查）
-    let canonical_path = match fs::canonicalize(&absolute_path) {
-        Ok(path) => path,
-        Err(e) => {
-            println!("❌ 无法解析路径: {:?} - {}", absolute_path, e);
-            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-                "error": "无效的文件路径"
-            })));
-        }
-    };
+    let project_id_str = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
     
-    let data_dir = match fs::canonicalize("./data") {
-        Ok(path) => path,
-        Err(_) => {
-            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "数据目录不存在"
-            })));
-        }
-    };
+    // Check if character exists first? Or just try link (FK constraint handles it, but better explicit error)
+    // ProjectCharacter::link handles insert.
     
-    if !canonical_path.starts_with(&data_dir) {
-        println!("❌ 路径不在数据目录内: {:?}", canonical_path);
-        return Ok(HttpResponse::Forbidden().json(serde_json::json!({
-            "error": "只能访问数据目录内的文件"
-        })));
+    match ProjectCharacter::link(
+        pool.as_ref(), 
+        project_id, 
+        req.char_id, 
+        req.display_order.unwrap_or(0)
+    ).await {
+        Ok(_) => {
+            // Return the full ProjectCharacter view
+            let char_data = sqlx::query_as::<_, Character>("SELECT * FROM characters WHERE id = $1")
+                .bind(req.char_id)
+                .fetch_one(pool.as_ref())
+                .await
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+                
+            Ok(HttpResponse::Ok().json(ProjectCharacter {
+                id: char_data.id,
+                name: char_data.name,
+                image_url: char_data.image_url,
+                prompt: char_data.prompt,
+                category: char_data.category,
+                tags: char_data.tags,
+                display_order: req.display_order.unwrap_or(0)
+            }))
+        },
+        Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("Failed to link: {}", e)
+        })))
     }
+}
+
+
+async fn delete_project_character(
+    path: web::Path<(String, String)>,
+    pool: web::Data<sqlx::PgPool>,
+) -> Result<HttpResponse> {
+    let (project_id_str, char_id_str) = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str).unwrap();
+    let char_id = Uuid::parse_str(&char_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid character ID"))?;
     
-    // 使用 open -R 命令在Finder中显示文件（macOS）
-    let output = AsyncCommand::new("open")
-        .args(&["-R", canonical_path.to_str().unwrap()])
-        .output()
-        .await;
-    
-    match output {
-        Ok(result) => {
-            if result.status.success() {
-                println!("✅ 已在Finder中打开: {:?}", canonical_path);
-                Ok(HttpResponse::Ok().json(serde_json::json!({
-                    "success": true,
-                    "message": "文件已在Finder中显示"
-                })))
-            } else {
-                let error_msg = String::from_utf8_lossy(&result.stderr);
-                println!("❌ 打开Finder失败: {}", error_msg);
-                Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                    "error": format!("打开Finder失败: {}", error_msg)
-                })))
+    match ProjectCharacter::unlink(pool.as_ref(), project_id, char_id).await {
+        Ok(_) => {
+            // 删除角色后，清除拼接的角色图
+            if let Ok(Some(project)) = Project::find_by_id(pool.as_ref(), project_id).await {
+                if let Some(ref image_url) = project.combined_characters_image {
+                    // 删除文件
+                    if image_url.starts_with("/data/") {
+                        let local_path = format!(".{}", image_url);
+                        if Path::new(&local_path).exists() {
+                            let _ = fs::remove_file(&local_path);
+                            println!("   🗑️  已删除拼接图片: {}", local_path);
+                        }
+                    }
+                    
+                    // 更新数据库
+                    let _ = sqlx::query(
+                        "UPDATE projects SET combined_characters_image = NULL, updated_at = NOW() WHERE id = $1"
+                    )
+                    .bind(project_id)
+                    .execute(pool.as_ref())
+                    .await;
+                    
+                    println!("   ✅ 已清除项目的拼接角色图");
+                }
             }
-        },
-        Err(e) => {
-            println!("❌ 执行open命令失败: {}", e);
-            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": format!("执行open命令失败: {}", e)
+            
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "success": true
             })))
-        }
+        },
+        Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": e.to_string()
+        }))),
     }
 }
 
-// 合成视频（模拟）
-async fn composite_video(
+
+async fn update_global_prompt(
     path: web::Path<String>,
     pool: web::Data<sqlx::PgPool>,
+    req: web::Json<UpdateGlobalPromptRequest>,
 ) -> Result<HttpResponse> {
     let project_id_str = path.into_inner();
     let project_id = Uuid::parse_str(&project_id_str)
         .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
 
-    println!("🎬 开始合成项目视频: {}", project_id);
+    let project = Project::find_by_id(pool.get_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Project not found"))?;
 
-    // 1. 获取所有分镜场景，按顺序排列
-    let scenes = StoryboardScene::find_by_project_id(pool.as_ref(), project_id)
+    let mut tx = pool.begin()
         .await
         .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    match sqlx::query(
+        "UPDATE projects SET global_image_prompt = $1, global_video_prompt = $2, updated_at = NOW() WHERE id = $3"
+    )
+    .bind(&req.global_image_prompt)
+    .bind(&req.global_video_prompt)
+    .bind(project_id)
+    .execute(&mut *tx)
+    .await
+    {
+        Ok(_) => {
+            let author = edit_author(&req.author);
+            for (field, old_value, new_value) in [
+                ("global_image_prompt", project.global_image_prompt, &req.global_image_prompt),
+                ("global_video_prompt", project.global_video_prompt, &req.global_video_prompt),
+            ] {
+                PromptEdit::record(&mut *tx, project_id, None, field, old_value, new_value.clone(), author.clone())
+                    .await
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+            }
+            tx.commit()
+                .await
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "success": true
+            })))
+        }
+        Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": e.to_string()
+        }))),
+    }
+}
 
-    // 2. 过滤出有视频的分镜
-    let video_scenes: Vec<_> = scenes.iter()
-        .filter(|s| s.latest_video_url.is_some())
-        .collect();
+// 更新项目基本信息（标题、类型、简介、封面）
+async fn update_project(
+    path: web::Path<String>,
+    pool: web::Data<sqlx::PgPool>,
+    req: web::Json<UpdateProjectRequest>,
+) -> Result<HttpResponse> {
+    let project_id_str = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+    let req = req.into_inner();
 
-    if video_scenes.is_empty() {
+    let title = req.title.map(|t| t.trim().to_string());
+    if title.as_deref() == Some("") {
         return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "没有可用的分镜视频，请先生成分镜视频"
+            "error": "项目标题不能为空"
         })));
     }
 
-    println!("📊 找到 {} 个分镜视频", video_scenes.len());
-
-    // 3. 创建输出目录
-    let project_dir = format!("data/projects/{}/composite", project_id);
-    fs::create_dir_all(&project_dir)?;
-
-    // 4. 创建临时文件列表
-    let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S").to_string();
-    let concat_list_path = format!("{}/concat_list_{}.txt", project_dir, timestamp);
-    let output_filename = format!("composite_{}.mp4", timestamp);
-    let output_path = format!("{}/{}", project_dir, output_filename);
-    let video_url = format!("/data/projects/{}/composite/{}", project_id, output_filename);
-
-    // 5. 写入视频文件列表
-    let mut concat_content = String::new();
-    for scene in &video_scenes {
-        if let Some(ref url) = scene.latest_video_url {
-            // 转换 URL 为本地路径
-            let local_path = if url.starts_with("/data/") {
-                format!(".{}", url)
-            } else if url.starts_with("http") {
-                // 如果是外部URL，跳过（在真实场景中可能需要下载）
-                println!("⚠️  跳过外部URL: {}", url);
-                continue;
-            } else {
-                url.clone()
-            };
+    if let Some(project_type) = &req.project_type {
+        if project_type != "video" && project_type != "comic" {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "项目类型必须是 video 或 comic"
+            })));
+        }
+    }
 
-            // 检查文件是否存在
-            if !Path::new(&local_path).exists() {
-                println!("⚠️  视频文件不存在: {}", local_path);
-                continue;
-            }
+    // 空字符串表示清空
+    let description = req.description.map(|d| Some(d.trim().to_string()).filter(|d| !d.is_empty()));
+    let cover_image_url = req.cover_image_url.map(|u| Some(u.trim().to_string()).filter(|u| !u.is_empty()));
 
-            concat_content.push_str(&format!("file '{}'\n", 
-                Path::new(&local_path).canonicalize()
-                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
-                    .to_str()
-                    .unwrap()
-            ));
+    if let Some(Some(url)) = &cover_image_url {
+        if !url.starts_with("/data/") && !url.starts_with("http://") && !url.starts_with("https://") {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "封面图URL格式无效"
+            })));
         }
     }
 
-    if concat_content.is_empty() {
+    if let Err(e) = validate_output_format(req.aspect_ratio.as_deref(), req.resolution.as_deref(), req.fps) {
         return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "没有找到有效的本地视频文件"
+            "error": e
         })));
     }
 
-    fs::write(&concat_list_path, concat_content)?;
-    println!("📝 创建文件列表: {}", concat_list_path);
+    if let Some(mode) = &req.prompt_translation {
+        if !PROMPT_TRANSLATION_MODES.contains(&mode.as_str()) {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("无效的翻译模式: {}，可选值: {}", mode, PROMPT_TRANSLATION_MODES.join(", "))
+            })));
+        }
+    }
 
-    // 6. 执行 ffmpeg 合成
-    println!("🎬 开始使用 ffmpeg 合成视频...");
-    let output = std::process::Command::new("ffmpeg")
-        .args(&[
-            "-f", "concat",
-            "-safe", "0",
-            "-i", &concat_list_path,
-            "-c", "copy",
-            "-y", // 覆盖已存在的文件
-            &output_path,
-        ])
-        .output()
-        .map_err(|e| {
-            println!("❌ ffmpeg 执行失败: {}", e);
-            std::io::Error::new(std::io::ErrorKind::Other, format!("ffmpeg execution failed: {}", e))
-        })?;
+    // 空字符串表示恢复默认
+    let image_provider = req.image_provider.map(|p| Some(p.trim().to_string()).filter(|p| !p.is_empty()));
+    let image_model = req.image_model.map(|m| Some(m.trim().to_string()).filter(|m| !m.is_empty()));
+    if let Some(Some(provider)) = &image_provider {
+        if !IMAGE_PROVIDERS.contains(&provider.as_str()) {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("无效的图片生成服务: {}，可选值: {}", provider, IMAGE_PROVIDERS.join(", "))
+            })));
+        }
+    }
 
-    if !output.status.success() {
-        let error_msg = String::from_utf8_lossy(&output.stderr);
-        println!("❌ ffmpeg 合成失败: {}", error_msg);
-        
-        // 清理临时文件
-        let _ = fs::remove_file(&concat_list_path);
-        
-        return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": format!("视频合成失败: {}", error_msg)
-        })));
+    if let Some(mode) = &req.continuity_mode {
+        if !CONTINUITY_MODES.contains(&mode.as_str()) {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("无效的连贯模式: {}，可选值: {}", mode, CONTINUITY_MODES.join(", "))
+            })));
+        }
     }
 
-    println!("✅ 视频合成成功: {}", output_path);
+    // 空字符串表示恢复服务器默认
+    let image_postprocess = match req.image_postprocess {
+        None => None,
+        Some(serde_json::Value::String(s)) if s.trim().is_empty() => Some(None),
+        Some(value) => match serde_json::from_value::<PostProcessConfig>(value) {
+            Ok(config) => {
+                if let Err(e) = config.validate() {
+                    return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                        "error": e
+                    })));
+                }
+                Some(Some(config))
+            }
+            Err(e) => {
+                return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": format!("无效的后处理配置: {}", e)
+                })));
+            }
+        },
+    };
 
-    // 7. 清理临时文件
-    let _ = fs::remove_file(&concat_list_path);
+    // 0 表示清除目标时长
+    let target_runtime = match req.target_runtime {
+        Some(t) if t < 0.0 || !t.is_finite() => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "目标时长必须为非负数"
+            })));
+        }
+        Some(t) => Some(if t > 0.0 { Some(t) } else { None }),
+        None => None,
+    };
 
-    // 8. 记录合成历史到数据库
-    let composite = CompositeVideo::create(
-        pool.as_ref(), 
-        project_id, 
-        video_url.clone(),
-        video_scenes.len() as i32
-    )
-        .await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    // 全部字段校验通过后在同一个事务中写入
+    let result: std::result::Result<Option<Project>, sqlx::Error> = async {
+        let mut tx = pool.begin().await?;
 
-    println!("🎞️ 视频合成记录已保存: ID {}", composite.id);
+        if req.aspect_ratio.is_some() || req.resolution.is_some() || req.fps.is_some() {
+            Project::update_output_format(&mut *tx, project_id, req.aspect_ratio, req.resolution, req.fps).await?;
+        }
+        if let Some(mode) = &req.prompt_translation {
+            Project::update_prompt_translation(&mut *tx, project_id, mode).await?;
+        }
+        if image_provider.is_some() || image_model.is_some() {
+            Project::update_image_provider(&mut *tx, project_id, image_provider, image_model).await?;
+        }
+        if let Some(mode) = &req.continuity_mode {
+            Project::update_continuity_mode(&mut *tx, project_id, mode).await?;
+        }
+        if let Some(config) = image_postprocess {
+            Project::update_image_postprocess(&mut *tx, project_id, config).await?;
+        }
+        if let Some(target_runtime) = target_runtime {
+            Project::update_target_runtime(&mut *tx, project_id, target_runtime).await?;
+        }
 
-    Ok(HttpResponse::Ok().json(serde_json::json!({
-        "success": true,
-        "message": "视频合成成功",
-        "composite_id": composite.id,
-        "video_url": video_url,
-        "scene_count": composite.scene_count,
-        "created_at": composite.created_at,
-    })))
+        let project = Project::update_metadata(
+            &mut *tx,
+            project_id,
+            title,
+            req.project_type,
+            description,
+            cover_image_url,
+        )
+        .await?;
+
+        // 项目不存在时回滚
+        if project.is_some() {
+            tx.commit().await?;
+        }
+        Ok(project)
+    }
+    .await;
+
+    match result {
+        Ok(Some(project)) => {
+            println!("✏️  项目信息已更新: {} ({})", project.title, project.id);
+            Ok(HttpResponse::Ok().json(project))
+        }
+        Ok(None) => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "项目不存在"
+        }))),
+        Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": e.to_string()
+        }))),
+    }
 }
 
-// 获取合成历史
-async fn get_composite_history(
+// 更新项目提示词变量（整体替换）
+async fn update_prompt_variables(
     path: web::Path<String>,
     pool: web::Data<sqlx::PgPool>,
+    req: web::Json<UpdatePromptVariablesRequest>,
 ) -> Result<HttpResponse> {
     let project_id_str = path.into_inner();
     let project_id = Uuid::parse_str(&project_id_str)
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
-
-    let composites = CompositeVideo::find_by_project_id(pool.as_ref(), project_id)
-        .await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
 
-    Ok(HttpResponse::Ok().json(composites))
+    let variables = match normalize_prompt_variables(req.into_inner().variables) {
+        Ok(variables) => variables,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": e
+            })));
+        }
+    };
+
+    match Project::update_prompt_variables(pool.as_ref(), project_id, variables).await {
+        Ok(Some(project)) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "success": true,
+            "variables": project.prompt_variables
+        }))),
+        Ok(None) => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "项目不存在"
+        }))),
+        Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": e.to_string()
+        }))),
+    }
 }
 
-// 导出项目首帧图
-async fn export_project_images(
+async fn update_project_script(
     path: web::Path<String>,
     pool: web::Data<sqlx::PgPool>,
+    req: web::Json<UpdateScriptRequest>,
 ) -> Result<HttpResponse> {
     let project_id_str = path.into_inner();
     let project_id = Uuid::parse_str(&project_id_str)
         .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
 
-    println!("📦 开始导出项目首帧图到临时目录: {}", project_id);
+    let project = Project::find_by_id(pool.get_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Project not found"))?;
 
-    // 1. 获取所有分镜场景，按顺序排列
-    let scenes = StoryboardScene::find_by_project_id(pool.as_ref(), project_id)
+    let mut tx = pool.begin()
         .await
         .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-
-    // 2. 过滤出有首帧图的分镜
-    let image_scenes: Vec<_> = scenes.iter()
-        .filter(|s| s.latest_image_url.is_some())
-        .collect();
-
-    if image_scenes.is_empty() {
-        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "没有可导出的首帧图，请先生成首帧图"
-        })));
+    match sqlx::query(
+        "UPDATE projects SET script = $1, updated_at = NOW() WHERE id = $2"
+    )
+    .bind(&req.script)
+    .bind(project_id)
+    .execute(&mut *tx)
+    .await
+    {
+        Ok(_) => {
+            PromptEdit::record(
+                &mut *tx,
+                project_id,
+                None,
+                "script",
+                project.script,
+                Some(req.script.clone()),
+                edit_author(&req.author),
+            )
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+            tx.commit()
+                .await
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+            Ok(HttpResponse::Ok().json(serde_json::json!({
+                "success": true
+            })))
+        }
+        Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": e.to_string()
+        }))),
     }
+}
 
-    println!("📊 找到 {} 个首帧图", image_scenes.len());
-
-    // 3. 创建临时导出目录（固定路径）
-    let export_path = format!("data/projects/{}/temp_export_images", project_id);
+async fn delete_character_global(
+    pool: web::Data<sqlx::PgPool>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let char_id_str = path.into_inner();
+    let char_id = Uuid::parse_str(&char_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid character ID"))?;
     
-    // 4. 如果目录已存在，先清空
-    if Path::new(&export_path).exists() {
-        println!("🗑️  清空现有临时目录: {}", export_path);
-        fs::remove_dir_all(&export_path)?;
+    match Character::delete(pool.as_ref(), char_id).await {
+        Ok(_) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "success": true
+        }))),
+        Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": e.to_string()
+        }))),
     }
-    
-    // 5. 创建空的导出目录
-    println!("📁 创建临时导出目录: {}", export_path);
-    fs::create_dir_all(&export_path)?;
-
-    // 6. 复制所有首帧图文件
-    for scene in &image_scenes {
-        if let Some(ref url) = scene.latest_image_url {
-            // 转换 URL 为本地路径
-            let local_path = if url.starts_with("/data/") {
-                format!(".{}", url)
-            } else if url.starts_with("http") {
-                println!("⚠️  跳过外部URL: {}", url);
-                continue;
-            } else {
-                url.clone()
-            };
+}
 
-            // 检查文件是否存在
-            if !Path::new(&local_path).exists() {
-                println!("⚠️  首帧图文件不存在: {}", local_path);
-                continue;
-            }
+// GPT-nano 使用的模型
+const GPT_NANO_MODEL: &str = "gpt-5-nano-2025-08-07";
 
-            // 获取文件扩展名
-            let ext = Path::new(&local_path)
-                .extension()
-                .and_then(|s| s.to_str())
-                .unwrap_or("png");
+// 调用 GPT-nano 文本模型，返回 message 类型输出中的文本
+// task 为调用场景（mock::GPT_NANO_TASK_*），模拟模式下随请求发送
+async fn call_gpt_nano(task: &str, input: String, temperature: f32, max_tokens: i32) -> std::io::Result<String> {
+    let api_key = std::env::var("GPT_NANO_API_KEY")
+        .map_err(|_| {
+            println!("❌ 错误: GPT_NANO_API_KEY 环境变量未设置");
+            std::io::Error::new(std::io::ErrorKind::Other, "GPT_NANO_API_KEY not found in environment")
+        })?;
 
-            // 确定输出文件名
-            let output_filename = format!("scene_{}.{}", scene.scene_index, ext);
-            let output_path = format!("{}/{}", export_path, output_filename);
+    let client = Client::new();
 
-            // 复制文件
-            fs::copy(&local_path, &output_path)
-                .map_err(|e| {
-                    println!("❌ 复制文件失败: {} -> {}: {}", local_path, output_path, e);
-                    std::io::Error::new(std::io::ErrorKind::Other, format!("复制文件失败: {}", e))
-                })?;
+    let gpt_request = GptNanoRequest {
+        model: GPT_NANO_MODEL.to_string(),
+        input,
+        temperature,
+        max_tokens,
+    };
 
-            println!("✅ 已复制: {} -> {}", local_path, output_filename);
-        }
-    }
+    println!("📡 正在调用 GPT-nano API...");
 
-    println!("✅ 所有首帧图已复制到临时目录: {}", export_path);
+    // 从环境变量读取 GPT-nano API 配置（必须设置）
+    let gpt_nano_base_url = std::env::var("GPT_NANO_BASE_URL")
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "GPT_NANO_BASE_URL not set"))?;
+    let gpt_nano_endpoint = std::env::var("GPT_NANO_ENDPOINT")
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "GPT_NANO_ENDPOINT not set"))?;
+    let gpt_nano_url = format!("{}{}", gpt_nano_base_url, gpt_nano_endpoint);
 
-    // 7. 打开导出目录
-    let canonical_path = fs::canonicalize(&export_path)
+    let mut request = client
+        .post(&gpt_nano_url)
+        .header("Content-Type", "application/json")
+        .header("Authorization", format!("Bearer {}", api_key));
+    if mock::enabled() {
+        request = request.header(mock::GPT_NANO_TASK_HEADER, task);
+    }
+    let response = request
+        .json(&gpt_request)
+        .send()
+        .await
         .map_err(|e| {
-            println!("❌ 无法解析路径: {:?} - {}", export_path, e);
-            std::io::Error::new(std::io::ErrorKind::Other, "无法解析路径")
+            println!("❌ GPT-nano API 请求失败: {}", e);
+            std::io::Error::new(std::io::ErrorKind::Other, format!("GPT-nano API request failed: {}", e))
         })?;
 
-    println!("📂 打开目录: {:?}", canonical_path);
-
-    #[cfg(target_os = "macos")]
-    {
-        let output = Command::new("open")
-            .arg(&canonical_path)
-            .output()
-            .map_err(|e| {
-                println!("❌ 执行open命令失败: {}", e);
-                std::io::Error::new(std::io::ErrorKind::Other, format!("执行open命令失败: {}", e))
-            })?;
-
-        if !output.status.success() {
-            let error_msg = String::from_utf8_lossy(&output.stderr);
-            println!("❌ open命令执行失败: {}", error_msg);
-        } else {
-            println!("✅ 已在Finder中打开目录");
-        }
-    }
+    let status = response.status();
+    println!("📥 API 响应状态码: {}", status);
 
-    #[cfg(not(target_os = "macos"))]
-    {
-        println!("⚠️  当前系统不支持自动打开文件管理器");
+    if !status.is_success() {
+        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+        println!("❌ API 返回错误: {}", error_text);
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            format!("GPT-nano API 调用失败: {}", error_text)
+        ));
     }
 
-    Ok(HttpResponse::Ok().json(serde_json::json!({
-        "success": true,
-        "message": "首帧图已导出到临时目录",
-        "export_path": export_path,
-        "image_count": image_scenes.len(),
-    })))
-}
+    // 先获取原始响应文本用于调试
+    let response_text = response.text().await
+        .map_err(|e| {
+            println!("❌ 无法读取响应文本: {}", e);
+            std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to read response: {}", e))
+        })?;
 
-// 导出项目视频
-async fn export_project_videos(
-    path: web::Path<String>,
-    pool: web::Data<sqlx::PgPool>,
-) -> Result<HttpResponse> {
-    let project_id_str = path.into_inner();
-    let project_id = Uuid::parse_str(&project_id_str)
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+    let _ = logger::log_model_interaction(
+        GPT_NANO_MODEL,
+        &serde_json::to_string_pretty(&gpt_request).unwrap_or_default(),
+        &response_text,
+    );
 
-    println!("📦 开始导出项目视频到临时目录: {}", project_id);
+    // 解析JSON响应
+    let gpt_response: GptNanoResponse = serde_json::from_str(&response_text)
+        .map_err(|e| {
+            println!("❌ 解析 GPT 响应失败: {}", e);
+            std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to parse GPT-nano response: {}", e))
+        })?;
 
-    // 1. 获取所有分镜场景，按顺序排列
-    let scenes = StoryboardScene::find_by_project_id(pool.as_ref(), project_id)
-        .await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    println!("✅ 成功解析 GPT 响应，output 数量: {}", gpt_response.output.len());
 
-    // 2. 过滤出有视频的分镜
-    let video_scenes: Vec<_> = scenes.iter()
-        .filter(|s| s.latest_video_url.is_some())
-        .collect();
+    // 提取响应文本
+    gpt_response.output
+        .iter()
+        .find(|o| o.output_type == "message")
+        .and_then(|o| o.content.first())
+        .map(|c| {
+            println!("📝 提取的文本类型: {}, 文本内容: {}", c.content_type, c.text.chars().take(200).collect::<String>());
+            c.text.clone()
+        })
+        .ok_or_else(|| {
+            println!("❌ 未找到文本内容");
+            std::io::Error::new(std::io::ErrorKind::Other, "GPT响应中没有找到文本内容，请检查API返回格式")
+        })
+}
 
-    if video_scenes.is_empty() {
-        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "没有可导出的视频，请先生成分镜视频"
-        })));
+// 提取模型输出中的 JSON（可能被 markdown 代码块包裹）
+fn extract_fenced_json(text: &str) -> &str {
+    if text.contains("```json") {
+        text.split("```json")
+            .nth(1)
+            .and_then(|s| s.split("```").next())
+            .unwrap_or(text)
+            .trim()
+    } else if text.contains("```") {
+        text.split("```")
+            .nth(1)
+            .and_then(|s| s.split("```").next())
+            .unwrap_or(text)
+            .trim()
+    } else {
+        text.trim()
     }
+}
 
-    println!("📊 找到 {} 个分镜视频", video_scenes.len());
-
-    // 3. 创建临时导出目录（固定路径）
-    let export_path = format!("data/projects/{}/temp_export", project_id);
-    
-    // 4. 如果目录已存在，先清空
-    if Path::new(&export_path).exists() {
-        println!("🗑️  清空现有临时目录: {}", export_path);
-        fs::remove_dir_all(&export_path)?;
-    }
+// 分析角色提示词
+async fn analyze_character_prompt(
+    req: web::Json<AnalyzeCharacterRequest>,
+) -> Result<HttpResponse> {
+    log_api_request("/api/characters/analyze", "POST", &format!("prompt length: {}", req.prompt.len()));
+    println!("🔍 开始分析角色提示词: {}", req.prompt);
     
-    // 5. 创建空的导出目录
-    println!("📁 创建临时导出目录: {}", export_path);
-    fs::create_dir_all(&export_path)?;
+    // 构建系统提示词，让模型返回JSON格式的角色信息
+    let system_prompt = format!(
+        r#"你是一个角色分析助手。请分析以下角色描述，提取出角色名称、分类和标签。
 
-    // 6. 复制所有视频文件
-    for scene in &video_scenes {
-        if let Some(ref url) = scene.latest_video_url {
-            // 转换 URL 为本地路径
-            let local_path = if url.starts_with("/data/") {
-                format!(".{}", url)
-            } else if url.starts_with("http") {
-                println!("⚠️  跳过外部URL: {}", url);
-                continue;
-            } else {
-                url.clone()
-            };
+**重要：请务必使用中文回复，所有字段内容都应该是中文。**
 
-            // 检查文件是否存在
-            if !Path::new(&local_path).exists() {
-                println!("⚠️  视频文件不存在: {}", local_path);
-                continue;
-            }
+角色描述：
+{}
 
-            // 确定输出文件名
-            let output_filename = format!("scene_{}.mp4", scene.scene_index);
-            let output_path = format!("{}/{}", export_path, output_filename);
+请以JSON格式返回结果，包含以下字段：
+- name: 角色名称（如果描述中没有明确名称，请根据描述生成一个合适的中文名字）
+- category: 角色分类（例如：主要角色、配角、反派等，必须用中文）
+- tags: 标签列表（数组格式，包含角色的特征、性格、能力等，3-5个中文标签）
 
-            // 复制文件
-            fs::copy(&local_path, &output_path)
-                .map_err(|e| {
-                    println!("❌ 复制文件失败: {} -> {}: {}", local_path, output_path, e);
-                    std::io::Error::new(std::io::ErrorKind::Other, format!("复制文件失败: {}", e))
-                })?;
+示例输出格式：
+{{
+  "name": "艾莉亚",
+  "category": "主要角色",
+  "tags": ["勇敢", "善良", "魔法师", "年轻", "冒险者"]
+}}
 
-            println!("✅ 已复制: {} -> {}", local_path, output_filename);
+请只返回JSON，不要添加其他说明文字。所有内容必须是中文。"#,
+        req.prompt
+    );
+    
+    // 调用 GPT-nano API
+    let text = match call_gpt_nano(mock::GPT_NANO_TASK_CHARACTER_ANALYSIS, system_prompt, 0.7, 500).await {
+        Ok(t) => t,
+        Err(e) => {
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": e.to_string()
+            })));
         }
+    };
+    
+    // 解析JSON响应
+    let json_text = extract_fenced_json(&text);
+    
+    println!("🔍 待解析的 JSON (truncated): {:.100}...", json_text);
+    
+    #[derive(Deserialize)]
+    struct ParsedCharacter {
+        name: String,
+        category: String,
+        tags: Vec<String>,
     }
-
-    println!("✅ 所有视频已复制到临时目录: {}", export_path);
-
-    // 7. 打开导出目录
-    let canonical_path = fs::canonicalize(&export_path)
+    
+    let parsed: ParsedCharacter = serde_json::from_str(json_text)
         .map_err(|e| {
-            println!("❌ 无法解析路径: {:?} - {}", export_path, e);
-            std::io::Error::new(std::io::ErrorKind::Other, "无法解析路径")
+            println!("❌ 解析角色 JSON 失败: {}", e);
+            println!("❌ JSON 文本 (truncated): {:.200}...", json_text);
+            std::io::Error::new(
+                std::io::ErrorKind::Other, 
+                format!("无法解析角色信息JSON。错误: {}", e)
+            )
         })?;
+    
+    println!("✅ 成功解析角色信息: 名称={}, 分类={}, 标签数={}", parsed.name, parsed.category, parsed.tags.len());
+    
+    Ok(HttpResponse::Ok().json(AnalyzeCharacterResponse {
+        name: parsed.name,
+        category: parsed.category,
+        tags: parsed.tags,
+    }))
+}
 
-    println!("📂 打开目录: {:?}", canonical_path);
+// ========================================
+// 剧本拆解分镜草稿
+// ========================================
 
-    #[cfg(target_os = "macos")]
-    {
-        let output = Command::new("open")
-            .arg(&canonical_path)
-            .output()
-            .map_err(|e| {
-                println!("❌ 执行open命令失败: {}", e);
-                std::io::Error::new(std::io::ErrorKind::Other, format!("执行open命令失败: {}", e))
-            })?;
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+struct AcceptDraftRequest {
+    replace: bool,  // true: 替换现有分镜（会删除其生成历史）；false: 追加到现有分镜之后
+    author: Option<String>,  // 替换模式下记录提示词编辑的修改人（可选）
+}
 
-        if !output.status.success() {
-            let error_msg = String::from_utf8_lossy(&output.stderr);
-            println!("❌ open命令执行失败: {}", error_msg);
-        } else {
-            println!("✅ 已在Finder中打开目录");
+// 读取对象中第一个存在的字符串字段（兼容中英文字段名）
+fn draft_str_field(item: &serde_json::Value, keys: &[&str]) -> Option<String> {
+    keys.iter()
+        .filter_map(|k| item.get(*k))
+        .filter_map(|v| v.as_str())
+        .map(|s| s.trim().to_string())
+        .find(|s| !s.is_empty())
+}
+
+// 解析模型返回的分镜草稿，兼容代码块包裹、前后附加说明文字、数组或 {"scenes": [...]} 等格式
+fn parse_storyboard_draft(text: &str) -> std::result::Result<Vec<DraftScene>, String> {
+    let json_text = extract_fenced_json(text);
+
+    let value: serde_json::Value = match serde_json::from_str(json_text) {
+        Ok(v) => v,
+        Err(e) => {
+            // 截取第一个 { 或 [ 到最后一个 } 或 ]
+            let start = json_text.find(['{', '[']);
+            let end = json_text.rfind(['}', ']']);
+            match (start, end) {
+                (Some(start), Some(end)) if end > start => serde_json::from_str(&json_text[start..=end])
+                    .map_err(|e| format!("无法解析分镜草稿JSON: {}", e))?,
+                _ => return Err(format!("无法解析分镜草稿JSON: {}", e)),
+            }
         }
+    };
+
+    let items = value.as_array()
+        .or_else(|| value.get("scenes").and_then(|v| v.as_array()))
+        .or_else(|| value.get("分镜").and_then(|v| v.as_array()))
+        .ok_or_else(|| "分镜草稿中没有找到分镜列表".to_string())?;
+
+    let mut scenes = Vec::new();
+    for item in items {
+        let Some(first_frame_prompt) = draft_str_field(item, &["first_frame_prompt", "首帧图提示词", "图提示词"]) else {
+            continue;
+        };
+        let scene_index = scenes.len() as i32 + 1;
+
+        let duration = item.get("duration")
+            .or_else(|| item.get("时长"))
+            .and_then(parse_duration)
+            .map(|d| clamp_scene_duration(scene_index, d));
+
+        let characters: Vec<String> = match item.get("characters").or_else(|| item.get("角色")) {
+            Some(serde_json::Value::Array(names)) => names.iter()
+                .filter_map(|n| n.as_str())
+                .map(|n| n.trim().to_string())
+                .filter(|n| !n.is_empty())
+                .collect(),
+            Some(serde_json::Value::String(names)) => names
+                .split([',', '，', '、'])
+                .map(|n| n.trim().to_string())
+                .filter(|n| !n.is_empty())
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        scenes.push(DraftScene {
+            scene_index,
+            duration,
+            first_frame_prompt,
+            video_prompt: draft_str_field(item, &["video_prompt", "视频提示词"]),
+            characters,
+        });
     }
 
-    #[cfg(not(target_os = "macos"))]
-    {
-        println!("⚠️  当前系统不支持自动打开文件管理器");
+    if scenes.is_empty() {
+        return Err("分镜草稿为空".to_string());
     }
 
-    Ok(HttpResponse::Ok().json(serde_json::json!({
-        "success": true,
-        "message": "视频已导出到临时目录",
-        "export_path": export_path,
-        "video_count": video_scenes.len(),
-        "is_new_export": true,
-    })))
+    Ok(scenes)
 }
 
-// 上传合成视频
-async fn upload_composite_video(
+// 根据项目剧本生成分镜草稿
+async fn generate_storyboard_draft(
     path: web::Path<String>,
-    mut payload: Multipart,
     pool: web::Data<sqlx::PgPool>,
 ) -> Result<HttpResponse> {
     let project_id_str = path.into_inner();
     let project_id = Uuid::parse_str(&project_id_str)
         .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+    log_api_request(&format!("/api/projects/{}/storyboard-drafts", project_id), "POST", "");
 
-    println!("📤 开始上传合成视频: {}", project_id);
-
-    // 创建合成目录
-    let composite_dir = format!("data/projects/{}/composite", project_id);
-    fs::create_dir_all(&composite_dir)?;
-
-    let mut video_path = PathBuf::new();
-
-    // 接收上传的视频文件
-    while let Some(item) = payload.next().await {
-        let mut field = item?;
-        let content_disposition = field.content_disposition();
-        
-        if let Some(filename) = content_disposition.get_filename() {
-            
-            // 生成时间戳文件名
-            let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S").to_string();
-            let ext = Path::new(filename)
-                .extension()
-                .and_then(|s| s.to_str())
-                .unwrap_or("mp4");
-            let new_filename = format!("upload_{}.{}", timestamp, ext);
-            
-            let filepath = PathBuf::from(&composite_dir).join(&new_filename);
-            video_path = filepath.clone();
-            
-            println!("📝 保存文件: {:?}", filepath);
-            
-            let mut f = web::block(move || std::fs::File::create(filepath))
-                .await??;
-
-            while let Some(chunk) = field.next().await {
-                let data = chunk?;
-                f = web::block(move || f.write_all(&data).map(|_| f)).await??;
-            }
-            
-            println!("✅ 文件上传完成: {}", new_filename);
-        }
-    }
+    let project = Project::find_by_id(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Project not found"))?;
 
-    if video_path.as_os_str().is_empty() {
+    let script = project.script.clone().unwrap_or_default();
+    if script.trim().is_empty() {
         return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "未收到视频文件"
+            "error": "项目剧本为空，无法拆解分镜"
         })));
     }
 
-    // 构建视频 URL
-    let video_url = format!(
-        "/data/projects/{}/composite/{}", 
-        project_id, 
-        video_path.file_name().unwrap().to_str().unwrap()
-    );
-
-    println!("🔗 视频URL: {}", video_url);
-
-    // 保存到数据库（scene_count=0 表示手动上传）
-    let composite = CompositeVideo::create(
-        pool.as_ref(), 
-        project_id, 
-        video_url.clone(),
-        0  // 0 表示手动上传
-    )
-    .await
-    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-
-    println!("💾 合成视频记录已保存: ID {}", composite.id);
-
-    Ok(HttpResponse::Ok().json(serde_json::json!({
-        "success": true,
-        "message": "视频上传成功",
-        "composite": composite,
-    })))
-}
-
-
-
-
-
-
-
+    let characters = ProjectCharacter::get_all_for_project(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let character_list = if characters.is_empty() {
+        "（项目暂无角色）".to_string()
+    } else {
+        characters.iter()
+            .map(|c| format!("- {}: {}", c.name, c.prompt.clone().unwrap_or_default()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
 
+    let is_comic = project.project_type.as_deref() == Some("comic");
+    let field_description = if is_comic {
+        r#"- first_frame_prompt: 该分镜画面的图片提示词（描述画面构图、场景、人物动作和表情）
+- characters: 该分镜出场的角色名称数组（必须使用角色列表中的名称）"#
+    } else {
+        r#"- duration: 分镜时长（秒，1-8 之间的数字）
+- first_frame_prompt: 该分镜首帧图的图片提示词（描述画面构图、场景、人物动作和表情）
+- video_prompt: 该分镜的视频提示词（描述镜头运动、人物动作和画面变化）
+- characters: 该分镜出场的角色名称数组（必须使用角色列表中的名称）"#
+    };
 
+    let input = format!(
+        r#"你是一个专业的分镜师。请将以下剧本拆解为分镜列表。
 
+角色列表：
+{}
 
-// ========================================
-// 角色管理 API handlers
-// ========================================
+剧本：
+{}
 
-#[derive(Deserialize)]
-struct CreateCharacterRequest {
-    name: String,
-    prompt: Option<String>,
-    display_order: Option<i32>,
-}
+请以JSON格式返回结果：{{"scenes": [...]}}，每个分镜包含以下字段：
+{}
 
-#[derive(Deserialize)]
-struct UpdateCharacterRequest {
-    name: String,
-    prompt: Option<String>,
-    category: Option<String>,
-    tags: Option<Vec<String>>,
-}
+请只返回JSON，不要添加其他说明文字。"#,
+        character_list, script, field_description
+    );
 
-#[derive(Deserialize)]
-struct UpdateGlobalPromptRequest {
-    global_image_prompt: Option<String>,
-    global_video_prompt: Option<String>,
-}
+    println!("🎬 开始拆解剧本: Project ID {} (剧本长度: {})", project_id, script.chars().count());
 
-#[derive(Deserialize)]
-struct SearchSystemCharactersRequest {
-    query: Option<String>,
-    limit: Option<i64>,
-}
+    let text = match call_gpt_nano(mock::GPT_NANO_TASK_STORYBOARD, input, 0.7, 8000).await {
+        Ok(t) => t,
+        Err(e) => {
+            log_api_error("generate_storyboard_draft", &e.to_string(), &format!("project_id: {}", project_id));
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": e.to_string()
+            })));
+        }
+    };
 
-async fn get_system_characters(
-    pool: web::Data<sqlx::PgPool>,
-    query: web::Query<SearchSystemCharactersRequest>,
-) -> Result<HttpResponse> {
-    let characters = Character::search(pool.as_ref(), query.query.clone(), query.limit)
+    let scenes = match parse_storyboard_draft(&text) {
+        Ok(s) => s,
+        Err(e) => {
+            log_api_error("generate_storyboard_draft", &e, &format!("Response: {}", text));
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": e
+            })));
+        }
+    };
+
+    let draft = StoryboardDraft::create(pool.as_ref(), project_id, scenes)
         .await
         .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-    Ok(HttpResponse::Ok().json(characters))
+
+    println!("✅ 分镜草稿已生成: Draft ID {} ({} 个分镜)", draft.id, draft.scenes.len());
+
+    Ok(HttpResponse::Ok().json(draft))
 }
 
-// 获取待生成角色列表
-async fn get_pending_characters(
+// 获取项目的分镜草稿列表
+async fn get_storyboard_drafts(
+    path: web::Path<String>,
     pool: web::Data<sqlx::PgPool>,
 ) -> Result<HttpResponse> {
-    let characters = Character::list_pending(pool.as_ref())
+    let project_id_str = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+
+    let drafts = StoryboardDraft::find_by_project_id(pool.as_ref(), project_id)
         .await
         .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-    
-    Ok(HttpResponse::Ok().json(characters))
-}
 
-// AI生成角色图片
-#[derive(Debug, Deserialize)]
-struct GenerateCharacterRequest {
-    prompt: Option<String>,
+    Ok(HttpResponse::Ok().json(drafts))
 }
 
-async fn generate_character_image(
-    path: web::Path<String>,
+// 采用分镜草稿，写入项目分镜
+async fn accept_storyboard_draft(
+    path: web::Path<(String, i32)>,
+    req: web::Json<AcceptDraftRequest>,
     pool: web::Data<sqlx::PgPool>,
-    req_body: Option<web::Json<GenerateCharacterRequest>>,
 ) -> Result<HttpResponse> {
-    let char_id_str = path.into_inner();
-    log_api_request(
-        &format!("/api/characters/{}/generate", char_id_str),
-        "POST",
-        &format!("prompt: {:?}", req_body.as_ref().and_then(|r| r.prompt.as_ref()))
-    );
-    
-    let char_id = match Uuid::parse_str(&char_id_str) {
-        Ok(id) => id,
-        Err(e) => {
-            log_api_error("generate_character_image", &e.to_string(), &format!("Invalid UUID: {}", char_id_str));
-            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-                "error": "Invalid character ID"
-            })));
-        }
-    };
-    
-    // 查询角色信息
-    let character = match sqlx::query_as::<_, Character>("SELECT * FROM characters WHERE id = $1")
-        .bind(char_id)
-        .fetch_optional(pool.as_ref())
-        .await {
-        Ok(Some(c)) => c,
-        Ok(None) => {
-            log_api_error("generate_character_image", "Character not found", &format!("ID: {}", char_id));
+    let (project_id_str, draft_id) = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+    let req = req.into_inner();
+
+    let draft = match StoryboardDraft::find_by_id(pool.as_ref(), draft_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+    {
+        Some(d) if d.project_id == project_id => d,
+        _ => {
             return Ok(HttpResponse::NotFound().json(serde_json::json!({
-                "error": "Character not found"
-            })));
-        },
-        Err(e) => {
-            log_api_error("generate_character_image", &e.to_string(), &format!("Database query failed for ID: {}", char_id));
-            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": format!("Database error: {}", e)
+                "error": "分镜草稿不存在"
             })));
         }
     };
-    
-    // 优先使用请求体中的prompt，否则使用数据库中的prompt
-    let prompt = if let Some(req) = req_body {
-        if let Some(p) = req.prompt.clone() {
-            if !p.trim().is_empty() {
-                p
-            } else {
-                match character.prompt {
-                    Some(p) => p,
-                    None => return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-                        "error": "Character has no prompt"
-                    })))
-                }
-            }
-        } else {
-            match character.prompt {
-                Some(p) => p,
-                None => return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-                    "error": "Character has no prompt"
-                })))
-            }
+
+    // 替换模式会删除现有分镜，存在锁定分镜时拒绝
+    let previous_scenes = if req.replace {
+        let scenes = StoryboardScene::find_by_project_id(pool.as_ref(), project_id)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        if let Some(locked) = scenes.iter().find(|s| s.is_locked()) {
+            return Ok(scene_locked_response(locked.id));
         }
+        scenes
     } else {
-        match character.prompt {
-            Some(p) => p,
-            None => return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-                "error": "Character has no prompt"
-            })))
-        }
+        Vec::new()
     };
-    
-    // Call Gemini API
-    let api_key = std::env::var("GEMINI_API_KEY").expect("GEMINI_API_KEY must be set");
-    let client = Client::new();
-    
-    // 从环境变量读取 Gemini API 配置（必须设置）
-    let gemini_base_url = std::env::var("GEMINI_BASE_URL").expect("GEMINI_BASE_URL must be set");
-    let gemini_model = std::env::var("GEMINI_MODEL").expect("GEMINI_MODEL must be set");
-    let gemini_endpoint = std::env::var("GEMINI_ENDPOINT").expect("GEMINI_ENDPOINT must be set");
-    let url = format!("{}{}", gemini_base_url, gemini_endpoint.replace("{model}", &gemini_model));
-    
-    let payload = serde_json::json!({
-        "contents": [{
-            "parts": [{"text": prompt}]
-        }],
-        "generationConfig": {
-            "temperature": 0.4,
-            "topK": 32,
-            "topP": 1,
-            "maxOutputTokens": 8192,
-        }
-    });
-    
-    log_gemini_request("generate_character_image", &payload);
 
-    // 尝试最多3次请求
-    let mut result: Option<serde_json::Value> = None;
-    
-    for attempt in 1..=3 {
-        let res = match client.post(&url)
-            .header("Content-Type", "application/json")
-            .header("x-goog-api-key", &api_key)
-            .json(&payload)
-            .send()
-            .await {
-            Ok(r) => r,
-            Err(e) => {
-                let err_msg = format!("Network error: {}", e);
-                if attempt < 3 {
-                    tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-                    continue;
-                }
-                log_api_error("generate_character_image", &err_msg, &format!("Gemini API call failed after {} attempts, char_id: {}", attempt, char_id));
-                return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                    "error": format!("Failed to call Gemini API after {} attempts: {}", attempt, err_msg)
-                })));
-            }
-        };
+    // 删除旧分镜和写入新分镜在同一个事务中完成，写入失败时保留原有分镜
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 
-        let status = res.status();
-        if !status.is_success() {
-            let error_text = res.text().await.unwrap_or_default();
-            log_gemini_response("generate_character_image", status.as_u16(), &error_text);
-            
-            // 检查是否是空响应错误
-            let err_msg = if error_text.contains("empty response") || error_text.contains("channel:empty_response") {
-                "Gemini 返回了空响应，可能是提示词触发了安全过滤或内容审核。请尝试修改角色描述，避免使用可能引起歧义的词汇。".to_string()
-            } else {
-                format!("API error (status {}): {}", status.as_u16(), error_text)
-            };
-            
-            if attempt < 3 {
-                tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-                continue;
-            }
-            
-            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": err_msg
-            })));
-        }
+    // 追加模式下，新分镜序号接在现有分镜之后
+    let index_offset = if req.replace {
+        let removed = StoryboardScene::delete_by_project_id(&mut *tx, project_id)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        println!("🗑️  已替换现有分镜: {} 个", removed);
+        0
+    } else {
+        StoryboardScene::find_by_project_id(pool.as_ref(), project_id)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+            .iter()
+            .map(|s| s.scene_index)
+            .max()
+            .unwrap_or(0)
+    };
 
-        match res.json::<serde_json::Value>().await {
-            Ok(r) => {
-                result = Some(r);
-                break;
-            },
-            Err(e) => {
-                let err_msg = format!("Failed to parse response: {}", e);
-                if attempt < 3 {
-                    tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-                    continue;
-                }
-                return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                    "error": err_msg
-                })));
-            }
-        }
-    }
+    let scenes_data: Vec<(i32, Option<f64>, Option<String>, Option<String>)> = draft.scenes
+        .iter()
+        .map(|s| (
+            index_offset + s.scene_index,
+            s.duration,
+            Some(s.first_frame_prompt.clone()),
+            s.video_prompt.clone(),
+        ))
+        .collect();
 
-    let result = result.unwrap();
+    let count = StoryboardScene::batch_create(&mut *tx, project_id, scenes_data)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 
-    // Extract image data with better error messages
-    let candidates = match result["candidates"].as_array() {
-        Some(c) if !c.is_empty() => c,
-        _ => {
-            log_api_error("generate_character_image", "Empty candidates array", &format!("Response: {}", serde_json::to_string(&result).unwrap_or_default()));
-            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "AI 模型返回了空结果，这可能是因为：\n1. 提示词包含敏感内容被过滤\n2. 提示词描述不够清晰\n3. API 服务临时故障\n\n建议：请尝试修改角色描述，使用更具体、更中性的语言描述角色外观。"
-            })));
-        }
-    };
-    
-    let content = match candidates.get(0).and_then(|c| c["content"].as_object()) {
-        Some(c) => c,
-        None => return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": "AI 响应格式错误：缺少内容字段"
-        })))
-    };
-    
-    let parts = match content["parts"].as_array() {
-        Some(p) => p,
-        None => return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": "AI 响应格式错误：缺少部件字段"
-        })))
-    };
-    
-    let mut image_data_base64 = None;
-    for part in parts {
-        if let Some(inline_data) = part["inlineData"].as_object() {
-            if let Some(data) = inline_data["data"].as_str() {
-                image_data_base64 = Some(data);
-                break;
+    // 替换模式下，把同序号旧分镜到新分镜的提示词变化记录为编辑历史（旧分镜的记录随分镜删除）
+    if req.replace {
+        let author = edit_author(&req.author);
+        let new_scenes = StoryboardScene::find_by_project_id(&mut *tx, project_id)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        for scene in &new_scenes {
+            let previous = previous_scenes.iter().find(|s| s.scene_index == scene.scene_index);
+            for (field, old_value, new_value) in [
+                ("first_frame_prompt", previous.and_then(|s| s.first_frame_prompt.clone()), &scene.first_frame_prompt),
+                ("video_prompt", previous.and_then(|s| s.video_prompt.clone()), &scene.video_prompt),
+            ] {
+                PromptEdit::record(&mut *tx, project_id, Some(scene.id), field, old_value, new_value.clone(), author.clone())
+                    .await
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
             }
         }
     }
-    
-    let image_data_base64 = match image_data_base64 {
-        Some(d) => d,
-        None => return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": "AI 返回的响应中没有找到图片数据"
-        })))
-    };
-    
-    // Decode and save image
-    let image_bytes = match base64::engine::general_purpose::STANDARD.decode(image_data_base64) {
-        Ok(b) => b,
-        Err(e) => return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": format!("Failed to decode base64: {}", e)
-        })))
-    };
-    
-    let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S").to_string();
-    let filename = format!("gemini_{}_{}.png", timestamp, char_id);
-    let filepath = format!("data/characters/{}", filename);
-    let image_url = format!("/data/characters/{}", filename);
-    
-    let mut file = match File::create(&filepath) {
-        Ok(f) => f,
-        Err(e) => return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": format!("Failed to create file: {}", e)
-        })))
-    };
-    if let Err(e) = file.write_all(&image_bytes) {
-        return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": format!("Failed to write file: {}", e)
-        })));
-    }
-        
-    // Update image_url and updated_at to change timestamp for cache busting
-    let updated_character = match sqlx::query_as::<_, Character>(
-        "UPDATE characters SET image_url = $1, updated_at = NOW() WHERE id = $2 RETURNING *"
-    )
-    .bind(image_url.clone())
-    .bind(char_id)
-    .fetch_one(pool.as_ref())
-    .await {
-        Ok(c) => c,
-        Err(e) => return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": format!("Failed to update character: {}", e)
-        })))
-    };
-    
-    println!("🎨 角色图片生成成功: {} (ID: {})", character.name, char_id);
-    
-    
-    // Log Success Response
-    log_gemini_response("generate_character_image", 200, &serde_json::to_string_pretty(&result).unwrap_or_default());
-
-    Ok(HttpResponse::Ok().json(serde_json::json!({
-        "success": true,
-        "image_url": image_url,
-        "character": updated_character,
-    })))
-}
-
-fn default_project_type() -> Option<String> {
-    Some("video".to_string())
-}
-
-#[derive(Debug, Deserialize)]
-struct Img2ImgRequest {
-    image_base64: String,
-    mime_type: String,
-    prompt: String,
-}
-
-// Image-to-image generation using Gemini
-async fn img2img_generate(
-    req: web::Json<Img2ImgRequest>,
-    _pool: web::Data<sqlx::PgPool>,
-) -> Result<HttpResponse> {
-    log_api_request("/api/characters/img2img", "POST", &format!("prompt: {}, mime_type: {}", req.prompt, req.mime_type));
-    
-    let api_key = std::env::var("GEMINI_API_KEY").expect("GEMINI_API_KEY must be set");
-    let client = Client::new();
-    
-    // 从环境变量读取 Gemini API 配置（必须设置）
-    let gemini_base_url = std::env::var("GEMINI_BASE_URL").expect("GEMINI_BASE_URL must be set");
-    let gemini_model = std::env::var("GEMINI_MODEL").expect("GEMINI_MODEL must be set");
-    let gemini_endpoint = std::env::var("GEMINI_ENDPOINT").expect("GEMINI_ENDPOINT must be set");
-    let url = format!("{}{}", gemini_base_url, gemini_endpoint.replace("{model}", &gemini_model));
-    
-    // Build request with image and text
-    let payload = serde_json::json!({
-        "contents": [{
-            "parts": [
-                {
-                    "inlineData": {
-                        "mimeType": req.mime_type,
-                        "data": req.image_base64
-                    }
-                },
-                {
-                    "text": format!("Based on this reference image, {}", req.prompt)
-                }
-            ]
-        }],
-        "generationConfig": {
-            "temperature": 0.4,
-            "topK": 32,
-            "topP": 1,
-            "maxOutputTokens": 8192,
-        }
-    });
-
-    // Log Request
-    log_gemini_request("img2img_generate", &payload);
 
-    let res = client.post(url)
-        .header("Content-Type", "application/json")
-        .header("x-goog-api-key", api_key)
-        .json(&payload)
-        .send()
+    tx.commit()
         .await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to call Gemini API: {}", e)))?;
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    recalculate_timeline(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 
-    if !res.status().is_success() {
-        let status = res.status().as_u16();
-        let error_text = res.text().await.unwrap_or_default();
-        log_gemini_response("img2img_generate", status, &error_text);
-        return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": format!("Gemini API error: {}", error_text)
-        })));
-    }
+    // 写入草稿中的出场角色，并返回项目中不存在的角色
+    let casting: Vec<(i32, Vec<String>)> = draft.scenes
+        .iter()
+        .map(|s| (index_offset + s.scene_index, s.characters.clone()))
+        .collect();
+    let unknown_characters = apply_scene_casting(pool.as_ref(), project_id, &casting)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 
-    let result: serde_json::Value = res.json().await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to parse Gemini response: {}", e)))?;
+    StoryboardDraft::delete(pool.as_ref(), draft_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 
-    // Extract image data
-    let candidates = result["candidates"].as_array().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Other, "No candidates in response"))?;
-    let content = candidates.get(0).and_then(|c| c["content"].as_object()).ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Other, "No content in candidate"))?;
-    let parts = content["parts"].as_array().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Other, "No parts in content"))?;
-    
-    let mut image_data_base64 = None;
-    for part in parts {
-        if let Some(inline_data) = part["inlineData"].as_object() {
-            if let Some(data) = inline_data["data"].as_str() {
-                image_data_base64 = Some(data);
-                break;
-            }
-        }
-    }
-    
-    let image_data_base64 = image_data_base64.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Other, "No image data found in response"))?;
-    
-    // Decode and save image
-    let image_bytes = base64::engine::general_purpose::STANDARD.decode(image_data_base64)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to decode base64: {}", e)))?;
-    
-    let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S").to_string();
-    let filename = format!("gemini_img2img_{}.png", timestamp);
-    let filepath = format!("data/characters/{}", filename);
-    let image_url = format!("/data/characters/{}", filename);
-    
-    let mut file = File::create(&filepath)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to create file: {}", e)))?;
-    file.write_all(&image_bytes)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to write file: {}", e)))?;
-    
-    println!("🎨 Image-to-image generation completed: {}", image_url);
-    
-    // Log Success Response
-    log_gemini_response("img2img_generate", 200, &serde_json::to_string_pretty(&result).unwrap_or_default());
+    println!("✅ 分镜草稿已采用: Draft ID {} ({} 个分镜)", draft_id, count);
 
     Ok(HttpResponse::Ok().json(serde_json::json!({
-        "success": true,
-        "image_url": image_url,
+        "message": "分镜草稿已采用",
+        "scene_count": count,
+        "unknown_characters": unknown_characters,
     })))
 }
 
-// 录用角色 (Pending -> Generated)
-async fn adopt_character(
-    path: web::Path<String>,
+// 丢弃分镜草稿
+async fn discard_storyboard_draft(
+    path: web::Path<(String, i32)>,
     pool: web::Data<sqlx::PgPool>,
 ) -> Result<HttpResponse> {
-    let char_id_str = path.into_inner();
-    let char_id = Uuid::parse_str(&char_id_str)
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid character ID"))?;
-        
-    let updated_character = sqlx::query_as::<_, Character>(
-        "UPDATE characters SET status = 1 WHERE id = $1 RETURNING *"
-    )
-    .bind(char_id)
-    .fetch_one(pool.as_ref())
-    .await
-    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-    
+    let (project_id_str, draft_id) = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+
+    match StoryboardDraft::find_by_id(pool.as_ref(), draft_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+    {
+        Some(d) if d.project_id == project_id => {}
+        _ => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "分镜草稿不存在"
+            })));
+        }
+    }
+
+    StoryboardDraft::delete(pool.as_ref(), draft_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "success": true,
-        "character": updated_character,
+        "message": "分镜草稿已丢弃"
     })))
 }
 
-async fn get_project_characters(
-    path: web::Path<String>,
-    pool: web::Data<sqlx::PgPool>,
-) -> Result<HttpResponse> {
-    let project_id_str = path.into_inner();
-    let project_id = Uuid::parse_str(&project_id_str)
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
-    
-    match ProjectCharacter::get_all_for_project(pool.as_ref(), project_id).await {
-        Ok(characters) => Ok(HttpResponse::Ok().json(characters)),
-        Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": e.to_string()
-        }))),
+// ========================================
+// 提示词编辑历史
+// ========================================
+
+#[derive(Debug, Deserialize)]
+struct PromptHistoryQuery {
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+struct RevertPromptEditRequest {
+    author: Option<String>,
+}
+
+// 编辑记录 + 逐词差异
+#[derive(Debug, Serialize)]
+struct PromptEditView {
+    #[serde(flatten)]
+    edit: PromptEdit,
+    diff: Vec<word_diff::DiffChunk>,
+}
+
+impl From<PromptEdit> for PromptEditView {
+    fn from(edit: PromptEdit) -> Self {
+        let diff = word_diff::diff(
+            edit.old_value.as_deref().unwrap_or(""),
+            edit.new_value.as_deref().unwrap_or(""),
+        );
+        PromptEditView { edit, diff }
     }
 }
 
+// 去掉空白的修改人，空字符串视为未提供
+fn edit_author(author: &Option<String>) -> Option<String> {
+    author
+        .as_ref()
+        .map(|a| a.trim().to_string())
+        .filter(|a| !a.is_empty())
+}
 
-async fn create_project_character(
+// 获取项目的提示词/剧本编辑历史（含分镜提示词）
+async fn get_project_prompt_history(
     path: web::Path<String>,
+    query: web::Query<PromptHistoryQuery>,
     pool: web::Data<sqlx::PgPool>,
-    req: web::Json<CreateCharacterRequest>,
 ) -> Result<HttpResponse> {
-    let project_id_str = path.into_inner();
-    let project_id = Uuid::parse_str(&project_id_str)
+    let project_id = Uuid::parse_str(&path.into_inner())
         .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
-    
-    // Create new character in global library
-    match Character::create(
-        pool.as_ref(), 
-        req.name.clone(), 
-        "".to_string(), 
-        req.prompt.clone(), 
-        Some(project_id)
-    ).await {
-        Ok(character) => {
-            // Link to project
-            match ProjectCharacter::link(pool.as_ref(), project_id, character.id, req.display_order.unwrap_or(0)).await {
-                Ok(_) => {
-                    // Return the view struct
-                     Ok(HttpResponse::Ok().json(ProjectCharacter { 
-                        id: character.id,
-                        name: character.name,
-                        image_url: character.image_url,
-                        prompt: character.prompt,
-                        category: character.category,
-                        tags: character.tags,
-                        display_order: req.display_order.unwrap_or(0)
-                    }))
-                },
-                Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                    "error": format!("Failed to link character: {}", e)
-                })))
-            }
-        },
-        Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": e.to_string()
-        }))),
-    }
-}
 
+    let edits = PromptEdit::find_by_project_id(pool.as_ref(), project_id, query.limit.unwrap_or(100).clamp(1, 500))
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 
-async fn update_project_character(
+    let views: Vec<PromptEditView> = edits.into_iter().map(PromptEditView::from).collect();
+    Ok(HttpResponse::Ok().json(views))
+}
+
+// 获取分镜的提示词编辑历史
+async fn get_scene_prompt_history(
+    path: web::Path<(String, i32)>,
+    query: web::Query<PromptHistoryQuery>,
     pool: web::Data<sqlx::PgPool>,
-    path: web::Path<(String, String)>,
-    req: web::Json<UpdateCharacterRequest>,
 ) -> Result<HttpResponse> {
-    let (_, char_id_str) = path.into_inner();
-    let char_id = Uuid::parse_str(&char_id_str)
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid character ID"))?;
-    
-    match Character::update(
-        pool.as_ref(), 
-        char_id, 
-        req.name.clone(), 
-        req.prompt.clone(),
-        req.category.clone(),
-        req.tags.clone()
-    ).await {
-        Ok(character) => Ok(HttpResponse::Ok().json(character)),
-        Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": e.to_string()
-        }))),
+    let (project_id_str, scene_id) = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+
+    if find_project_scene(pool.as_ref(), project_id, scene_id).await?.is_none() {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "分镜不存在"
+        })));
     }
+
+    let edits = PromptEdit::find_by_scene_id(pool.as_ref(), scene_id, query.limit.unwrap_or(100).clamp(1, 500))
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    let views: Vec<PromptEditView> = edits.into_iter().map(PromptEditView::from).collect();
+    Ok(HttpResponse::Ok().json(views))
 }
 
-async fn get_character_detail(
-    path: web::Path<String>,
+// 将字段恢复为某次编辑之前的内容（恢复操作本身也会记录为一次编辑）
+async fn revert_prompt_edit(
+    path: web::Path<(String, i32)>,
+    req: web::Json<RevertPromptEditRequest>,
     pool: web::Data<sqlx::PgPool>,
 ) -> Result<HttpResponse> {
-    let char_id_str = path.into_inner();
-    let char_id = Uuid::parse_str(&char_id_str)
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid character ID"))?;
+    let (project_id_str, edit_id) = path.into_inner();
+    let project_id = Uuid::parse_str(&project_id_str)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+    let author = edit_author(&req.author);
 
-    let char = sqlx::query_as::<_, Character>("SELECT * FROM characters WHERE id = $1")
-        .bind(char_id)
-        .fetch_optional(pool.as_ref())
+    let edit = match PromptEdit::find_by_id(pool.as_ref(), edit_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+    {
+        Some(edit) if edit.project_id == project_id => edit,
+        _ => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "编辑记录不存在"
+            })));
+        }
+    };
+
+    // 恢复内容和新的编辑记录一起提交
+    let mut tx = pool.begin()
         .await
         .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let current = match edit.scene_id {
+        Some(scene_id) => {
+            let Some(scene) = find_project_scene(pool.as_ref(), project_id, scene_id).await? else {
+                return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                    "error": "分镜不存在"
+                })));
+            };
+            if scene.is_locked() {
+                return Ok(scene_locked_response(scene_id));
+            }
+            if !SCENE_PROMPT_FIELDS.contains(&edit.field.as_str()) {
+                return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": format!("不支持恢复的字段: {}", edit.field)
+                })));
+            }
 
-    match char {
-        Some(c) => Ok(HttpResponse::Ok().json(c)),
-        None => Ok(HttpResponse::NotFound().json(serde_json::json!({"error": "Character not found"}))),
-    }
+            let current = if edit.field == "first_frame_prompt" {
+                scene.first_frame_prompt
+            } else {
+                scene.video_prompt
+            };
+            // 字段名来自白名单，可以安全拼接
+            sqlx::query(&format!(
+                "UPDATE storyboard_scenes SET {} = $1, updated_at = NOW() WHERE id = $2",
+                edit.field
+            ))
+            .bind(&edit.old_value)
+            .bind(scene_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+            current
+        }
+        None => {
+            let project = Project::find_by_id(pool.as_ref(), project_id)
+                .await
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+                .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Project not found"))?;
+            if !PROJECT_PROMPT_FIELDS.contains(&edit.field.as_str()) {
+                return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": format!("不支持恢复的字段: {}", edit.field)
+                })));
+            }
+
+            let current = match edit.field.as_str() {
+                "global_image_prompt" => project.global_image_prompt,
+                "global_video_prompt" => project.global_video_prompt,
+                _ => project.script,
+            };
+            sqlx::query(&format!(
+                "UPDATE projects SET {} = $1, updated_at = NOW() WHERE id = $2",
+                edit.field
+            ))
+            .bind(&edit.old_value)
+            .bind(project_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+            current
+        }
+    };
+
+    let revert = PromptEdit::record(
+        &mut *tx,
+        project_id,
+        edit.scene_id,
+        &edit.field,
+        current,
+        edit.old_value.clone(),
+        author,
+    )
+    .await
+    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    tx.commit()
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    println!("↩️ 已恢复 {} 到编辑 #{} 之前的内容", edit.field, edit.id);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "field": edit.field,
+        "scene_id": edit.scene_id,
+        "value": edit.old_value,
+        "edit": revert.map(PromptEditView::from)
+    })))
 }
 
-async fn update_character_global(
-    pool: web::Data<sqlx::PgPool>,
-    path: web::Path<String>,
-    req: web::Json<UpdateCharacterRequest>,
-) -> Result<HttpResponse> {
-    let char_id_str = path.into_inner();
-    let char_id = Uuid::parse_str(&char_id_str)
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid character ID"))?;
-    
-    match Character::update(
-        pool.as_ref(), 
-        char_id, 
-        req.name.clone(), 
-        req.prompt.clone(),
-        req.category.clone(),
-        req.tags.clone()
-    ).await {
-        Ok(character) => Ok(HttpResponse::Ok().json(character)),
-        Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": e.to_string()
-        }))),
-    }
-    }
+// ========================================
+// 项目快照
+// ========================================
 
-async fn create_character_global(
-    pool: web::Data<sqlx::PgPool>,
-    req: web::Json<CreateCharacterRequest>,
-) -> Result<HttpResponse> {
-    log_api_request("/api/characters", "POST", &format!("name: {}, prompt: {:?}", req.name, req.prompt));
-    
-    match Character::create(
-        pool.as_ref(), 
-        req.name.clone(), 
-        "".to_string(), 
-        req.prompt.clone(), 
-        None
-    ).await {
-        Ok(character) => Ok(HttpResponse::Ok().json(character)),
-        Err(e) => {
-            log_api_error("create_character_global", &e.to_string(), &format!("name: {}", req.name));
-            Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": e.to_string()
-            })))
-        },
+#[derive(Debug, Deserialize)]
+struct CreateSnapshotRequest {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SnapshotDiffQuery {
+    from: i32,
+    to: String,  // 快照ID，或 "current" 表示与项目当前状态比较
+}
+
+// 获取属于该项目的快照
+async fn find_project_snapshot(
+    pool: &sqlx::PgPool,
+    project_id: Uuid,
+    snapshot_id: i32,
+) -> std::io::Result<Option<ProjectSnapshot>> {
+    let snapshot = ProjectSnapshot::find_by_id(pool, snapshot_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    Ok(snapshot.filter(|s| s.project_id == project_id))
+}
+
+// 比较两个快照内容，返回项目字段和分镜的差异
+fn diff_snapshot_data(from: &SnapshotData, to: &SnapshotData) -> serde_json::Value {
+    fn push_change<T: PartialEq + Serialize>(
+        changes: &mut Vec<serde_json::Value>,
+        field: &str,
+        from: &T,
+        to: &T,
+    ) {
+        if from != to {
+            changes.push(serde_json::json!({ "field": field, "from": from, "to": to }));
+        }
+    }
+
+    let mut project_changes = Vec::new();
+    push_change(&mut project_changes, "script", &from.script, &to.script);
+    push_change(&mut project_changes, "global_image_prompt", &from.global_image_prompt, &to.global_image_prompt);
+    push_change(&mut project_changes, "global_video_prompt", &from.global_video_prompt, &to.global_video_prompt);
+
+    let find = |scenes: &[SnapshotScene], id: i32| scenes.iter().find(|s| s.scene_id == id).cloned();
+
+    let mut changed_scenes = Vec::new();
+    let mut removed_scenes = Vec::new();
+    for old in &from.scenes {
+        let Some(new) = find(&to.scenes, old.scene_id) else {
+            removed_scenes.push(old.clone());
+            continue;
+        };
+
+        let mut changes = Vec::new();
+        push_change(&mut changes, "scene_index", &old.scene_index, &new.scene_index);
+        push_change(&mut changes, "duration", &old.duration, &new.duration);
+        push_change(&mut changes, "first_frame_prompt", &old.first_frame_prompt, &new.first_frame_prompt);
+        push_change(&mut changes, "video_prompt", &old.video_prompt, &new.video_prompt);
+        push_change(&mut changes, "latest_image_url", &old.latest_image_url, &new.latest_image_url);
+        push_change(&mut changes, "latest_video_url", &old.latest_video_url, &new.latest_video_url);
+        push_change(&mut changes, "character_ids", &old.character_ids, &new.character_ids);
+        push_change(&mut changes, "status", &old.status, &new.status);
+        push_change(&mut changes, "sequence", &old.sequence, &new.sequence);
+
+        if !changes.is_empty() {
+            changed_scenes.push(serde_json::json!({
+                "scene_id": old.scene_id,
+                "changes": changes
+            }));
+        }
     }
+
+    let added_scenes: Vec<SnapshotScene> = to.scenes
+        .iter()
+        .filter(|s| find(&from.scenes, s.scene_id).is_none())
+        .cloned()
+        .collect();
+
+    serde_json::json!({
+        "project": project_changes,
+        "changed_scenes": changed_scenes,
+        "added_scenes": added_scenes,
+        "removed_scenes": removed_scenes,
+    })
 }
 
-async fn upload_character_image_global(
+// 创建项目快照
+async fn create_project_snapshot(
     path: web::Path<String>,
+    req_body: web::Json<CreateSnapshotRequest>,
     pool: web::Data<sqlx::PgPool>,
-    mut payload: Multipart,
 ) -> Result<HttpResponse> {
-    let char_id_str = path.into_inner();
-    let char_id = Uuid::parse_str(&char_id_str)
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid character ID"))?;
-
-    let mut filename = String::new();
-    
-    while let Ok(Some(mut field)) = payload.try_next().await {
-        let content_type = field.content_disposition();
-        
-        if let Some(name) = content_type.get_name() {
-            if name == "file" {
-                let ext = Path::new(content_type.get_filename().unwrap_or("image.png"))
-                    .extension()
-                    .and_then(|e| e.to_str())
-                    .unwrap_or("png");
-                
-                filename = format!("{}.{}", char_id, ext);
-                let filepath = format!("data/characters/{}", filename);
-                
-                // Ensure directory exists
-                fs::create_dir_all("data/characters")?;
-                
-                let mut f = File::create(filepath)?;
-                
-                while let Ok(Some(chunk)) = field.try_next().await {
-                    f.write_all(&chunk)?;
-                }
-            }
-        }
-    }
+    let project_id = Uuid::parse_str(&path.into_inner())
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
 
-    if filename.is_empty() {
+    let name = req_body.name.trim().to_string();
+    if name.is_empty() {
         return Ok(HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "No file uploaded"
+            "error": "快照名称不能为空"
         })));
     }
 
-    let filepath = format!("data/characters/{}", filename);
-    
-    // 上传到 Cloudflare R2
-    let cloudflare_storage = cloudflare::CloudflareStorage::new()
-        .await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to create Cloudflare storage: {}", e)))?;
-    
-    let cloudflare_url = cloudflare_storage.get_or_upload(pool.as_ref(), &filepath, "image")
+    let Some(project) = Project::find_by_id(pool.as_ref(), project_id)
         .await
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to upload to Cloudflare: {}", e)))?;
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+    else {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "项目不存在"
+        })));
+    };
 
-    println!("   ✅ 角色图片已上传到 Cloudflare: {}", cloudflare_url);
-    
-    // Update character image_url with Cloudflare URL
-    match sqlx::query("UPDATE characters SET image_url = $1 WHERE id = $2")
-        .bind(&cloudflare_url)
-        .bind(char_id)
-        .execute(pool.as_ref())
+    let data = SnapshotData::capture(pool.as_ref(), &project)
         .await
-    {
-        Ok(_) => Ok(HttpResponse::Ok().json(serde_json::json!({
-            "success": true,
-            "image_url": cloudflare_url
-        }))),
-        Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": e.to_string()
-        }))),
-    }
-}
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let snapshot = ProjectSnapshot::create(pool.as_ref(), project_id, name, data)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 
+    println!("📸 已创建项目快照: {} ({} 个分镜)", snapshot.name, snapshot.data.scenes.len());
 
-#[derive(Deserialize)]
-struct LinkCharacterRequest {
-    char_id: Uuid,
-    display_order: Option<i32>,
+    Ok(HttpResponse::Ok().json(snapshot))
 }
 
-async fn link_project_character(
+// 获取项目快照列表（最新在前）
+async fn get_project_snapshots(
     path: web::Path<String>,
     pool: web::Data<sqlx::PgPool>,
-    req: web::Json<LinkCharacterRequest>,
 ) -> Result<HttpResponse> {
-    let project_id_str = path.into_inner();
-    let project_id = Uuid::parse_str(&project_id_str)
+    let project_id = Uuid::parse_str(&path.into_inner())
         .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
-    
-    // Check if character exists first? Or just try link (FK constraint handles it, but better explicit error)
-    // ProjectCharacter::link handles insert.
-    
-    match ProjectCharacter::link(
-        pool.as_ref(), 
-        project_id, 
-        req.char_id, 
-        req.display_order.unwrap_or(0)
-    ).await {
-        Ok(_) => {
-            // Return the full ProjectCharacter view
-            let char_data = sqlx::query_as::<_, Character>("SELECT * FROM characters WHERE id = $1")
-                .bind(req.char_id)
-                .fetch_one(pool.as_ref())
-                .await
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-                
-            Ok(HttpResponse::Ok().json(ProjectCharacter {
-                id: char_data.id,
-                name: char_data.name,
-                image_url: char_data.image_url,
-                prompt: char_data.prompt,
-                category: char_data.category,
-                tags: char_data.tags,
-                display_order: req.display_order.unwrap_or(0)
-            }))
-        },
-        Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": format!("Failed to link: {}", e)
-        })))
-    }
-}
 
+    let snapshots = ProjectSnapshot::list_by_project_id(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 
-async fn delete_project_character(
-    path: web::Path<(String, String)>,
+    Ok(HttpResponse::Ok().json(snapshots))
+}
+
+// 比较两个快照（或快照与当前状态）
+async fn diff_project_snapshots(
+    path: web::Path<String>,
+    query: web::Query<SnapshotDiffQuery>,
     pool: web::Data<sqlx::PgPool>,
 ) -> Result<HttpResponse> {
-    let (project_id_str, char_id_str) = path.into_inner();
-    let project_id = Uuid::parse_str(&project_id_str).unwrap();
-    let char_id = Uuid::parse_str(&char_id_str)
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid character ID"))?;
-    
-    match ProjectCharacter::unlink(pool.as_ref(), project_id, char_id).await {
-        Ok(_) => {
-            // 删除角色后，清除拼接的角色图
-            if let Ok(Some(project)) = Project::find_by_id(pool.as_ref(), project_id).await {
-                if let Some(ref image_url) = project.combined_characters_image {
-                    // 删除文件
-                    if image_url.starts_with("/data/") {
-                        let local_path = format!(".{}", image_url);
-                        if Path::new(&local_path).exists() {
-                            let _ = fs::remove_file(&local_path);
-                            println!("   🗑️  已删除拼接图片: {}", local_path);
-                        }
-                    }
-                    
-                    // 更新数据库
-                    let _ = sqlx::query(
-                        "UPDATE projects SET combined_characters_image = NULL, updated_at = NOW() WHERE id = $1"
-                    )
-                    .bind(project_id)
-                    .execute(pool.as_ref())
-                    .await;
-                    
-                    println!("   ✅ 已清除项目的拼接角色图");
-                }
+    let project_id = Uuid::parse_str(&path.into_inner())
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
+
+    let Some(from) = find_project_snapshot(pool.as_ref(), project_id, query.from).await? else {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": format!("快照不存在: {}", query.from)
+        })));
+    };
+
+    let to = if query.to == "current" {
+        let project = Project::find_by_id(pool.as_ref(), project_id)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Project not found"))?;
+        SnapshotData::capture(pool.as_ref(), &project)
+            .await
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+    } else {
+        let Ok(to_id) = query.to.parse::<i32>() else {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "to 必须是快照ID或 current"
+            })));
+        };
+        match find_project_snapshot(pool.as_ref(), project_id, to_id).await? {
+            Some(snapshot) => snapshot.data.0,
+            None => {
+                return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                    "error": format!("快照不存在: {}", to_id)
+                })));
             }
-            
-            Ok(HttpResponse::Ok().json(serde_json::json!({
-                "success": true
-            })))
-        },
-        Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": e.to_string()
-        }))),
-    }
-}
+        }
+    };
 
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "from": from.id,
+        "to": query.to,
+        "diff": diff_snapshot_data(&from.data, &to),
+    })))
+}
 
-async fn update_global_prompt(
-    path: web::Path<String>,
+// 恢复项目快照（恢复前自动保存当前状态，不删除任何生成的媒体）
+async fn restore_project_snapshot(
+    path: web::Path<(String, i32)>,
     pool: web::Data<sqlx::PgPool>,
-    req: web::Json<UpdateGlobalPromptRequest>,
 ) -> Result<HttpResponse> {
-    let project_id_str = path.into_inner();
+    let (project_id_str, snapshot_id) = path.into_inner();
     let project_id = Uuid::parse_str(&project_id_str)
         .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
-    
-    match sqlx::query(
-        "UPDATE projects SET global_image_prompt = $1, global_video_prompt = $2, updated_at = NOW() WHERE id = $3"
+
+    let Some(snapshot) = find_project_snapshot(pool.as_ref(), project_id, snapshot_id).await? else {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "快照不存在"
+        })));
+    };
+
+    let project = Project::find_by_id(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Project not found"))?;
+
+    // 自动备份当前状态，恢复操作本身也可以撤销
+    let current = SnapshotData::capture(pool.as_ref(), &project)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let backup = ProjectSnapshot::create(
+        pool.as_ref(),
+        project_id,
+        format!("恢复「{}」前自动备份", snapshot.name),
+        current,
     )
-    .bind(&req.global_image_prompt)
-    .bind(&req.global_video_prompt)
-    .bind(project_id)
-    .execute(pool.get_ref())
     .await
-    {
-        Ok(_) => Ok(HttpResponse::Ok().json(serde_json::json!({
-            "success": true
-        }))),
-        Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": e.to_string()
-        }))),
-    }
+    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    let result = snapshot.restore(pool.as_ref())
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    let total_runtime = recalculate_timeline(pool.as_ref(), project_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    println!(
+        "⏪ 已恢复项目快照: {} (恢复 {} 个，重建 {} 个，跳过锁定 {} 个)",
+        snapshot.name,
+        result.restored_scenes.len(),
+        result.recreated_scenes.len(),
+        result.skipped_locked_scenes.len()
+    );
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true,
+        "backup_snapshot_id": backup.id,
+        "result": result,
+        "total_runtime": total_runtime
+    })))
 }
 
-async fn update_project_script(
-    path: web::Path<String>,
+// 删除项目快照
+async fn delete_project_snapshot(
+    path: web::Path<(String, i32)>,
     pool: web::Data<sqlx::PgPool>,
-    req: web::Json<UpdateScriptRequest>,
 ) -> Result<HttpResponse> {
-    let project_id_str = path.into_inner();
+    let (project_id_str, snapshot_id) = path.into_inner();
     let project_id = Uuid::parse_str(&project_id_str)
         .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid project ID"))?;
-    
-    match sqlx::query(
-        "UPDATE projects SET script = $1, updated_at = NOW() WHERE id = $2"
-    )
-    .bind(&req.script)
-    .bind(project_id)
-    .execute(pool.get_ref())
-    .await
-    {
-        Ok(_) => Ok(HttpResponse::Ok().json(serde_json::json!({
-            "success": true
-        }))),
-        Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": e.to_string()
-        }))),
+
+    if find_project_snapshot(pool.as_ref(), project_id, snapshot_id).await?.is_none() {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "快照不存在"
+        })));
     }
+
+    ProjectSnapshot::delete(pool.as_ref(), snapshot_id)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "success": true
+    })))
 }
 
-async fn delete_character_global(
-    pool: web::Data<sqlx::PgPool>,
-    path: web::Path<String>,
-) -> Result<HttpResponse> {
-    let char_id_str = path.into_inner();
-    let char_id = Uuid::parse_str(&char_id_str)
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid character ID"))?;
-    
-    match Character::delete(pool.as_ref(), char_id).await {
-        Ok(_) => Ok(HttpResponse::Ok().json(serde_json::json!({
-            "success": true
-        }))),
-        Err(e) => Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": e.to_string()
-        }))),
+// ========================================
+// 孤立文件清理
+// ========================================
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct GcRequest {
+    dry_run: bool,           // 默认只报告不删除
+    min_age_hours: Option<u64>, // 跳过最近修改的文件，默认 24 小时
+}
+
+impl Default for GcRequest {
+    fn default() -> Self {
+        GcRequest {
+            dry_run: true,
+            min_age_hours: None,
+        }
     }
 }
 
-// 分析角色提示词
-async fn analyze_character_prompt(
-    req: web::Json<AnalyzeCharacterRequest>,
+// 扫描 data 目录中数据库不再引用的文件，dry_run=false 时删除
+async fn collect_garbage(
+    req: web::Json<GcRequest>,
+    pool: web::Data<sqlx::PgPool>,
 ) -> Result<HttpResponse> {
-    log_api_request("/api/characters/analyze", "POST", &format!("prompt length: {}", req.prompt.len()));
-    println!("🔍 开始分析角色提示词: {}", req.prompt);
-    
-    let api_key = std::env::var("GPT_NANO_API_KEY")
-        .map_err(|_| {
-            println!("❌ 错误: GPT_NANO_API_KEY 环境变量未设置");
-            std::io::Error::new(std::io::ErrorKind::Other, "GPT_NANO_API_KEY not found in environment")
-        })?;
-    
-    let client = Client::new();
-    
-    // 构建系统提示词，让模型返回JSON格式的角色信息
-    let system_prompt = format!(
-        r#"你是一个角色分析助手。请分析以下角色描述，提取出角色名称、分类和标签。
-
-**重要：请务必使用中文回复，所有字段内容都应该是中文。**
+    let (dry_run, min_age_hours) = (req.dry_run, req.min_age_hours.unwrap_or(24));
 
-角色描述：
-{}
+    let urls = models::referenced_media_urls(pool.as_ref())
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let (project_ids, job_ids) = models::live_owner_ids(pool.as_ref())
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let refs = gc::LiveReferences::new(urls, project_ids, job_ids);
 
-请以JSON格式返回结果，包含以下字段：
-- name: 角色名称（如果描述中没有明确名称，请根据描述生成一个合适的中文名字）
-- category: 角色分类（例如：主要角色、配角、反派等，必须用中文）
-- tags: 标签列表（数组格式，包含角色的特征、性格、能力等，3-5个中文标签）
+    println!("🧹 开始扫描孤立文件 (dry_run: {}, 跳过 {} 小时内修改的文件)", dry_run, min_age_hours);
 
-示例输出格式：
-{{
-  "name": "艾莉亚",
-  "category": "主要角色",
-  "tags": ["勇敢", "善良", "魔法师", "年轻", "冒险者"]
-}}
+    let min_age = std::time::Duration::from_secs(min_age_hours * 3600);
+    let result = web::block(move || -> std::io::Result<_> {
+        let data_dir = Path::new("./data");
+        let orphans = gc::scan(data_dir, &refs, min_age)?;
+        let deleted = if dry_run { None } else { Some(gc::delete(data_dir, &orphans)) };
+        Ok((orphans, deleted))
+    })
+    .await?;
 
-请只返回JSON，不要添加其他说明文字。所有内容必须是中文。"#,
-        req.prompt
-    );
-    
-    // 调用 GPT-nano API
-    let gpt_request = GptNanoRequest {
-        model: "gpt-5-nano-2025-08-07".to_string(),
-        input: system_prompt,
-        temperature: 0.7,
-        max_tokens: 500,
-    };
-    
-    println!("📡 正在调用 GPT-nano API...");
-    
-    // 从环境变量读取 GPT-nano API 配置（必须设置）
-    let gpt_nano_base_url = std::env::var("GPT_NANO_BASE_URL")
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "GPT_NANO_BASE_URL not set"))?;
-    let gpt_nano_endpoint = std::env::var("GPT_NANO_ENDPOINT")
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "GPT_NANO_ENDPOINT not set"))?;
-    let gpt_nano_url = format!("{}{}", gpt_nano_base_url, gpt_nano_endpoint);
-    
-    let response = client
-        .post(&gpt_nano_url)
-        .header("Content-Type", "application/json")
-        .header("Authorization", format!("Bearer {}", api_key))
-        .json(&gpt_request)
-        .send()
-        .await
-        .map_err(|e| {
-            println!("❌ GPT-nano API 请求失败: {}", e);
-            std::io::Error::new(std::io::ErrorKind::Other, format!("GPT-nano API request failed: {}", e))
-        })?;
-    
-    let status = response.status();
-    println!("📥 API 响应状态码: {}", status);
-    
-    if !status.is_success() {
-        let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-        println!("❌ API 返回错误: {}", error_text);
-        return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
-            "error": format!("GPT-nano API 调用失败: {}", error_text)
-        })));
-    }
-    
-    // 先获取原始响应文本用于调试
-    let response_text = response.text().await
-        .map_err(|e| {
-            println!("❌ 无法读取响应文本: {}", e);
-            std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to read response: {}", e))
-        })?;
-    
-    println!("📄 API 原始响应: {}", &response_text[..response_text.len().min(500)]);
-    
-    // 解析JSON响应
-    let gpt_response: GptNanoResponse = serde_json::from_str(&response_text)
-        .map_err(|e| {
-            println!("❌ 解析 GPT 响应失败: {}", e);
-            std::io::Error::new(std::io::ErrorKind::Other, format!("Failed to parse GPT-nano response: {}", e))
-        })?;
-    
-    println!("✅ 成功解析 GPT 响应，output 数量: {}", gpt_response.output.len());
-    
-    // 提取响应文本
-    let text = gpt_response.output
-        .iter()
-        .find(|o| o.output_type == "message")
-        .and_then(|o| {
-            println!("📝 找到 message 类型的输出，content 数量: {}", o.content.len());
-            o.content.first()
-        })
-        .map(|c| {
-            println!("📝 提取的文本类型: {}, 文本内容: {}", c.content_type, &c.text[..c.text.len().min(200)]);
-            c.text.as_str()
-        })
-        .ok_or_else(|| {
-            println!("❌ 未找到文本内容");
-            std::io::Error::new(std::io::ErrorKind::Other, "GPT响应中没有找到文本内容，请检查API返回格式")
-        })?;
-    
-    // 解析JSON响应
-    // 先尝试提取JSON（可能被markdown代码块包裹）
-    let json_text = if text.contains("```json") {
-        println!("🔧 检测到 JSON 代码块，正在提取...");
-        text.split("```json")
-            .nth(1)
-            .and_then(|s| s.split("```").next())
-            .unwrap_or(text)
-            .trim()
-    } else if text.contains("```") {
-        println!("🔧 检测到代码块，正在提取...");
-        text.split("```")
-            .nth(1)
-            .and_then(|s| s.split("```").next())
-            .unwrap_or(text)
-            .trim()
-    } else {
-        text.trim()
+    let (orphans, deleted) = match result {
+        Ok(r) => r,
+        Err(e) => {
+            log_api_error("collect_garbage", &e.to_string(), "");
+            return Ok(HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": format!("扫描孤立文件失败: {}", e)
+            })));
+        }
     };
-    
-    println!("🔍 待解析的 JSON (truncated): {:.100}...", json_text);
-    
-    #[derive(Deserialize)]
-    struct ParsedCharacter {
-        name: String,
-        category: String,
-        tags: Vec<String>,
+
+    let total_bytes: u64 = orphans.iter().map(|o| o.size_bytes).sum();
+    println!("🧹 发现 {} 个孤立文件/目录，共 {} 字节", orphans.len(), total_bytes);
+
+    let mut response = serde_json::json!({
+        "dry_run": dry_run,
+        "orphan_count": orphans.len(),
+        "total_bytes": total_bytes,
+        "orphans": orphans,
+    });
+
+    if let Some((deleted_count, freed_bytes, errors)) = deleted {
+        println!("🗑️  已删除 {} 个孤立文件/目录，释放 {} 字节", deleted_count, freed_bytes);
+        for e in &errors {
+            println!("⚠️  删除失败: {}", e);
+        }
+        response["deleted_count"] = serde_json::json!(deleted_count);
+        response["freed_bytes"] = serde_json::json!(freed_bytes);
+        response["errors"] = serde_json::json!(errors);
     }
-    
-    let parsed: ParsedCharacter = serde_json::from_str(json_text)
-        .map_err(|e| {
-            println!("❌ 解析角色 JSON 失败: {}", e);
-            println!("❌ JSON 文本 (truncated): {:.200}...", json_text);
-            std::io::Error::new(
-                std::io::ErrorKind::Other, 
-                format!("无法解析角色信息JSON。错误: {}", e)
-            )
-        })?;
-    
-    println!("✅ 成功解析角色信息: 名称={}, 分类={}, 标签数={}", parsed.name, parsed.category, parsed.tags.len());
-    
-    Ok(HttpResponse::Ok().json(AnalyzeCharacterResponse {
-        name: parsed.name,
-        category: parsed.category,
-        tags: parsed.tags,
-    }))
+
+    Ok(HttpResponse::Ok().json(response))
 }
 
+// 服务监听地址（MOCK_AI 模式下模型服务也指向这里）
+const BIND_HOST: &str = "127.0.0.1";
+const BIND_PORT: u16 = 3001;
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv::dotenv().ok();
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
 
+    // 模拟模式：所有模型调用指向本服务的 /mock 接口
+    let mock_ai = mock::enabled();
+    if mock_ai {
+        mock::install_env(&format!("http://{}:{}", BIND_HOST, BIND_PORT));
+        println!("🧪 MOCK_AI 已开启：图片、视频、文本模型和 R2 上传均使用本地模拟服务");
+    }
+
     println!("🚀 视频虚拟剪辑服务启动中...");
-    println!("📡 监听地址: http://localhost:3001");
+    println!("📡 监听地址: http://{}:{}", BIND_HOST, BIND_PORT);
     println!("🎬 虚拟剪辑接口: POST /api/video/virtual-cut");
 
     // 创建数据库连接池
@@ -4151,6 +8457,23 @@ async fn main() -> std::io::Result<()> {
     fs::create_dir_all("data/projects")?;
     fs::create_dir_all("data/analysis")?;
 
+    // 上次运行中断的批量任务
+    match GenerationBatch::mark_interrupted(&pool).await {
+        Ok(0) => {}
+        Ok(n) => println!("⚠️  {} 个批量生成任务因服务重启中断", n),
+        Err(e) => eprintln!("⚠️  检查批量任务失败: {}", e),
+    }
+
+    // 回收站自动清理（TRASH_RETENTION_DAYS 天后彻底删除，设为 0 关闭）
+    let trash_retention_days = std::env::var("TRASH_RETENTION_DAYS")
+        .ok()
+        .and_then(|s| s.parse::<i64>().ok())
+        .unwrap_or(30);
+    if trash_retention_days > 0 {
+        println!("🗑️  回收站项目保留 {} 天", trash_retention_days);
+        actix_web::rt::spawn(auto_purge_trash(pool.clone(), trash_retention_days));
+    }
+
     HttpServer::new(move || {
         let cors = Cors::permissive();
         
@@ -4169,14 +8492,52 @@ async fn main() -> std::io::Result<()> {
             // 我的项目 API 路由
             .route("/api/projects", web::get().to(get_projects))
             .route("/api/projects", web::post().to(create_project))
+            .route("/api/projects/import-bundle", web::post().to(import_project_bundle))
             .route("/api/projects/template/video", web::get().to(download_video_template))
             .route("/api/projects/template/comic", web::get().to(download_comic_template))
+            .route("/api/search", web::get().to(search_projects))
+            .route("/api/maintenance/gc", web::post().to(collect_garbage))
             .route("/api/projects/{id}", web::get().to(get_project_detail))
             .route("/api/projects/{id}", web::delete().to(delete_project))
+            .route("/api/projects/{id}", web::patch().to(update_project))
+            .route("/api/projects/{id}/archive", web::post().to(archive_project))
+            .route("/api/projects/{id}/unarchive", web::post().to(unarchive_project))
+            .route("/api/projects/{id}/restore", web::post().to(restore_project))
+            .route("/api/projects/{id}/purge", web::delete().to(purge_project))
             .route("/api/projects/{id}/history", web::get().to(get_project_history))
+            .route("/api/projects/{id}/export.json", web::get().to(export_project_json))
+            .route("/api/projects/{id}/bundle.zip", web::get().to(export_project_bundle))
+            .route("/api/projects/{id}/duplicate", web::post().to(duplicate_project))
+            .route("/api/projects/{id}/storyboard-drafts", web::get().to(get_storyboard_drafts))
+            .route("/api/projects/{id}/storyboard-drafts", web::post().to(generate_storyboard_draft))
+            .route("/api/projects/{id}/storyboard-drafts/{draft_id}/accept", web::post().to(accept_storyboard_draft))
+            .route("/api/projects/{id}/storyboard-drafts/{draft_id}", web::delete().to(discard_storyboard_draft))
+            .route("/api/projects/{id}/prompt-history", web::get().to(get_project_prompt_history))
+            .route("/api/projects/{id}/prompt-history/{edit_id}/revert", web::post().to(revert_prompt_edit))
+            .route("/api/projects/{id}/snapshots", web::get().to(get_project_snapshots))
+            .route("/api/projects/{id}/snapshots", web::post().to(create_project_snapshot))
+            .route("/api/projects/{id}/snapshots/diff", web::get().to(diff_project_snapshots))
+            .route("/api/projects/{id}/snapshots/{snapshot_id}/restore", web::post().to(restore_project_snapshot))
+            .route("/api/projects/{id}/snapshots/{snapshot_id}", web::delete().to(delete_project_snapshot))
             .route("/api/projects/{id}/script", web::put().to(update_project_script))
+            .route("/api/projects/{id}/timeline", web::get().to(get_project_timeline))
+            .route("/api/projects/{id}/generate-images", web::post().to(batch_generate_first_frames))
+            .route("/api/projects/{id}/batches", web::get().to(get_generation_batches))
+            .route("/api/projects/{id}/batches/{batch_id}", web::get().to(get_generation_batch))
+            .route("/api/projects/{id}/scenes/order", web::put().to(reorder_scenes))
             .route("/api/projects/{id}/scenes/{scene_id}", web::put().to(update_scene_prompts))
+            .route("/api/projects/{id}/scenes/{scene_id}/status", web::put().to(update_scene_status))
+            .route("/api/projects/{id}/scenes/{scene_id}/sequence", web::put().to(update_scene_sequence))
+            .route("/api/projects/{id}/scenes/{scene_id}/notes", web::get().to(get_scene_notes))
+            .route("/api/projects/{id}/scenes/{scene_id}/prompt-history", web::get().to(get_scene_prompt_history))
+            .route("/api/projects/{id}/scenes/{scene_id}/notes", web::post().to(create_scene_note))
+            .route("/api/projects/{id}/scenes/{scene_id}/characters", web::get().to(get_scene_characters))
+            .route("/api/projects/{id}/scenes/{scene_id}/characters", web::put().to(update_scene_characters))
+            .route("/api/projects/{id}/scenes/{scene_id}/characters/infer", web::post().to(infer_scene_characters))
             .route("/api/projects/{id}/scenes/{scene_id}/generate-image", web::post().to(generate_first_frame))
+            .route("/api/projects/{id}/scenes/{scene_id}/candidates/{batch_id}", web::get().to(get_image_candidates))
+            .route("/api/projects/{id}/scenes/{scene_id}/candidates/{history_id}/select", web::put().to(select_image_candidate))
+            .route("/api/projects/{id}/scenes/{scene_id}/edit-region", web::post().to(edit_scene_region))
             .route("/api/projects/{id}/scenes/{scene_id}/generate-video", web::post().to(generate_storyboard_video))
             .route("/api/projects/{id}/scenes/{scene_id}/video-status/{video_id}", web::get().to(poll_video_status))
             // Global Character Routes
@@ -4199,6 +8560,7 @@ async fn main() -> std::io::Result<()> {
             .route("/api/projects/{id}/characters/{char_id}", web::put().to(update_project_character))
             .route("/api/projects/{id}/characters/{char_id}", web::delete().to(delete_project_character))
             .route("/api/projects/{id}/global-prompt", web::put().to(update_global_prompt))
+            .route("/api/projects/{id}/variables", web::put().to(update_prompt_variables))
             .route("/api/projects/{id}/stitch-characters", web::post().to(stitch_character_images))
             .route("/api/projects/{id}/combined-characters", web::delete().to(delete_combined_characters_image))
 
@@ -4214,8 +8576,13 @@ async fn main() -> std::io::Result<()> {
             .route("/api/characters/img2img", web::post().to(img2img_generate))
             .route("/api/characters/analyze", web::post().to(analyze_character_prompt))
             .route("/data/{filename:.*}", web::get().to(serve_data))
+            .configure(|cfg| {
+                if mock_ai {
+                    mock::configure(cfg);
+                }
+            })
     })
-    .bind(("127.0.0.1", 3001))?
+    .bind((BIND_HOST, BIND_PORT))?
     .run()
     .await
 }
@@ -4262,23 +8629,310 @@ fn log_api_error(endpoint: &str, error: &str, context: &str) {
     eprintln!("[ERROR] {} - {}", endpoint, error);
 }
 
-// Gemini API 请求日志
-fn log_gemini_request(endpoint: &str, payload: &serde_json::Value) {
-    let content = format!(
-        "REQUEST to {}\nPayload: {}",
-        endpoint,
-        serde_json::to_string_pretty(payload).unwrap_or_default()
-    );
-    write_log("GEMINI_REQUEST", &content);
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_and_read_body_json, call_service, init_service, TestRequest};
+
+    // 在随机端口启动模拟服务，返回指向它的 VEO 查询配置
+    fn start_mock_veo() -> VeoQueryConfig {
+        let server = HttpServer::new(|| App::new().configure(mock::configure))
+            .bind(("127.0.0.1", 0))
+            .expect("bind mock server");
+        let base_url = format!("http://{}/mock/veo", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+
+        VeoQueryConfig {
+            base_url,
+            query_endpoint: "/query".to_string(),
+            api_key: "mock".to_string(),
+        }
+    }
 
-// Gemini API 响应日志
-fn log_gemini_response(endpoint: &str, status: u16, response: &str) {
-    let content = format!(
-        "RESPONSE from {} (Status: {})\nBody: {}",
-        endpoint,
-        status,
-        response
-    );
-    write_log("GEMINI_RESPONSE", &content);
+    // 创建项目、分镜和模拟 VEO 任务，并像 generate_storyboard_video 一样记录任务提示词
+    async fn create_video_task(pool: &sqlx::PgPool, base_url: &str, prompt: &str) -> (Project, StoryboardScene, String) {
+        let project = Project::create(pool, "视频轮询测试".to_string(), None, Some("video".to_string()))
+            .await
+            .expect("create project");
+        StoryboardScene::batch_create(pool, project.id, vec![(1, Some(2.0), None, Some(prompt.to_string()))])
+            .await
+            .expect("create scene");
+        let scene = StoryboardScene::find_by_project_id(pool, project.id)
+            .await
+            .expect("find scene")
+            .remove(0);
+
+        let created: serde_json::Value = Client::new()
+            .post(format!("{}/create", base_url))
+            .json(&serde_json::json!({ "prompt": prompt, "duration": 2 }))
+            .send()
+            .await
+            .expect("create mock video task")
+            .json()
+            .await
+            .expect("parse mock create response");
+        let video_id = created["id"].as_str().expect("video id").to_string();
+        VideoTask::create(pool, &video_id, scene.id, prompt, Some("mock translated prompt"))
+            .await
+            .expect("save video task");
+
+        (project, scene, video_id)
+    }
+
+    // 反复调用 poll_video_status，直到任务结束
+    async fn poll_until_finished(
+        pool: &sqlx::PgPool,
+        veo_config: &VeoQueryConfig,
+        project_id: Uuid,
+        scene_id: i32,
+        video_id: &str,
+    ) -> serde_json::Value {
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(veo_config.clone()))
+                .route("/api/projects/{id}/scenes/{scene_id}/video-status/{video_id}", web::get().to(poll_video_status)),
+        )
+        .await;
+
+        for _ in 0..20 {
+            let req = TestRequest::get()
+                .uri(&format!("/api/projects/{}/scenes/{}/video-status/{}", project_id, scene_id, video_id))
+                .to_request();
+            let body: serde_json::Value = call_and_read_body_json(&app, req).await;
+            if matches!(body["status"].as_str(), Some("completed" | "failed" | "error")) {
+                return body;
+            }
+        }
+        panic!("video task {} did not finish", video_id);
+    }
+
+    fn scene(id: i32, duration: Option<f64>, status: &str, timing: Option<(&str, &str)>) -> StoryboardScene {
+        StoryboardScene {
+            id,
+            project_id: Uuid::nil(),
+            scene_index: id,
+            start_time: timing.map(|(start, _)| start.to_string()),
+            end_time: timing.map(|(_, end)| end.to_string()),
+            duration,
+            first_frame_prompt: None,
+            video_prompt: None,
+            latest_image_url: None,
+            latest_video_url: None,
+            status: status.to_string(),
+            sequence: None,
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn parse_timestamp_reverses_format_timestamp() {
+        assert_eq!(parse_timestamp(&format_timestamp(3725.5)), Some(3725.5));
+        assert_eq!(parse_timestamp("00:00:08.000"), Some(8.0));
+        assert_eq!(parse_timestamp("8.0"), None);
+        assert_eq!(parse_timestamp("00:00:aa"), None);
+    }
+
+    #[test]
+    fn image_extension_follows_content() {
+        let mut jpeg = Vec::new();
+        image::DynamicImage::new_rgb8(2, 2)
+            .write_to(&mut std::io::Cursor::new(&mut jpeg), image::ImageOutputFormat::Jpeg(90))
+            .expect("encode jpeg");
+        assert_eq!(image_extension(&jpeg), "jpg");
+        assert_eq!(image_extension(b"not an image"), "png");
+    }
+
+    #[test]
+    fn img2img_source_requires_exactly_one_source() {
+        let character_id = Uuid::new_v4();
+        assert!(img2img_source(&Img2ImgRequest::default()).is_err());
+        assert!(img2img_source(&Img2ImgRequest {
+            character_id: Some(character_id),
+            history_id: Some(1),
+            ..Default::default()
+        })
+        .is_err());
+
+        let upload = Img2ImgRequest { image_base64: Some("AAAA".to_string()), ..Default::default() };
+        assert!(img2img_source(&upload).is_err());
+        assert_eq!(
+            img2img_source(&Img2ImgRequest { mime_type: Some("image/png".to_string()), ..upload }),
+            Ok(Img2ImgSource::Upload { data: "AAAA".to_string(), mime_type: "image/png".to_string() })
+        );
+
+        for url in ["/tmp/a.png", "/data/../etc/passwd", "https://example.com/a.png"] {
+            assert!(img2img_source(&Img2ImgRequest { image_url: Some(url.to_string()), ..Default::default() }).is_err());
+        }
+        assert_eq!(
+            img2img_source(&Img2ImgRequest { image_url: Some("/data/characters/a.png".to_string()), ..Default::default() }),
+            Ok(Img2ImgSource::Url("/data/characters/a.png".to_string()))
+        );
+        assert_eq!(
+            img2img_source(&Img2ImgRequest { character_id: Some(character_id), ..Default::default() }),
+            Ok(Img2ImgSource::Character(character_id))
+        );
+        assert_eq!(
+            img2img_source(&Img2ImgRequest { history_id: Some(7), ..Default::default() }),
+            Ok(Img2ImgSource::History(7))
+        );
+    }
+
+    #[test]
+    fn img2img_target_resolves_defaults() {
+        let source_id = Uuid::new_v4();
+        let other_id = Uuid::new_v4();
+        let save_to = |target: &str| Some(target.to_string());
+
+        assert!(img2img_target(&Img2ImgRequest::default(), None).is_err());
+        assert!(img2img_target(&Img2ImgRequest { save_to: save_to("gallery"), ..Default::default() }, None).is_err());
+        assert_eq!(
+            img2img_target(&Img2ImgRequest { save_to: save_to("preview"), ..Default::default() }, None),
+            Ok(Img2ImgTarget::Preview)
+        );
+
+        // 角色目标默认使用源角色，target_character_id 优先
+        let from_character = Img2ImgRequest {
+            save_to: save_to("character"),
+            character_id: Some(source_id),
+            ..Default::default()
+        };
+        assert_eq!(img2img_target(&from_character, None), Ok(Img2ImgTarget::Character(source_id)));
+        assert_eq!(
+            img2img_target(&Img2ImgRequest { target_character_id: Some(other_id), ..from_character }, None),
+            Ok(Img2ImgTarget::Character(other_id))
+        );
+        assert_eq!(
+            img2img_target(
+                &Img2ImgRequest { save_to: save_to("derived_character"), character_id: Some(source_id), ..Default::default() },
+                None
+            ),
+            Ok(Img2ImgTarget::DerivedCharacter(source_id))
+        );
+        assert!(img2img_target(&Img2ImgRequest { save_to: save_to("character"), ..Default::default() }, None).is_err());
+
+        // 分镜目标默认使用源历史记录所属分镜，scene_id 优先
+        let to_scene = Img2ImgRequest { save_to: save_to("scene"), ..Default::default() };
+        assert!(img2img_target(&to_scene, None).is_err());
+        assert_eq!(img2img_target(&to_scene, Some(3)), Ok(Img2ImgTarget::Scene(3)));
+        assert_eq!(
+            img2img_target(&Img2ImgRequest { scene_id: Some(5), ..to_scene }, Some(3)),
+            Ok(Img2ImgTarget::Scene(5))
+        );
+    }
+
+    #[test]
+    fn scene_timeline_keeps_locked_timing() {
+        let scenes = [
+            scene(1, Some(3.0), "draft", None),
+            scene(2, Some(4.0), "locked", Some(("00:00:05.000", "00:00:09.000"))),
+            scene(3, None, "approved", Some(("00:00:00.000", "00:00:01.000"))),
+            // 锁定但没有时间戳时按时长排
+            scene(4, Some(2.0), "locked", None),
+        ];
+        assert_eq!(scene_timeline(&scenes), [(0.0, 3.0), (5.0, 9.0), (9.0, 17.0), (17.0, 19.0)]);
+        assert!(scene_timeline(&[]).is_empty());
+    }
+
+    #[actix_web::test]
+    async fn generate_first_frame_rejects_malformed_body() {
+        // 请求体在访问数据库之前解析，延迟连接的连接池不会真正连接
+        let pool = sqlx::PgPool::connect_lazy("postgres://localhost/unused").expect("lazy pool");
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(pool))
+                .route("/api/projects/{id}/scenes/{scene_id}/generate-image", web::post().to(generate_first_frame)),
+        )
+        .await;
+
+        let req = TestRequest::post()
+            .uri(&format!("/api/projects/{}/scenes/1/generate-image", Uuid::new_v4()))
+            .insert_header(("content-type", "application/json"))
+            .set_payload(r#"{"candidates": "two""#)
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), actix_web::http::StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    #[ignore = "需要已导入 schema.sql 的 PostgreSQL（DATABASE_URL）和 ffmpeg"]
+    async fn poll_video_status_saves_completed_mock_video() {
+        let pool = db::create_pool().await.expect("connect DATABASE_URL");
+        let veo_config = start_mock_veo();
+        let (project, scene, video_id) = create_video_task(&pool, &veo_config.base_url, "镜头缓慢推进").await;
+
+        let body = poll_until_finished(&pool, &veo_config, project.id, scene.id, &video_id).await;
+        assert_eq!(body["status"], "completed", "{}", body);
+        let video_url = body["video_url"].as_str().expect("video_url");
+        assert!(video_url.starts_with(&format!("/data/projects/{}/videos/", project.id)));
+        assert!(Path::new(&format!(".{}", video_url)).exists());
+
+        let scene = StoryboardScene::find_by_id(&pool, scene.id).await.unwrap().unwrap();
+        assert_eq!(scene.latest_video_url.as_deref(), Some(video_url));
+
+        // 历史记录使用创建任务时保存的提示词和翻译
+        let history = GenerationHistory::find_by_scene_and_type(&pool, scene.id, "video").await.unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].result_url, video_url);
+        assert_eq!(history[0].prompt, "镜头缓慢推进");
+        assert_eq!(history[0].translated_prompt.as_deref(), Some("mock translated prompt"));
+        assert!(VideoTask::find(&pool, &video_id).await.unwrap().is_none());
+
+        Project::delete(&pool, project.id).await.unwrap();
+    }
+
+    #[actix_web::test]
+    #[ignore = "需要已导入 schema.sql 的 PostgreSQL（DATABASE_URL）"]
+    async fn poll_video_status_reports_failed_mock_video() {
+        let pool = db::create_pool().await.expect("connect DATABASE_URL");
+        let veo_config = start_mock_veo();
+        let (project, scene, video_id) = create_video_task(&pool, &veo_config.base_url, "镜头缓慢推进 [mock:fail]").await;
+
+        let body = poll_until_finished(&pool, &veo_config, project.id, scene.id, &video_id).await;
+        assert_eq!(body["status"], "failed", "{}", body);
+        assert_eq!(body["error"], "模拟视频生成失败");
+
+        let scene = StoryboardScene::find_by_id(&pool, scene.id).await.unwrap().unwrap();
+        assert!(scene.latest_video_url.is_none());
+        assert!(GenerationHistory::find_by_scene_and_type(&pool, scene.id, "video").await.unwrap().is_empty());
+        assert!(VideoTask::find(&pool, &video_id).await.unwrap().is_none());
+
+        Project::delete(&pool, project.id).await.unwrap();
+    }
+
+    #[actix_web::test]
+    #[ignore = "需要已导入 schema.sql 的 PostgreSQL（DATABASE_URL）"]
+    async fn poll_video_status_rejects_task_of_other_scene() {
+        let pool = db::create_pool().await.expect("connect DATABASE_URL");
+        let veo_config = start_mock_veo();
+        let (project, scene, video_id) = create_video_task(&pool, &veo_config.base_url, "镜头缓慢推进").await;
+        StoryboardScene::batch_create(&pool, project.id, vec![(2, Some(2.0), None, None)])
+            .await
+            .expect("create scene");
+        let other = StoryboardScene::find_by_project_id(&pool, project.id)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|s| s.id != scene.id)
+            .expect("other scene");
+
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(pool.clone()))
+                .app_data(web::Data::new(veo_config.clone()))
+                .route("/api/projects/{id}/scenes/{scene_id}/video-status/{video_id}", web::get().to(poll_video_status)),
+        )
+        .await;
+        let req = TestRequest::get()
+            .uri(&format!("/api/projects/{}/scenes/{}/video-status/{}", project.id, other.id, video_id))
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), actix_web::http::StatusCode::BAD_REQUEST);
+
+        let other = StoryboardScene::find_by_id(&pool, other.id).await.unwrap().unwrap();
+        assert!(other.latest_video_url.is_none());
+
+        Project::delete(&pool, project.id).await.unwrap();
+    }
 }
@@ -0,0 +1,742 @@
+// ========================================
+// 离线开发用的模拟 AI 服务
+// ========================================
+//
+// MOCK_AI=true 时，启动前把 Gemini、OpenAI 图片、SD WebUI、VEO、GPT-nano 的接口地址指向本服务的 /mock 路由，
+// R2 上传改为返回本服务上的本地文件地址。结果只由请求内容决定，相同请求得到相同的图片、视频和文本。
+//
+// 提示词中包含以下标记时模拟失败：
+// - [mock:safety]  图片生成被安全策略拦截
+// - [mock:fail]    视频生成在处理过程中失败
+
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+use actix_multipart::Multipart;
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use base64::Engine as _;
+use futures_util::TryStreamExt;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+/// GPT-nano 请求的调用场景标记（仅模拟模式下发送），模拟接口按它返回对应格式的文本
+pub const GPT_NANO_TASK_HEADER: &str = "X-Mock-Task";
+pub const GPT_NANO_TASK_TRANSLATE: &str = "translate";
+pub const GPT_NANO_TASK_CHARACTER_ANALYSIS: &str = "character_analysis";
+pub const GPT_NANO_TASK_STORYBOARD: &str = "storyboard";
+
+// 模拟图片的长边像素
+const IMAGE_LONG_SIDE: u32 = 512;
+// 模拟视频的长边像素和帧率
+const VIDEO_LONG_SIDE: u32 = 640;
+const VIDEO_FPS: u32 = 24;
+// 模拟视频生成目录
+const VIDEO_DIR: &str = "data/mock/videos";
+
+// VEO 任务每次查询推进一步
+const VIDEO_PROGRESS_STEPS: &[(&str, i64)] = &[
+    ("queued", 0),
+    ("processing", 20),
+    ("processing", 45),
+    ("processing", 70),
+    ("processing", 90),
+    ("completed", 100),
+];
+// 模拟失败的任务在该步骤后返回 failed
+const VIDEO_FAIL_AFTER_STEP: usize = 2;
+
+pub fn enabled() -> bool {
+    matches!(std::env::var("MOCK_AI").as_deref(), Ok("true") | Ok("1"))
+}
+
+/// 把各模型服务的环境变量指向本服务（base_url 由监听地址得出）的模拟接口，需在启动 HTTP 服务前调用
+pub fn install_env(base_url: &str) {
+    let base = format!("{}/mock", base_url);
+    let vars = [
+        ("R2_PUBLIC_URL", base_url.to_string()),
+        ("GEMINI_BASE_URL", format!("{}/gemini", base)),
+        ("GEMINI_API_KEY", "mock".to_string()),
+        ("GEMINI_MODEL", "mock-image".to_string()),
+        ("GEMINI_ENDPOINT", "/models/{model}/generateContent".to_string()),
+        ("GEMINI_SUPPORTS_CANDIDATE_COUNT", "true".to_string()),
+        ("GEMINI_MAX_RETRIES", "0".to_string()),
+        ("OPENAI_IMAGE_BASE_URL", format!("{}/openai", base)),
+        ("OPENAI_IMAGE_API_KEY", "mock".to_string()),
+        ("SD_WEBUI_BASE_URL", format!("{}/sd", base)),
+        ("VEO_BASE_URL", format!("{}/veo", base)),
+        ("VEO_API_KEY", "mock".to_string()),
+        ("VEO_MODEL", "mock-video".to_string()),
+        ("VEO_CREATE_ENDPOINT", "/create".to_string()),
+        ("VEO_QUERY_ENDPOINT", "/query".to_string()),
+        ("GPT_NANO_BASE_URL", format!("{}/gpt-nano", base)),
+        ("GPT_NANO_API_KEY", "mock".to_string()),
+        ("GPT_NANO_ENDPOINT", "/responses".to_string()),
+    ];
+    for (name, value) in vars {
+        std::env::set_var(name, value);
+    }
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/mock")
+            // 请求中带有 base64 参考图
+            .app_data(web::JsonConfig::default().limit(64 * 1024 * 1024))
+            .route("/gemini/models/{model}/generateContent", web::post().to(gemini_generate_content))
+            .route("/openai/images/generations", web::post().to(openai_generations))
+            .route("/openai/images/edits", web::post().to(openai_edits))
+            .route("/sd/sdapi/v1/{mode}", web::post().to(sd_webui_generate))
+            .route("/veo/create", web::post().to(veo_create))
+            .route("/veo/query", web::get().to(veo_query))
+            .route("/veo/videos/{filename}", web::get().to(veo_video_file))
+            .route("/gpt-nano/responses", web::post().to(gpt_nano_responses)),
+    );
+}
+
+fn hash_hex(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part.as_bytes());
+        hasher.update([0u8]);
+    }
+    hex::encode(hasher.finalize())
+}
+
+// 按 "w:h" 比例计算尺寸，宽高对齐到 16
+fn dimensions(aspect_ratio: Option<&str>, long_side: u32) -> (u32, u32) {
+    let ratio = aspect_ratio
+        .and_then(|r| r.split_once(':'))
+        .and_then(|(w, h)| Some((w.trim().parse::<f64>().ok()?, h.trim().parse::<f64>().ok()?)))
+        .filter(|(w, h)| *w > 0.0 && *h > 0.0);
+    let Some((w, h)) = ratio else {
+        return (long_side, long_side);
+    };
+    let short_side = ((long_side as f64 * w.min(h) / w.max(h) / 16.0).round() as u32).max(1) * 16;
+    if w >= h {
+        (long_side, short_side)
+    } else {
+        (short_side, long_side)
+    }
+}
+
+// 由种子生成的渐变占位图（PNG）
+fn placeholder_png(seed: &str, width: u32, height: u32) -> std::io::Result<Vec<u8>> {
+    let digest = Sha256::digest(seed.as_bytes());
+    let (from, to) = ([digest[0], digest[1], digest[2]], [digest[3], digest[4], digest[5]]);
+    let stripe = 16 + (digest[6] as u32 % 48);
+
+    let image = image::RgbImage::from_fn(width, height, |x, y| {
+        let t = (x + y) as f32 / (width + height) as f32;
+        let shade = if ((x + y) / stripe).is_multiple_of(2) { 1.0 } else { 0.85 };
+        image::Rgb(std::array::from_fn(|i| {
+            ((from[i] as f32 * (1.0 - t) + to[i] as f32 * t) * shade) as u8
+        }))
+    });
+
+    let mut bytes = Cursor::new(Vec::new());
+    image::DynamicImage::ImageRgb8(image)
+        .write_to(&mut bytes, image::ImageOutputFormat::Png)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    Ok(bytes.into_inner())
+}
+
+fn placeholder_base64(seed: &str, width: u32, height: u32) -> std::io::Result<String> {
+    Ok(base64::engine::general_purpose::STANDARD.encode(placeholder_png(seed, width, height)?))
+}
+
+fn is_safety_blocked(prompt: &str) -> bool {
+    prompt.contains("[mock:safety]")
+}
+
+// ========================================
+// Gemini generateContent
+// ========================================
+
+async fn gemini_generate_content(body: web::Json<serde_json::Value>) -> Result<HttpResponse> {
+    let parts = body["contents"][0]["parts"].as_array().cloned().unwrap_or_default();
+    let prompt: String = parts.iter().filter_map(|p| p["text"].as_str()).collect::<Vec<_>>().join("\n");
+    let reference_hashes: Vec<String> = parts
+        .iter()
+        .filter_map(|p| p["inlineData"]["data"].as_str())
+        .map(|data| hash_hex(&[data]))
+        .collect();
+    let candidate_count = body["generationConfig"]["candidateCount"].as_u64().unwrap_or(1).max(1);
+    let (width, height) = dimensions(body["generationConfig"]["imageConfig"]["aspectRatio"].as_str(), IMAGE_LONG_SIDE);
+
+    println!("🧪 模拟 Gemini 生图: {} 张, 参考图 {} 张", candidate_count, reference_hashes.len());
+
+    if is_safety_blocked(&prompt) {
+        return Ok(HttpResponse::Ok().json(serde_json::json!({
+            "candidates": [],
+            "promptFeedback": { "blockReason": "SAFETY" }
+        })));
+    }
+
+    let references = reference_hashes.join(",");
+    let candidates: Vec<serde_json::Value> = (0..candidate_count)
+        .map(|i| {
+            let seed = hash_hex(&["gemini", &prompt, &references, &i.to_string()]);
+            Ok(serde_json::json!({
+                "content": {
+                    "parts": [{
+                        "inlineData": {
+                            "mimeType": "image/png",
+                            "data": placeholder_base64(&seed, width, height)?
+                        }
+                    }]
+                },
+                "finishReason": "STOP"
+            }))
+        })
+        .collect::<std::io::Result<_>>()?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "candidates": candidates })))
+}
+
+// ========================================
+// OpenAI Images API
+// ========================================
+
+#[derive(Debug, Deserialize)]
+struct OpenAiGenerationRequest {
+    prompt: String,
+    n: Option<usize>,
+    size: Option<String>,
+}
+
+fn openai_response(kind: &str, prompt: &str, n: usize, size: Option<&str>) -> std::io::Result<HttpResponse> {
+    if is_safety_blocked(prompt) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": { "code": "moderation_blocked", "message": "Your request was rejected by the safety system." }
+        })));
+    }
+
+    // "1024x1536" 转为比例
+    let aspect_ratio = size.map(|s| s.replace('x', ":"));
+    let (width, height) = dimensions(aspect_ratio.as_deref(), IMAGE_LONG_SIDE);
+    let data: Vec<serde_json::Value> = (0..n.max(1))
+        .map(|i| {
+            let seed = hash_hex(&["openai", kind, prompt, &i.to_string()]);
+            Ok(serde_json::json!({ "b64_json": placeholder_base64(&seed, width, height)? }))
+        })
+        .collect::<std::io::Result<_>>()?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "data": data })))
+}
+
+async fn openai_generations(body: web::Json<OpenAiGenerationRequest>) -> Result<HttpResponse> {
+    println!("🧪 模拟 OpenAI 生图: {} 张", body.n.unwrap_or(1));
+    Ok(openai_response("generations", &body.prompt, body.n.unwrap_or(1), body.size.as_deref())?)
+}
+
+async fn openai_edits(mut payload: Multipart) -> Result<HttpResponse> {
+    let mut fields: HashMap<String, String> = HashMap::new();
+    let mut references = 0;
+    while let Some(mut field) = payload.try_next().await? {
+        let name = field.content_disposition().get_name().unwrap_or_default().to_string();
+        let mut data = Vec::new();
+        while let Some(chunk) = field.try_next().await? {
+            data.extend_from_slice(&chunk);
+        }
+        if name.starts_with("image") {
+            references += 1;
+        } else {
+            fields.insert(name, String::from_utf8_lossy(&data).to_string());
+        }
+    }
+
+    let prompt = fields.get("prompt").cloned().unwrap_or_default();
+    let n = fields.get("n").and_then(|n| n.parse().ok()).unwrap_or(1);
+    println!("🧪 模拟 OpenAI 参考图生图: {} 张, 参考图 {} 张", n, references);
+    Ok(openai_response("edits", &prompt, n, fields.get("size").map(String::as_str))?)
+}
+
+// ========================================
+// SD WebUI txt2img / img2img
+// ========================================
+
+#[derive(Debug, Deserialize)]
+struct SdWebUiRequest {
+    prompt: String,
+    width: Option<u32>,
+    height: Option<u32>,
+    batch_size: Option<usize>,
+}
+
+async fn sd_webui_generate(path: web::Path<String>, body: web::Json<SdWebUiRequest>) -> Result<HttpResponse> {
+    let mode = path.into_inner();
+    let count = body.batch_size.unwrap_or(1).max(1);
+    println!("🧪 模拟 SD WebUI {}: {} 张", mode, count);
+
+    let aspect_ratio = format!("{}:{}", body.width.unwrap_or(1), body.height.unwrap_or(1));
+    let (width, height) = dimensions(Some(&aspect_ratio), IMAGE_LONG_SIDE);
+    let images: Vec<String> = (0..count)
+        .map(|i| placeholder_base64(&hash_hex(&["sd_webui", &mode, &body.prompt, &i.to_string()]), width, height))
+        .collect::<std::io::Result<_>>()?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "images": images })))
+}
+
+// ========================================
+// VEO 视频任务
+// ========================================
+
+#[derive(Debug, Deserialize)]
+struct VeoCreateRequest {
+    prompt: String,
+    #[serde(default)]
+    images: Vec<String>,
+    duration: Option<u32>,
+    aspect_ratio: Option<String>,
+}
+
+struct VideoTask {
+    seed: String,
+    duration: u32,
+    aspect_ratio: Option<String>,
+    fail: bool,
+    polls: usize,
+}
+
+fn video_tasks() -> std::io::Result<MutexGuard<'static, HashMap<String, VideoTask>>> {
+    static TASKS: OnceLock<Mutex<HashMap<String, VideoTask>>> = OnceLock::new();
+    TASKS
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))
+}
+
+async fn veo_create(body: web::Json<VeoCreateRequest>) -> Result<HttpResponse> {
+    static NEXT_TASK: AtomicU64 = AtomicU64::new(1);
+
+    let duration = body.duration.unwrap_or(8).clamp(1, 8);
+    let seed = hash_hex(&[
+        "veo",
+        &body.prompt,
+        &body.images.join(","),
+        &duration.to_string(),
+        body.aspect_ratio.as_deref().unwrap_or(""),
+    ]);
+    let video_id = format!("mock-{}-{}", &seed[..12], NEXT_TASK.fetch_add(1, Ordering::SeqCst));
+
+    println!("🧪 模拟 VEO 创建视频任务: {} ({}秒)", video_id, duration);
+
+    video_tasks()?.insert(
+        video_id.clone(),
+        VideoTask {
+            seed,
+            duration,
+            aspect_ratio: body.aspect_ratio.clone(),
+            fail: body.prompt.contains("[mock:fail]"),
+            polls: 0,
+        },
+    );
+
+    let (status, progress) = VIDEO_PROGRESS_STEPS[0];
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "id": video_id,
+        "status": status,
+        "progress": progress,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+struct VeoQuery {
+    id: String,
+}
+
+// 每次查询推进一步进度，最后一步生成占位视频并返回下载地址
+async fn veo_query(req: HttpRequest, query: web::Query<VeoQuery>) -> Result<HttpResponse> {
+    let (step, seed, duration, aspect_ratio, fail) = {
+        let mut tasks = video_tasks()?;
+        let Some(task) = tasks.get_mut(&query.id) else {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": format!("video task not found: {}", query.id)
+            })));
+        };
+        task.polls += 1;
+        let step = task.polls.min(VIDEO_PROGRESS_STEPS.len() - 1);
+        (step, task.seed.clone(), task.duration, task.aspect_ratio.clone(), task.fail)
+    };
+
+    if fail && step > VIDEO_FAIL_AFTER_STEP {
+        let (_, progress) = VIDEO_PROGRESS_STEPS[VIDEO_FAIL_AFTER_STEP];
+        println!("🧪 模拟 VEO 任务失败: {}", query.id);
+        return Ok(HttpResponse::Ok().json(serde_json::json!({
+            "id": query.id,
+            "status": "failed",
+            "progress": progress,
+            "error": "模拟视频生成失败"
+        })));
+    }
+
+    let (status, progress) = VIDEO_PROGRESS_STEPS[step];
+    println!("🧪 模拟 VEO 查询: {} {} ({}%)", query.id, status, progress);
+
+    if status != "completed" {
+        return Ok(HttpResponse::Ok().json(serde_json::json!({
+            "id": query.id,
+            "status": status,
+            "progress": progress,
+        })));
+    }
+
+    let filename = format!("{}.mp4", &seed[..32]);
+    if let Err(e) = render_placeholder_video(&seed, &filename, duration, aspect_ratio.as_deref()).await {
+        eprintln!("❌ 模拟视频生成失败: {}", e);
+        return Ok(HttpResponse::Ok().json(serde_json::json!({
+            "id": query.id,
+            "status": "failed",
+            "progress": progress,
+            "error": e.to_string()
+        })));
+    }
+
+    // 下载地址跟随请求的主机，测试中在随机端口启动时也能访问
+    let conn = req.connection_info();
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "id": query.id,
+        "status": status,
+        "progress": progress,
+        "video_url": format!("{}://{}/mock/veo/videos/{}", conn.scheme(), conn.host(), filename),
+    })))
+}
+
+// 用 ffmpeg 生成纯色占位视频，相同种子复用已生成的文件
+async fn render_placeholder_video(
+    seed: &str,
+    filename: &str,
+    duration: u32,
+    aspect_ratio: Option<&str>,
+) -> std::io::Result<()> {
+    let output_path = format!("{}/{}", VIDEO_DIR, filename);
+    if std::path::Path::new(&output_path).exists() {
+        return Ok(());
+    }
+    std::fs::create_dir_all(VIDEO_DIR)?;
+
+    let (width, height) = dimensions(aspect_ratio, VIDEO_LONG_SIDE);
+    let color = &seed[..6];
+    let output = tokio::process::Command::new("ffmpeg")
+        .args([
+            "-y",
+            "-f", "lavfi",
+            "-i", &format!("color=c=0x{}:s={}x{}:d={}:r={}", color, width, height, duration, VIDEO_FPS),
+            "-pix_fmt", "yuv420p",
+            "-movflags", "+faststart",
+            &output_path,
+        ])
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            format!("ffmpeg failed: {}", String::from_utf8_lossy(&output.stderr)),
+        ));
+    }
+    Ok(())
+}
+
+async fn veo_video_file(path: web::Path<String>) -> Result<actix_files::NamedFile> {
+    let filename = path.into_inner();
+    if !filename.ends_with(".mp4") || !filename.trim_end_matches(".mp4").chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid filename").into());
+    }
+    Ok(actix_files::NamedFile::open(format!("{}/{}", VIDEO_DIR, filename))?)
+}
+
+// ========================================
+// GPT-nano responses
+// ========================================
+
+#[derive(Debug, Deserialize)]
+struct GptNanoRequest {
+    input: String,
+}
+
+// 按请求头中的调用场景返回对应格式的文本
+async fn gpt_nano_responses(req: HttpRequest, body: web::Json<GptNanoRequest>) -> Result<HttpResponse> {
+    let input = body.input.as_str();
+    let task = req.headers().get(GPT_NANO_TASK_HEADER).and_then(|v| v.to_str().ok()).unwrap_or("");
+    let text = match task {
+        GPT_NANO_TASK_TRANSLATE => {
+            println!("🧪 模拟 GPT-nano 翻译");
+            let source = input.split_once("\n\n").map(|(_, t)| t).unwrap_or(input);
+            format!("[mock translation] {}", source)
+        }
+        GPT_NANO_TASK_CHARACTER_ANALYSIS => {
+            println!("🧪 模拟 GPT-nano 角色分析");
+            mock_character_analysis(input)
+        }
+        GPT_NANO_TASK_STORYBOARD => {
+            println!("🧪 模拟 GPT-nano 剧本拆解");
+            mock_storyboard(input)
+        }
+        _ => {
+            println!("🧪 模拟 GPT-nano 文本");
+            format!("[mock response {}]", &hash_hex(&[input])[..8])
+        }
+    };
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "output": [{
+            "type": "message",
+            "content": [{ "type": "output_text", "text": text }]
+        }]
+    })))
+}
+
+// 提取提示词中 "标题：" 到下一个空行之间的内容
+fn prompt_section<'a>(input: &'a str, heading: &str) -> &'a str {
+    input
+        .split_once(heading)
+        .map(|(_, rest)| rest.trim_start_matches('\n'))
+        .map(|rest| rest.split("\n\n").next().unwrap_or(rest))
+        .unwrap_or("")
+        .trim()
+}
+
+fn mock_character_analysis(input: &str) -> String {
+    let description = prompt_section(input, "角色描述：");
+    let hash = hash_hex(&[description]);
+    serde_json::json!({
+        "name": format!("角色{}", &hash[..4]),
+        "category": "主要角色",
+        "tags": ["模拟", "测试", "角色"],
+    })
+    .to_string()
+}
+
+// 每段（或每句）剧本拆成一个分镜，出场角色为该段中提到的角色
+fn mock_storyboard(input: &str) -> String {
+    let character_names: Vec<&str> = prompt_section(input, "角色列表：")
+        .lines()
+        .filter_map(|line| line.strip_prefix("- "))
+        .filter_map(|line| line.split(':').next())
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .collect();
+
+    let script = input
+        .split_once("剧本：")
+        .map(|(_, rest)| rest)
+        .and_then(|rest| rest.split("请以JSON格式返回结果").next())
+        .unwrap_or("")
+        .trim();
+
+    let mut segments: Vec<&str> = script.lines().map(str::trim).filter(|l| !l.is_empty()).collect();
+    if segments.len() <= 1 {
+        segments = script
+            .split_inclusive(['。', '！', '？', '.', '!', '?'])
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .collect();
+    }
+    if segments.is_empty() {
+        segments.push(script);
+    }
+
+    let scenes: Vec<serde_json::Value> = segments
+        .iter()
+        .take(12)
+        .map(|segment| {
+            let characters: Vec<&str> = character_names.iter().copied().filter(|name| segment.contains(name)).collect();
+            serde_json::json!({
+                "duration": 5,
+                "first_frame_prompt": format!("画面：{}", segment),
+                "video_prompt": format!("镜头缓慢推进，{}", segment),
+                "characters": characters,
+            })
+        })
+        .collect();
+
+    serde_json::json!({ "scenes": scenes }).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_and_read_body_json, call_service, init_service, read_body_json, TestRequest};
+    use actix_web::App;
+
+    fn decode_image(data: &serde_json::Value) -> image::DynamicImage {
+        let bytes = base64::engine::general_purpose::STANDARD.decode(data.as_str().expect("base64 image")).unwrap();
+        image::load_from_memory(&bytes).unwrap()
+    }
+
+    #[test]
+    fn dimensions_follow_aspect_ratio() {
+        assert_eq!(dimensions(Some("16:9"), 512), (512, 288));
+        assert_eq!(dimensions(Some("9:16"), 512), (288, 512));
+        assert_eq!(dimensions(Some(" 4 : 3 "), 640), (640, 480));
+        assert_eq!(dimensions(Some("1:1"), 512), (512, 512));
+        assert_eq!(dimensions(None, 512), (512, 512));
+        assert_eq!(dimensions(Some("wide"), 512), (512, 512));
+        assert_eq!(dimensions(Some("0:9"), 512), (512, 512));
+    }
+
+    #[test]
+    fn mock_storyboard_splits_script_and_matches_characters() {
+        let input = "你是一个专业的分镜师。\n\n角色列表：\n- 小明: 男孩\n- 小红: 女孩\n\n剧本：\n小明走进教室。\n小红和小明打招呼。\n\n请以JSON格式返回结果：{\"scenes\": [...]}";
+        let result: serde_json::Value = serde_json::from_str(&mock_storyboard(input)).unwrap();
+        let scenes = result["scenes"].as_array().expect("scenes");
+        assert_eq!(scenes.len(), 2);
+        assert_eq!(scenes[0]["first_frame_prompt"], "画面：小明走进教室。");
+        assert_eq!(scenes[0]["characters"], serde_json::json!(["小明"]));
+        assert_eq!(scenes[1]["characters"], serde_json::json!(["小明", "小红"]));
+        assert_eq!(scenes[1]["duration"], 5);
+    }
+
+    #[test]
+    fn mock_storyboard_splits_single_line_by_sentence() {
+        let input = "角色列表：\n（项目暂无角色）\n\n剧本：\n天亮了。鸟儿在唱歌！\n\n请以JSON格式返回结果";
+        let result: serde_json::Value = serde_json::from_str(&mock_storyboard(input)).unwrap();
+        let scenes = result["scenes"].as_array().expect("scenes");
+        assert_eq!(scenes.len(), 2);
+        assert_eq!(scenes[1]["video_prompt"], "镜头缓慢推进，鸟儿在唱歌！");
+        assert_eq!(scenes[1]["characters"], serde_json::json!([]));
+    }
+
+    #[actix_web::test]
+    async fn gemini_returns_requested_candidates() {
+        let app = init_service(App::new().configure(configure)).await;
+        let body = serde_json::json!({
+            "contents": [{ "parts": [{ "text": "一只猫" }] }],
+            "generationConfig": { "candidateCount": 2, "imageConfig": { "aspectRatio": "16:9" } }
+        });
+        let req = TestRequest::post()
+            .uri("/mock/gemini/models/mock-image/generateContent")
+            .set_json(&body)
+            .to_request();
+        let response: serde_json::Value = call_and_read_body_json(&app, req).await;
+        let candidates = response["candidates"].as_array().expect("candidates");
+        assert_eq!(candidates.len(), 2);
+        let image = decode_image(&candidates[0]["content"]["parts"][0]["inlineData"]["data"]);
+        assert_eq!((image.width(), image.height()), (512, 288));
+        // 不同候选使用不同种子
+        assert_ne!(
+            candidates[0]["content"]["parts"][0]["inlineData"]["data"],
+            candidates[1]["content"]["parts"][0]["inlineData"]["data"]
+        );
+
+        // 相同请求得到相同结果
+        let req = TestRequest::post()
+            .uri("/mock/gemini/models/mock-image/generateContent")
+            .set_json(&body)
+            .to_request();
+        let again: serde_json::Value = call_and_read_body_json(&app, req).await;
+        assert_eq!(again, response);
+    }
+
+    #[actix_web::test]
+    async fn gemini_blocks_safety_marker() {
+        let app = init_service(App::new().configure(configure)).await;
+        let req = TestRequest::post()
+            .uri("/mock/gemini/models/mock-image/generateContent")
+            .set_json(serde_json::json!({ "contents": [{ "parts": [{ "text": "一只猫 [mock:safety]" }] }] }))
+            .to_request();
+        let response: serde_json::Value = call_and_read_body_json(&app, req).await;
+        assert_eq!(response["candidates"], serde_json::json!([]));
+        assert_eq!(response["promptFeedback"]["blockReason"], "SAFETY");
+    }
+
+    #[actix_web::test]
+    async fn openai_and_sd_webui_return_images() {
+        let app = init_service(App::new().configure(configure)).await;
+
+        let req = TestRequest::post()
+            .uri("/mock/openai/images/generations")
+            .set_json(serde_json::json!({ "prompt": "一只猫", "n": 3, "size": "1024x1536" }))
+            .to_request();
+        let response: serde_json::Value = call_and_read_body_json(&app, req).await;
+        let data = response["data"].as_array().expect("data");
+        assert_eq!(data.len(), 3);
+        let image = decode_image(&data[0]["b64_json"]);
+        assert_eq!((image.width(), image.height()), (336, 512));
+
+        let req = TestRequest::post()
+            .uri("/mock/openai/images/generations")
+            .set_json(serde_json::json!({ "prompt": "一只猫 [mock:safety]" }))
+            .to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), actix_web::http::StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = read_body_json(response).await;
+        assert_eq!(body["error"]["code"], "moderation_blocked");
+
+        let req = TestRequest::post()
+            .uri("/mock/sd/sdapi/v1/txt2img")
+            .set_json(serde_json::json!({ "prompt": "一只猫", "width": 768, "height": 512, "batch_size": 2 }))
+            .to_request();
+        let response: serde_json::Value = call_and_read_body_json(&app, req).await;
+        let images = response["images"].as_array().expect("images");
+        assert_eq!(images.len(), 2);
+        let image = decode_image(&images[0]);
+        assert_eq!((image.width(), image.height()), (512, 336));
+    }
+
+    #[actix_web::test]
+    async fn veo_failed_task_reports_failure() {
+        let app = init_service(App::new().configure(configure)).await;
+        let req = TestRequest::post()
+            .uri("/mock/veo/create")
+            .set_json(serde_json::json!({ "prompt": "镜头推进 [mock:fail]", "duration": 2 }))
+            .to_request();
+        let created: serde_json::Value = call_and_read_body_json(&app, req).await;
+        assert_eq!(created["status"], "queued");
+        let video_id = created["id"].as_str().expect("video id").to_string();
+
+        let mut statuses = Vec::new();
+        for _ in 0..VIDEO_FAIL_AFTER_STEP + 1 {
+            let req = TestRequest::get().uri(&format!("/mock/veo/query?id={}", video_id)).to_request();
+            let response: serde_json::Value = call_and_read_body_json(&app, req).await;
+            statuses.push(response["status"].as_str().unwrap_or_default().to_string());
+            if response["status"] == "failed" {
+                assert_eq!(response["error"], "模拟视频生成失败");
+                assert!(response.get("video_url").is_none());
+            }
+        }
+        assert_eq!(statuses, ["processing", "processing", "failed"]);
+
+        let req = TestRequest::get().uri("/mock/veo/query?id=missing").to_request();
+        let response = call_service(&app, req).await;
+        assert_eq!(response.status(), actix_web::http::StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn gpt_nano_dispatches_on_task_header() {
+        let app = init_service(App::new().configure(configure)).await;
+        let call = |task: Option<&'static str>, input: &'static str| {
+            let mut req = TestRequest::post().uri("/mock/gpt-nano/responses");
+            if let Some(task) = task {
+                req = req.insert_header((GPT_NANO_TASK_HEADER, task));
+            }
+            req.set_json(serde_json::json!({ "input": input })).to_request()
+        };
+        let text = |response: &serde_json::Value| {
+            assert_eq!(response["output"][0]["type"], "message");
+            assert_eq!(response["output"][0]["content"][0]["type"], "output_text");
+            response["output"][0]["content"][0]["text"].as_str().expect("text").to_string()
+        };
+
+        let response = call_and_read_body_json(&app, call(Some(GPT_NANO_TASK_TRANSLATE), "Translate:\n\n一只猫")).await;
+        assert_eq!(text(&response), "[mock translation] 一只猫");
+
+        let response = call_and_read_body_json(
+            &app,
+            call(Some(GPT_NANO_TASK_CHARACTER_ANALYSIS), "角色描述：\n红发少女\n\n请只返回JSON"),
+        )
+        .await;
+        let analysis: serde_json::Value = serde_json::from_str(&text(&response)).unwrap();
+        assert!(analysis["name"].as_str().unwrap().starts_with("角色"));
+        assert_eq!(analysis["category"], "主要角色");
+
+        let response = call_and_read_body_json(&app, call(Some(GPT_NANO_TASK_STORYBOARD), "剧本：\n天亮了。")).await;
+        let storyboard: serde_json::Value = serde_json::from_str(&text(&response)).unwrap();
+        assert_eq!(storyboard["scenes"].as_array().map(Vec::len), Some(1));
+
+        // 没有场景标记时不根据提示词内容猜测
+        let response = call_and_read_body_json(&app, call(None, "Translate the following: 一只猫")).await;
+        assert!(text(&response).starts_with("[mock response "));
+    }
+}
@@ -1,8 +1,11 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use std::collections::HashMap;
 use uuid::Uuid;
 
+use crate::postprocess::PostProcessConfig;
+
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct Job {
     pub id: Uuid,
@@ -218,6 +221,20 @@ pub struct Project {
     pub global_video_prompt: Option<String>,
     pub combined_characters_image: Option<String>,
     pub project_type: Option<String>,
+    pub description: Option<String>,
+    pub aspect_ratio: Option<String>,
+    pub resolution: Option<String>,
+    pub fps: Option<i32>,
+    pub target_runtime: Option<f64>,
+    pub prompt_variables: sqlx::types::Json<HashMap<String, String>>,
+    pub prompt_translation: String,
+    pub image_provider: Option<String>,
+    pub image_model: Option<String>,
+    pub continuity_mode: String,
+    pub image_postprocess: Option<sqlx::types::Json<PostProcessConfig>>,
+    pub forked_from: Option<Uuid>,
+    pub archived_at: Option<DateTime<Utc>>,
+    pub deleted_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -234,17 +251,37 @@ pub struct StoryboardScene {
     pub video_prompt: Option<String>,
     pub latest_image_url: Option<String>,
     pub latest_video_url: Option<String>,
+    pub status: String,
+    pub sequence: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+/// 分镜审核状态
+pub const SCENE_STATUSES: &[&str] = &["draft", "in_review", "approved", "locked"];
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct SceneReviewNote {
+    pub id: i32,
+    pub scene_id: i32,
+    pub author: String,
+    pub note: String,
+    pub status: Option<String>,  // 随备注一起变更的状态（可选）
+    pub created_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct GenerationHistory {
     pub id: i32,
     pub scene_id: i32,
     pub generation_type: String,
     pub prompt: String,
+    pub translated_prompt: Option<String>,
     pub result_url: String,
+    pub candidate_batch_id: Option<Uuid>,  // 同一次请求生成的多个候选图共享的批次ID
+    pub source_history_id: Option<i32>,    // 局部重绘的原图版本
+    pub mask_url: Option<String>,          // 局部重绘使用的蒙版
+    pub original_url: Option<String>,      // 后处理前的原图
     pub created_at: DateTime<Utc>,
 }
 
@@ -257,12 +294,42 @@ pub struct CompositeVideo {
     pub created_at: DateTime<Utc>,
 }
 
+/// 项目列表的可见范围
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProjectVisibility {
+    Active,    // 未归档、未删除（默认）
+    Archived,  // 已归档（不含回收站）
+    Trashed,   // 回收站
+    All,
+}
+
+impl ProjectVisibility {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "active" => Some(Self::Active),
+            "archived" => Some(Self::Archived),
+            "trash" | "trashed" => Some(Self::Trashed),
+            "all" => Some(Self::All),
+            _ => None,
+        }
+    }
+
+    fn condition(&self) -> &'static str {
+        match self {
+            Self::Active => "archived_at IS NULL AND deleted_at IS NULL",
+            Self::Archived => "archived_at IS NOT NULL AND deleted_at IS NULL",
+            Self::Trashed => "deleted_at IS NOT NULL",
+            Self::All => "TRUE",
+        }
+    }
+}
+
 // ========================================
 // Project 数据库操作
 // ========================================
 impl Project {
-    pub async fn create(
-        pool: &sqlx::PgPool,
+    pub async fn create<'e>(
+        executor: impl sqlx::PgExecutor<'e>,
         title: String,
         script: Option<String>,
         project_type: Option<String>,
@@ -277,10 +344,205 @@ impl Project {
         .bind(title)
         .bind(script)
         .bind(project_type.unwrap_or_else(|| "video".to_string()))
+        .fetch_one(executor)
+        .await
+    }
+
+    /// 复制项目（标题、剧本、全局提示词、类型、简介、输出格式、目标时长、提示词变量、图片生成服务、连贯模式、后处理配置），并记录来源项目
+    pub async fn create_fork(
+        pool: &sqlx::PgPool,
+        source: &Project,
+        title: String,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, Project>(
+            r#"
+            INSERT INTO projects (title, script, global_image_prompt, global_video_prompt, project_type, description, aspect_ratio, resolution, fps, target_runtime, prompt_variables, prompt_translation, image_provider, image_model, continuity_mode, image_postprocess, forked_from)
+            VALUES ($1, $2, $3, $4, $5, $6, COALESCE($7, '9:16'), COALESCE($8, '1080p'), COALESCE($9, 24), $10, $11, $12, $13, $14, $15, $16, $17)
+            RETURNING *
+            "#,
+        )
+        .bind(title)
+        .bind(&source.script)
+        .bind(&source.global_image_prompt)
+        .bind(&source.global_video_prompt)
+        .bind(&source.project_type)
+        .bind(&source.description)
+        .bind(&source.aspect_ratio)
+        .bind(&source.resolution)
+        .bind(source.fps)
+        .bind(source.target_runtime)
+        .bind(&source.prompt_variables)
+        .bind(&source.prompt_translation)
+        .bind(&source.image_provider)
+        .bind(&source.image_model)
+        .bind(&source.continuity_mode)
+        .bind(&source.image_postprocess)
+        .bind(source.id)
         .fetch_one(pool)
         .await
     }
 
+    /// 更新项目基本信息，None 表示保持不变；简介和封面传入 Some(None) 表示清空
+    pub async fn update_metadata<'e>(
+        executor: impl sqlx::PgExecutor<'e>,
+        id: Uuid,
+        title: Option<String>,
+        project_type: Option<String>,
+        description: Option<Option<String>>,
+        cover_image_url: Option<Option<String>>,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Project>(
+            r#"
+            UPDATE projects
+            SET title = COALESCE($2, title),
+                project_type = COALESCE($3, project_type),
+                description = CASE WHEN $4 THEN $5 ELSE description END,
+                cover_image_url = CASE WHEN $6 THEN $7 ELSE cover_image_url END,
+                updated_at = NOW()
+            WHERE id = $1
+            RETURNING *
+            "#,
+        )
+        .bind(id)
+        .bind(title)
+        .bind(project_type)
+        .bind(description.is_some())
+        .bind(description.flatten())
+        .bind(cover_image_url.is_some())
+        .bind(cover_image_url.flatten())
+        .fetch_optional(executor)
+        .await
+    }
+
+    /// 更新项目输出格式（画面比例、分辨率、帧率），None 表示保持不变
+    pub async fn update_output_format<'e>(
+        executor: impl sqlx::PgExecutor<'e>,
+        id: Uuid,
+        aspect_ratio: Option<String>,
+        resolution: Option<String>,
+        fps: Option<i32>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            UPDATE projects
+            SET aspect_ratio = COALESCE($2, aspect_ratio),
+                resolution = COALESCE($3, resolution),
+                fps = COALESCE($4, fps),
+                updated_at = NOW()
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .bind(aspect_ratio)
+        .bind(resolution)
+        .bind(fps)
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /// 设置目标时长（秒），None 表示清除
+    pub async fn update_target_runtime<'e>(
+        executor: impl sqlx::PgExecutor<'e>,
+        id: Uuid,
+        target_runtime: Option<f64>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE projects SET target_runtime = $1, updated_at = NOW() WHERE id = $2")
+            .bind(target_runtime)
+            .bind(id)
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    /// 更新提示词翻译模式（original / translated / both）
+    pub async fn update_prompt_translation<'e>(
+        executor: impl sqlx::PgExecutor<'e>,
+        id: Uuid,
+        mode: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE projects SET prompt_translation = $1, updated_at = NOW() WHERE id = $2")
+            .bind(mode)
+            .bind(id)
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    /// 更新首帧图连贯模式（off / sequence / always）
+    pub async fn update_continuity_mode<'e>(
+        executor: impl sqlx::PgExecutor<'e>,
+        id: Uuid,
+        mode: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE projects SET continuity_mode = $1, updated_at = NOW() WHERE id = $2")
+            .bind(mode)
+            .bind(id)
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    /// 更新首帧图后处理配置，None 表示恢复服务器默认
+    pub async fn update_image_postprocess<'e>(
+        executor: impl sqlx::PgExecutor<'e>,
+        id: Uuid,
+        config: Option<PostProcessConfig>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE projects SET image_postprocess = $1, updated_at = NOW() WHERE id = $2")
+            .bind(config.map(sqlx::types::Json))
+            .bind(id)
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    /// 更新图片生成服务和模型，None 表示保持不变，Some(None) 表示恢复默认
+    /// 更换服务但未指定模型时，模型恢复为新服务的默认模型
+    pub async fn update_image_provider<'e>(
+        executor: impl sqlx::PgExecutor<'e>,
+        id: Uuid,
+        provider: Option<Option<String>>,
+        model: Option<Option<String>>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            UPDATE projects
+            SET image_provider = CASE WHEN $2 THEN $3 ELSE image_provider END,
+                image_model = CASE
+                    WHEN $4 THEN $5
+                    WHEN $2 AND image_provider IS DISTINCT FROM $3 THEN NULL
+                    ELSE image_model
+                END,
+                updated_at = NOW()
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .bind(provider.is_some())
+        .bind(provider.flatten())
+        .bind(model.is_some())
+        .bind(model.flatten())
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /// 替换项目的提示词变量
+    pub async fn update_prompt_variables<'e>(
+        executor: impl sqlx::PgExecutor<'e>,
+        id: Uuid,
+        variables: HashMap<String, String>,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Project>(
+            "UPDATE projects SET prompt_variables = $1, updated_at = NOW() WHERE id = $2 RETURNING *",
+        )
+        .bind(sqlx::types::Json(variables))
+        .bind(id)
+        .fetch_optional(executor)
+        .await
+    }
+
     pub async fn find_by_id(pool: &sqlx::PgPool, id: Uuid) -> Result<Option<Self>, sqlx::Error> {
         sqlx::query_as::<_, Project>("SELECT * FROM projects WHERE id = $1")
             .bind(id)
@@ -290,12 +552,14 @@ impl Project {
 
     pub async fn list_all(
         pool: &sqlx::PgPool,
+        visibility: ProjectVisibility,
         limit: i64,
         offset: i64,
     ) -> Result<Vec<Self>, sqlx::Error> {
-        sqlx::query_as::<_, Project>(
-            "SELECT * FROM projects ORDER BY created_at DESC LIMIT $1 OFFSET $2",
-        )
+        sqlx::query_as::<_, Project>(&format!(
+            "SELECT * FROM projects WHERE {} ORDER BY created_at DESC LIMIT $1 OFFSET $2",
+            visibility.condition()
+        ))
         .bind(limit)
         .bind(offset)
         .fetch_all(pool)
@@ -305,12 +569,14 @@ impl Project {
     pub async fn list_by_type(
         pool: &sqlx::PgPool,
         project_type: &str,
+        visibility: ProjectVisibility,
         limit: i64,
         offset: i64,
     ) -> Result<Vec<Self>, sqlx::Error> {
-        sqlx::query_as::<_, Project>(
-            "SELECT * FROM projects WHERE project_type = $1 ORDER BY created_at DESC LIMIT $2 OFFSET $3",
-        )
+        sqlx::query_as::<_, Project>(&format!(
+            "SELECT * FROM projects WHERE project_type = $1 AND {} ORDER BY created_at DESC LIMIT $2 OFFSET $3",
+            visibility.condition()
+        ))
         .bind(project_type)
         .bind(limit)
         .bind(offset)
@@ -318,6 +584,53 @@ impl Project {
         .await
     }
 
+    /// 归档或取消归档项目
+    pub async fn set_archived(pool: &sqlx::PgPool, id: Uuid, archived: bool) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Project>(
+            r#"
+            UPDATE projects
+            SET archived_at = CASE WHEN $2 THEN COALESCE(archived_at, NOW()) ELSE NULL END,
+                updated_at = NOW()
+            WHERE id = $1
+            RETURNING *
+            "#,
+        )
+        .bind(id)
+        .bind(archived)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// 移入回收站（软删除）
+    pub async fn move_to_trash(pool: &sqlx::PgPool, id: Uuid) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Project>(
+            "UPDATE projects SET deleted_at = COALESCE(deleted_at, NOW()), updated_at = NOW() WHERE id = $1 RETURNING *",
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// 从回收站恢复
+    pub async fn restore(pool: &sqlx::PgPool, id: Uuid) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Project>(
+            "UPDATE projects SET deleted_at = NULL, updated_at = NOW() WHERE id = $1 RETURNING *",
+        )
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// 查询在回收站中超过指定天数的项目
+    pub async fn find_expired_trash(pool: &sqlx::PgPool, retention_days: i64) -> Result<Vec<Uuid>, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT id FROM projects WHERE deleted_at IS NOT NULL AND deleted_at < NOW() - make_interval(days => $1::INTEGER)",
+        )
+        .bind(retention_days)
+        .fetch_all(pool)
+        .await
+    }
+
     pub async fn delete(pool: &sqlx::PgPool, id: Uuid) -> Result<(), sqlx::Error> {
         let mut tx = pool.begin().await?;
 
@@ -361,6 +674,12 @@ impl Project {
             .execute(&mut *tx)
             .await?;
 
+        // 删除项目的分镜草稿
+        sqlx::query("DELETE FROM storyboard_drafts WHERE project_id = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+
         // 删除项目本身
         sqlx::query("DELETE FROM projects WHERE id = $1")
             .bind(id)
@@ -386,13 +705,18 @@ impl Project {
 // StoryboardScene 数据库操作
 // ========================================
 impl StoryboardScene {
+    /// 锁定的分镜不允许重新生成、修改提示词或上传文件
+    pub fn is_locked(&self) -> bool {
+        self.status == "locked"
+    }
 
-
-    pub async fn batch_create(
-        pool: &sqlx::PgPool,
+    /// 批量创建分镜，可传入连接池或事务
+    pub async fn batch_create<'a>(
+        conn: impl sqlx::Acquire<'a, Database = sqlx::Postgres>,
         project_id: Uuid,
         scenes: Vec<(i32, Option<f64>, Option<String>, Option<String>)>,
     ) -> Result<u64, sqlx::Error> {
+        let mut conn = conn.acquire().await?;
         let mut count = 0u64;
 
         for (scene_index, duration, first_frame_prompt, video_prompt) in scenes {
@@ -409,7 +733,7 @@ impl StoryboardScene {
             .bind(duration)
             .bind(&first_frame_prompt)
             .bind(&video_prompt)
-            .execute(pool)
+            .execute(&mut *conn)
             .await?;
 
             count += 1;
@@ -418,15 +742,42 @@ impl StoryboardScene {
         Ok(count)
     }
 
-    pub async fn find_by_project_id(
+    /// 将分镜（序号、时间轴、时长、提示词、场次）复制到另一个项目，不包含生成结果
+    pub async fn copy_to_project(
         pool: &sqlx::PgPool,
+        source: &StoryboardScene,
+        project_id: Uuid,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, StoryboardScene>(
+            r#"
+            INSERT INTO storyboard_scenes (
+                project_id, scene_index, start_time, end_time, duration, first_frame_prompt, video_prompt, sequence
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING *
+            "#,
+        )
+        .bind(project_id)
+        .bind(source.scene_index)
+        .bind(&source.start_time)
+        .bind(&source.end_time)
+        .bind(source.duration)
+        .bind(&source.first_frame_prompt)
+        .bind(&source.video_prompt)
+        .bind(&source.sequence)
+        .fetch_one(pool)
+        .await
+    }
+
+    pub async fn find_by_project_id<'e>(
+        executor: impl sqlx::PgExecutor<'e>,
         project_id: Uuid,
     ) -> Result<Vec<Self>, sqlx::Error> {
         sqlx::query_as::<_, StoryboardScene>(
             "SELECT * FROM storyboard_scenes WHERE project_id = $1 ORDER BY scene_index",
         )
         .bind(project_id)
-        .fetch_all(pool)
+        .fetch_all(executor)
         .await
     }
 
@@ -437,8 +788,8 @@ impl StoryboardScene {
             .await
     }
 
-    pub async fn update_prompts(
-        pool: &sqlx::PgPool,
+    pub async fn update_prompts<'e>(
+        executor: impl sqlx::PgExecutor<'e>,
         id: i32,
         first_frame_prompt: Option<String>,
         video_prompt: Option<String>,
@@ -455,13 +806,108 @@ impl StoryboardScene {
         .bind(video_prompt)
         .bind(duration)
         .bind(id)
-        .execute(pool)
+        .execute(executor)
         .await?;
         Ok(())
     }
 
-    pub async fn update_latest_image(
+    /// 更新审核状态
+    pub async fn update_status(pool: &sqlx::PgPool, id: i32, status: &str) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, StoryboardScene>(
+            "UPDATE storyboard_scenes SET status = $1, updated_at = NOW() WHERE id = $2 RETURNING *"
+        )
+        .bind(status)
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// 设置所属场次，None 表示清空
+    pub async fn update_sequence(pool: &sqlx::PgPool, id: i32, sequence: Option<String>) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, StoryboardScene>(
+            "UPDATE storyboard_scenes SET sequence = $1, updated_at = NOW() WHERE id = $2 RETURNING *"
+        )
+        .bind(sequence)
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// 查找同一项目中序号在前的最近一个分镜
+    pub async fn find_previous(
         pool: &sqlx::PgPool,
+        project_id: Uuid,
+        scene_index: i32,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, StoryboardScene>(
+            r#"
+            SELECT * FROM storyboard_scenes
+            WHERE project_id = $1 AND scene_index < $2
+            ORDER BY scene_index DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(project_id)
+        .bind(scene_index)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// 写入计算出的时间轴位置
+    pub async fn update_timing<'e>(
+        executor: impl sqlx::PgExecutor<'e>,
+        id: i32,
+        start_time: String,
+        end_time: String,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE storyboard_scenes SET start_time = $1, end_time = $2 WHERE id = $3")
+            .bind(start_time)
+            .bind(end_time)
+            .bind(id)
+            .execute(executor)
+            .await?;
+        Ok(())
+    }
+
+    /// 按给定顺序重新编号项目分镜（scene_ids 必须包含项目的全部分镜）
+    pub async fn reorder(pool: &sqlx::PgPool, project_id: Uuid, scene_ids: &[i32]) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        // 先改为负数序号，避免与唯一约束 (project_id, scene_index) 冲突
+        sqlx::query("UPDATE storyboard_scenes SET scene_index = -scene_index WHERE project_id = $1")
+            .bind(project_id)
+            .execute(&mut *tx)
+            .await?;
+
+        for (i, scene_id) in scene_ids.iter().enumerate() {
+            sqlx::query(
+                "UPDATE storyboard_scenes SET scene_index = $1, updated_at = NOW() WHERE id = $2 AND project_id = $3"
+            )
+            .bind(i as i32 + 1)
+            .bind(scene_id)
+            .bind(project_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// 删除项目的全部分镜（生成历史随外键级联删除）
+    pub async fn delete_by_project_id<'e>(
+        executor: impl sqlx::PgExecutor<'e>,
+        project_id: Uuid,
+    ) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM storyboard_scenes WHERE project_id = $1")
+            .bind(project_id)
+            .execute(executor)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    pub async fn update_latest_image<'e>(
+        executor: impl sqlx::PgExecutor<'e>,
         id: i32,
         image_url: String,
     ) -> Result<(), sqlx::Error> {
@@ -474,13 +920,13 @@ impl StoryboardScene {
         )
         .bind(image_url)
         .bind(id)
-        .execute(pool)
+        .execute(executor)
         .await?;
         Ok(())
     }
 
-    pub async fn update_latest_video(
-        pool: &sqlx::PgPool,
+    pub async fn update_latest_video<'e>(
+        executor: impl sqlx::PgExecutor<'e>,
         id: i32,
         video_url: String,
     ) -> Result<(), sqlx::Error> {
@@ -493,69 +939,285 @@ impl StoryboardScene {
         )
         .bind(video_url)
         .bind(id)
-        .execute(pool)
+        .execute(executor)
         .await?;
         Ok(())
     }
 }
 
 // ========================================
-// GenerationHistory 数据库操作
+// SceneReviewNote 数据库操作
 // ========================================
-impl GenerationHistory {
+impl SceneReviewNote {
     pub async fn create(
         pool: &sqlx::PgPool,
         scene_id: i32,
-        generation_type: String,
-        prompt: String,
-        result_url: String,
+        author: String,
+        note: String,
+        status: Option<String>,
     ) -> Result<Self, sqlx::Error> {
-        sqlx::query_as::<_, GenerationHistory>(
+        sqlx::query_as::<_, SceneReviewNote>(
             r#"
-            INSERT INTO generation_history (scene_id, generation_type, prompt, result_url)
+            INSERT INTO scene_review_notes (scene_id, author, note, status)
             VALUES ($1, $2, $3, $4)
             RETURNING *
             "#,
         )
         .bind(scene_id)
-        .bind(generation_type)
-        .bind(prompt)
-        .bind(result_url)
+        .bind(author)
+        .bind(note)
+        .bind(status)
         .fetch_one(pool)
         .await
     }
 
-    pub async fn find_by_scene_id(
-        pool: &sqlx::PgPool,
-        scene_id: i32,
-    ) -> Result<Vec<Self>, sqlx::Error> {
-        sqlx::query_as::<_, GenerationHistory>(
-            "SELECT * FROM generation_history WHERE scene_id = $1 ORDER BY created_at DESC",
+    pub async fn find_by_scene_id(pool: &sqlx::PgPool, scene_id: i32) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, SceneReviewNote>(
+            "SELECT * FROM scene_review_notes WHERE scene_id = $1 ORDER BY created_at DESC",
         )
         .bind(scene_id)
         .fetch_all(pool)
         .await
     }
+}
 
-    pub async fn find_by_scene_and_type(
-        pool: &sqlx::PgPool,
-        scene_id: i32,
-        generation_type: &str,
-    ) -> Result<Vec<Self>, sqlx::Error> {
-        sqlx::query_as::<_, GenerationHistory>(
-            "SELECT * FROM generation_history WHERE scene_id = $1 AND generation_type = $2 ORDER BY created_at DESC",
-        )
-        .bind(scene_id)
-        .bind(generation_type)
-        .fetch_all(pool)
-        .await
-    }
+// ========================================
+// StoryboardDraft 数据库操作
+// ========================================
 
-    pub async fn find_by_project_id(
-        pool: &sqlx::PgPool,
-        project_id: Uuid,
-    ) -> Result<Vec<Self>, sqlx::Error> {
-        sqlx::query_as::<_, GenerationHistory>(
+/// 草稿中的单个分镜
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DraftScene {
+    pub scene_index: i32,
+    pub duration: Option<f64>,
+    pub first_frame_prompt: String,
+    pub video_prompt: Option<String>,
+    #[serde(default)]
+    pub characters: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct StoryboardDraft {
+    pub id: i32,
+    pub project_id: Uuid,
+    pub scenes: sqlx::types::Json<Vec<DraftScene>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl StoryboardDraft {
+    pub async fn create(
+        pool: &sqlx::PgPool,
+        project_id: Uuid,
+        scenes: Vec<DraftScene>,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, StoryboardDraft>(
+            "INSERT INTO storyboard_drafts (project_id, scenes) VALUES ($1, $2) RETURNING *",
+        )
+        .bind(project_id)
+        .bind(sqlx::types::Json(scenes))
+        .fetch_one(pool)
+        .await
+    }
+
+    pub async fn find_by_id(pool: &sqlx::PgPool, id: i32) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, StoryboardDraft>("SELECT * FROM storyboard_drafts WHERE id = $1")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+    }
+
+    pub async fn find_by_project_id(
+        pool: &sqlx::PgPool,
+        project_id: Uuid,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, StoryboardDraft>(
+            "SELECT * FROM storyboard_drafts WHERE project_id = $1 ORDER BY created_at DESC",
+        )
+        .bind(project_id)
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn delete(pool: &sqlx::PgPool, id: i32) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM storyboard_drafts WHERE id = $1")
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+}
+
+// ========================================
+// GenerationHistory 数据库操作
+// ========================================
+impl GenerationHistory {
+    pub async fn create<'e>(
+        executor: impl sqlx::PgExecutor<'e>,
+        scene_id: i32,
+        generation_type: String,
+        prompt: String,
+        result_url: String,
+    ) -> Result<Self, sqlx::Error> {
+        Self::create_with_translation(executor, scene_id, generation_type, prompt, None, result_url).await
+    }
+
+    /// 创建历史记录，同时保存发送给模型的翻译文本
+    pub async fn create_with_translation<'e>(
+        executor: impl sqlx::PgExecutor<'e>,
+        scene_id: i32,
+        generation_type: String,
+        prompt: String,
+        translated_prompt: Option<String>,
+        result_url: String,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, GenerationHistory>(
+            r#"
+            INSERT INTO generation_history (scene_id, generation_type, prompt, translated_prompt, result_url)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING *
+            "#,
+        )
+        .bind(scene_id)
+        .bind(generation_type)
+        .bind(prompt)
+        .bind(translated_prompt)
+        .bind(result_url)
+        .fetch_one(executor)
+        .await
+    }
+
+    /// 记录一张首帧候选图（单张生成时 candidate_batch_id 为空）
+    pub async fn create_candidate(
+        pool: &sqlx::PgPool,
+        scene_id: i32,
+        prompt: String,
+        translated_prompt: Option<String>,
+        result_url: String,
+        original_url: Option<String>,
+        candidate_batch_id: Option<Uuid>,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, GenerationHistory>(
+            r#"
+            INSERT INTO generation_history (scene_id, generation_type, prompt, translated_prompt, result_url, original_url, candidate_batch_id)
+            VALUES ($1, 'image', $2, $3, $4, $5, $6)
+            RETURNING *
+            "#,
+        )
+        .bind(scene_id)
+        .bind(prompt)
+        .bind(translated_prompt)
+        .bind(result_url)
+        .bind(original_url)
+        .bind(candidate_batch_id)
+        .fetch_one(pool)
+        .await
+    }
+
+    /// 记录后处理前的原图
+    pub async fn set_original_url(pool: &sqlx::PgPool, id: i32, original_url: String) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, GenerationHistory>(
+            "UPDATE generation_history SET original_url = $1 WHERE id = $2 RETURNING *",
+        )
+        .bind(original_url)
+        .bind(id)
+        .fetch_one(pool)
+        .await
+    }
+
+    pub async fn find_by_id(pool: &sqlx::PgPool, id: i32) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, GenerationHistory>("SELECT * FROM generation_history WHERE id = $1")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+    }
+
+    /// 记录一次基于已有图片的编辑（局部重绘 / 图生图），关联原图版本，局部重绘时附带蒙版
+    pub async fn create_edit(
+        pool: &sqlx::PgPool,
+        scene_id: i32,
+        prompt: String,
+        translated_prompt: Option<String>,
+        result_url: String,
+        source_history_id: Option<i32>,
+        mask_url: Option<String>,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, GenerationHistory>(
+            r#"
+            INSERT INTO generation_history (scene_id, generation_type, prompt, translated_prompt, result_url, source_history_id, mask_url)
+            VALUES ($1, 'image', $2, $3, $4, $5, $6)
+            RETURNING *
+            "#,
+        )
+        .bind(scene_id)
+        .bind(prompt)
+        .bind(translated_prompt)
+        .bind(result_url)
+        .bind(source_history_id)
+        .bind(mask_url)
+        .fetch_one(pool)
+        .await
+    }
+
+    /// 查找生成该图片的最近一条历史记录
+    pub async fn find_latest_by_result_url(
+        pool: &sqlx::PgPool,
+        scene_id: i32,
+        result_url: &str,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, GenerationHistory>(
+            "SELECT * FROM generation_history WHERE scene_id = $1 AND result_url = $2 ORDER BY created_at DESC LIMIT 1",
+        )
+        .bind(scene_id)
+        .bind(result_url)
+        .fetch_optional(pool)
+        .await
+    }
+
+    pub async fn find_by_candidate_batch(
+        pool: &sqlx::PgPool,
+        scene_id: i32,
+        candidate_batch_id: Uuid,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, GenerationHistory>(
+            "SELECT * FROM generation_history WHERE scene_id = $1 AND candidate_batch_id = $2 ORDER BY id ASC",
+        )
+        .bind(scene_id)
+        .bind(candidate_batch_id)
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn find_by_scene_id(
+        pool: &sqlx::PgPool,
+        scene_id: i32,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, GenerationHistory>(
+            "SELECT * FROM generation_history WHERE scene_id = $1 ORDER BY created_at DESC",
+        )
+        .bind(scene_id)
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn find_by_scene_and_type(
+        pool: &sqlx::PgPool,
+        scene_id: i32,
+        generation_type: &str,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, GenerationHistory>(
+            "SELECT * FROM generation_history WHERE scene_id = $1 AND generation_type = $2 ORDER BY created_at DESC",
+        )
+        .bind(scene_id)
+        .bind(generation_type)
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn find_by_project_id(
+        pool: &sqlx::PgPool,
+        project_id: Uuid,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, GenerationHistory>(
             r#"
             SELECT gh.*
             FROM generation_history gh
@@ -621,8 +1283,8 @@ impl GenerationHistory {
 // CompositeVideo 数据库操作
 // ========================================
 impl CompositeVideo {
-    pub async fn create(
-        pool: &sqlx::PgPool,
+    pub async fn create<'e>(
+        executor: impl sqlx::PgExecutor<'e>,
         project_id: Uuid,
         video_url: String,
         scene_count: i32,
@@ -637,7 +1299,7 @@ impl CompositeVideo {
         .bind(project_id)
         .bind(video_url)
         .bind(scene_count)
-        .fetch_one(pool)
+        .fetch_one(executor)
         .await
     }
 
@@ -689,8 +1351,23 @@ pub struct ProjectCharacter {
 }
 
 impl Character {
-    pub async fn create(
-        pool: &sqlx::PgPool,
+    /// 可用于分镜选角的角色：已关联到项目的角色，以及从该项目导入的待生成角色
+    pub async fn list_castable<'e>(executor: impl sqlx::PgExecutor<'e>, project_id: Uuid) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Self>(
+            r#"
+            SELECT * FROM characters
+            WHERE id IN (SELECT character_id FROM project_characters WHERE project_id = $1)
+               OR source_project_id = $1
+            ORDER BY created_at ASC
+            "#
+        )
+        .bind(project_id)
+        .fetch_all(executor)
+        .await
+    }
+
+    pub async fn create<'e>(
+        executor: impl sqlx::PgExecutor<'e>,
         name: String,
         image_url: String,
         prompt: Option<String>,
@@ -703,13 +1380,13 @@ impl Character {
         .bind(image_url)
         .bind(prompt)
         .bind(source_project_id)
-        .fetch_one(pool)
+        .fetch_one(executor)
         .await
     }
 
     // 创建待生成角色
-    pub async fn create_pending(
-        pool: &sqlx::PgPool,
+    pub async fn create_pending<'e>(
+        executor: impl sqlx::PgExecutor<'e>,
         name: String,
         prompt: Option<String>,
         category: Option<String>,
@@ -724,12 +1401,12 @@ impl Character {
         .bind(category)
         .bind(tags)
         .bind(source_project_id)
-        .fetch_one(pool)
+        .fetch_one(executor)
         .await
     }
 
-    pub async fn update(
-        pool: &sqlx::PgPool, 
+    pub async fn update<'e>(
+        executor: impl sqlx::PgExecutor<'e>, 
         id: Uuid, 
         name: String, 
         prompt: Option<String>,
@@ -764,7 +1441,7 @@ impl Character {
         query_builder.push(" RETURNING *");
 
         query_builder.build_query_as::<Self>()
-            .fetch_one(pool)
+            .fetch_one(executor)
             .await
     }
     
@@ -772,6 +1449,50 @@ impl Character {
 
 
 
+    pub async fn find_by_id(pool: &sqlx::PgPool, id: Uuid) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Self>("SELECT * FROM characters WHERE id = $1")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+    }
+
+    /// 基于已有角色创建衍生角色（沿用分类、标签和来源项目）
+    pub async fn create_derived(
+        pool: &sqlx::PgPool,
+        base: &Character,
+        name: String,
+        image_url: String,
+        prompt: Option<String>,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, Self>(
+            r#"
+            INSERT INTO characters (name, image_url, prompt, category, tags, status, derived_from, source_project_id)
+            VALUES ($1, $2, $3, $4, $5, 1, $6, $7)
+            RETURNING *
+            "#
+        )
+        .bind(name)
+        .bind(image_url)
+        .bind(prompt)
+        .bind(&base.category)
+        .bind(&base.tags)
+        .bind(base.id)
+        .bind(base.source_project_id)
+        .fetch_one(pool)
+        .await
+    }
+
+    /// 更新角色图片（同时更新 updated_at 用于缓存破坏）
+    pub async fn update_image(pool: &sqlx::PgPool, id: Uuid, image_url: String) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, Self>(
+            "UPDATE characters SET image_url = $1, updated_at = NOW() WHERE id = $2 RETURNING *"
+        )
+        .bind(image_url)
+        .bind(id)
+        .fetch_one(pool)
+        .await
+    }
+
     pub async fn delete(pool: &sqlx::PgPool, id: Uuid) -> Result<(), sqlx::Error> {
         let mut tx = pool.begin().await?;
 
@@ -844,8 +1565,8 @@ impl Character {
 
     // 更新角色状态并设置图片URL
     #[allow(dead_code)]
-    pub async fn update_status(
-        pool: &sqlx::PgPool,
+    pub async fn update_status<'e>(
+        executor: impl sqlx::PgExecutor<'e>,
         id: Uuid,
         image_url: String,
     ) -> Result<Self, sqlx::Error> {
@@ -854,7 +1575,7 @@ impl Character {
         )
         .bind(image_url)
         .bind(id)
-        .fetch_one(pool)
+        .fetch_one(executor)
         .await
     }
 }
@@ -875,47 +1596,1001 @@ impl ProjectCharacter {
         .await
     }
 
-    pub async fn link(pool: &sqlx::PgPool, project_id: Uuid, character_id: Uuid, display_order: i32) -> Result<(), sqlx::Error> {
+    pub async fn link<'e>(executor: impl sqlx::PgExecutor<'e>, project_id: Uuid, character_id: Uuid, display_order: i32) -> Result<(), sqlx::Error> {
         sqlx::query(
             "INSERT INTO project_characters (project_id, character_id, display_order) VALUES ($1, $2, $3)"
         )
         .bind(project_id)
         .bind(character_id)
         .bind(display_order)
-        .execute(pool)
+        .execute(executor)
         .await?;
         Ok(())
     }
 
+    /// 将一个项目的角色关联（含显示顺序）复制到另一个项目
+    pub async fn copy_links(pool: &sqlx::PgPool, from_project_id: Uuid, to_project_id: Uuid) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO project_characters (project_id, character_id, display_order)
+            SELECT $2, character_id, display_order
+            FROM project_characters
+            WHERE project_id = $1
+            "#
+        )
+        .bind(from_project_id)
+        .bind(to_project_id)
+        .execute(pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
     pub async fn unlink(pool: &sqlx::PgPool, project_id: Uuid, character_id: Uuid) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
         sqlx::query("DELETE FROM project_characters WHERE project_id = $1 AND character_id = $2")
             .bind(project_id)
             .bind(character_id)
-            .execute(pool)
+            .execute(&mut *tx)
             .await?;
+
+        // 同时移除该角色在项目各分镜中的出场记录
+        sqlx::query(
+            "DELETE FROM scene_characters WHERE character_id = $1 AND scene_id IN (SELECT id FROM storyboard_scenes WHERE project_id = $2)"
+        )
+        .bind(character_id)
+        .bind(project_id)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
         Ok(())
     }
 }
 
 // ========================================
-// UploadedFile - Cloudflare R2 文件上传记录
+// PromptEdit - 提示词/剧本编辑历史
 // ========================================
 
+/// 可记录编辑历史的字段
+pub const SCENE_PROMPT_FIELDS: &[&str] = &["first_frame_prompt", "video_prompt"];
+pub const PROJECT_PROMPT_FIELDS: &[&str] = &["global_image_prompt", "global_video_prompt", "script"];
+
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
-pub struct UploadedFile {
+pub struct PromptEdit {
     pub id: i32,
-    pub file_hash: String,
-    pub cloudflare_url: String,
-    pub file_type: String,
-    pub file_size_bytes: i64,
+    pub project_id: Uuid,
+    pub scene_id: Option<i32>,  // 为空表示项目级字段（全局提示词、剧本）
+    pub field: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    pub author: Option<String>,
     pub created_at: DateTime<Utc>,
 }
 
-impl UploadedFile {
-    /// 根据文件哈希查找记录
-    pub async fn find_by_hash(pool: &sqlx::PgPool, hash: &str) -> Result<Option<Self>, sqlx::Error> {
-        sqlx::query_as::<_, Self>("SELECT * FROM uploaded_files WHERE file_hash = $1")
-            .bind(hash)
+impl PromptEdit {
+    /// 记录一次编辑，内容未变化时不记录；可传入事务，与内容的修改一起提交
+    pub async fn record<'e>(
+        executor: impl sqlx::PgExecutor<'e>,
+        project_id: Uuid,
+        scene_id: Option<i32>,
+        field: &str,
+        old_value: Option<String>,
+        new_value: Option<String>,
+        author: Option<String>,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        if old_value == new_value {
+            return Ok(None);
+        }
+
+        sqlx::query_as::<_, PromptEdit>(
+            r#"
+            INSERT INTO prompt_edits (project_id, scene_id, field, old_value, new_value, author)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING *
+            "#,
+        )
+        .bind(project_id)
+        .bind(scene_id)
+        .bind(field)
+        .bind(old_value)
+        .bind(new_value)
+        .bind(author)
+        .fetch_one(executor)
+        .await
+        .map(Some)
+    }
+
+    pub async fn find_by_id(pool: &sqlx::PgPool, id: i32) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, PromptEdit>("SELECT * FROM prompt_edits WHERE id = $1")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+    }
+
+    /// 项目的全部编辑记录（含分镜），最新在前
+    pub async fn find_by_project_id(
+        pool: &sqlx::PgPool,
+        project_id: Uuid,
+        limit: i64,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, PromptEdit>(
+            "SELECT * FROM prompt_edits WHERE project_id = $1 ORDER BY created_at DESC, id DESC LIMIT $2",
+        )
+        .bind(project_id)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn find_by_scene_id(
+        pool: &sqlx::PgPool,
+        scene_id: i32,
+        limit: i64,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, PromptEdit>(
+            "SELECT * FROM prompt_edits WHERE scene_id = $1 ORDER BY created_at DESC, id DESC LIMIT $2",
+        )
+        .bind(scene_id)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+    }
+}
+
+// ========================================
+// GenerationBatch - 批量生成任务
+// ========================================
+
+/// 批量任务中单个分镜的状态
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchItem {
+    pub scene_id: i32,
+    pub status: String,  // pending / running / succeeded / failed / skipped
+    pub result_url: Option<String>,
+    pub error: Option<String>,
+}
+
+impl BatchItem {
+    pub fn pending(scene_id: i32) -> Self {
+        BatchItem { scene_id, status: "pending".to_string(), result_url: None, error: None }
+    }
+
+    pub fn skipped(scene_id: i32, reason: &str) -> Self {
+        BatchItem { scene_id, status: "skipped".to_string(), result_url: None, error: Some(reason.to_string()) }
+    }
+
+    fn is_finished(&self) -> bool {
+        matches!(self.status.as_str(), "succeeded" | "failed" | "skipped")
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct GenerationBatch {
+    pub id: Uuid,
+    pub project_id: Uuid,
+    pub generation_type: String,
+    pub status: String,  // running / completed / failed / interrupted
+    pub concurrency: i32,
+    pub total: i32,
+    pub completed: i32,
+    pub succeeded: i32,
+    pub failed: i32,
+    pub skipped: i32,
+    pub items: sqlx::types::Json<Vec<BatchItem>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+// 统计 (已完成, 成功, 失败, 跳过)
+fn count_batch_items(items: &[BatchItem]) -> (i32, i32, i32, i32) {
+    let count = |status: &str| items.iter().filter(|i| i.status == status).count() as i32;
+    let completed = items.iter().filter(|i| i.is_finished()).count() as i32;
+    (completed, count("succeeded"), count("failed"), count("skipped"))
+}
+
+impl GenerationBatch {
+    pub async fn create(
+        pool: &sqlx::PgPool,
+        project_id: Uuid,
+        generation_type: &str,
+        concurrency: i32,
+        items: Vec<BatchItem>,
+    ) -> Result<Self, sqlx::Error> {
+        let (completed, succeeded, failed, skipped) = count_batch_items(&items);
+        let status = if completed as usize == items.len() { "completed" } else { "running" };
+        sqlx::query_as::<_, GenerationBatch>(
+            r#"
+            INSERT INTO generation_batches (
+                id, project_id, generation_type, status, concurrency, total, completed, succeeded, failed, skipped, items
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(project_id)
+        .bind(generation_type)
+        .bind(status)
+        .bind(concurrency)
+        .bind(items.len() as i32)
+        .bind(completed)
+        .bind(succeeded)
+        .bind(failed)
+        .bind(skipped)
+        .bind(sqlx::types::Json(items))
+        .fetch_one(pool)
+        .await
+    }
+
+    /// 写入最新的分镜状态，全部完成时标记为 completed
+    pub async fn update_items(pool: &sqlx::PgPool, id: Uuid, items: &[BatchItem]) -> Result<(), sqlx::Error> {
+        let (completed, succeeded, failed, skipped) = count_batch_items(items);
+        let status = if completed as usize == items.len() { "completed" } else { "running" };
+        sqlx::query(
+            r#"
+            UPDATE generation_batches
+            SET status = $1, completed = $2, succeeded = $3, failed = $4, skipped = $5, items = $6, updated_at = NOW()
+            WHERE id = $7
+            "#,
+        )
+        .bind(status)
+        .bind(completed)
+        .bind(succeeded)
+        .bind(failed)
+        .bind(skipped)
+        .bind(sqlx::types::Json(items))
+        .bind(id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn find_by_id(pool: &sqlx::PgPool, id: Uuid) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, GenerationBatch>("SELECT * FROM generation_batches WHERE id = $1")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+    }
+
+    pub async fn find_by_project_id(pool: &sqlx::PgPool, project_id: Uuid, limit: i64) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, GenerationBatch>(
+            "SELECT * FROM generation_batches WHERE project_id = $1 ORDER BY created_at DESC LIMIT $2",
+        )
+        .bind(project_id)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+    }
+
+    /// 后台任务异常退出时，未完成的分镜标记为失败，任务标记为 failed
+    pub async fn mark_failed(pool: &sqlx::PgPool, id: Uuid, error: &str) -> Result<(), sqlx::Error> {
+        let Some(batch) = Self::find_by_id(pool, id).await? else {
+            return Ok(());
+        };
+        let mut items = batch.items.0;
+        for item in items.iter_mut().filter(|i| !i.is_finished()) {
+            item.status = "failed".to_string();
+            item.error = Some(error.to_string());
+        }
+        let (completed, succeeded, failed, skipped) = count_batch_items(&items);
+        sqlx::query(
+            r#"
+            UPDATE generation_batches
+            SET status = 'failed', completed = $1, succeeded = $2, failed = $3, skipped = $4, items = $5, updated_at = NOW()
+            WHERE id = $6
+            "#,
+        )
+        .bind(completed)
+        .bind(succeeded)
+        .bind(failed)
+        .bind(skipped)
+        .bind(sqlx::types::Json(items))
+        .bind(id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// 服务重启后，未完成的批量任务标记为 interrupted
+    pub async fn mark_interrupted(pool: &sqlx::PgPool) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query(
+            "UPDATE generation_batches SET status = 'interrupted', updated_at = NOW() WHERE status = 'running'",
+        )
+        .execute(pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+// ========================================
+// PromptTranslation - 提示词翻译缓存
+// ========================================
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct PromptTranslation {
+    pub prompt_hash: String,
+    pub source_text: String,
+    pub translated_text: String,
+    pub model: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl PromptTranslation {
+    pub async fn find_by_hash(pool: &sqlx::PgPool, prompt_hash: &str) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, PromptTranslation>("SELECT * FROM prompt_translations WHERE prompt_hash = $1")
+            .bind(prompt_hash)
+            .fetch_optional(pool)
+            .await
+    }
+
+    pub async fn save(
+        pool: &sqlx::PgPool,
+        prompt_hash: &str,
+        source_text: &str,
+        translated_text: &str,
+        model: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO prompt_translations (prompt_hash, source_text, translated_text, model)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (prompt_hash) DO UPDATE
+            SET translated_text = EXCLUDED.translated_text, model = EXCLUDED.model, created_at = NOW()
+            "#,
+        )
+        .bind(prompt_hash)
+        .bind(source_text)
+        .bind(translated_text)
+        .bind(model)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
+
+// ========================================
+// VideoTask - 视频生成任务
+// ========================================
+
+/// 提交给 VEO 的视频任务，保存创建时实际使用的提示词
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct VideoTask {
+    pub video_id: String,
+    pub scene_id: i32,
+    pub prompt: String,
+    pub translated_prompt: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl VideoTask {
+    pub async fn create(
+        pool: &sqlx::PgPool,
+        video_id: &str,
+        scene_id: i32,
+        prompt: &str,
+        translated_prompt: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO video_tasks (video_id, scene_id, prompt, translated_prompt)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (video_id) DO NOTHING
+            "#,
+        )
+        .bind(video_id)
+        .bind(scene_id)
+        .bind(prompt)
+        .bind(translated_prompt)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn find(pool: &sqlx::PgPool, video_id: &str) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, VideoTask>("SELECT * FROM video_tasks WHERE video_id = $1")
+            .bind(video_id)
+            .fetch_optional(pool)
+            .await
+    }
+
+    /// 任务结束（完成或失败）后删除记录
+    pub async fn delete(pool: &sqlx::PgPool, video_id: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM video_tasks WHERE video_id = $1")
+            .bind(video_id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+}
+
+// ========================================
+// ProjectSnapshot - 项目快照（恢复点）
+// ========================================
+
+/// 快照中的单个分镜
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotScene {
+    pub scene_id: i32,
+    pub scene_index: i32,
+    pub duration: Option<f64>,
+    pub first_frame_prompt: Option<String>,
+    pub video_prompt: Option<String>,
+    pub latest_image_url: Option<String>,
+    pub latest_video_url: Option<String>,
+    #[serde(default)]
+    pub character_ids: Vec<Uuid>,
+    // 以下字段旧快照中没有（status 为空），恢复时保持分镜当前的值
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub sequence: Option<String>,
+    #[serde(default)]
+    pub start_time: Option<String>,
+    #[serde(default)]
+    pub end_time: Option<String>,
+}
+
+/// 快照内容：剧本、全局提示词和全部分镜
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotData {
+    pub script: Option<String>,
+    pub global_image_prompt: Option<String>,
+    pub global_video_prompt: Option<String>,
+    pub scenes: Vec<SnapshotScene>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ProjectSnapshot {
+    pub id: i32,
+    pub project_id: Uuid,
+    pub name: String,
+    pub data: sqlx::types::Json<SnapshotData>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// 快照列表项（不含快照内容）
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ProjectSnapshotSummary {
+    pub id: i32,
+    pub project_id: Uuid,
+    pub name: String,
+    pub scene_count: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+/// 恢复快照的结果
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotRestoreResult {
+    pub restored_scenes: Vec<i32>,
+    pub recreated_scenes: Vec<i32>,  // 快照之后被删除、按快照重新创建的分镜（新ID）
+    pub skipped_locked_scenes: Vec<i32>,
+    pub kept_scenes: Vec<i32>,  // 快照之后新增的分镜，保留并排在最后
+}
+
+impl SnapshotData {
+    /// 读取项目当前状态
+    pub async fn capture(pool: &sqlx::PgPool, project: &Project) -> Result<Self, sqlx::Error> {
+        let scenes = StoryboardScene::find_by_project_id(pool, project.id).await?;
+
+        let mut snapshot_scenes = Vec::with_capacity(scenes.len());
+        for scene in scenes {
+            let character_ids: Vec<Uuid> = sqlx::query_scalar(
+                "SELECT character_id FROM scene_characters WHERE scene_id = $1 ORDER BY character_id"
+            )
+            .bind(scene.id)
+            .fetch_all(pool)
+            .await?;
+
+            snapshot_scenes.push(SnapshotScene {
+                scene_id: scene.id,
+                scene_index: scene.scene_index,
+                duration: scene.duration,
+                first_frame_prompt: scene.first_frame_prompt,
+                video_prompt: scene.video_prompt,
+                latest_image_url: scene.latest_image_url,
+                latest_video_url: scene.latest_video_url,
+                character_ids,
+                status: Some(scene.status),
+                sequence: scene.sequence,
+                start_time: scene.start_time,
+                end_time: scene.end_time,
+            });
+        }
+
+        Ok(SnapshotData {
+            script: project.script.clone(),
+            global_image_prompt: project.global_image_prompt.clone(),
+            global_video_prompt: project.global_video_prompt.clone(),
+            scenes: snapshot_scenes,
+        })
+    }
+}
+
+impl ProjectSnapshot {
+    pub async fn create(
+        pool: &sqlx::PgPool,
+        project_id: Uuid,
+        name: String,
+        data: SnapshotData,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as::<_, ProjectSnapshot>(
+            "INSERT INTO project_snapshots (project_id, name, data) VALUES ($1, $2, $3) RETURNING *",
+        )
+        .bind(project_id)
+        .bind(name)
+        .bind(sqlx::types::Json(data))
+        .fetch_one(pool)
+        .await
+    }
+
+    pub async fn find_by_id(pool: &sqlx::PgPool, id: i32) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, ProjectSnapshot>("SELECT * FROM project_snapshots WHERE id = $1")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+    }
+
+    pub async fn list_by_project_id(
+        pool: &sqlx::PgPool,
+        project_id: Uuid,
+    ) -> Result<Vec<ProjectSnapshotSummary>, sqlx::Error> {
+        sqlx::query_as::<_, ProjectSnapshotSummary>(
+            r#"
+            SELECT id, project_id, name, jsonb_array_length(data->'scenes') AS scene_count, created_at
+            FROM project_snapshots
+            WHERE project_id = $1
+            ORDER BY created_at DESC
+            "#,
+        )
+        .bind(project_id)
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn delete(pool: &sqlx::PgPool, id: i32) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM project_snapshots WHERE id = $1")
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    /// 将项目恢复到快照状态
+    ///
+    /// 改写分镜的全部内容（提示词、时长、时间戳、当前选用的图片/视频、审核状态、序列和出场角色），
+    /// 不删除任何分镜、生成历史或媒体文件。
+    /// 锁定的分镜保持原样；快照之后新增的分镜保留并排在快照分镜之后。
+    /// 提示词和剧本的变化记录为编辑历史，与恢复一起提交。
+    pub async fn restore(&self, pool: &sqlx::PgPool) -> Result<SnapshotRestoreResult, sqlx::Error> {
+        let data = &self.data.0;
+        let mut tx = pool.begin().await?;
+
+        let project_prompts: (Option<String>, Option<String>, Option<String>) = sqlx::query_as(
+            "SELECT script, global_image_prompt, global_video_prompt FROM projects WHERE id = $1 FOR UPDATE"
+        )
+        .bind(self.project_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            UPDATE projects
+            SET script = $1, global_image_prompt = $2, global_video_prompt = $3, updated_at = NOW()
+            WHERE id = $4
+            "#,
+        )
+        .bind(&data.script)
+        .bind(&data.global_image_prompt)
+        .bind(&data.global_video_prompt)
+        .bind(self.project_id)
+        .execute(&mut *tx)
+        .await?;
+
+        let (script, global_image_prompt, global_video_prompt) = project_prompts;
+        for (field, old_value, new_value) in [
+            ("script", script, &data.script),
+            ("global_image_prompt", global_image_prompt, &data.global_image_prompt),
+            ("global_video_prompt", global_video_prompt, &data.global_video_prompt),
+        ] {
+            PromptEdit::record(&mut *tx, self.project_id, None, field, old_value, new_value.clone(), None).await?;
+        }
+
+        let current: Vec<(i32, String, Option<String>, Option<String>)> = sqlx::query_as(
+            r#"
+            SELECT id, status, first_frame_prompt, video_prompt
+            FROM storyboard_scenes
+            WHERE project_id = $1
+            ORDER BY scene_index ASC
+            "#
+        )
+        .bind(self.project_id)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        // 先改为负数序号，避免与唯一约束 (project_id, scene_index) 冲突
+        sqlx::query("UPDATE storyboard_scenes SET scene_index = -scene_index WHERE project_id = $1")
+            .bind(self.project_id)
+            .execute(&mut *tx)
+            .await?;
+
+        let mut result = SnapshotRestoreResult {
+            restored_scenes: Vec::new(),
+            recreated_scenes: Vec::new(),
+            skipped_locked_scenes: Vec::new(),
+            kept_scenes: Vec::new(),
+        };
+
+        let mut scenes: Vec<&SnapshotScene> = data.scenes.iter().collect();
+        scenes.sort_by_key(|s| s.scene_index);
+
+        let mut next_index = 1;
+        for snapshot_scene in scenes {
+            let existing = current.iter().find(|(id, ..)| *id == snapshot_scene.scene_id);
+
+            let scene_id = match existing {
+                Some((id, status, ..)) if status == "locked" => {
+                    sqlx::query("UPDATE storyboard_scenes SET scene_index = $1 WHERE id = $2")
+                        .bind(next_index)
+                        .bind(id)
+                        .execute(&mut *tx)
+                        .await?;
+                    result.skipped_locked_scenes.push(*id);
+                    next_index += 1;
+                    continue;
+                }
+                Some((id, _, first_frame_prompt, video_prompt)) => {
+                    // 旧快照（没有 status）不改写状态、序列和时间戳
+                    sqlx::query(
+                        r#"
+                        UPDATE storyboard_scenes
+                        SET scene_index = $1, duration = $2, first_frame_prompt = $3, video_prompt = $4,
+                            latest_image_url = $5, latest_video_url = $6,
+                            status = COALESCE($7, status),
+                            sequence = CASE WHEN $7 IS NULL THEN sequence ELSE $8 END,
+                            start_time = CASE WHEN $7 IS NULL THEN start_time ELSE $9 END,
+                            end_time = CASE WHEN $7 IS NULL THEN end_time ELSE $10 END,
+                            updated_at = NOW()
+                        WHERE id = $11
+                        "#,
+                    )
+                    .bind(next_index)
+                    .bind(snapshot_scene.duration)
+                    .bind(&snapshot_scene.first_frame_prompt)
+                    .bind(&snapshot_scene.video_prompt)
+                    .bind(&snapshot_scene.latest_image_url)
+                    .bind(&snapshot_scene.latest_video_url)
+                    .bind(&snapshot_scene.status)
+                    .bind(&snapshot_scene.sequence)
+                    .bind(&snapshot_scene.start_time)
+                    .bind(&snapshot_scene.end_time)
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+
+                    for (field, old_value, new_value) in [
+                        ("first_frame_prompt", first_frame_prompt, &snapshot_scene.first_frame_prompt),
+                        ("video_prompt", video_prompt, &snapshot_scene.video_prompt),
+                    ] {
+                        PromptEdit::record(
+                            &mut *tx,
+                            self.project_id,
+                            Some(*id),
+                            field,
+                            old_value.clone(),
+                            new_value.clone(),
+                            None,
+                        )
+                        .await?;
+                    }
+                    result.restored_scenes.push(*id);
+                    *id
+                }
+                None => {
+                    let id: i32 = sqlx::query_scalar(
+                        r#"
+                        INSERT INTO storyboard_scenes (
+                            project_id, scene_index, duration, first_frame_prompt, video_prompt,
+                            latest_image_url, latest_video_url, status, sequence, start_time, end_time
+                        )
+                        VALUES ($1, $2, $3, $4, $5, $6, $7, COALESCE($8, 'draft'), $9, $10, $11)
+                        RETURNING id
+                        "#,
+                    )
+                    .bind(self.project_id)
+                    .bind(next_index)
+                    .bind(snapshot_scene.duration)
+                    .bind(&snapshot_scene.first_frame_prompt)
+                    .bind(&snapshot_scene.video_prompt)
+                    .bind(&snapshot_scene.latest_image_url)
+                    .bind(&snapshot_scene.latest_video_url)
+                    .bind(&snapshot_scene.status)
+                    .bind(&snapshot_scene.sequence)
+                    .bind(&snapshot_scene.start_time)
+                    .bind(&snapshot_scene.end_time)
+                    .fetch_one(&mut *tx)
+                    .await?;
+                    result.recreated_scenes.push(id);
+                    id
+                }
+            };
+            next_index += 1;
+
+            // 出场角色：跳过已从角色库删除的角色
+            sqlx::query("DELETE FROM scene_characters WHERE scene_id = $1")
+                .bind(scene_id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query(
+                r#"
+                INSERT INTO scene_characters (scene_id, character_id)
+                SELECT $1, id FROM characters WHERE id = ANY($2)
+                ON CONFLICT DO NOTHING
+                "#,
+            )
+            .bind(scene_id)
+            .bind(&snapshot_scene.character_ids)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        // 快照之后新增的分镜按原顺序排在最后
+        for (id, ..) in &current {
+            if data.scenes.iter().any(|s| s.scene_id == *id) {
+                continue;
+            }
+            sqlx::query("UPDATE storyboard_scenes SET scene_index = $1 WHERE id = $2")
+                .bind(next_index)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            result.kept_scenes.push(*id);
+            next_index += 1;
+        }
+
+        tx.commit().await?;
+        Ok(result)
+    }
+}
+
+// ========================================
+// SceneCharacter - 分镜出场角色
+// ========================================
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct SceneCharacter {
+    pub scene_id: i32,
+    pub character_id: Uuid,
+    pub created_at: DateTime<Utc>,
+}
+
+impl SceneCharacter {
+    /// 获取分镜的出场角色（按项目角色顺序排列）
+    pub async fn get_for_scene(pool: &sqlx::PgPool, scene_id: i32) -> Result<Vec<ProjectCharacter>, sqlx::Error> {
+        sqlx::query_as::<_, ProjectCharacter>(
+            r#"
+            SELECT c.id, c.name, c.image_url, c.prompt, c.category, c.tags, COALESCE(pc.display_order, 0) AS display_order
+            FROM scene_characters sc
+            JOIN characters c ON c.id = sc.character_id
+            JOIN storyboard_scenes s ON s.id = sc.scene_id
+            LEFT JOIN project_characters pc ON pc.character_id = c.id AND pc.project_id = s.project_id
+            WHERE sc.scene_id = $1
+            ORDER BY display_order ASC, c.name ASC
+            "#
+        )
+        .bind(scene_id)
+        .fetch_all(pool)
+        .await
+    }
+
+    /// 替换分镜的出场角色
+    pub async fn set_for_scene<'a>(
+        conn: impl sqlx::Acquire<'a, Database = sqlx::Postgres>,
+        scene_id: i32,
+        character_ids: &[Uuid],
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = conn.begin().await?;
+
+        sqlx::query("DELETE FROM scene_characters WHERE scene_id = $1")
+            .bind(scene_id)
+            .execute(&mut *tx)
+            .await?;
+
+        for character_id in character_ids {
+            sqlx::query(
+                "INSERT INTO scene_characters (scene_id, character_id) VALUES ($1, $2) ON CONFLICT DO NOTHING"
+            )
+            .bind(scene_id)
+            .bind(character_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// 复制分镜的出场角色到另一个分镜
+    pub async fn copy(pool: &sqlx::PgPool, from_scene_id: i32, to_scene_id: i32) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO scene_characters (scene_id, character_id)
+            SELECT $2, character_id FROM scene_characters WHERE scene_id = $1
+            ON CONFLICT DO NOTHING
+            "#
+        )
+        .bind(from_scene_id)
+        .bind(to_scene_id)
+        .execute(pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+// ========================================
+// SearchHit - 项目全文搜索
+// ========================================
+
+/// 搜索命中记录（项目字段、分镜提示词或生成历史提示词）
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct SearchHit {
+    pub kind: String,              // 'project' / 'scene' / 'history'
+    pub field: String,             // 命中的字段名
+    pub project_id: Uuid,
+    pub project_title: String,
+    pub scene_id: Option<i32>,
+    pub scene_index: Option<i32>,
+    pub history_id: Option<i32>,
+    pub content: String,
+    pub thumbnail_url: Option<String>,
+    pub rank: f32,
+}
+
+impl SearchHit {
+    /// 搜索项目标题/简介/剧本/全局提示词、分镜提示词和生成历史提示词
+    ///
+    /// `patterns` 为每个关键词对应的 ILIKE 模式，必须全部命中；第一个模式可以走 trigram 索引。
+    /// 排序分数 = 字段权重 × (0.5 + 与完整查询的 word_similarity)
+    pub async fn search(
+        pool: &sqlx::PgPool,
+        query: &str,
+        patterns: &[String],
+        limit: i64,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        let Some(first_pattern) = patterns.first() else {
+            return Ok(Vec::new());
+        };
+
+        sqlx::query_as::<_, Self>(
+            r#"
+            WITH project_covers AS (
+                SELECT p.id,
+                       COALESCE(p.cover_image_url, (
+                           SELECT s.latest_image_url FROM storyboard_scenes s
+                           WHERE s.project_id = p.id AND s.latest_image_url IS NOT NULL
+                           ORDER BY s.scene_index ASC LIMIT 1
+                       )) AS cover
+                FROM projects p
+            ),
+            hits AS (
+                SELECT 'project' AS kind, 'title' AS field, p.id AS project_id, NULL::INTEGER AS scene_id,
+                       NULL::INTEGER AS scene_index, NULL::INTEGER AS history_id, p.title AS content, 1.0 AS weight
+                FROM projects p WHERE p.title ILIKE $2 AND p.title ILIKE ALL($3)
+                UNION ALL
+                SELECT 'project', 'description', p.id, NULL, NULL, NULL, p.description, 0.8
+                FROM projects p WHERE p.description ILIKE $2 AND p.description ILIKE ALL($3)
+                UNION ALL
+                SELECT 'project', 'script', p.id, NULL, NULL, NULL, p.script, 0.6
+                FROM projects p WHERE p.script ILIKE $2 AND p.script ILIKE ALL($3)
+                UNION ALL
+                SELECT 'project', 'global_image_prompt', p.id, NULL, NULL, NULL, p.global_image_prompt, 0.5
+                FROM projects p WHERE p.global_image_prompt ILIKE $2 AND p.global_image_prompt ILIKE ALL($3)
+                UNION ALL
+                SELECT 'project', 'global_video_prompt', p.id, NULL, NULL, NULL, p.global_video_prompt, 0.5
+                FROM projects p WHERE p.global_video_prompt ILIKE $2 AND p.global_video_prompt ILIKE ALL($3)
+                UNION ALL
+                SELECT 'scene', 'first_frame_prompt', s.project_id, s.id, s.scene_index, NULL, s.first_frame_prompt, 0.9
+                FROM storyboard_scenes s WHERE s.first_frame_prompt ILIKE $2 AND s.first_frame_prompt ILIKE ALL($3)
+                UNION ALL
+                SELECT 'scene', 'video_prompt', s.project_id, s.id, s.scene_index, NULL, s.video_prompt, 0.8
+                FROM storyboard_scenes s WHERE s.video_prompt ILIKE $2 AND s.video_prompt ILIKE ALL($3)
+                UNION ALL
+                SELECT 'history', 'prompt', s.project_id, s.id, s.scene_index, h.id, h.prompt, 0.4
+                FROM generation_history h
+                JOIN storyboard_scenes s ON s.id = h.scene_id
+                WHERE h.prompt ILIKE $2 AND h.prompt ILIKE ALL($3)
+            )
+            SELECT hits.kind, hits.field, hits.project_id, p.title AS project_title,
+                   hits.scene_id, hits.scene_index, hits.history_id, hits.content,
+                   CASE
+                       WHEN hits.history_id IS NOT NULL THEN COALESCE(
+                           (SELECT h.result_url FROM generation_history h
+                            WHERE h.id = hits.history_id AND h.generation_type = 'image'),
+                           sc.latest_image_url)
+                       WHEN hits.scene_id IS NOT NULL THEN sc.latest_image_url
+                       ELSE pc.cover
+                   END AS thumbnail_url,
+                   (hits.weight * (0.5 + word_similarity($1, hits.content)))::REAL AS rank
+            FROM hits
+            JOIN projects p ON p.id = hits.project_id
+            JOIN project_covers pc ON pc.id = hits.project_id
+            LEFT JOIN storyboard_scenes sc ON sc.id = hits.scene_id
+            WHERE p.deleted_at IS NULL
+            ORDER BY rank DESC, p.updated_at DESC
+            LIMIT $4
+            "#,
+        )
+        .bind(query)
+        .bind(first_pattern)
+        .bind(patterns)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+    }
+}
+
+// ========================================
+// 媒体文件引用（用于清理孤立文件）
+// ========================================
+
+/// 数据库中引用的所有媒体 URL
+pub async fn referenced_media_urls(pool: &sqlx::PgPool) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"
+        SELECT url FROM (
+            SELECT cover_image_url AS url FROM projects
+            UNION ALL SELECT combined_characters_image FROM projects
+            UNION ALL SELECT latest_image_url FROM storyboard_scenes
+            UNION ALL SELECT latest_video_url FROM storyboard_scenes
+            UNION ALL SELECT result_url FROM generation_history
+            UNION ALL SELECT mask_url FROM generation_history
+            UNION ALL SELECT original_url FROM generation_history
+            UNION ALL SELECT video_url FROM composite_videos
+            UNION ALL SELECT image_url FROM characters
+            UNION ALL SELECT s->>'latest_image_url' FROM project_snapshots, jsonb_array_elements(data->'scenes') s
+            UNION ALL SELECT s->>'latest_video_url' FROM project_snapshots, jsonb_array_elements(data->'scenes') s
+        ) refs
+        WHERE url IS NOT NULL AND url <> ''
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// 所有项目ID和任务ID（包括回收站中的项目）
+pub async fn live_owner_ids(pool: &sqlx::PgPool) -> Result<(Vec<Uuid>, Vec<Uuid>), sqlx::Error> {
+    let project_ids = sqlx::query_scalar("SELECT id FROM projects").fetch_all(pool).await?;
+    let job_ids = sqlx::query_scalar("SELECT id FROM jobs").fetch_all(pool).await?;
+    Ok((project_ids, job_ids))
+}
+
+// ========================================
+// UploadedFile - Cloudflare R2 文件上传记录
+// ========================================
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct UploadedFile {
+    pub id: i32,
+    pub file_hash: String,
+    pub cloudflare_url: String,
+    pub file_type: String,
+    pub file_size_bytes: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+impl UploadedFile {
+    /// 根据文件哈希查找记录
+    pub async fn find_by_hash(pool: &sqlx::PgPool, hash: &str) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Self>("SELECT * FROM uploaded_files WHERE file_hash = $1")
+            .bind(hash)
+            .fetch_optional(pool)
+            .await
+    }
+
+    /// 根据 Cloudflare URL 查找记录
+    pub async fn find_by_url(pool: &sqlx::PgPool, url: &str) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Self>("SELECT * FROM uploaded_files WHERE cloudflare_url = $1 ORDER BY id DESC LIMIT 1")
+            .bind(url)
             .fetch_optional(pool)
             .await
     }
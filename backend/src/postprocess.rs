@@ -0,0 +1,460 @@
+use std::io::Cursor;
+
+use image::imageops::{self, FilterType};
+use image::{ColorType, DynamicImage, GenericImageView, ImageEncoder, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+/// 画面比例适配方式：裁剪 / 补边 / 不处理
+pub const FIT_MODES: &[&str] = &["crop", "pad", "none"];
+/// 输出格式
+pub const OUTPUT_FORMATS: &[&str] = &["png", "jpeg", "webp"];
+/// 水印位置
+pub const WATERMARK_POSITIONS: &[&str] = &["top_left", "top_right", "bottom_left", "bottom_right", "center"];
+/// 未设置质量时的 JPEG 质量
+const DEFAULT_JPEG_QUALITY: u8 = 90;
+
+// ========================================
+// 配置
+// ========================================
+
+/// 图片后处理配置（生成和上传的首帧图都会经过，默认关闭）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PostProcessConfig {
+    pub enabled: bool,
+    pub fit: String,        // crop / pad / none
+    pub pad_color: String,  // 补边颜色，如 "#000000"
+    pub resize: bool,       // 缩放到项目分辨率（包括放大）
+    pub format: String,     // png / jpeg / webp
+    pub quality: Option<u8>, // JPEG 质量 1-100，不设置时为 90（WebP 只支持无损编码，不能设置质量）
+    pub watermark: Option<WatermarkConfig>,
+}
+
+/// 水印配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WatermarkConfig {
+    pub image_url: String,  // 水印图片（仅限 /data 下的本地文件），建议使用透明 PNG
+    pub position: String,
+    pub opacity: f32,       // 0-1
+    pub scale: f32,         // 水印宽度占画面宽度的比例
+    pub margin: u32,        // 距画面边缘的像素
+}
+
+impl Default for PostProcessConfig {
+    fn default() -> Self {
+        PostProcessConfig {
+            enabled: false,
+            fit: "crop".to_string(),
+            pad_color: "#000000".to_string(),
+            resize: true,
+            format: "png".to_string(),
+            quality: None,
+            watermark: None,
+        }
+    }
+}
+
+impl Default for WatermarkConfig {
+    fn default() -> Self {
+        WatermarkConfig {
+            image_url: String::new(),
+            position: "bottom_right".to_string(),
+            opacity: 0.6,
+            scale: 0.15,
+            margin: 24,
+        }
+    }
+}
+
+impl PostProcessConfig {
+    /// 服务器默认配置（IMAGE_POSTPROCESS_* 环境变量），无效时使用内置默认值
+    pub fn from_env() -> Self {
+        let defaults = PostProcessConfig::default();
+        let watermark = env_string("IMAGE_WATERMARK").map(|image_url| {
+            let defaults = WatermarkConfig::default();
+            WatermarkConfig {
+                image_url,
+                position: env_string("IMAGE_WATERMARK_POSITION").unwrap_or(defaults.position),
+                opacity: env_number("IMAGE_WATERMARK_OPACITY", defaults.opacity),
+                scale: env_number("IMAGE_WATERMARK_SCALE", defaults.scale),
+                margin: env_number("IMAGE_WATERMARK_MARGIN", defaults.margin),
+            }
+        });
+
+        let config = PostProcessConfig {
+            enabled: env_string("IMAGE_POSTPROCESS").map(|v| v == "true" || v == "1").unwrap_or(defaults.enabled),
+            fit: env_string("IMAGE_POSTPROCESS_FIT").unwrap_or_else(|| defaults.fit.clone()),
+            pad_color: env_string("IMAGE_POSTPROCESS_PAD_COLOR").unwrap_or_else(|| defaults.pad_color.clone()),
+            resize: env_string("IMAGE_POSTPROCESS_RESIZE").map(|v| v == "true" || v == "1").unwrap_or(defaults.resize),
+            format: env_string("IMAGE_POSTPROCESS_FORMAT").unwrap_or_else(|| defaults.format.clone()),
+            quality: env_string("IMAGE_POSTPROCESS_QUALITY").and_then(|v| v.parse().ok()),
+            watermark,
+        };
+
+        match config.validate() {
+            Ok(()) => config,
+            Err(e) => {
+                eprintln!("⚠️  图片后处理环境变量无效，使用默认配置: {}", e);
+                defaults
+            }
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !FIT_MODES.contains(&self.fit.as_str()) {
+            return Err(format!("无效的比例适配方式: {}，可选值: {}", self.fit, FIT_MODES.join(", ")));
+        }
+        parse_color(&self.pad_color).ok_or_else(|| format!("无效的补边颜色: {}", self.pad_color))?;
+        if !OUTPUT_FORMATS.contains(&self.format.as_str()) {
+            return Err(format!("无效的输出格式: {}，可选值: {}", self.format, OUTPUT_FORMATS.join(", ")));
+        }
+        if let Some(quality) = self.quality {
+            if !(1..=100).contains(&quality) {
+                return Err("输出质量必须在 1 到 100 之间".to_string());
+            }
+            if self.format == "webp" {
+                return Err("WebP 只支持无损编码，不能设置输出质量".to_string());
+            }
+        }
+        if let Some(watermark) = &self.watermark {
+            if watermark.image_url.trim().is_empty() {
+                return Err("水印图片不能为空".to_string());
+            }
+            // 后处理时直接读取水印文件，只允许 /data 下的本地文件
+            if !watermark.image_url.starts_with("/data/") || watermark.image_url.contains("..") {
+                return Err("水印图片必须是 /data 下的本地文件".to_string());
+            }
+            if !WATERMARK_POSITIONS.contains(&watermark.position.as_str()) {
+                return Err(format!(
+                    "无效的水印位置: {}，可选值: {}",
+                    watermark.position,
+                    WATERMARK_POSITIONS.join(", ")
+                ));
+            }
+            if !(0.0..=1.0).contains(&watermark.opacity) {
+                return Err("水印透明度必须在 0 到 1 之间".to_string());
+            }
+            if !(watermark.scale > 0.0 && watermark.scale <= 1.0) {
+                return Err("水印比例必须大于 0 且不超过 1".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// 输出文件扩展名
+    pub fn extension(&self) -> &'static str {
+        match self.format.as_str() {
+            "jpeg" => "jpg",
+            "webp" => "webp",
+            _ => "png",
+        }
+    }
+}
+
+fn env_string(name: &str) -> Option<String> {
+    std::env::var(name).ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+fn env_number<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name).ok().and_then(|v| v.trim().parse().ok()).unwrap_or(default)
+}
+
+// "#RRGGBB" 或 "#RRGGBBAA"
+fn parse_color(color: &str) -> Option<Rgba<u8>> {
+    let hex = color.trim().trim_start_matches('#');
+    if hex.len() != 6 && hex.len() != 8 {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    let alpha = if hex.len() == 8 { channel(6)? } else { 255 };
+    Some(Rgba([channel(0)?, channel(2)?, channel(4)?, alpha]))
+}
+
+// ========================================
+// 处理流程
+// ========================================
+
+/// 依次执行：比例适配 → 缩放 → 水印 → 编码
+/// target 为项目输出尺寸（宽, 高），None 时跳过比例适配和缩放
+pub fn process(
+    original: &[u8],
+    config: &PostProcessConfig,
+    target: Option<(u32, u32)>,
+    watermark: Option<&DynamicImage>,
+) -> image::ImageResult<Vec<u8>> {
+    let mut image = image::load_from_memory(original)?;
+
+    if let Some((width, height)) = target {
+        image = match config.fit.as_str() {
+            "crop" => {
+                let cropped = crop_to_ratio(&image, width, height);
+                if config.resize {
+                    cropped.resize_exact(width, height, FilterType::Lanczos3)
+                } else {
+                    cropped
+                }
+            }
+            "pad" => {
+                let color = parse_color(&config.pad_color).unwrap_or(Rgba([0, 0, 0, 255]));
+                let (canvas_width, canvas_height) = if config.resize {
+                    (width, height)
+                } else {
+                    padded_size(image.width(), image.height(), width, height)
+                };
+                // 等比缩放到画布内（不缩放时原图已能放入画布）
+                let fitted = if config.resize {
+                    image.resize(canvas_width, canvas_height, FilterType::Lanczos3)
+                } else {
+                    image
+                };
+                pad_to(&fitted, canvas_width, canvas_height, color)
+            }
+            _ if config.resize => {
+                // 保持原比例，短边缩放到项目分辨率
+                let short_side = width.min(height) as f64;
+                let scale = short_side / image.width().min(image.height()).max(1) as f64;
+                let new_width = ((image.width() as f64 * scale).round() as u32).max(1);
+                let new_height = ((image.height() as f64 * scale).round() as u32).max(1);
+                image.resize_exact(new_width, new_height, FilterType::Lanczos3)
+            }
+            _ => image,
+        };
+    }
+
+    if let (Some(settings), Some(mark)) = (&config.watermark, watermark) {
+        image = apply_watermark(image, mark, settings);
+    }
+
+    encode(&image, config)
+}
+
+// 居中裁剪到目标比例
+fn crop_to_ratio(image: &DynamicImage, target_width: u32, target_height: u32) -> DynamicImage {
+    let (width, height) = image.dimensions();
+    let target_ratio = target_width as f64 / target_height as f64;
+    let ratio = width as f64 / height as f64;
+
+    let (crop_width, crop_height) = if ratio > target_ratio {
+        (((height as f64 * target_ratio).round() as u32).clamp(1, width), height)
+    } else {
+        (width, ((width as f64 / target_ratio).round() as u32).clamp(1, height))
+    };
+    if (crop_width, crop_height) == (width, height) {
+        return image.clone();
+    }
+    image.crop_imm((width - crop_width) / 2, (height - crop_height) / 2, crop_width, crop_height)
+}
+
+// 不缩放时，能容纳原图的最小目标比例画布
+fn padded_size(width: u32, height: u32, target_width: u32, target_height: u32) -> (u32, u32) {
+    let target_ratio = target_width as f64 / target_height as f64;
+    if width as f64 / height as f64 > target_ratio {
+        (width, ((width as f64 / target_ratio).round() as u32).max(height))
+    } else {
+        (((height as f64 * target_ratio).round() as u32).max(width), height)
+    }
+}
+
+// 把图片居中放到纯色画布上
+fn pad_to(image: &DynamicImage, width: u32, height: u32, color: Rgba<u8>) -> DynamicImage {
+    let mut canvas = RgbaImage::from_pixel(width, height, color);
+    let x = (width as i64 - image.width() as i64) / 2;
+    let y = (height as i64 - image.height() as i64) / 2;
+    imageops::overlay(&mut canvas, &image.to_rgba8(), x, y);
+    DynamicImage::ImageRgba8(canvas)
+}
+
+fn apply_watermark(image: DynamicImage, mark: &DynamicImage, settings: &WatermarkConfig) -> DynamicImage {
+    let mut canvas = image.to_rgba8();
+    let (width, height) = canvas.dimensions();
+
+    let mark_width = ((width as f32 * settings.scale).round() as u32).clamp(1, width);
+    let mark_height = ((mark.height() as f32 * mark_width as f32 / mark.width().max(1) as f32).round() as u32)
+        .clamp(1, height);
+    let mut mark = mark.resize_exact(mark_width, mark_height, FilterType::Lanczos3).to_rgba8();
+    for pixel in mark.pixels_mut() {
+        pixel[3] = (pixel[3] as f32 * settings.opacity).round() as u8;
+    }
+
+    let margin = settings.margin as i64;
+    let right = width as i64 - mark_width as i64 - margin;
+    let bottom = height as i64 - mark_height as i64 - margin;
+    let (x, y) = match settings.position.as_str() {
+        "top_left" => (margin, margin),
+        "top_right" => (right, margin),
+        "bottom_left" => (margin, bottom),
+        "center" => ((width - mark_width) as i64 / 2, (height - mark_height) as i64 / 2),
+        _ => (right, bottom),
+    };
+    imageops::overlay(&mut canvas, &mark, x.max(0), y.max(0));
+    DynamicImage::ImageRgba8(canvas)
+}
+
+fn encode(image: &DynamicImage, config: &PostProcessConfig) -> image::ImageResult<Vec<u8>> {
+    let mut bytes = Cursor::new(Vec::new());
+    match config.format.as_str() {
+        "jpeg" => {
+            // JPEG 不支持透明通道
+            let rgb = image.to_rgb8();
+            image::codecs::jpeg::JpegEncoder::new_with_quality(&mut bytes, config.quality.unwrap_or(DEFAULT_JPEG_QUALITY))
+                .write_image(rgb.as_raw(), rgb.width(), rgb.height(), ColorType::Rgb8)?;
+        }
+        "webp" => {
+            let rgba = image.to_rgba8();
+            image::codecs::webp::WebPEncoder::new_lossless(&mut bytes)
+                .write_image(rgba.as_raw(), rgba.width(), rgba.height(), ColorType::Rgba8)?;
+        }
+        _ => image.write_to(&mut bytes, image::ImageOutputFormat::Png)?,
+    }
+    Ok(bytes.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(width: u32, height: u32, color: Rgba<u8>) -> Vec<u8> {
+        let mut bytes = Cursor::new(Vec::new());
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(width, height, color))
+            .write_to(&mut bytes, image::ImageOutputFormat::Png)
+            .unwrap();
+        bytes.into_inner()
+    }
+
+    fn enabled(fit: &str) -> PostProcessConfig {
+        PostProcessConfig { enabled: true, fit: fit.to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn parse_color_accepts_rgb_and_rgba() {
+        assert_eq!(parse_color("#ff8000"), Some(Rgba([255, 128, 0, 255])));
+        assert_eq!(parse_color(" 00000080 "), Some(Rgba([0, 0, 0, 128])));
+        assert_eq!(parse_color("#fff"), None);
+        assert_eq!(parse_color("#gg0000"), None);
+        assert_eq!(parse_color("#ff00000"), None);
+    }
+
+    #[test]
+    fn crop_to_ratio_keeps_center() {
+        let wide = DynamicImage::ImageRgba8(RgbaImage::new(400, 100));
+        assert_eq!(crop_to_ratio(&wide, 16, 9).dimensions(), (178, 100));
+
+        let tall = DynamicImage::ImageRgba8(RgbaImage::new(100, 400));
+        assert_eq!(crop_to_ratio(&tall, 16, 9).dimensions(), (100, 56));
+
+        let exact = DynamicImage::ImageRgba8(RgbaImage::new(160, 90));
+        assert_eq!(crop_to_ratio(&exact, 1920, 1080).dimensions(), (160, 90));
+    }
+
+    #[test]
+    fn padded_size_fits_original() {
+        assert_eq!(padded_size(100, 100, 16, 9), (178, 100));
+        assert_eq!(padded_size(400, 100, 16, 9), (400, 225));
+        assert_eq!(padded_size(160, 90, 16, 9), (160, 90));
+        assert_eq!(padded_size(100, 100, 9, 16), (100, 178));
+    }
+
+    #[test]
+    fn validate_rejects_invalid_configs() {
+        assert!(PostProcessConfig::default().validate().is_ok());
+
+        let invalid = [
+            PostProcessConfig { fit: "stretch".to_string(), ..Default::default() },
+            PostProcessConfig { pad_color: "black".to_string(), ..Default::default() },
+            PostProcessConfig { format: "gif".to_string(), ..Default::default() },
+            PostProcessConfig { format: "jpeg".to_string(), quality: Some(0), ..Default::default() },
+            PostProcessConfig { format: "jpeg".to_string(), quality: Some(101), ..Default::default() },
+            PostProcessConfig { format: "webp".to_string(), quality: Some(90), ..Default::default() },
+        ];
+        for config in &invalid {
+            assert!(config.validate().is_err(), "{:?}", config);
+        }
+
+        let jpeg = PostProcessConfig { format: "jpeg".to_string(), quality: Some(80), ..Default::default() };
+        assert!(jpeg.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_invalid_watermarks() {
+        let with_watermark = |watermark: WatermarkConfig| PostProcessConfig {
+            watermark: Some(watermark),
+            ..Default::default()
+        };
+        let local = WatermarkConfig { image_url: "/data/watermark.png".to_string(), ..Default::default() };
+        assert!(with_watermark(local.clone()).validate().is_ok());
+
+        let invalid = [
+            WatermarkConfig { image_url: "  ".to_string(), ..local.clone() },
+            WatermarkConfig { image_url: "https://example.com/mark.png".to_string(), ..local.clone() },
+            WatermarkConfig { image_url: "/etc/passwd".to_string(), ..local.clone() },
+            WatermarkConfig { image_url: "/data/../secret.png".to_string(), ..local.clone() },
+            WatermarkConfig { position: "middle".to_string(), ..local.clone() },
+            WatermarkConfig { opacity: 1.5, ..local.clone() },
+            WatermarkConfig { scale: 0.0, ..local.clone() },
+        ];
+        for watermark in invalid {
+            assert!(with_watermark(watermark).validate().is_err());
+        }
+    }
+
+    #[test]
+    fn process_crops_and_resizes_to_target() {
+        let output = process(&png(400, 100, Rgba([255, 0, 0, 255])), &enabled("crop"), Some((64, 36)), None).unwrap();
+        let image = image::load_from_memory(&output).unwrap();
+        assert_eq!(image.dimensions(), (64, 36));
+        assert_eq!(image.to_rgba8().get_pixel(32, 18), &Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn process_pads_with_color() {
+        let config = PostProcessConfig { pad_color: "#00ff00".to_string(), ..enabled("pad") };
+        let output = process(&png(40, 40, Rgba([255, 0, 0, 255])), &config, Some((64, 36)), None).unwrap();
+        let image = image::load_from_memory(&output).unwrap().to_rgba8();
+        assert_eq!(image.dimensions(), (64, 36));
+        assert_eq!(image.get_pixel(0, 18), &Rgba([0, 255, 0, 255]));
+        assert_eq!(image.get_pixel(32, 18), &Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn process_without_resize_keeps_resolution() {
+        let config = PostProcessConfig { resize: false, ..enabled("pad") };
+        let output = process(&png(100, 100, Rgba([255, 0, 0, 255])), &config, Some((16, 9)), None).unwrap();
+        assert_eq!(image::load_from_memory(&output).unwrap().dimensions(), (178, 100));
+
+        let config = PostProcessConfig { resize: false, ..enabled("none") };
+        let output = process(&png(30, 20, Rgba([255, 0, 0, 255])), &config, Some((16, 9)), None).unwrap();
+        assert_eq!(image::load_from_memory(&output).unwrap().dimensions(), (30, 20));
+    }
+
+    #[test]
+    fn process_encodes_requested_format() {
+        let original = png(20, 20, Rgba([0, 0, 255, 255]));
+        for (format, expected) in [
+            ("png", image::ImageFormat::Png),
+            ("jpeg", image::ImageFormat::Jpeg),
+            ("webp", image::ImageFormat::WebP),
+        ] {
+            let config = PostProcessConfig { format: format.to_string(), ..enabled("none") };
+            let output = process(&original, &config, None, None).unwrap();
+            assert_eq!(image::guess_format(&output).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn process_applies_watermark() {
+        let settings = WatermarkConfig {
+            image_url: "/data/watermark.png".to_string(),
+            position: "top_left".to_string(),
+            opacity: 1.0,
+            scale: 0.5,
+            margin: 0,
+        };
+        let config = PostProcessConfig { watermark: Some(settings), ..enabled("none") };
+        let mark = DynamicImage::ImageRgba8(RgbaImage::from_pixel(10, 10, Rgba([255, 255, 255, 255])));
+        let output = process(&png(40, 40, Rgba([0, 0, 0, 255])), &config, None, Some(&mark)).unwrap();
+        let image = image::load_from_memory(&output).unwrap().to_rgba8();
+        assert_eq!(image.get_pixel(5, 5), &Rgba([255, 255, 255, 255]));
+        assert_eq!(image.get_pixel(35, 35), &Rgba([0, 0, 0, 255]));
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+
+/// 提示词模板渲染上下文
+///
+/// 支持的占位符：
+/// - `{{character:角色名}}` 展开为该角色的提示词
+/// - `{{var:变量名}}` 展开为项目变量的值
+pub struct PromptContext {
+    characters: HashMap<String, String>,
+    variables: HashMap<String, String>,
+}
+
+impl PromptContext {
+    pub fn new<I>(characters: I, variables: HashMap<String, String>) -> Self
+    where
+        I: IntoIterator<Item = (String, Option<String>)>,
+    {
+        // 没有提示词的角色不加入上下文，引用时按未解析处理
+        let characters = characters
+            .into_iter()
+            .filter_map(|(name, prompt)| {
+                let prompt = prompt?.trim().to_string();
+                if prompt.is_empty() {
+                    None
+                } else {
+                    Some((name.trim().to_string(), prompt))
+                }
+            })
+            .collect();
+
+        PromptContext { characters, variables }
+    }
+
+    fn resolve(&self, placeholder: &str) -> Option<&str> {
+        let (kind, name) = placeholder.split_once(':')?;
+        let name = name.trim();
+        match kind.trim() {
+            "character" | "角色" => self.characters.get(name).map(|s| s.as_str()),
+            "var" | "变量" => self.variables.get(name).map(|s| s.as_str()),
+            _ => None,
+        }
+    }
+}
+
+/// 展开提示词中的占位符；存在无法解析的占位符时返回它们的列表（去重，保持出现顺序）
+pub fn render(template: &str, ctx: &PromptContext) -> Result<String, Vec<String>> {
+    let mut output = String::with_capacity(template.len());
+    let mut unresolved: Vec<String> = Vec::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        let end = start + 2 + len;
+
+        output.push_str(&rest[..start]);
+        match ctx.resolve(&rest[start + 2..end]) {
+            Some(value) => output.push_str(value),
+            None => {
+                let raw = rest[start..end + 2].to_string();
+                if !unresolved.contains(&raw) {
+                    unresolved.push(raw);
+                }
+            }
+        }
+        rest = &rest[end + 2..];
+    }
+    output.push_str(rest);
+
+    if unresolved.is_empty() {
+        Ok(output)
+    } else {
+        Err(unresolved)
+    }
+}
+
+/// 校验变量名：不能为空，且不能包含占位符语法字符
+pub fn is_valid_variable_name(name: &str) -> bool {
+    let name = name.trim();
+    !name.is_empty() && !name.contains(['{', '}', ':'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> PromptContext {
+        PromptContext::new(
+            vec![
+                (" 小明 ".to_string(), Some(" short black hair, school uniform ".to_string())),
+                ("小红".to_string(), Some("   ".to_string())),
+                ("小刚".to_string(), None),
+            ],
+            HashMap::from([
+                ("地点".to_string(), "rainy Tokyo street".to_string()),
+                ("时间".to_string(), "night".to_string()),
+            ]),
+        )
+    }
+
+    #[test]
+    fn render_expands_characters_and_variables() {
+        assert_eq!(
+            render("{{character:小明}} walking, {{var:地点}} at {{ 变量 : 时间 }}", &context()),
+            Ok("short black hair, school uniform walking, rainy Tokyo street at night".to_string())
+        );
+        assert_eq!(render("{{角色:小明}}", &context()), Ok("short black hair, school uniform".to_string()));
+        assert_eq!(render("no placeholders", &context()), Ok("no placeholders".to_string()));
+    }
+
+    #[test]
+    fn render_reports_missing_and_unknown_placeholders() {
+        // 未定义的变量、没有提示词的角色、未知类型和缺少类型的占位符都无法解析，重复的只报告一次
+        assert_eq!(
+            render(
+                "{{var:天气}} {{character:小红}} {{character:小刚}} {{scene:1}} {{地点}} {{var:天气}}",
+                &context()
+            ),
+            Err(vec![
+                "{{var:天气}}".to_string(),
+                "{{character:小红}}".to_string(),
+                "{{character:小刚}}".to_string(),
+                "{{scene:1}}".to_string(),
+                "{{地点}}".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn render_keeps_unclosed_braces() {
+        assert_eq!(render("{{var:地点}} {{var:时间", &context()), Ok("rainy Tokyo street {{var:时间".to_string()));
+    }
+
+    #[test]
+    fn variable_names_reject_placeholder_syntax() {
+        assert!(is_valid_variable_name("地点"));
+        assert!(!is_valid_variable_name("  "));
+        assert!(!is_valid_variable_name("a:b"));
+        assert!(!is_valid_variable_name("{{x}}"));
+    }
+}
@@ -0,0 +1,11 @@
+// 文本处理的通用函数
+
+/// 是否为中日韩文字（按单字切分、需要翻译的文字）
+pub fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30FF     // 平假名、片假名
+        | 0x3400..=0x4DBF   // CJK 扩展 A
+        | 0x4E00..=0x9FFF   // CJK 统一表意文字
+        | 0xAC00..=0xD7AF   // 韩文音节
+        | 0xF900..=0xFAFF)  // CJK 兼容表意文字
+}
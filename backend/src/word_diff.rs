@@ -0,0 +1,184 @@
+use serde::Serialize;
+
+use crate::text::is_cjk;
+
+/// 差异片段
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffOp {
+    Equal,
+    Insert,
+    Delete,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffChunk {
+    pub op: DiffOp,
+    pub text: String,
+}
+
+// 超过该规模（去掉公共前后缀后的 token 数乘积）时不再逐词比较，整体作为替换
+const MAX_LCS_CELLS: usize = 4_000_000;
+
+/// 按词比较两段文本
+///
+/// 英文按单词切分，中文等 CJK 文字按单字切分，空白和标点各自成为一个片段。
+pub fn diff(old: &str, new: &str) -> Vec<DiffChunk> {
+    let a = tokenize(old);
+    let b = tokenize(new);
+
+    // 去掉公共前缀和后缀，缩小 LCS 规模
+    let prefix = a.iter().zip(&b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+
+    let mut chunks = Vec::new();
+    push(&mut chunks, DiffOp::Equal, &a[..prefix]);
+
+    let a_mid = &a[prefix..a.len() - suffix];
+    let b_mid = &b[prefix..b.len() - suffix];
+    if a_mid.len() * b_mid.len() > MAX_LCS_CELLS {
+        push(&mut chunks, DiffOp::Delete, a_mid);
+        push(&mut chunks, DiffOp::Insert, b_mid);
+    } else {
+        lcs_diff(a_mid, b_mid, &mut chunks);
+    }
+
+    push(&mut chunks, DiffOp::Equal, &a[a.len() - suffix..]);
+    chunks
+}
+
+fn lcs_diff(a: &[&str], b: &[&str], chunks: &mut Vec<DiffChunk>) {
+    let (n, m) = (a.len(), b.len());
+
+    // lengths[i][j] = a[i..] 与 b[j..] 的最长公共子序列长度
+    let mut lengths = vec![0u32; (n + 1) * (m + 1)];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i * (m + 1) + j] = if a[i] == b[j] {
+                lengths[(i + 1) * (m + 1) + j + 1] + 1
+            } else {
+                lengths[(i + 1) * (m + 1) + j].max(lengths[i * (m + 1) + j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if a[i] == b[j] {
+            push(chunks, DiffOp::Equal, &a[i..i + 1]);
+            i += 1;
+            j += 1;
+        } else if lengths[(i + 1) * (m + 1) + j] >= lengths[i * (m + 1) + j + 1] {
+            push(chunks, DiffOp::Delete, &a[i..i + 1]);
+            i += 1;
+        } else {
+            push(chunks, DiffOp::Insert, &b[j..j + 1]);
+            j += 1;
+        }
+    }
+    push(chunks, DiffOp::Delete, &a[i..]);
+    push(chunks, DiffOp::Insert, &b[j..]);
+}
+
+// 追加片段，与上一个同类片段合并
+fn push(chunks: &mut Vec<DiffChunk>, op: DiffOp, tokens: &[&str]) {
+    if tokens.is_empty() {
+        return;
+    }
+    let text = tokens.concat();
+    match chunks.last_mut() {
+        Some(last) if last.op == op => last.text.push_str(&text),
+        _ => chunks.push(DiffChunk { op, text }),
+    }
+}
+
+fn tokenize(text: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut word_start: Option<usize> = None;
+
+    for (i, c) in text.char_indices() {
+        if c.is_alphanumeric() && !is_cjk(c) {
+            word_start.get_or_insert(i);
+            continue;
+        }
+        if let Some(start) = word_start.take() {
+            tokens.push(&text[start..i]);
+        }
+        tokens.push(&text[i..i + c.len_utf8()]);
+    }
+    if let Some(start) = word_start {
+        tokens.push(&text[start..]);
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ops(chunks: &[DiffChunk]) -> Vec<(DiffOp, &str)> {
+        chunks.iter().map(|c| (c.op.clone(), c.text.as_str())).collect()
+    }
+
+    #[test]
+    fn tokenize_splits_words_cjk_and_punctuation() {
+        assert_eq!(tokenize("a cat, 2 dogs"), ["a", " ", "cat", ",", " ", "2", " ", "dogs"]);
+        assert_eq!(tokenize("红色的car"), ["红", "色", "的", "car"]);
+        assert_eq!(tokenize("カメラ zoom"), ["カ", "メ", "ラ", " ", "zoom"]);
+        assert!(tokenize("").is_empty());
+    }
+
+    #[test]
+    fn diff_of_equal_text_is_single_chunk() {
+        assert_eq!(ops(&diff("same text", "same text")), [(DiffOp::Equal, "same text")]);
+        assert!(diff("", "").is_empty());
+    }
+
+    #[test]
+    fn diff_replaces_changed_word() {
+        assert_eq!(
+            ops(&diff("a red cat", "a blue cat")),
+            [(DiffOp::Equal, "a "), (DiffOp::Delete, "red"), (DiffOp::Insert, "blue"), (DiffOp::Equal, " cat")]
+        );
+    }
+
+    #[test]
+    fn diff_handles_insertions_and_deletions() {
+        assert_eq!(ops(&diff("", "new")), [(DiffOp::Insert, "new")]);
+        assert_eq!(ops(&diff("old", "")), [(DiffOp::Delete, "old")]);
+        assert_eq!(
+            ops(&diff("小猫在睡觉", "小猫在花园里睡觉")),
+            [(DiffOp::Equal, "小猫在"), (DiffOp::Insert, "花园里"), (DiffOp::Equal, "睡觉")]
+        );
+    }
+
+    #[test]
+    fn lcs_keeps_common_subsequence() {
+        let mut chunks = Vec::new();
+        lcs_diff(&["a", "b", "c", "d"], &["b", "x", "d"], &mut chunks);
+        assert_eq!(
+            ops(&chunks),
+            [
+                (DiffOp::Delete, "a"),
+                (DiffOp::Equal, "b"),
+                (DiffOp::Delete, "c"),
+                (DiffOp::Insert, "x"),
+                (DiffOp::Equal, "d"),
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_chunks_reconstruct_both_texts() {
+        let (old, new) = ("The hero walks slowly 走进森林。", "A hero runs 跑进黑暗的森林!");
+        let chunks = diff(old, new);
+        let rebuild = |skip: DiffOp| chunks.iter().filter(|c| c.op != skip).map(|c| c.text.as_str()).collect::<String>();
+        assert_eq!(rebuild(DiffOp::Insert), old);
+        assert_eq!(rebuild(DiffOp::Delete), new);
+    }
+}
@@ -89,7 +89,8 @@ export default function NewCharacterPage() {
                     body: JSON.stringify({
                         image_base64: base64Data.data,
                         mime_type: base64Data.mimeType,
-                        prompt: formData.prompt
+                        prompt: formData.prompt,
+                        save_to: 'preview'
                     }),
                 })
 
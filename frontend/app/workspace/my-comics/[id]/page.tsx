@@ -1314,7 +1314,11 @@ function MediaTab({
             const endpoint = isImage ? 'generate-image' : 'generate-video';
             const response = await fetch(
                 `http://localhost:3001/api/projects/${projectId}/scenes/${sceneId}/${endpoint}`,
-                { method: 'POST' }
+                {
+                    method: 'POST',
+                    headers: { 'Content-Type': 'application/json' },
+                    body: JSON.stringify({}),
+                }
             );
 
             if (response.ok) {